@@ -1,6 +1,5 @@
 #import waw_earth_render::types::{EarthMaterial, AtlasTile}
 #import waw_earth_render::bindings::{
-    bathyometry_attachment,
     earth, earth_view, nighttime_attachment,
     attachments, daytime_attachment, ocean_attachment
 }
@@ -53,7 +52,8 @@ fn terrain(tile: AtlasTile, info: FragmentInfo) -> vec3<f32> {
 
 fn ocean(tile: AtlasTile, info: FragmentInfo, surface_gradient: vec3<f32>) -> vec3<f32> {
     let world_position = vec4<f32>(apply_height(info.world_coordinate, info.height), 1.0);
-    let depth = sample_attachment(tile, bathyometry_attachment, attachments.bathyometry).x;
+    // 1 at the sea level and 0 at the deepest point of the topography
+    let depth = 1.0 - clamp(info.height / min(earth.min_height, -1.0), 0.0, 1.0);
     let base_ocean_color = calculate_ocean_color(depth);
     let normal = calculate_water_normal(world_position.xz * 0.0000001, info.world_coordinate.normal, globals.time * 10.0, depth);
     var pbr_input: PbrInput                 = pbr_input_new();
@@ -0,0 +1,103 @@
+//! Benchmarks of the preprocess stages on a synthetic source.
+//!
+//! Run with `cargo bench -p waw_earth_preprocess --features bench`.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use waw_earth_preprocess::harness::{Harness, SyntheticEarth, SyntheticRaster, SyntheticSource};
+
+fn harness() -> Harness {
+    let source = SyntheticSource {
+        pixels_per_degree: 4,
+        hole: Some([35.0, 15.0, 45.0, 25.0]),
+        ..SyntheticSource::new(SyntheticRaster::Gradient)
+    };
+
+    let mut harness = Harness::new("bench", &source).unwrap();
+    harness.lod_count = 3;
+    harness.texture_size = 130;
+    harness.fill_radius = 16.0;
+    harness
+}
+
+fn stages(c: &mut Criterion) {
+    let harness = harness();
+
+    let mut group = c.benchmark_group("stages");
+    group.sample_size(10);
+
+    group.bench_function("reproject", |b| {
+        b.iter_batched(
+            || harness.earth("reproject").unwrap(),
+            |mut earth| earth.reproject().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("reproject_to_tiles", |b| {
+        b.iter_batched(
+            || harness.earth("reproject_to_tiles").unwrap(),
+            |mut earth| earth.reproject_to_tiles().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("split_and_stitch", |b| {
+        b.iter_batched(
+            || {
+                let mut earth = harness.earth("split_and_stitch").unwrap();
+                earth.reproject().unwrap();
+                earth
+            },
+            |mut earth| earth.split_and_stitch().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    let split = |name| -> SyntheticEarth {
+        let mut earth = harness.earth(name).unwrap();
+        earth.reproject().unwrap();
+        earth.split_and_stitch().unwrap();
+        earth
+    };
+
+    group.bench_function("downsample_and_stitch", |b| {
+        b.iter_batched(
+            || split("downsample_and_stitch"),
+            |mut earth| earth.downsample_and_stitch().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("create_mask_and_fill_no_data", |b| {
+        b.iter_batched(
+            || {
+                let mut earth = split("create_mask_and_fill_no_data");
+                earth.downsample_and_stitch().unwrap();
+                earth
+            },
+            |earth| earth.fill().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+fn pipelines(c: &mut Criterion) {
+    let harness = harness();
+
+    let mut group = c.benchmark_group("pipelines");
+    group.sample_size(10);
+
+    group.bench_function("classic", |b| {
+        b.iter(|| harness.classic("classic").unwrap())
+    });
+    group.bench_function("streaming", |b| {
+        b.iter(|| harness.streaming("streaming").unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, stages, pipelines);
+criterion_main!(benches);
@@ -1,8 +1,8 @@
 use clap::Parser;
-use std::env::set_var;
+use std::{env::set_var, process::ExitCode};
 use waw_earth_preprocess::prelude::*;
 
-fn main() {
+fn main() -> ExitCode {
     unsafe {
         if true {
             set_var("RAYON_NUM_THREADS", "0");
@@ -14,10 +14,46 @@ fn main() {
     }
 
     let args = Cli::parse();
-    match args.command {
-        Command::Earth(args) => {
-            let (src_dataset, mut context) = PreprocessContext::from_cli(args).unwrap();
-            preprocess(src_dataset, &mut context);
+    let progress = Progress::new(TerminalProgress::default());
+
+    let result = match args.command {
+        Command::Earth(args) => PreprocessContext::from_cli(args).and_then(
+            |(src_dataset, mut context)| preprocess(src_dataset, &mut context, &progress),
+        ),
+        Command::Job(args) => EarthJob::load(&args.job_path).and_then(|job| {
+            if args.check {
+                job.validate()
+            } else {
+                preprocess_job(&job, &progress)
+            }
+        }),
+        Command::Verify(args) => verify_earth(&args.earth_path, args.tolerance).and_then(|report| {
+            report.write(args.output.as_deref())?;
+
+            match report.issue_count() {
+                0 => Ok(()),
+                count => Err(PreprocessError::InvalidEarth(count)),
+            }
+        }),
+        Command::Convert(args) => {
+            convert_earth(&args.earth_path, args.attachment.as_ref(), args.unpack, &progress)
+        }
+        Command::Derive(args) => {
+            derive_terrain(&args.earth_path, &args.products, &args.options, &progress)
         }
+        Command::Sdf(args) => generate_sdf(
+            &args.earth_path,
+            &args.source,
+            &args.output,
+            &args.options,
+            &progress,
+        ),
+    };
+
+    if let Err(error) = result {
+        eprintln!("Preprocessing failed: {error}");
+        return ExitCode::FAILURE;
     }
+
+    ExitCode::SUCCESS
 }
@@ -1,8 +1,15 @@
-use crate::core::{PreprocessDataType, PreprocessNoData, ProgressCallback};
+use crate::{
+    core::{
+        PreprocessDataType, PreprocessNoData, PreprocessSource, RasterizeOptions, WarpBackend,
+    },
+    derive::{TerrainOptions, TerrainProduct},
+    progress::ProgressSink,
+    sdf::SdfOptions,
+};
 use clap::{Subcommand, Parser, Args};
 use gdal::raster::GdalDataType;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Mutex, time::Duration};
 use waw_earth_render::prelude::*;
 
 const BAR_SIZE: u64 = 10000;
@@ -15,14 +22,78 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Command {
-    Earth(EarthCli)
+    Earth(EarthCli),
+    /// Preprocesses all attachments described by a job file.
+    Job(JobCli),
+    /// Checks a processed earth for missing or broken tiles and reports them as RON.
+    Verify(VerifyCli),
+    /// Converts the attachments of an earth between loose tiles and tile archives.
+    Convert(ConvertCli),
+    /// Computes a signed distance field from a binary or categorical attachment.
+    Sdf(SdfCli),
+    /// Derives slope, aspect, hillshade and normal attachments from the topography.
+    Derive(DeriveCli),
+}
+
+#[derive(Args, Debug)]
+pub struct DeriveCli {
+    pub earth_path: PathBuf,
+    /// The products to derive (slope, aspect, hillshade or normals), defaults to all of them.
+    #[arg(short, long = "product", value_delimiter = ',')]
+    pub products: Vec<TerrainProduct>,
+    #[command(flatten)]
+    pub options: TerrainOptions,
+}
+
+#[derive(Args, Debug)]
+pub struct SdfCli {
+    pub earth_path: PathBuf,
+    /// The binary or categorical attachment, like the ocean mask or country IDs.
+    pub source: AttachmentLabel,
+    /// The label of the distance field attachment.
+    pub output: AttachmentLabel,
+    #[command(flatten)]
+    pub options: SdfOptions,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertCli {
+    pub earth_path: PathBuf,
+    /// Only converts this attachment, instead of all of them.
+    #[arg(short, long)]
+    pub attachment: Option<AttachmentLabel>,
+    /// Unpacks the archives into loose tiles, instead of packing the loose tiles.
+    #[arg(long, default_value_t = false)]
+    pub unpack: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyCli {
+    pub earth_path: PathBuf,
+    /// Writes the report to this file, instead of stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The maximum difference between a border pixel and the one of its neighbour.
+    #[arg(long, default_value_t = 0.0)]
+    pub tolerance: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct JobCli {
+    /// The RON file describing the earth and its attachments.
+    pub job_path: PathBuf,
+    /// Only validates the job file, without preprocessing anything.
+    #[arg(long, default_value_t = false)]
+    pub check: bool,
 }
 
 #[derive(Args, Debug)]
 #[command(version, about)]
 pub struct EarthCli {
+    /// Source files or directories, optionally as
+    /// `path[,priority=<i32>][,no_data=<f64>][,scale=<f64>][,offset=<f64>]`.
     #[arg(required = true)]
-    pub src_path: Vec<PathBuf>,
+    pub src_path: Vec<PreprocessSource>,
     #[arg(required = true)]
     // cloud be optional and use current directory, but this would be risky in combination with overwrite
     pub earth_path: PathBuf,
@@ -31,12 +102,22 @@ pub struct EarthCli {
 
     #[arg(short, long, default_value_t = false)]
     pub overwrite: bool,
+    /// Merges the sources into the tiles of an existing earth, instead of rebuilding it.
+    /// This requires the raw tiles of the previous run, which packed attachments do not keep.
+    #[arg(short, long, default_value_t = false, conflicts_with = "overwrite")]
+    pub update: bool,
+    /// Packs the tiles of the attachment into a single archive, once they are processed.
+    #[arg(long, default_value_t = false)]
+    pub pack: bool,
     #[arg(default_value = "source")]
     pub no_data: PreprocessNoData,
     #[arg(default_value = "source")]
     pub data_type: PreprocessDataType,
     #[arg(default_value_t = 16.0)]
     pub fill_radius: f32,
+    /// Width in pixels, over which overlapping sources of different priorities are blended.
+    #[arg(long, default_value_t = 0.0)]
+    pub blend_width: f32,
     #[arg(default_value_t = false)]
     pub create_mask: bool,
 
@@ -53,6 +134,42 @@ pub struct EarthCli {
     pub mip_level_count: u32,
     #[arg(default_value = "ru16")]
     pub format: AttachmentFormat,
+    /// Resampling method used for reprojecting the sources
+    /// (nearest, bilinear, cubic, cubicspline, lanczos, average or mode).
+    /// Defaults to nearest for categorical attachments and bilinear otherwise.
+    #[arg(long)]
+    pub reproject_resampling: Option<ResamplingMethod>,
+    /// Resampling method used for downsampling the lods.
+    /// Defaults to mode for categorical attachments and bilinear otherwise.
+    #[arg(long)]
+    pub downsample_resampling: Option<ResamplingMethod>,
+    /// Compression of the tile files
+    /// (none, deflate, zstd, lerc[:max_error], webp[:quality] or jpeg[:quality]).
+    #[arg(long, default_value = "none")]
+    pub compression: TileCompression,
+    /// How vector sources (.gpkg, .shp, .geojson) are rasterized.
+    #[command(flatten)]
+    pub rasterize: RasterizeOptions,
+    /// How the sources are reprojected onto the cube (gdal or native).
+    /// The native backend computes the pixels in parallel without GDAL's custom transformer,
+    /// but only supports geographic (EPSG:4326) and equirectangular sources.
+    #[arg(long, default_value = "gdal")]
+    pub warp_backend: WarpBackend,
+    /// Reprojects the sources directly into tiles, instead of creating whole face images first.
+    /// The tiles are identical, but the temporary disk space and memory stay bounded.
+    #[arg(long, default_value_t = false, conflicts_with = "update")]
+    pub streaming: bool,
+    /// The memory in MiB, that the streaming pipeline may use for the tiles in flight
+    /// and the block cache of GDAL. Defaults to the limits of GDAL and one tile per thread.
+    #[arg(long, requires = "streaming")]
+    pub memory_budget: Option<u64>,
+    /// Stores the sources as this time slice of the attachment, counted from zero.
+    #[arg(long, requires = "time_slices")]
+    pub time_slice: Option<u32>,
+    /// The number of time slices of the attachment, like twelve for monthly composites.
+    /// Every slice is preprocessed on its own into the same tile layout.
+    #[arg(long, default_value_t = 0, requires = "time_slice")]
+    pub time_slices: u32,
 }
 
 impl Default for EarthCli {
@@ -62,9 +179,12 @@ impl Default for EarthCli {
             earth_path: "assets/earth/data".into(),
             temp_path: None,
             overwrite: false,
+            update: false,
+            pack: false,
             no_data: PreprocessNoData::Source,
             data_type: PreprocessDataType::DataType(GdalDataType::UInt8),
             fill_radius: 0.0,
+            blend_width: 0.0,
             create_mask: false,
             lod_count: Some(6),
             attachment_label: AttachmentLabel::Topography,
@@ -72,46 +192,60 @@ impl Default for EarthCli {
             border_size: 2,
             mip_level_count: 4,
             format: AttachmentFormat::Rgb8U,
+            reproject_resampling: None,
+            downsample_resampling: None,
+            compression: TileCompression::None,
+            rasterize: RasterizeOptions::default(),
+            warp_backend: WarpBackend::Gdal,
+            streaming: false,
+            memory_budget: None,
+            time_slice: None,
+            time_slices: 0,
         }
     }
 }
 
-pub(crate) struct PreprocessBar<'a> {
-    name: String,
-    bar: ProgressBar,
-    callback: Box<ProgressCallback<'a>>,
+/// Shows the progress of every stage as a bar in the terminal.
+#[derive(Default)]
+pub struct TerminalProgress {
+    bar: Mutex<Option<ProgressBar>>,
 }
 
-impl PreprocessBar<'_> {
-    pub(crate) fn new(name: String) -> Self {
+impl ProgressSink for TerminalProgress {
+    fn stage_started(&self, stage: &str) {
         let bar = ProgressBar::new(BAR_SIZE).with_style(
             ProgressStyle::with_template(
-                &(name.clone() + " dataset: {bar} {percent} % [{elapsed}/{duration}])"),
+                &(stage.to_string() + " dataset: {bar} {percent} % [{elapsed}/{duration}])"),
             )
             .unwrap(),
         );
 
-        let callback = Box::new({
-            let progress_bar = bar.clone();
-            move |completion| {
-                progress_bar.set_position((completion * BAR_SIZE as f64) as u64);
-                true
-            }
-        });
-
-        Self {
-            name,
-            bar,
-            callback,
+        *self.bar.lock().unwrap() = Some(bar);
+    }
+
+    fn stage_progress(&self, _stage: &str, completion: f64) {
+        if let Some(bar) = self.bar.lock().unwrap().as_ref() {
+            bar.set_position((completion * BAR_SIZE as f64) as u64);
+        }
+    }
+
+    fn stage_finished(&self, stage: &str, duration: Duration) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_and_clear();
         }
+
+        println!("{stage} took: {duration:?}");
     }
 
-    pub(crate) fn callback(&self) -> &ProgressCallback<'_> {
-        self.callback.as_ref()
+    fn message(&self, message: &str) {
+        println!("{message}");
     }
 
-    pub(crate) fn finish(&self) {
-        self.bar.finish_and_clear();
-        println!("{} took: {:?}", self.name, self.bar.elapsed());
+    fn warning(&self, message: &str) {
+        // the bar would be drawn over the warning otherwise
+        match self.bar.lock().unwrap().as_ref() {
+            Some(bar) => bar.suspend(|| eprintln!("Warning: {message}")),
+            None => eprintln!("Warning: {message}"),
+        }
     }
 }
@@ -1,30 +1,43 @@
 use crate::{
     cli::EarthCli,
-    core::{PreprocessError, PreprocessResult},
+    core::{
+        NativeWarp, PreprocessError, PreprocessResult, RasterizeOptions, StableHasher,
+        WarpBackend, is_vector_source, rasterize_source, translate,
+    },
 };
 use gdal::{
     Dataset, DatasetOptions, DriverManager, GdalOpenFlags, GeoTransform,
-    programs::raster::build_vrt,
-    raster::{ColorInterpretation, GdalDataType, GdalType, RasterCreationOptions},
+    programs::raster::{BuildVRTOptions, build_vrt},
+    raster::{Buffer, ColorInterpretation, GdalDataType, GdalType, RasterCreationOptions},
 };
 use glam::{IVec2, U64Vec2};
 use itertools::Itertools;
+use num::NumCast;
+use serde::Deserialize;
 use std::{
+    collections::HashSet,
     fs,
+    hash::{Hash, Hasher},
+    io,
     path::{Path, PathBuf},
-    process::Command,
+    process,
     str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 use waw_earth_render::{
-    data::{AttachmentConfig, AttachmentLabel},
+    data::{
+        AttachmentConfig, AttachmentFormat, AttachmentLabel, ResamplingMethod, TileCompression,
+    },
     math::TileCoordinate,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
 pub enum PreprocessNoData {
     Source,
     NoData(f64),
-    Alpha, // Todo: implement this
+    /// Uses the alpha band of the source (or adds one) to track which pixels are valid.
+    Alpha,
 }
 
 impl FromStr for PreprocessNoData {
@@ -41,7 +54,81 @@ impl FromStr for PreprocessNoData {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A source file or directory, together with its blending settings.
+///
+/// Parsed from `path[,priority=<i32>][,no_data=<f64>][,scale=<f64>][,offset=<f64>]`,
+/// where the options are split off from the end, so that the path itself may contain commas.
+/// Where sources overlap, the valid pixels of the one with the highest priority win.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct PreprocessSource {
+    pub path: PathBuf,
+    pub priority: i32,
+    /// Overrides the no data value of the source files.
+    pub no_data: Option<f64>,
+    /// Converts the values of the source files into `scale * value + offset`,
+    /// e.g. to merge heights, that were stored in different units or ranges.
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl From<PathBuf> for PreprocessSource {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            priority: 0,
+            no_data: None,
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl From<&str> for PreprocessSource {
+    fn from(path: &str) -> Self {
+        PathBuf::from(path).into()
+    }
+}
+
+impl FromStr for PreprocessSource {
+    type Err = PreprocessError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PreprocessError::InvalidSource(s.to_string());
+
+        let mut path = s;
+        let mut options = Vec::new();
+
+        // everything before the first part, that is not a known option, belongs to the path
+        while let Some((rest, option)) = path.rsplit_once(',')
+            && let Some((key, value)) = option.split_once('=')
+            && SOURCE_OPTIONS.contains(&key.trim())
+        {
+            options.push((key.trim(), value.trim()));
+            path = rest;
+        }
+
+        let mut source = Self::from(path.trim());
+
+        for (key, value) in options.into_iter().rev() {
+            match key {
+                "priority" => source.priority = value.parse().map_err(|_| invalid())?,
+                "no_data" => source.no_data = Some(value.parse()?),
+                "scale" => source.scale = value.parse().map_err(|_| invalid())?,
+                "offset" => source.offset = value.parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        if source.path.as_os_str().is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(source)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
 pub enum PreprocessDataType {
     Source,
     DataType(GdalDataType),
@@ -59,6 +146,38 @@ impl FromStr for PreprocessDataType {
     }
 }
 
+// job files use the same notation as the cli
+impl TryFrom<String> for PreprocessNoData {
+    type Error = PreprocessError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for PreprocessSource {
+    type Error = PreprocessError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for PreprocessDataType {
+    type Error = PreprocessError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The options, that can follow the path of a [`PreprocessSource`].
+const SOURCE_OPTIONS: [&str; 4] = ["priority", "no_data", "scale", "offset"];
+
+const DELETE_ATTEMPTS: usize = 4;
+/// The directory inside of the earth, that caches the rasterized vector sources.
+const RASTERIZED_DIR: &str = "rasterized";
+/// The directory inside of the attachment, that keeps the tiles before they are filled,
+/// stripped and compressed, so that later runs can rebuild and update them.
+const RAW_DIR: &str = "raw";
+
 pub(crate) struct FaceInfo {
     pub(crate) lod: u32,
     pub(crate) pixel_start: IVec2,
@@ -70,11 +189,35 @@ pub struct PreprocessContext {
     pub(crate) data_type: GdalDataType,
     pub(crate) no_data_value: Option<f64>,
     pub(crate) rasterbands: Vec<RasterbandConfig>,
+    /// The index of the band, that stores the validity of each pixel, if [`PreprocessNoData::Alpha`] is used.
+    pub(crate) alpha_band: Option<usize>,
+    pub(crate) src_alpha_band: Option<usize>,
+    /// All source files, sorted by ascending priority.
+    pub(crate) sources: Vec<PreprocessSource>,
+    /// The width in pixels, over which overlapping sources of different priorities are blended.
+    pub(crate) blend_width: f32,
+    /// One virtual dataset per priority, if the sources are blended.
+    pub(crate) blend_layers: Vec<PathBuf>,
+    /// Keeps the virtual datasets of the sources, until the context is dropped.
+    pub(crate) virtual_datasets: VirtualDatasets,
+    /// Restricts processing to these tiles of the highest lod, if only parts of the attachment are rebuilt.
+    pub(crate) dirty_tiles: Option<HashSet<TileCoordinate>>,
     pub(crate) tile_dir: PathBuf,
+    /// All stages before the filling read and write the tiles in this directory.
+    pub(crate) raw_dir: PathBuf,
     pub(crate) temp_dir: PathBuf,
     pub(crate) fill_radius: f32,
     pub(crate) create_mask: bool,
     pub(crate) overwrite: bool,
+    /// Merges the sources into the existing tiles, instead of rebuilding the attachment.
+    pub(crate) update: bool,
+    /// Packs the tiles into a single archive, once they are processed.
+    pub(crate) pack: bool,
+    pub(crate) warp_backend: WarpBackend,
+    /// Whether the sources are reprojected directly into tiles.
+    pub(crate) streaming: bool,
+    /// The memory in bytes, that the streaming pipeline may use.
+    pub(crate) memory_budget: Option<u64>,
 
     pub(crate) min_height: f32,
     pub(crate) max_height: f32,
@@ -92,9 +235,12 @@ impl PreprocessContext {
             earth_path,
             temp_path,
             overwrite,
+            update,
+            pack,
             no_data,
             data_type,
             fill_radius,
+            blend_width,
             create_mask,
             lod_count,
             attachment_label,
@@ -102,8 +248,27 @@ impl PreprocessContext {
             border_size,
             mip_level_count,
             format,
+            reproject_resampling,
+            downsample_resampling,
+            compression,
+            rasterize,
+            warp_backend,
+            streaming,
+            memory_budget,
+            time_slice,
+            time_slices,
         } = args;
 
+        // rasterized vector sources contain classes or ids, which must not be interpolated
+        let is_categorical = attachment_label.is_categorical()
+            || src_path.iter().any(|source| is_vector_source(&source.path));
+
+        let (default_reproject, default_downsample) = if is_categorical {
+            (ResamplingMethod::Nearest, ResamplingMethod::Mode)
+        } else {
+            (ResamplingMethod::Bilinear, ResamplingMethod::Bilinear)
+        };
+
         PreprocessContext::initialize(
             earth_path,
             lod_count,
@@ -114,14 +279,27 @@ impl PreprocessContext {
                 mip_level_count,
                 mask: create_mask,
                 format,
+                reproject_resampling: reproject_resampling.unwrap_or(default_reproject),
+                downsample_resampling: downsample_resampling.unwrap_or(default_downsample),
+                compression,
+                packed: false,
+                time_slices,
             },
+            time_slice,
             src_path,
             temp_path,
             no_data,
             data_type,
             fill_radius,
+            blend_width,
             create_mask,
             overwrite,
+            update,
+            pack,
+            rasterize,
+            warp_backend,
+            streaming,
+            memory_budget,
         )
     }
 
@@ -132,36 +310,144 @@ impl PreprocessContext {
 
         attachment_label: AttachmentLabel,
         attachment: AttachmentConfig,
+        time_slice: Option<u32>,
 
-        src_path: Vec<PathBuf>,
+        src_path: Vec<PreprocessSource>,
         temp_dir: Option<PathBuf>,
         no_data: PreprocessNoData,
         data_type: PreprocessDataType,
         fill_radius: f32,
+        blend_width: f32,
         create_mask: bool,
         overwrite: bool,
+        update: bool,
+        pack: bool,
+        rasterize: RasterizeOptions,
+        warp_backend: WarpBackend,
+        streaming: bool,
+        memory_budget: Option<u64>,
     ) -> PreprocessResult<(Dataset, Self)> {
-        let mut src_datasets = src_path
+        let mut sources = src_path
             .iter()
-            .flat_map(|src_path| {
-                if src_path.is_dir() {
-                    iter_directory(src_path).collect_vec()
+            .map(|source| -> PreprocessResult<Vec<PreprocessSource>> {
+                let paths = if source.path.is_dir() {
+                    iter_directory(&source.path)?.try_collect()?
+                } else {
+                    vec![source.path.clone()]
+                };
+
+                Ok(paths
+                    .into_iter()
+                    .map(|path| PreprocessSource {
+                        path,
+                        ..source.clone()
+                    })
+                    .collect())
+            })
+            .flatten_ok()
+            .filter_ok(|source| {
+                is_vector_source(&source.path)
+                    || source
+                        .path
+                        .extension()
+                        .is_some_and(|extension| extension == "tif" || extension == "tiff")
+            })
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        if sources.is_empty() {
+            return Err(PreprocessError::NoSourceFiles(
+                src_path.into_iter().map(|source| source.path).collect(),
+            ));
+        }
+
+        // later sources are drawn on top of earlier ones
+        sources.sort_by_key(|source| source.priority);
+
+        // vector sources are rasterized up front and then processed like raster sources
+        let rasterized_data_type = match data_type {
+            PreprocessDataType::Source => format_data_type(attachment.format),
+            PreprocessDataType::DataType(data_type) => data_type,
+        };
+        let rasterized_no_data = match no_data {
+            PreprocessNoData::NoData(value) => Some(value),
+            _ => None,
+        };
+        // a face spans about 90 degrees
+        let default_resolution = lod_count.map(|lod_count| {
+            90.0 / (attachment.center_size() as f64 * 2f64.powi(lod_count as i32 - 1))
+        });
+
+        let sources = sources
+            .into_iter()
+            .map(|source| {
+                if is_vector_source(&source.path) {
+                    rasterize_source(
+                        &source,
+                        &rasterize,
+                        rasterized_data_type,
+                        rasterized_no_data,
+                        default_resolution,
+                        &earth_path.join(RASTERIZED_DIR),
+                    )
                 } else {
-                    vec![src_path.clone()]
+                    Ok(source)
                 }
             })
-            //.flatten()
-            .filter(|path| {
-                let path = path.to_str().unwrap();
-                path.ends_with(".tif") || path.ends_with(".tiff")
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        let src_datasets = sources
+            .iter()
+            .map(|source| Dataset::open(&source.path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // all sources are merged into a single virtual dataset, so they have to be compatible
+        let spatial_ref = src_datasets[0].spatial_ref()?;
+        let band_count = src_datasets[0].raster_count();
+
+        for (source, dataset) in sources.iter().zip(&src_datasets).skip(1) {
+            if dataset.spatial_ref()? != spatial_ref {
+                return Err(PreprocessError::MismatchedSpatialRef(source.path.clone()));
+            }
+            if dataset.raster_count() != band_count {
+                return Err(PreprocessError::MismatchedBandCount {
+                    path: source.path.clone(),
+                    expected: band_count,
+                    found: dataset.raster_count(),
+                });
+            }
+        }
+
+        let mut virtual_datasets = VirtualDatasets::default();
+
+        let mut src_datasets = sources
+            .iter()
+            .zip(src_datasets)
+            .enumerate()
+            .map(|(index, (source, dataset))| {
+                with_source_overrides(
+                    index,
+                    source,
+                    dataset,
+                    rasterized_no_data,
+                    &mut virtual_datasets,
+                )
             })
-            .map(|path| Dataset::open(path).unwrap())
-            .collect_vec();
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        let blend_layers = if blend_width > 0.0 {
+            create_blend_layers(&sources, &src_datasets, &mut virtual_datasets)?
+        } else {
+            Vec::new()
+        };
 
         let src_dataset = if src_datasets.len() == 1 {
             src_datasets.remove(0)
         } else {
-            build_vrt(None, &src_datasets, None)?
+            // the streaming pipeline opens the mosaic on every thread, so it needs a path
+            let path = virtual_datasets.path("mosaic");
+            drop(build_vrt(Some(&path), &src_datasets, None)?);
+
+            Dataset::open(path)?
         };
 
         let data_type = match data_type {
@@ -169,66 +455,427 @@ impl PreprocessContext {
             PreprocessDataType::DataType(data_type) => data_type,
         };
 
+        // heights in metres are stored as they are, so the data type has to match the format
+        let metre_data_type = match attachment.format {
+            AttachmentFormat::R16I => Some(GdalDataType::Int16),
+            AttachmentFormat::R32F => Some(GdalDataType::Float32),
+            _ => None,
+        };
+
+        if attachment_label == AttachmentLabel::Topography
+            && metre_data_type.is_some_and(|metre_data_type| metre_data_type != data_type)
+        {
+            return Err(PreprocessError::MismatchedDataType {
+                format: attachment.format,
+                data_type,
+            });
+        }
+
+        if attachment.time_slices > 0 && attachment_label == AttachmentLabel::Topography {
+            return Err(PreprocessError::TimeSlicedTopography);
+        }
+
+        if let Some(slice) = time_slice
+            && slice >= attachment.time_slices
+        {
+            return Err(PreprocessError::TimeSliceOutOfRange {
+                slice,
+                count: attachment.time_slices,
+            });
+        }
+
+        let compression = attachment.compression;
+
+        // the image codecs only support 8 bit gray, RGB or RGBA tiles and lossless LERC is
+        // Huffman coded, which the tile loader can not decode
+        let supports_compression = match compression {
+            TileCompression::Lerc { max_error } => match data_type {
+                GdalDataType::Float32 | GdalDataType::Float64 => max_error > 0.0,
+                _ => max_error >= 1.0,
+            },
+            TileCompression::WebP { .. } => {
+                matches!(attachment.format, AttachmentFormat::Rgb8U | AttachmentFormat::Rgba8U)
+                    && data_type == GdalDataType::UInt8
+            }
+            TileCompression::Jpeg { .. } => {
+                matches!(attachment.format, AttachmentFormat::R8Unorm | AttachmentFormat::Rgb8U)
+                    && data_type == GdalDataType::UInt8
+            }
+            _ => true,
+        };
+
+        if !supports_compression {
+            return Err(PreprocessError::IncompatibleCompression {
+                compression,
+                format: attachment.format,
+            });
+        }
+
+        // the mask is stored in the least significant bit of each value
+        if create_mask && compression.is_lossy() {
+            return Err(PreprocessError::LossyMask(compression));
+        }
+
         let mut rasterbands = src_dataset
             .rasterbands()
-            .map(|rasterband| {
-                let rasterband = rasterband.unwrap();
-                RasterbandConfig {
-                    color_interpretation: rasterband.color_interpretation(),
-                }
+            .map_ok(|rasterband| RasterbandConfig {
+                color_interpretation: rasterband.color_interpretation(),
             })
-            .collect_vec();
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let no_data_value = match no_data {
-            PreprocessNoData::Source => src_dataset.rasterband(1)?.no_data_value(),
-            PreprocessNoData::NoData(value) => Some(value),
+        let src_alpha_band = rasterbands
+            .iter()
+            .position(|band| band.color_interpretation == ColorInterpretation::AlphaBand)
+            .map(|index| index + 1);
+
+        let (no_data_value, alpha_band) = match no_data {
+            PreprocessNoData::Source => (src_dataset.rasterband(1)?.no_data_value(), None),
+            PreprocessNoData::NoData(value) => (Some(value), None),
             PreprocessNoData::Alpha => {
-                rasterbands.push(RasterbandConfig {
-                    color_interpretation: ColorInterpretation::AlphaBand,
+                // sources without an alpha band get one, which the warp derives from their mask
+                let alpha_band = src_alpha_band.unwrap_or_else(|| {
+                    rasterbands.push(RasterbandConfig {
+                        color_interpretation: ColorInterpretation::AlphaBand,
+                    });
+                    rasterbands.len()
                 });
-                None
+
+                (None, Some(alpha_band))
             }
         };
 
-        let tile_dir = earth_path.join(String::from(&attachment_label));
+        // merging and blending only replace the valid pixels of the other sources
+        if (update || blend_layers.len() > 1) && no_data_value.is_none() && alpha_band.is_none() {
+            return Err(PreprocessError::UnknownValidPixels);
+        }
+
+        // every time slice is stored like an attachment of its own
+        let tile_dir = match time_slice {
+            Some(slice) => earth_path.join(String::from(&attachment_label.time_slice(slice))),
+            None => earth_path.join(String::from(&attachment_label)),
+        };
+
+        let raw_dir = tile_dir.join(RAW_DIR);
 
         let temp_dir = match temp_dir {
             None => tile_dir.join("temp"),
             Some(path) => path,
         };
 
-        Ok((
-            src_dataset,
-            Self {
-                data_type,
-                no_data_value,
-                rasterbands,
-                tile_dir,
-                temp_dir,
-                fill_radius,
-                overwrite,
-                min_height: f32::MAX,
-                max_height: f32::MIN,
-                create_mask,
-
-                attachment_label,
-                attachment,
-                earth_path,
-                lod_count,
-            },
-        ))
+        let context = Self {
+            data_type,
+            no_data_value,
+            rasterbands,
+            alpha_band,
+            src_alpha_band: alpha_band.and(src_alpha_band),
+            sources,
+            blend_width,
+            blend_layers,
+            virtual_datasets,
+            dirty_tiles: None,
+            tile_dir,
+            raw_dir,
+            temp_dir,
+            fill_radius,
+            overwrite,
+            update,
+            pack,
+            min_height: f32::MAX,
+            max_height: f32::MIN,
+            create_mask,
+            warp_backend,
+            streaming,
+            memory_budget: memory_budget.map(|mebibytes| mebibytes * 1024 * 1024),
+
+            attachment_label,
+            attachment,
+            earth_path,
+            lod_count,
+        };
+
+        // unsupported sources should fail before anything is processed
+        if warp_backend == WarpBackend::Native {
+            NativeWarp::new(&src_dataset, &context)?;
+        }
+
+        Ok((src_dataset, context))
+    }
+}
+
+impl PreprocessContext {
+    /// Hashes all settings, that affect the content of the produced tiles.
+    pub(crate) fn settings_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+
+        (self.data_type as u32).hash(&mut hasher);
+        self.no_data_value.map(f64::to_bits).hash(&mut hasher);
+        self.alpha_band.hash(&mut hasher);
+        for band in &self.rasterbands {
+            band.color_interpretation.c_int().hash(&mut hasher);
+        }
+        self.fill_radius.to_bits().hash(&mut hasher);
+        self.blend_width.to_bits().hash(&mut hasher);
+        self.create_mask.hash(&mut hasher);
+        self.lod_count.hash(&mut hasher);
+        self.attachment.texture_size.hash(&mut hasher);
+        self.attachment.border_size.hash(&mut hasher);
+        self.attachment.mip_level_count.hash(&mut hasher);
+        self.attachment.mask.hash(&mut hasher);
+        self.attachment.format.hash(&mut hasher);
+        self.attachment.reproject_resampling.hash(&mut hasher);
+        self.attachment.downsample_resampling.hash(&mut hasher);
+        self.attachment.compression.hash(&mut hasher);
+        self.warp_backend.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    /// The lod of the tiles with the highest resolution, once the lod count is known.
+    pub(crate) fn max_lod(&self) -> PreprocessResult<u32> {
+        Ok(self.lod_count.ok_or(PreprocessError::MissingLodCount)? - 1)
+    }
+
+    /// The indices of all bands, that are not the alpha band.
+    pub(crate) fn color_bands(&self) -> Vec<usize> {
+        (1..=self.rasterbands.len())
+            .filter(|&index| Some(index) != self.alpha_band)
+            .collect()
+    }
+
+    /// Whether the alpha band is dropped once it has been turned into the mask,
+    /// because the attachment format has no channel left for it.
+    pub(crate) fn strips_alpha_band(&self) -> bool {
+        self.alpha_band.is_some()
+            && self.rasterbands.len() > self.attachment.format.channel_count() as usize
+    }
+
+    /// Whether overlapping sources are blended, instead of being drawn on top of each other.
+    pub(crate) fn blends_sources(&self) -> bool {
+        self.blend_layers.len() > 1
     }
+
+    pub(crate) fn is_dirty(&self, tile: TileCoordinate) -> bool {
+        self.dirty_tiles
+            .as_ref()
+            .is_none_or(|dirty_tiles| dirty_tiles.contains(&tile))
+    }
+}
+
+/// The virtual datasets of a context, which are deleted together with it.
+///
+/// They are created next to the system's temporary files, because the preprocessing clears
+/// the temp directory while the datasets are still in use.
+#[derive(Default)]
+pub(crate) struct VirtualDatasets {
+    paths: Vec<PathBuf>,
+}
+
+impl VirtualDatasets {
+    /// Reserves a path, that is unique across all contexts of the process.
+    fn path(&mut self, name: &str) -> PathBuf {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        let path = std::env::temp_dir().join(format!("waw_{}_{id}_{name}.vrt", process::id()));
+        self.paths.push(path.clone());
+
+        path
+    }
+}
+
+impl Drop for VirtualDatasets {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            // datasets, that were never written, are missing already
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Wraps the dataset, so that it uses the no data value and the scaling of the source,
+/// if they were specified.
+/// Scaled values are stored as floats and use the no data value of the attachment,
+/// so that it does not collide with a converted value.
+fn with_source_overrides(
+    index: usize,
+    source: &PreprocessSource,
+    mut dataset: Dataset,
+    no_data_value: Option<f64>,
+    virtual_datasets: &mut VirtualDatasets,
+) -> PreprocessResult<Dataset> {
+    if let Some(no_data) = source.no_data {
+        let options = BuildVRTOptions::new([
+            "-srcnodata".to_string(),
+            no_data.to_string(),
+            "-vrtnodata".to_string(),
+            no_data.to_string(),
+        ])?;
+
+        // the mosaic references its sources by path, so this can not be an in-memory dataset
+        let path = virtual_datasets.path(&format!("source{index}"));
+
+        // the dataset is written to disk, once it is closed
+        drop(build_vrt(Some(&path), &[dataset], Some(options))?);
+        dataset = Dataset::open(path)?;
+    }
+
+    if source.scale != 1.0 || source.offset != 0.0 {
+        let mut args = [
+            "-of",
+            "VRT",
+            "-ot",
+            "Float32",
+            "-scale",
+            "0",
+            "1",
+            &source.offset.to_string(),
+            &(source.offset + source.scale).to_string(),
+        ]
+        .map(String::from)
+        .to_vec();
+
+        if let Some(no_data_value) = no_data_value {
+            args.extend(["-a_nodata".to_string(), no_data_value.to_string()]);
+        }
+
+        let path = virtual_datasets.path(&format!("scaled{index}"));
+
+        drop(translate(&dataset, &path, &args)?);
+        dataset = Dataset::open(path)?;
+    }
+
+    Ok(dataset)
+}
+
+/// Creates one virtual dataset for all sources of each priority.
+fn create_blend_layers(
+    sources: &[PreprocessSource],
+    datasets: &[Dataset],
+    virtual_datasets: &mut VirtualDatasets,
+) -> PreprocessResult<Vec<PathBuf>> {
+    sources
+        .iter()
+        .zip(datasets)
+        .chunk_by(|(source, _)| source.priority)
+        .into_iter()
+        .map(|(priority, layer)| {
+            let path = virtual_datasets.path(&format!("layer{priority}"));
+            let layer_datasets = layer.map(|(_, dataset)| dataset).collect_vec();
+
+            build_vrt(Some(&path), &layer_datasets, None)?;
+
+            Ok(path)
+        })
+        .collect()
 }
 
 pub(crate) struct RasterbandConfig {
     color_interpretation: ColorInterpretation,
 }
 
+/// The number of rows, that are read at once when computing the height range.
+const HEIGHT_RANGE_ROWS: usize = 256;
+
+/// Extends the height range of the context by all valid pixels of the dataset.
+/// Only the topography has a height range.
+pub(crate) fn extend_height_range(
+    dataset: &Dataset,
+    context: &mut PreprocessContext,
+) -> PreprocessResult<()> {
+    if context.attachment_label != AttachmentLabel::Topography {
+        return Ok(());
+    }
+
+    let range = height_range(
+        dataset,
+        context.color_bands()[0],
+        context.alpha_band,
+        context.no_data_value,
+    )?;
+
+    if let Some((min_height, max_height)) = range {
+        context.min_height = context.min_height.min(min_height);
+        context.max_height = context.max_height.max(max_height);
+    }
+
+    Ok(())
+}
+
+/// The minimum and maximum of all valid pixels in the height band,
+/// or `None` if the band has no valid pixels.
+fn height_range(
+    dataset: &Dataset,
+    height_band: usize,
+    alpha_band: Option<usize>,
+    no_data_value: Option<f64>,
+) -> PreprocessResult<Option<(f32, f32)>> {
+    let (width, height) = dataset.raster_size();
+    let height_band = dataset.rasterband(height_band)?;
+    let alpha_band = alpha_band
+        .map(|index| dataset.rasterband(index))
+        .transpose()?;
+
+    let mut range: Option<(f32, f32)> = None;
+
+    for row in (0..height).step_by(HEIGHT_RANGE_ROWS) {
+        let window = (width, HEIGHT_RANGE_ROWS.min(height - row));
+
+        let heights = height_band.read_as::<f64>((0, row as isize), window, window, None)?;
+        let alpha = alpha_band
+            .as_ref()
+            .map(|band| band.read_as::<f64>((0, row as isize), window, window, None))
+            .transpose()?;
+
+        for (index, &value) in heights.data().iter().enumerate() {
+            let valid = match &alpha {
+                Some(alpha) => alpha.data()[index] != 0.0,
+                None => no_data_value != Some(value),
+            };
+
+            if valid && value.is_finite() {
+                let value = value as f32;
+                range = Some(range.map_or((value, value), |(min, max)| {
+                    (min.min(value), max.max(value))
+                }));
+            }
+        }
+    }
+
+    Ok(range)
+}
+
+/// Determines the valid pixels, using either the alpha band or the no data value.
+pub(crate) fn valid_pixels<T: Copy + PartialEq + NumCast>(
+    buffers: &[Buffer<T>],
+    context: &PreprocessContext,
+) -> PreprocessResult<Vec<bool>> {
+    let pixel_count = buffers[0].data().len();
+
+    let valid = if let Some(alpha_band) = context.alpha_band {
+        buffers[alpha_band - 1]
+            .data()
+            .iter()
+            .map(|value| value.to_f64() != Some(0.0))
+            .collect()
+    } else if let Some(no_data_value) = context.no_data_value {
+        let no_data_value = T::from(no_data_value).ok_or(PreprocessError::NoDataOutOfRange)?;
+
+        (0..pixel_count)
+            .map(|index| buffers.iter().any(|buffer| buffer.data()[index] != no_data_value))
+            .collect()
+    } else {
+        return Err(PreprocessError::UnknownValidPixels);
+    };
+
+    Ok(valid)
+}
+
+/// Opens the raw tile, which has not been filled yet, or `None` if it does not exist.
 pub(crate) fn load_tile_dataset_if_exists(
     tile_coordinate: TileCoordinate,
     context: &PreprocessContext,
 ) -> PreprocessResult<Option<Dataset>> {
-    let tile_path = tile_coordinate.path(&context.tile_dir);
+    let tile_path = tile_coordinate.path(&context.raw_dir);
 
     let dataset = if tile_path.is_file() {
         Some(Dataset::open(tile_path)?)
@@ -243,10 +890,12 @@ pub(crate) fn update_tile_dataset(
     tile_coordinate: TileCoordinate,
     context: &PreprocessContext,
 ) -> PreprocessResult<Dataset> {
-    let tile_path = tile_coordinate.path(&context.tile_dir);
+    update_dataset(&tile_coordinate.path(&context.raw_dir))
+}
 
+pub(crate) fn update_dataset(path: &Path) -> PreprocessResult<Dataset> {
     Ok(Dataset::open_ex(
-        tile_path,
+        path,
         DatasetOptions {
             open_flags: GdalOpenFlags::GDAL_OF_UPDATE,
             ..Default::default()
@@ -258,9 +907,11 @@ pub(crate) fn create_tile_dataset<T: Copy + GdalType>(
     tile_coordinate: TileCoordinate,
     context: &PreprocessContext,
 ) -> PreprocessResult<Dataset> {
-    let tile_path = tile_coordinate.path(&context.tile_dir);
+    let tile_path = tile_coordinate.path(&context.raw_dir);
 
-    fs::create_dir_all(tile_path.parent().unwrap()).unwrap(); // make sure the parent directories do exist
+    if let Some(parent) = tile_path.parent() {
+        fs::create_dir_all(parent)?; // make sure the parent directories do exist
+    }
 
     create_empty_dataset::<T>(
         &tile_path,
@@ -270,39 +921,196 @@ pub(crate) fn create_tile_dataset<T: Copy + GdalType>(
     )
 }
 
+/// The data type, in which the tiles of the format are stored.
+pub(crate) fn format_data_type(format: AttachmentFormat) -> GdalDataType {
+    match format {
+        AttachmentFormat::R8Unorm | AttachmentFormat::Rgb8U | AttachmentFormat::Rgba8U => {
+            GdalDataType::UInt8
+        }
+        AttachmentFormat::R16U | AttachmentFormat::Rg16U => GdalDataType::UInt16,
+        AttachmentFormat::R16I => GdalDataType::Int16,
+        AttachmentFormat::R32F => GdalDataType::Float32,
+    }
+}
+
 pub(crate) fn create_empty_dataset<T: Copy + GdalType>(
     dst_path: &Path,
     size: U64Vec2,
     geo_transform: Option<GeoTransform>,
     context: &PreprocessContext,
+) -> PreprocessResult<Dataset> {
+    let bands = context.rasterbands.iter().collect_vec();
+
+    create_dataset_with_bands::<T>(dst_path, size, geo_transform, &bands, context)
+}
+
+/// Replaces the tile at the path with a copy, that only contains its color bands.
+pub(crate) fn strip_alpha_band<T: Copy + GdalType>(
+    tile_path: &Path,
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
+    let temp_path = tile_path.with_extension("strip.tif");
+    let size = context.attachment.texture_size as usize;
+
+    let color_bands = context.color_bands();
+    let bands = color_bands
+        .iter()
+        .map(|&index| &context.rasterbands[index - 1])
+        .collect_vec();
+
+    {
+        let src_dataset = Dataset::open(tile_path)?;
+        let dst_dataset = create_dataset_with_bands::<T>(
+            &temp_path,
+            U64Vec2::splat(size as u64),
+            None,
+            &bands,
+            context,
+        )?;
+
+        for (dst_index, &src_index) in color_bands.iter().enumerate() {
+            let mut buffer =
+                src_dataset
+                    .rasterband(src_index)?
+                    .read_as::<T>((0, 0), (size, size), (size, size), None)?;
+
+            dst_dataset
+                .rasterband(dst_index + 1)?
+                .write::<T>((0, 0), (size, size), &mut buffer)?;
+        }
+    }
+
+    fs::rename(temp_path, tile_path)?;
+
+    Ok(())
+}
+
+pub(crate) const TILING_OPTIONS: [&str; 4] = [
+    "TILED=YES",
+    "BLOCKXSIZE=512",
+    "BLOCKYSIZE=512",
+    //  "SPARSE_OK=TRUE",
+    "INTERLEAVE=PIXEL", // Todo: benchmark pixel vs band
+];
+
+/// Replaces the tile at the path with a compressed copy.
+/// The tiles are only compressed once they are complete, since every update would
+/// append the rewritten blocks to the file and degrade lossy compressions further.
+pub(crate) fn compress_tile(tile_path: &Path, context: &PreprocessContext) -> PreprocessResult<()> {
+    let temp_path = tile_path.with_extension("compress.tif");
+
+    let predictor = match context.data_type {
+        GdalDataType::Float32 | GdalDataType::Float64 => 3,
+        _ => 2,
+    };
+
+    let compression_options = match context.attachment.compression {
+        TileCompression::None => vec![],
+        TileCompression::Deflate => {
+            vec![
+                "COMPRESS=DEFLATE".to_string(),
+                format!("PREDICTOR={predictor}"),
+            ]
+        }
+        TileCompression::Zstd => {
+            vec![
+                "COMPRESS=ZSTD".to_string(),
+                format!("PREDICTOR={predictor}"),
+            ]
+        }
+        TileCompression::Lerc { max_error } => {
+            vec![
+                "COMPRESS=LERC".to_string(),
+                format!("MAX_Z_ERROR={max_error}"),
+            ]
+        }
+        TileCompression::WebP { quality } if quality >= 100 => {
+            vec![
+                "COMPRESS=WEBP".to_string(),
+                "WEBP_LOSSLESS=TRUE".to_string(),
+            ]
+        }
+        TileCompression::WebP { quality } => {
+            vec!["COMPRESS=WEBP".to_string(), format!("WEBP_LEVEL={quality}")]
+        }
+        TileCompression::Jpeg { quality } => {
+            vec![
+                "COMPRESS=JPEG".to_string(),
+                format!("JPEG_QUALITY={quality}"),
+            ]
+        }
+    };
+
+    let options = TILING_OPTIONS
+        .iter()
+        .map(|option| option.to_string())
+        .chain(compression_options)
+        .collect::<RasterCreationOptions>();
+
+    {
+        let driver = DriverManager::get_driver_by_name("GTiff")?;
+        Dataset::open(tile_path)?.create_copy(&driver, &temp_path, &options)?;
+    }
+
+    fs::rename(temp_path, tile_path)?;
+
+    Ok(())
+}
+
+/// Creates a dataset, that only lives in memory, e.g. as the destination of a warp.
+pub(crate) fn create_memory_dataset<T: Copy + GdalType>(
+    size: U64Vec2,
+    geo_transform: Option<GeoTransform>,
+    context: &PreprocessContext,
+) -> PreprocessResult<Dataset> {
+    let driver = DriverManager::get_driver_by_name("MEM")?;
+    let bands = context.rasterbands.iter().collect_vec();
+
+    let mut dst =
+        driver.create_with_band_type::<T, _>("", size.x as _, size.y as _, bands.len())?;
+
+    configure_dataset(&mut dst, geo_transform, &bands, context)?;
+
+    Ok(dst)
+}
+
+fn create_dataset_with_bands<T: Copy + GdalType>(
+    dst_path: &Path,
+    size: U64Vec2,
+    geo_transform: Option<GeoTransform>,
+    bands: &[&RasterbandConfig],
+    context: &PreprocessContext,
 ) -> PreprocessResult<Dataset> {
     let driver = DriverManager::get_driver_by_name("GTiff")?;
 
     // Todo: consider copying the photometric info
 
-    let options = RasterCreationOptions::from_iter(
-        [
-            "TILED=YES",
-            "BLOCKXSIZE=512",
-            "BLOCKYSIZE=512",
-            //  "SPARSE_OK=TRUE",
-            "INTERLEAVE=PIXEL", // Todo: benchmark pixel vs band
-        ],
-    );
+    let options = RasterCreationOptions::from_iter(TILING_OPTIONS);
 
     let mut dst = driver.create_with_band_type_with_options::<T, _>(
         dst_path,
         size.x as _,
         size.y as _,
-        context.rasterbands.len(),
+        bands.len(),
         &options,
     )?;
 
+    configure_dataset(&mut dst, geo_transform, bands, context)?;
+
+    Ok(dst)
+}
+
+fn configure_dataset(
+    dst: &mut Dataset,
+    geo_transform: Option<GeoTransform>,
+    bands: &[&RasterbandConfig],
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
     if let Some(geo_transform) = geo_transform {
         dst.set_geo_transform(&geo_transform)?;
     }
 
-    for (i, band) in context.rasterbands.iter().enumerate() {
+    for (i, band) in bands.iter().enumerate() {
         let mut dst_band = dst.rasterband(i + 1)?;
         dst_band.set_no_data_value(context.no_data_value)?;
         dst_band.set_color_interpretation(
@@ -310,34 +1118,121 @@ pub(crate) fn create_empty_dataset<T: Copy + GdalType>(
         )?;
     }
 
-    Ok(dst)
+    Ok(())
 }
 
-pub fn delete_directory(directory: &Path) {
-    // This method has issues with deleting hidden files on MacOS
-    // let _ = fs::remove_dir_all(directory).unwrap();
+pub fn delete_directory(directory: &Path) -> PreprocessResult<()> {
+    // On MacOS, deleting a file may also delete its hidden `._` companion file, which makes
+    // `remove_dir_all` fail midway with `NotFound`, so we simply try again.
+    for _ in 0..DELETE_ATTEMPTS {
+        match fs::remove_dir_all(directory) {
+            Ok(()) => return Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                if !directory.exists() {
+                    return Ok(());
+                }
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
 
-    Command::new("rm")
-        .arg("-rf")
-        .arg(directory)
-        .output()
-        .unwrap();
+    Ok(fs::remove_dir_all(directory)?)
 }
 
-pub fn clear_directory(directory: &Path) {
-    delete_directory(directory);
-    fs::create_dir_all(directory).unwrap();
+pub fn clear_directory(directory: &Path) -> PreprocessResult<()> {
+    delete_directory(directory)?;
+    fs::create_dir_all(directory)?;
+
+    Ok(())
 }
 
-pub fn iter_directory(directory: &Path) -> impl Iterator<Item = PathBuf> {
-    fs::read_dir(directory).unwrap().filter_map(|entry| {
-        let path = entry.unwrap().path();
-        let name = path.file_name().unwrap().to_string_lossy();
+pub fn iter_directory(
+    directory: &Path,
+) -> PreprocessResult<impl Iterator<Item = PreprocessResult<PathBuf>>> {
+    Ok(fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.path()))
+        .filter_ok(|path: &PathBuf| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
 
-        if !name.starts_with("._") && !name.ends_with(".aux.xml") {
-            Some(path)
-        } else {
-            None
+            !name.starts_with("._") && !name.ends_with(".aux.xml")
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn source_options_follow_the_path() {
+        let source = "data/a,b.tif, priority=2,no_data=-9999,scale=0.5,offset=-100"
+            .parse::<PreprocessSource>()
+            .unwrap();
+
+        assert_eq!(source.path, PathBuf::from("data/a,b.tif"));
+        assert_eq!(source.priority, 2);
+        assert_eq!(source.no_data, Some(-9999.0));
+        assert_eq!((source.scale, source.offset), (0.5, -100.0));
+
+        let source = "data/x=1,y.tif".parse::<PreprocessSource>().unwrap();
+        assert_eq!(source, PreprocessSource::from("data/x=1,y.tif"));
+
+        assert!(
+            "data/a.tif,priority=high"
+                .parse::<PreprocessSource>()
+                .is_err()
+        );
+        assert!(",priority=1".parse::<PreprocessSource>().is_err());
+    }
+
+    #[test]
+    fn virtual_datasets_are_deleted_with_their_owner() {
+        let mut virtual_datasets = VirtualDatasets::default();
+        let first = virtual_datasets.path("mosaic");
+        let second = virtual_datasets.path("mosaic");
+        assert_ne!(first, second);
+
+        fs::write(&first, "").unwrap();
+        drop(virtual_datasets);
+
+        assert!(!first.exists());
+    }
+
+    /// A single row in memory, with the heights in the first and the alpha in the second band.
+    fn height_dataset(heights: &[f32], alpha: &[f32]) -> Dataset {
+        let driver = DriverManager::get_driver_by_name("MEM").unwrap();
+        let dataset = driver
+            .create_with_band_type::<f32, _>("", heights.len(), 1, 2)
+            .unwrap();
+
+        for (index, values) in [heights, alpha].into_iter().enumerate() {
+            let mut buffer = Buffer::new((values.len(), 1), values.to_vec());
+            dataset
+                .rasterband(index + 1)
+                .unwrap()
+                .write((0, 0), (values.len(), 1), &mut buffer)
+                .unwrap();
         }
-    })
+
+        dataset
+    }
+
+    #[test]
+    fn height_range_skips_invalid_pixels() {
+        let dataset = height_dataset(
+            &[-32768.0, -10_500.0, 0.0, 8_800.0, f32::NAN, 9_999.0],
+            &[1.0, 1.0, 1.0, 1.0, 1.0, 0.0],
+        );
+
+        // the no data value and not a number are never part of the range
+        let range = height_range(&dataset, 1, None, Some(-32768.0)).unwrap();
+        assert_eq!(range, Some((-10_500.0, 9_999.0)));
+
+        // the alpha band replaces the no data value
+        let range = height_range(&dataset, 1, Some(2), None).unwrap();
+        assert_eq!(range, Some((-32768.0, 8_800.0)));
+
+        let empty = height_dataset(&[-32768.0; 4], &[0.0; 4]);
+        assert_eq!(height_range(&empty, 1, None, Some(-32768.0)).unwrap(), None);
+        assert_eq!(height_range(&empty, 1, Some(2), None).unwrap(), None);
+    }
 }
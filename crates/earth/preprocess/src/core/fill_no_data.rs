@@ -1,11 +1,15 @@
 use crate::core::{
     CountingProgressCallback, PreprocessContext, PreprocessError, PreprocessResult,
-    ProgressCallback, fill_no_data, update_tile_dataset,
+    ProgressCallback, compress_tile, fill_no_data, strip_alpha_band, update_dataset,
 };
-use gdal::raster::{Buffer, GdalDataType, GdalType};
-use itertools::{Itertools, izip};
+use gdal::{
+    Dataset,
+    raster::{Buffer, GdalDataType, GdalType},
+};
+use itertools::Itertools;
 use rayon::prelude::*;
-use waw_earth_render::math::TileCoordinate;
+use std::{fs, path::Path};
+use waw_earth_render::{data::TileCompression, math::TileCoordinate};
 
 trait BitMask {
     fn apply(&self, mask: u8) -> Self;
@@ -35,99 +39,176 @@ impl BitMask for i16 {
     }
 }
 
-fn create_mask_and_fill_no_data_gen<T: GdalType + BitMask>(
-    tiles: &[TileCoordinate],
-    context: &PreprocessContext,
-    progress_callback: Option<&ProgressCallback>,
-) -> PreprocessResult<()> {
-    let progress_callback = CountingProgressCallback::new(tiles.len() as u64, progress_callback);
+type Masks = Vec<(usize, Buffer<u8>)>;
 
-    tiles.par_iter().try_for_each(|&tile| {
-        let src_dataset = update_tile_dataset(tile, context)?;
+/// Reads the masks of all color bands, before their invalid pixels are filled.
+fn read_masks(dataset: &Dataset, context: &PreprocessContext) -> PreprocessResult<Masks> {
+    Ok(match context.alpha_band {
+        // the alpha band is the mask of all color bands
+        Some(alpha_band) => {
+            let alpha_data = dataset.rasterband(alpha_band)?.read_band_as()?;
 
-        let masks: Vec<Buffer<u8>> = src_dataset
-            .rasterbands()
-            .map(|src_raster| {
-                let raster = src_raster?;
+            context
+                .color_bands()
+                .into_iter()
+                .map(|index| (index, alpha_data.clone()))
+                .collect()
+        }
+        None => (1..=dataset.raster_count())
+            .map(|index| {
+                let raster = dataset.rasterband(index)?;
                 let mask = raster.open_mask_band()?;
                 let mask_data = mask.read_band_as()?;
 
-                Ok::<Buffer<u8>, PreprocessError>(mask_data)
+                Ok::<(usize, Buffer<u8>), PreprocessError>((index, mask_data))
             })
-            .try_collect()?;
-
-        fill_no_data(&src_dataset, context.fill_radius as f64)?;
-
-        for (mask, band) in izip!(masks, src_dataset.rasterbands()) {
-            let mut band = band?;
-            let mut band_data: Buffer<f32> = band.read_band_as()?;
-
-            for (&mask, value) in mask.data().iter().zip(band_data.data_mut()) {
-                *value = value.apply(mask); // all valid pixels have LSB == 1, all invalid pixels have LSB == 0
-            }
-
-            band.write(
-                (0, 0),
-                (
-                    context.attachment.texture_size as usize,
-                    context.attachment.texture_size as usize,
-                ),
-                &mut band_data,
-            )?;
+            .try_collect()?,
+    })
+}
+
+type ApplyMasks = fn(&Dataset, Masks, &PreprocessContext) -> PreprocessResult<()>;
+
+fn apply_masks<T: Copy + GdalType + BitMask>(
+    dataset: &Dataset,
+    masks: Masks,
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
+    for (index, mask) in masks {
+        let mut band = dataset.rasterband(index)?;
+        let mut band_data: Buffer<T> = band.read_band_as()?;
+
+        for (&mask, value) in mask.data().iter().zip(band_data.data_mut()) {
+            *value = value.apply(mask); // all valid pixels have LSB == 1, all invalid pixels have LSB == 0
         }
 
-        progress_callback.increment();
+        band.write(
+            (0, 0),
+            (
+                context.attachment.texture_size as usize,
+                context.attachment.texture_size as usize,
+            ),
+            &mut band_data,
+        )?;
+    }
 
-        Ok::<(), PreprocessError>(())
-    })
+    Ok(())
 }
 
-fn only_fill_no_data_gen(
-    tiles: &[TileCoordinate],
+/// Finishes a copy of the raw tile and only replaces the tile once the copy is complete.
+/// The raw tile stays untouched, so an interrupted run can simply finish the tile again.
+fn finish_tile<T: Copy + GdalType>(
+    tile: TileCoordinate,
+    apply_masks: Option<ApplyMasks>,
     context: &PreprocessContext,
-    progress_callback: Option<&ProgressCallback>,
 ) -> PreprocessResult<()> {
-    let progress_callback = CountingProgressCallback::new(tiles.len() as u64, progress_callback);
+    let tile_path = tile.path(&context.tile_dir);
+    let temp_path = tile_path.with_extension("fill.tif");
 
-    tiles.par_iter().try_for_each(|&tile| {
-        let src_dataset = update_tile_dataset(tile, context)?;
+    if let Some(parent) = tile_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::copy(tile.path(&context.raw_dir), &temp_path)?;
 
-        fill_no_data(&src_dataset, context.fill_radius as f64)?;
+    // a failed tile must not leave its partial copy next to the finished tiles
+    if let Err(error) = finish_copy::<T>(&temp_path, apply_masks, context) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error);
+    }
 
-        progress_callback.increment();
+    fs::rename(temp_path, tile_path)?;
+
+    Ok(())
+}
+
+fn finish_copy<T: Copy + GdalType>(
+    temp_path: &Path,
+    apply_masks: Option<ApplyMasks>,
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
+    {
+        let dataset = update_dataset(temp_path)?;
+
+        let masks = apply_masks
+            .is_some()
+            .then(|| read_masks(&dataset, context))
+            .transpose()?;
+
+        if context.create_mask || context.fill_radius > 0.0 {
+            fill_no_data(&dataset, context.fill_radius as f64, context.alpha_band)?;
+        }
+
+        if let (Some(apply_masks), Some(masks)) = (apply_masks, masks) {
+            apply_masks(&dataset, masks, context)?;
+        }
+    }
+
+    // once the alpha band has been turned into the mask, it is no longer needed
+    if context.strips_alpha_band() {
+        strip_alpha_band::<T>(temp_path, context)?;
+    }
+
+    if context.attachment.compression != TileCompression::None {
+        compress_tile(temp_path, context)?;
+    }
+
+    Ok(())
+}
+
+fn finish_tiles_gen<T: Copy + GdalType>(
+    tiles: &[TileCoordinate],
+    apply_masks: Option<ApplyMasks>,
+    context: &PreprocessContext,
+    progress_callback: &CountingProgressCallback,
+) -> PreprocessResult<()> {
+    tiles.par_iter().try_for_each(|&tile| {
+        finish_tile::<T>(tile, apply_masks, context)?;
+
+        progress_callback.increment()?;
 
         Ok::<(), PreprocessError>(())
     })
 }
 
+/// Creates the mask and fills the no data values of the raw tiles, strips their alpha band
+/// and compresses them into the finished tiles of the attachment.
 pub fn create_mask_and_fill_no_data(
     tiles: &[TileCoordinate],
     context: &PreprocessContext,
     progress_callback: Option<&ProgressCallback>,
 ) -> PreprocessResult<()> {
-    macro_rules! create_mask_and_fill_no_data_gen {
+    let apply_masks: Option<ApplyMasks> = if context.create_mask {
+        Some(match context.data_type {
+            GdalDataType::Unknown => return Err(PreprocessError::UnknownRasterbandDataType),
+            GdalDataType::UInt8 => apply_masks::<u8>,
+            GdalDataType::UInt16 => apply_masks::<u16>,
+            GdalDataType::Int16 => apply_masks::<i16>,
+            GdalDataType::Float32 => apply_masks::<f32>,
+            data_type => return Err(PreprocessError::UnsupportedDataType(data_type)),
+        })
+    } else {
+        None
+    };
+
+    let progress_callback = CountingProgressCallback::new(tiles.len() as u64, progress_callback);
+
+    macro_rules! finish_tiles_gen {
         ($data_type:ty) => {
-            create_mask_and_fill_no_data_gen::<$data_type>(tiles, context, progress_callback)
+            finish_tiles_gen::<$data_type>(tiles, apply_masks, context, &progress_callback)
         };
     }
 
-    if context.create_mask {
-        match context.data_type {
-            GdalDataType::Unknown => Err(PreprocessError::UnknownRasterbandDataType),
-            GdalDataType::UInt8 => create_mask_and_fill_no_data_gen!(u8),
-            GdalDataType::UInt16 => create_mask_and_fill_no_data_gen!(u16),
-            GdalDataType::UInt32 => panic!("This is not supported."),
-            GdalDataType::UInt64 => panic!("This is not supported."),
-            GdalDataType::Int8 => panic!("This is not supported."),
-            GdalDataType::Int16 => create_mask_and_fill_no_data_gen!(i16),
-            GdalDataType::Int32 => panic!("This is not supported."),
-            GdalDataType::Int64 => panic!("This is not supported."),
-            GdalDataType::Float32 => create_mask_and_fill_no_data_gen!(f32),
-            GdalDataType::Float64 => panic!("This is not supported."),
-        }?
-    } else if context.fill_radius > 0.0 {
-        only_fill_no_data_gen(tiles, context, progress_callback)?
-    };
-
-    Ok(())
+    match context.data_type {
+        GdalDataType::Unknown => Err(PreprocessError::UnknownRasterbandDataType),
+        GdalDataType::UInt8 => finish_tiles_gen!(u8),
+        GdalDataType::UInt16 => finish_tiles_gen!(u16),
+        GdalDataType::UInt32 => finish_tiles_gen!(u32),
+        GdalDataType::UInt64 => finish_tiles_gen!(u64),
+        GdalDataType::Int8 => finish_tiles_gen!(i8),
+        GdalDataType::Int16 => finish_tiles_gen!(i16),
+        GdalDataType::Int32 => finish_tiles_gen!(i32),
+        GdalDataType::Int64 => finish_tiles_gen!(i64),
+        GdalDataType::Float32 => finish_tiles_gen!(f32),
+        GdalDataType::Float64 => finish_tiles_gen!(f64),
+    }
 }
@@ -0,0 +1,217 @@
+use crate::core::{LON_LAT_PROJ4, PreprocessResult, unit_position_from_lon_lat};
+use gdal::{
+    Dataset, GeoTransformEx,
+    spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef},
+};
+use glam::{DVec2, IVec2};
+use itertools::{Itertools, iproduct};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use waw_earth_render::math::{Coordinate, TileCoordinate};
+
+/// The number of samples taken along each edge of a raster, when computing its footprint.
+const EDGE_SAMPLES: usize = 64;
+/// The number of samples taken along each axis of a footprint, when computing its tiles.
+const TILE_SAMPLES: usize = 256;
+
+/// The geographic extent of a dataset in degrees longitude and latitude.
+///
+/// Datasets crossing the antimeridian are covered by two footprints, one on each side of it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Footprint {
+    pub lon_min: f64,
+    pub lat_min: f64,
+    pub lon_max: f64,
+    pub lat_max: f64,
+}
+
+impl Footprint {
+    pub fn from_dataset(dataset: &Dataset) -> PreprocessResult<Vec<Self>> {
+        let (width, height) = dataset.raster_size();
+        let geo_transform = dataset.geo_transform()?;
+
+        let mut src_spatial_ref = dataset.spatial_ref()?;
+        let mut dst_spatial_ref = SpatialRef::from_proj4(LON_LAT_PROJ4)?;
+        src_spatial_ref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        dst_spatial_ref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        let transform = CoordTransform::new(&src_spatial_ref, &dst_spatial_ref)?;
+
+        // The extent of a reprojected rectangle is not spanned by its corners,
+        // so we sample the whole outline of the raster instead, one edge after another.
+        let (mut x, mut y): (Vec<f64>, Vec<f64>) = iproduct!(0..4, 0..=EDGE_SAMPLES)
+            .map(|(edge, i)| {
+                let t = i as f64 / EDGE_SAMPLES as f64;
+                [(t, 0.0), (t, 1.0), (0.0, t), (1.0, t)][edge]
+            })
+            .map(|(u, v)| geo_transform.apply(u * width as f64, v * height as f64))
+            .unzip();
+
+        transform.transform_coords(&mut x, &mut y, &mut [])?;
+
+        let edges = x
+            .chunks(EDGE_SAMPLES + 1)
+            .zip(y.chunks(EDGE_SAMPLES + 1))
+            .map(|(x, y)| {
+                x.iter()
+                    .zip(y)
+                    .filter(|(x, y)| x.is_finite() && y.is_finite())
+                    .map(|(&lon, &lat)| (lon, lat))
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        // the longitude jumps by almost a full turn, where an edge crosses the antimeridian
+        let crosses_antimeridian = edges.iter().any(|edge| {
+            edge.iter()
+                .tuple_windows()
+                .any(|((a, _), (b, _))| (a - b).abs() > 180.0)
+        });
+
+        // the longitudes east of the antimeridian continue past 180 degrees
+        let footprint = edges
+            .iter()
+            .flatten()
+            .fold(Self::EMPTY, |footprint, &(lon, lat)| {
+                let lon = if crosses_antimeridian && lon < 0.0 {
+                    lon + 360.0
+                } else {
+                    lon
+                };
+
+                Self {
+                    lon_min: footprint.lon_min.min(lon),
+                    lat_min: footprint.lat_min.min(lat),
+                    lon_max: footprint.lon_max.max(lon),
+                    lat_max: footprint.lat_max.max(lat),
+                }
+            });
+
+        Ok(footprint.split_at_antimeridian())
+    }
+
+    /// Splits a footprint, whose longitudes continue past 180 degrees,
+    /// into one footprint on each side of the antimeridian.
+    fn split_at_antimeridian(self) -> Vec<Self> {
+        if self.is_empty() || self.lon_max <= 180.0 {
+            return vec![self];
+        }
+
+        vec![
+            Self {
+                lon_max: 180.0,
+                ..self
+            },
+            Self {
+                lon_min: -180.0,
+                lon_max: self.lon_max - 360.0,
+                ..self
+            },
+        ]
+    }
+
+    pub const EMPTY: Self = Self {
+        lon_min: f64::MAX,
+        lat_min: f64::MAX,
+        lon_max: f64::MIN,
+        lat_max: f64::MIN,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        self.lon_min > self.lon_max || self.lat_min > self.lat_max
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.lon_min <= other.lon_max
+            && other.lon_min <= self.lon_max
+            && self.lat_min <= other.lat_max
+            && other.lat_min <= self.lat_max
+    }
+
+    /// Computes all tiles of the lod, that overlap with the footprint.
+    /// The result is conservative and may include some tiles just outside of the footprint.
+    pub fn tiles(&self, lod: u32) -> HashSet<TileCoordinate> {
+        if self.is_empty() {
+            return HashSet::new();
+        }
+
+        let tile_count = 1 << lod;
+
+        let mut face_bounds: [Option<(DVec2, DVec2)>; 6] = [None; 6];
+
+        for (i, j) in iproduct!(0..=TILE_SAMPLES, 0..=TILE_SAMPLES) {
+            let lon = self.lon_min + (self.lon_max - self.lon_min) * i as f64 / TILE_SAMPLES as f64;
+            let lat = self.lat_min + (self.lat_max - self.lat_min) * j as f64 / TILE_SAMPLES as f64;
+
+            let unit_position = unit_position_from_lon_lat(lon.to_radians(), lat.to_radians());
+            let coordinate = Coordinate::from_unit_position(unit_position, true);
+
+            let bounds = &mut face_bounds[coordinate.face as usize];
+            *bounds = Some(match *bounds {
+                None => (coordinate.uv, coordinate.uv),
+                Some((start, end)) => (start.min(coordinate.uv), end.max(coordinate.uv)),
+            });
+        }
+
+        // pad by one sample spacing (a face spans roughly 90 degrees) and one tile
+        let sample_spacing = (self.lon_max - self.lon_min).max(self.lat_max - self.lat_min)
+            / TILE_SAMPLES as f64
+            / 90.0;
+        let padding = 2.0 * sample_spacing + 1.0 / tile_count as f64;
+
+        face_bounds
+            .iter()
+            .enumerate()
+            .filter_map(|(face, bounds)| bounds.map(|bounds| (face as u32, bounds)))
+            .flat_map(|(face, (start, end))| {
+                let xy_start = ((start - padding) * tile_count as f64)
+                    .floor()
+                    .as_ivec2()
+                    .max(IVec2::ZERO);
+                let xy_end = ((end + padding) * tile_count as f64)
+                    .floor()
+                    .as_ivec2()
+                    .min(IVec2::splat(tile_count - 1));
+
+                iproduct!(xy_start.x..=xy_end.x, xy_start.y..=xy_end.y)
+                    .map(move |(x, y)| TileCoordinate::new(face, lod, IVec2::new(x, y)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn footprint_is_split_at_antimeridian() {
+        let footprint = Footprint {
+            lon_min: 170.0,
+            lat_min: -10.0,
+            lon_max: 200.0,
+            lat_max: 10.0,
+        };
+
+        let parts = footprint.split_at_antimeridian();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!((parts[0].lon_min, parts[0].lon_max), (170.0, 180.0));
+        assert_eq!((parts[1].lon_min, parts[1].lon_max), (-180.0, -160.0));
+
+        // the tiles of both parts cover the region on either side, but not the opposite side
+        let tiles = parts
+            .iter()
+            .flat_map(|part| part.tiles(3))
+            .collect::<HashSet<_>>();
+        let tile_at = |lon: f64, lat: f64| {
+            let unit_position = unit_position_from_lon_lat(lon.to_radians(), lat.to_radians());
+            let coordinate = Coordinate::from_unit_position(unit_position, true);
+
+            TileCoordinate::new(coordinate.face, 3, (coordinate.uv * 8.0).as_ivec2())
+        };
+
+        assert!(tiles.contains(&tile_at(175.0, 0.0)));
+        assert!(tiles.contains(&tile_at(-165.0, 0.0)));
+        assert!(!tiles.contains(&tile_at(0.0, 0.0)));
+    }
+}
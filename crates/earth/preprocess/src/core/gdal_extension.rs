@@ -6,22 +6,26 @@ use gag::Gag;
 use gdal::{
     Dataset, GeoTransform,
     errors::{GdalError, Result as GdalResult},
+    raster::ResampleAlg,
 };
 use gdal_sys::{
-    CPLErr, CPLErrorReset, CPLGetLastErrorMsg, CPLGetLastErrorNo, GDALAccess::GA_Update,
-    GDALChunkAndWarpImage, GDALCreateWarpOptions, GDALDestroyWarpOperation, GDALDestroyWarpOptions,
-    GDALDummyProgress, GDALFillNodata, GDALOpenShared, GDALResampleAlg, GDALSuggestedWarpOutput,
+    CPLErr, CPLErrorReset, CPLGetLastErrorMsg, CPLGetLastErrorNo, CSLSetNameValue,
+    GDALAccess::GA_Update, GDALChunkAndWarpImage, GDALCreateWarpOptions, GDALDestroyWarpOperation,
+    GDALDestroyWarpOptions, GDALDummyProgress, GDALFillNodata, GDALOpenShared, GDALResampleAlg,
+    GDALSuggestedWarpOutput, GDALTranslate, GDALTranslateOptionsFree, GDALTranslateOptionsNew,
 };
-use glam::U64Vec2;
+use glam::{DVec2, U64Vec2};
 use itertools::Itertools;
 use std::{
     ffi::{CStr, CString, c_char, c_double, c_int, c_void},
+    iter,
     os::unix::ffi::OsStrExt,
     path::Path,
     ptr, slice,
     sync::atomic::{AtomicU64, Ordering},
 };
 use thread_local::ThreadLocal;
+use waw_earth_render::data::ResamplingMethod;
 
 type UnusedFunction = unsafe extern "C" fn(_: *mut c_void) -> *mut c_void;
 #[unsafe(no_mangle)]
@@ -64,18 +68,30 @@ pub struct GDALCustomTransformer {
     pub(crate) inner: Box<dyn Transformer>,
 }
 
+/// The working memory in bytes of a single warp operation.
+pub(crate) const WARP_MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Warps the source into the destination.
+/// The scale is the number of destination pixels per source pixel along both axes,
+/// which GDAL estimates for every chunk of the warp, if none is given.
 pub fn warp(
     src: &Dataset,
     dst: &Dataset,
     context: &PreprocessContext,
     transformer: &mut GDALCustomTransformer,
+    scale: Option<DVec2>,
     mut progress_callback: Option<&ProgressCallback>,
 ) -> PreprocessResult<()> {
     let (width, height) = dst.raster_size();
 
     // make sure, that these outlive the warp operation
-    let band_count = context.rasterbands.len() as c_int;
-    let mut bands = (1..=band_count).collect_vec();
+    // the alpha band is not warped like the others, but derived from the validity of the source
+    let mut bands = context
+        .color_bands()
+        .into_iter()
+        .map(|index| index as c_int)
+        .collect_vec();
+    let band_count = bands.len() as c_int;
     let mut src_no_data = src
         .rasterband(1)?
         .no_data_value()
@@ -89,15 +105,16 @@ pub fn warp(
     let options = unsafe { &mut *GDALCreateWarpOptions() };
     options.hSrcDS = src.c_dataset();
     options.hDstDS = dst.c_dataset();
-    // options.eResampleAlg = GDALResampleAlg::GRA_NearestNeighbour;
-    options.eResampleAlg = GDALResampleAlg::GRA_Bilinear;
-    options.dfWarpMemoryLimit = 1024f64.powi(2) * 64.0; // Todo: figure out, why this affects reprojection at the poles
+    options.eResampleAlg = warp_resample_alg(context.attachment.reproject_resampling);
+    options.dfWarpMemoryLimit = WARP_MEMORY_LIMIT as f64; // Todo: figure out, why this affects reprojection at the poles
 
     // for some reason this is not automatically recognized, so we have to set it manually
     options.eWorkingDataType = context.data_type as u32;
     options.nBandCount = band_count;
     options.panSrcBands = bands.as_mut_ptr();
     options.panDstBands = bands.as_mut_ptr();
+    options.nSrcAlphaBand = context.src_alpha_band.unwrap_or(0) as c_int;
+    options.nDstAlphaBand = context.alpha_band.unwrap_or(0) as c_int;
     options.padfSrcNoDataReal = if !src_no_data.is_empty() {
         src_no_data.as_mut_ptr()
     } else {
@@ -109,6 +126,15 @@ pub fn warp(
         ptr::null_mut()
     };
 
+    if let Some(scale) = scale {
+        for (key, value) in [(c"XSCALE", scale.x), (c"YSCALE", scale.y)] {
+            let value = CString::new(value.to_string()).unwrap();
+
+            options.papszWarpOptions =
+                unsafe { CSLSetNameValue(options.papszWarpOptions, key.as_ptr(), value.as_ptr()) };
+        }
+    }
+
     options.pfnTransformer = Some(transformer_c);
     options.pTransformerArg = ptr::addr_of_mut!(*transformer).cast();
 
@@ -136,12 +162,50 @@ pub fn warp(
     Ok(())
 }
 
-pub fn fill_no_data(src: &Dataset, fill_radius: f64) -> PreprocessResult<()> {
-    for raster_band in src.rasterbands() {
+pub(crate) fn warp_resample_alg(method: ResamplingMethod) -> GDALResampleAlg::Type {
+    match method {
+        ResamplingMethod::Nearest => GDALResampleAlg::GRA_NearestNeighbour,
+        ResamplingMethod::Bilinear => GDALResampleAlg::GRA_Bilinear,
+        ResamplingMethod::Cubic => GDALResampleAlg::GRA_Cubic,
+        ResamplingMethod::CubicSpline => GDALResampleAlg::GRA_CubicSpline,
+        ResamplingMethod::Lanczos => GDALResampleAlg::GRA_Lanczos,
+        ResamplingMethod::Average => GDALResampleAlg::GRA_Average,
+        ResamplingMethod::Mode => GDALResampleAlg::GRA_Mode,
+    }
+}
+
+pub(crate) fn raster_io_resample_alg(method: ResamplingMethod) -> ResampleAlg {
+    match method {
+        ResamplingMethod::Nearest => ResampleAlg::NearestNeighbour,
+        ResamplingMethod::Bilinear => ResampleAlg::Bilinear,
+        ResamplingMethod::Cubic => ResampleAlg::Cubic,
+        ResamplingMethod::CubicSpline => ResampleAlg::CubicSpline,
+        ResamplingMethod::Lanczos => ResampleAlg::Lanczos,
+        ResamplingMethod::Average => ResampleAlg::Average,
+        ResamplingMethod::Mode => ResampleAlg::Mode,
+    }
+}
+
+/// Interpolates all invalid pixels within the fill radius.
+/// If an alpha band is given, it decides which pixels are invalid, instead of the no data value.
+pub fn fill_no_data(
+    src: &Dataset,
+    fill_radius: f64,
+    alpha_band: Option<usize>,
+) -> PreprocessResult<()> {
+    let mask_band = alpha_band.map(|index| src.rasterband(index)).transpose()?;
+
+    for (index, raster_band) in (1..).zip(src.rasterbands()) {
+        if Some(index) == alpha_band {
+            continue;
+        }
+
         unsafe {
             let rv = GDALFillNodata(
                 raster_band?.c_rasterband(),
-                ptr::null_mut(),
+                mask_band
+                    .as_ref()
+                    .map_or(ptr::null_mut(), |mask_band| mask_band.c_rasterband()),
                 fill_radius,
                 0,
                 0,
@@ -159,6 +223,43 @@ pub fn fill_no_data(src: &Dataset, fill_radius: f64) -> PreprocessResult<()> {
     Ok(())
 }
 
+/// Translates the source into a new dataset at the path, using the arguments of `gdal_translate`.
+pub(crate) fn translate(
+    src: &Dataset,
+    dst_path: &Path,
+    args: &[String],
+) -> PreprocessResult<Dataset> {
+    let c_path = CString::new(dst_path.as_os_str().as_bytes()).map_err(GdalError::from)?;
+    let c_args = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(GdalError::from)?;
+
+    // the arguments are not modified, the C API is just not const-correct
+    let mut c_arg_ptrs = c_args
+        .iter()
+        .map(|arg| arg.as_ptr() as *mut c_char)
+        .chain(iter::once(ptr::null_mut()))
+        .collect_vec();
+
+    unsafe {
+        let options = GDALTranslateOptionsNew(c_arg_ptrs.as_mut_ptr(), ptr::null_mut());
+        if options.is_null() {
+            return Err(PreprocessError::Gdal(last_cpl_err(CPLErr::CE_Failure)));
+        }
+
+        let dataset = GDALTranslate(c_path.as_ptr(), src.c_dataset(), options, ptr::null_mut());
+        GDALTranslateOptionsFree(options);
+
+        if dataset.is_null() {
+            return Err(PreprocessError::Gdal(last_cpl_err(CPLErr::CE_Failure)));
+        }
+
+        Ok(Dataset::from_c_dataset(dataset))
+    }
+}
+
 pub type ProgressCallback<'a> = dyn Fn(f64) -> bool + Sync + 'a;
 
 #[unsafe(no_mangle)]
@@ -183,10 +284,17 @@ impl<'a> CountingProgressCallback<'a> {
         }
     }
 
-    pub(crate) fn increment(&self) {
+    /// Reports the next item as complete and fails, if the callback asks to abort.
+    pub(crate) fn increment(&self) -> PreprocessResult<()> {
         if let Some(progress_callback) = self.progress_callback {
-            progress_callback(self.counter.fetch_add(1, Ordering::Relaxed) as f64 / self.count);
+            let completion = self.counter.fetch_add(1, Ordering::Relaxed) as f64 / self.count;
+
+            if !progress_callback(completion) {
+                return Err(PreprocessError::Cancelled);
+            }
         }
+
+        Ok(())
     }
 }
 
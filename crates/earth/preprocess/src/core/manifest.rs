@@ -0,0 +1,268 @@
+use crate::core::{
+    Footprint, PreprocessContext, PreprocessError, PreprocessResult, PreprocessSource,
+};
+use gdal::Dataset;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+use waw_earth_render::math::TileCoordinate;
+
+const MANIFEST_FILE: &str = "manifest.ron";
+
+/// A FNV-1a hasher, whose output is stable between runs (unlike the std `DefaultHasher`).
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// The stages of the preprocessing pipeline, that can be skipped once they are complete.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PreprocessStage {
+    Reprojecting,
+    Splitting,
+    Blending,
+    Downsampling,
+    Filling,
+}
+
+/// Identifies a version of a source file, its blending settings and the region of the earth it covers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SourceRecord {
+    pub path: PathBuf,
+    /// Hash of the size and modification time of the file.
+    ///
+    /// The content is not hashed, because reading all sources would take about as long as
+    /// processing them. A file, that is replaced by one with the same size and modification
+    /// time, is therefore not detected as changed.
+    pub hash: u64,
+    pub priority: i32,
+    pub no_data: Option<f64>,
+    /// The footprints of the source, which are split at the antimeridian.
+    pub footprints: Vec<Footprint>,
+}
+
+impl SourceRecord {
+    pub fn new(source: &PreprocessSource) -> PreprocessResult<Self> {
+        let path = &source.path;
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let mut hasher = StableHasher::default();
+        metadata.len().hash(&mut hasher);
+        modified.as_nanos().hash(&mut hasher);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            hash: hasher.finish(),
+            priority: source.priority,
+            no_data: source.no_data,
+            footprints: Footprint::from_dataset(&Dataset::open(path)?)?,
+        })
+    }
+}
+
+/// Keeps track of the progress of preprocessing an attachment, so that an interrupted or
+/// repeated run can skip all work that is already complete.
+///
+/// The manifest is stored alongside the tiles of the attachment.
+/// It is invalidated completely, once the settings of the [`PreprocessContext`] change.
+/// Changing, adding or removing source files only invalidates the tiles within their footprints.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TileManifest {
+    pub settings_hash: u64,
+    pub sources: Vec<SourceRecord>,
+    pub completed_stages: Vec<PreprocessStage>,
+    /// All tiles that have been written to the raw directory of the attachment.
+    pub tiles: HashSet<TileCoordinate>,
+    /// The tiles of the highest lod that have to be rebuilt, because their sources changed.
+    pub dirty_tiles: HashSet<TileCoordinate>,
+    pub lod_count: Option<u32>,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl TileManifest {
+    fn path(context: &PreprocessContext) -> PathBuf {
+        context.tile_dir.join(MANIFEST_FILE)
+    }
+
+    /// Loads the manifest of the attachment, or starts a new one if it is missing or outdated.
+    pub fn load(context: &PreprocessContext) -> Self {
+        let settings_hash = context.settings_hash();
+
+        fs::read_to_string(Self::path(context))
+            .ok()
+            .and_then(|encoded| ron::from_str::<Self>(&encoded).ok())
+            .filter(|manifest| manifest.settings_hash == settings_hash)
+            // earths processed before the raw tiles were kept can not be rebuilt incrementally
+            .filter(|manifest| manifest.tiles.is_empty() || context.raw_dir.is_dir())
+            .unwrap_or_else(|| Self {
+                settings_hash,
+                min_height: f32::MAX,
+                max_height: f32::MIN,
+                ..Default::default()
+            })
+    }
+
+    pub fn save(&self, context: &PreprocessContext) -> PreprocessResult<()> {
+        let encoded = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|error| PreprocessError::Config(error.to_string()))?;
+        fs::create_dir_all(&context.tile_dir)?;
+        fs::write(Self::path(context), encoded)?;
+
+        Ok(())
+    }
+
+    pub fn is_complete(&self, stage: PreprocessStage) -> bool {
+        self.completed_stages.contains(&stage)
+    }
+
+    /// Marks the stage as complete and records all tiles it has written.
+    pub fn complete(
+        &mut self,
+        stage: PreprocessStage,
+        tiles: &[TileCoordinate],
+        context: &PreprocessContext,
+    ) {
+        self.tiles.extend(
+            tiles
+                .iter()
+                .filter(|tile| tile.path(&context.raw_dir).is_file()),
+        );
+
+        if stage == PreprocessStage::Reprojecting {
+            self.lod_count = context.lod_count;
+            self.min_height = context.min_height;
+            self.max_height = context.max_height;
+        }
+
+        if stage == PreprocessStage::Filling {
+            self.dirty_tiles.clear();
+        }
+
+        if !self.is_complete(stage) {
+            self.completed_stages.push(stage);
+        }
+    }
+
+    /// Compares the sources of the context with the ones of the previous run and invalidates
+    /// the tiles within the footprints of all sources that have changed.
+    ///
+    /// Additionally, this restores the results of the previous reprojection into the context.
+    pub fn update_sources(&mut self, context: &mut PreprocessContext) -> PreprocessResult<()> {
+        let sources = context
+            .sources
+            .iter()
+            .map(SourceRecord::new)
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        let changed_footprints = sources
+            .iter()
+            .filter(|source| !self.sources.contains(source))
+            .chain(self.sources.iter().filter(|source| !sources.contains(source)))
+            .flat_map(|source| source.footprints.iter().copied())
+            .collect::<Vec<_>>();
+
+        if !changed_footprints.is_empty() {
+            match self.lod_count {
+                Some(lod_count) if self.is_complete(PreprocessStage::Splitting) => {
+                    for footprint in changed_footprints {
+                        self.dirty_tiles.extend(footprint.tiles(lod_count - 1));
+                    }
+
+                    self.invalidate_dirty_tiles(context)?;
+                }
+                _ => {
+                    // nothing usable has been produced yet, so we start from scratch
+                    *self = Self {
+                        settings_hash: self.settings_hash,
+                        min_height: f32::MAX,
+                        max_height: f32::MIN,
+                        ..Default::default()
+                    };
+                }
+            }
+
+            self.completed_stages.clear();
+        }
+
+        self.sources = sources;
+
+        if context.lod_count.is_none() {
+            context.lod_count = self.lod_count;
+        }
+        context.min_height = self.min_height;
+        context.max_height = self.max_height;
+        context.dirty_tiles = (!self.dirty_tiles.is_empty()).then(|| self.dirty_tiles.clone());
+
+        Ok(())
+    }
+
+    /// The tiles that have to be processed by the remaining stages.
+    /// These are either all tiles, or only the dirty ones and their ancestors.
+    pub fn pending_tiles(&self) -> Vec<TileCoordinate> {
+        if self.dirty_tiles.is_empty() {
+            self.tiles.iter().copied().collect()
+        } else {
+            let affected_tiles = self.affected_tiles();
+
+            self.tiles
+                .iter()
+                .filter(|tile| affected_tiles.contains(tile))
+                .copied()
+                .collect()
+        }
+    }
+
+    /// The dirty tiles and all of their ancestors.
+    fn affected_tiles(&self) -> HashSet<TileCoordinate> {
+        let mut affected_tiles = HashSet::new();
+
+        for &tile in &self.dirty_tiles {
+            let mut tile = Some(tile);
+
+            while let Some(current) = tile
+                && affected_tiles.insert(current)
+            {
+                tile = current.parent();
+            }
+        }
+
+        affected_tiles
+    }
+
+    fn invalidate_dirty_tiles(&mut self, context: &PreprocessContext) -> PreprocessResult<()> {
+        for tile in self.affected_tiles() {
+            if self.tiles.remove(&tile) {
+                for tile_path in [tile.path(&context.raw_dir), tile.path(&context.tile_dir)] {
+                    if tile_path.is_file() {
+                        fs::remove_file(tile_path)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
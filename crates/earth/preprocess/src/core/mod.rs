@@ -1,11 +1,19 @@
 mod dataset;
 mod fill_no_data;
+mod footprint;
 mod gdal_extension;
+mod manifest;
+mod native_warp;
+mod rasterize;
 mod result;
 mod transformers;
 
 pub use dataset::*;
 pub use fill_no_data::*;
+pub use footprint::*;
 pub use gdal_extension::*;
+pub use manifest::*;
+pub use native_warp::*;
+pub use rasterize::*;
 pub use result::*;
 pub use transformers::*;
@@ -0,0 +1,786 @@
+use crate::core::{
+    PreprocessContext, PreprocessError, PreprocessResult, ProgressCallback, SuggestedWarpOutput,
+    face_coordinate_from_lon_lat, lon_lat_from_face_uv, raster_io_resample_alg,
+};
+use gdal::{
+    Dataset, GeoTransform, GeoTransformEx,
+    raster::{Buffer, GdalDataType, GdalType},
+};
+use glam::{DVec2, IVec2};
+use itertools::{Itertools, iproduct};
+use num::NumCast;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    f64::consts::{PI, TAU},
+    str::FromStr,
+};
+use waw_earth_render::data::ResamplingMethod;
+
+/// The number of source samples along each axis, used to find the footprint of the source on a face.
+const FOOTPRINT_SAMPLES: usize = 256;
+/// The size of the blocks, that face images are warped in.
+const BLOCK_SIZE: usize = 512;
+/// The number of source pixels, above which the window of a block is read at a lower resolution.
+const MAX_WINDOW_PIXELS: usize = 1 << 22;
+/// The number of source pixels per destination pixel, that a window may always contain.
+const WINDOW_PIXELS_PER_PIXEL: usize = 16;
+/// Pixels with a lower density are treated as invalid, like GDAL does.
+const MIN_DENSITY: f64 = 0.00001;
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+
+/// The implementation, that reprojects the sources onto the faces of the cube.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WarpBackend {
+    /// The GDAL warper with a custom transformer, which supports every spatial reference.
+    #[default]
+    Gdal,
+    /// A parallel warper without any GDAL callbacks,
+    /// which supports geographic (EPSG:4326) and equirectangular sources.
+    Native,
+}
+
+impl FromStr for WarpBackend {
+    type Err = PreprocessError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "gdal" => Ok(Self::Gdal),
+            "native" => Ok(Self::Native),
+            other => Err(PreprocessError::UnknownWarpBackend(other.to_string())),
+        }
+    }
+}
+
+/// The projections, that the native warp inverts by itself.
+#[derive(Clone, Copy, Debug)]
+enum SourceProjection {
+    /// Longitude and latitude in degrees.
+    LonLat,
+    /// Equidistant cylindrical coordinates in metres, like PROJ's `eqc`.
+    Equirectangular {
+        radius: f64,
+        /// in radians
+        lon_0: f64,
+        /// in radians
+        lat_0: f64,
+        cos_lat_ts: f64,
+        x_0: f64,
+        y_0: f64,
+    },
+}
+
+impl SourceProjection {
+    /// Parses the projection from its PROJ.4 definition.
+    /// Other datums than WGS84 are rejected, since they would have to be shifted first.
+    fn from_proj4(proj4: &str) -> Option<Self> {
+        let parameters: HashMap<&str, &str> = proj4
+            .split_whitespace()
+            .filter_map(|parameter| parameter.strip_prefix('+'))
+            .map(|parameter| parameter.split_once('=').unwrap_or((parameter, "")))
+            .collect();
+
+        let number = |name: &str, default: f64| {
+            parameters
+                .get(name)
+                .map_or(Some(default), |value| value.parse::<f64>().ok())
+        };
+
+        let is_wgs84 = matches!(parameters.get("datum"), None | Some(&"WGS84"))
+            && matches!(
+                parameters.get("ellps"),
+                None | Some(&"WGS84") | Some(&"GRS80")
+            )
+            && matches!(parameters.get("pm"), None | Some(&"greenwich"))
+            && !parameters.contains_key("towgs84")
+            && !parameters.contains_key("nadgrids");
+
+        if !is_wgs84 {
+            return None;
+        }
+
+        match *parameters.get("proj")? {
+            "longlat" | "lonlat" | "latlong" | "latlon" => Some(Self::LonLat),
+            "eqc" => {
+                if parameters.get("units").is_some_and(|units| *units != "m")
+                    || parameters.contains_key("to_meter")
+                {
+                    return None;
+                }
+
+                let radius = match parameters.get("R") {
+                    Some(_) => number("R", 0.0)?,
+                    None => number("a", WGS84_SEMI_MAJOR_AXIS)?,
+                };
+
+                Some(Self::Equirectangular {
+                    radius,
+                    lon_0: number("lon_0", 0.0)?.to_radians(),
+                    lat_0: number("lat_0", 0.0)?.to_radians(),
+                    cos_lat_ts: number("lat_ts", 0.0)?.to_radians().cos(),
+                    x_0: number("x_0", 0.0)?,
+                    y_0: number("y_0", 0.0)?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Projects the longitude and latitude (in degrees).
+    fn project(self, lon: f64, lat: f64) -> DVec2 {
+        match self {
+            Self::LonLat => DVec2::new(lon, lat),
+            Self::Equirectangular {
+                radius,
+                lon_0,
+                lat_0,
+                cos_lat_ts,
+                x_0,
+                y_0,
+            } => {
+                let lon = (lon.to_radians() - lon_0 + PI).rem_euclid(TAU) - PI;
+
+                DVec2::new(
+                    x_0 + radius * cos_lat_ts * lon,
+                    y_0 + radius * (lat.to_radians() - lat_0),
+                )
+            }
+        }
+    }
+
+    /// The longitude and latitude (in degrees) of the position, if it lies on the earth.
+    fn unproject(self, position: DVec2) -> Option<(f64, f64)> {
+        let (lon, lat) = match self {
+            Self::LonLat => (position.x, position.y),
+            Self::Equirectangular {
+                radius,
+                lon_0,
+                lat_0,
+                cos_lat_ts,
+                x_0,
+                y_0,
+            } => (
+                ((position.x - x_0) / (radius * cos_lat_ts) + lon_0).to_degrees(),
+                ((position.y - y_0) / radius + lat_0).to_degrees(),
+            ),
+        };
+
+        (-90.0..=90.0).contains(&lat).then_some((lon, lat))
+    }
+}
+
+/// Reprojects geographic and equirectangular sources onto the faces of the cube,
+/// by mapping every destination pixel to the source and resampling it directly.
+///
+/// In contrast to [`warp`](crate::core::warp), no custom transformer is passed to GDAL,
+/// so that the pixels can be computed in parallel.
+/// The resampling kernels are not widened when downsampling, so sources, that are much finer
+/// than the tiles, should use average or mode resampling.
+pub struct NativeWarp {
+    projection: SourceProjection,
+    geo_transform: GeoTransform,
+    inverse_geo_transform: GeoTransform,
+    raster_size: (usize, usize),
+    /// The horizontal extent of the source in its projected coordinates.
+    x_range: (f64, f64),
+    color_bands: Vec<usize>,
+    alpha_band: Option<usize>,
+    src_alpha_band: Option<usize>,
+    band_count: usize,
+    src_no_data: Option<f64>,
+    dst_no_data: Option<f64>,
+    data_type: GdalDataType,
+    method: ResamplingMethod,
+}
+
+impl NativeWarp {
+    pub fn new(src: &Dataset, context: &PreprocessContext) -> PreprocessResult<Self> {
+        let method = context.attachment.reproject_resampling;
+
+        if matches!(
+            method,
+            ResamplingMethod::CubicSpline | ResamplingMethod::Lanczos
+        ) {
+            return Err(PreprocessError::UnsupportedNativeResampling(method));
+        }
+
+        let proj4 = src.spatial_ref()?.to_proj4()?;
+        let geo_transform = src.geo_transform()?;
+
+        // rotated sources would need a full affine inverse for their footprint
+        let projection = SourceProjection::from_proj4(&proj4)
+            .filter(|_| geo_transform[2] == 0.0 && geo_transform[4] == 0.0)
+            .ok_or(PreprocessError::UnsupportedNativeProjection(proj4))?;
+
+        let raster_size = src.raster_size();
+        let x_start = geo_transform[0];
+        let x_end = geo_transform[0] + geo_transform[1] * raster_size.0 as f64;
+
+        Ok(Self {
+            projection,
+            geo_transform,
+            inverse_geo_transform: geo_transform.invert()?,
+            raster_size,
+            x_range: (x_start.min(x_end), x_start.max(x_end)),
+            color_bands: context.color_bands(),
+            alpha_band: context.alpha_band,
+            src_alpha_band: context.src_alpha_band,
+            band_count: context.rasterbands.len(),
+            src_no_data: src.rasterband(1)?.no_data_value(),
+            dst_no_data: context.no_data_value,
+            data_type: context.data_type,
+            method,
+        })
+    }
+
+    /// An upper bound of the memory in bytes, that warping a region with this many pixels
+    /// needs in addition to the returned bands.
+    pub(crate) fn working_memory(&self, region_pixels: usize) -> u64 {
+        let color_count = self.color_bands.len();
+
+        // the source position of every pixel and its corners, its footprint, values and density
+        let region = region_pixels
+            * (2 * size_of::<Option<DVec2>>()
+                + size_of::<Option<(DVec2, DVec2)>>()
+                + (color_count + 1) * size_of::<f64>());
+
+        // the bands read from the source, followed by their values and densities
+        let window = max_window_pixels(region_pixels) * 2 * (color_count + 1) * size_of::<f64>();
+
+        (region + window) as u64
+    }
+
+    /// Estimates the region of the face covered by the source and a matching resolution,
+    /// in the same layout as GDAL's suggestion, so that both backends are sized alike.
+    pub fn suggested_output(&self, face: u32) -> Option<SuggestedWarpOutput> {
+        let (width, height) = self.raster_size;
+        let mut uv_min = DVec2::MAX;
+        let mut uv_max = DVec2::MIN;
+
+        for (x, y) in iproduct!(0..=FOOTPRINT_SAMPLES, 0..=FOOTPRINT_SAMPLES) {
+            let pixel = DVec2::new(x as f64 * width as f64, y as f64 * height as f64)
+                / FOOTPRINT_SAMPLES as f64;
+            let position = DVec2::from(self.geo_transform.apply(pixel.x, pixel.y));
+
+            let Some((lon, lat)) = self.projection.unproject(position) else {
+                continue;
+            };
+
+            let coordinate = face_coordinate_from_lon_lat(lon, lat);
+
+            if coordinate.face == face {
+                uv_min = uv_min.min(coordinate.uv);
+                uv_max = uv_max.max(coordinate.uv);
+            }
+        }
+
+        if uv_min.x > uv_max.x {
+            return None;
+        }
+
+        // the footprint may reach a little beyond the samples
+        let padding = (uv_max - uv_min) * 4.0 / FOOTPRINT_SAMPLES as f64;
+        let uv_min = (uv_min - padding).max(DVec2::ZERO);
+        let uv_max = (uv_max + padding).min(DVec2::ONE);
+        let uv_size = uv_max - uv_min;
+
+        // the same number of pixels along the diagonal of the source and the output
+        let diagonal = ((width * width + height * height) as f64).sqrt();
+        let resolution = (uv_size.length() / diagonal).max(f64::EPSILON);
+        let size = (uv_size / resolution).ceil().max(DVec2::ONE).as_u64vec2();
+
+        Some(SuggestedWarpOutput {
+            size,
+            geo_transform: [
+                uv_min.x,
+                resolution,
+                0.0,
+                uv_min.y + size.y as f64 * resolution,
+                0.0,
+                -resolution,
+            ],
+        })
+    }
+
+    /// Warps the source onto a region of the face, whose pixels are mapped to uv coordinates
+    /// by the geo transform. Returns the data of every band of the destination.
+    pub(crate) fn warp_region<T: Copy + GdalType + NumCast>(
+        &self,
+        src: &Dataset,
+        face: u32,
+        geo_transform: &GeoTransform,
+        size: (usize, usize),
+    ) -> PreprocessResult<Vec<Vec<T>>> {
+        let (width, height) = size;
+
+        let source_pixel = |x: f64, y: f64| {
+            let uv = DVec2::from(geo_transform.apply(x, y));
+            self.source_pixel(face, uv)
+        };
+
+        let pixels = (0..width * height)
+            .into_par_iter()
+            .map(|index| source_pixel((index % width) as f64 + 0.5, (index / width) as f64 + 0.5))
+            .collect::<Vec<_>>();
+
+        // the footprint of a pixel spans the source pixels between its corners
+        let footprints = if matches!(
+            self.method,
+            ResamplingMethod::Average | ResamplingMethod::Mode
+        ) {
+            let corners = (0..(width + 1) * (height + 1))
+                .into_par_iter()
+                .map(|index| {
+                    source_pixel((index % (width + 1)) as f64, (index / (width + 1)) as f64)
+                })
+                .collect::<Vec<_>>();
+
+            (0..width * height)
+                .into_par_iter()
+                .map(|index| {
+                    let (x, y) = (index % width, index / width);
+                    let corners = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .map(|(dx, dy)| corners[(y + dy) * (width + 1) + x + dx]);
+
+                    let corners = corners.into_iter().collect::<Option<Vec<_>>>()?;
+                    let min = corners.iter().copied().reduce(DVec2::min)?;
+                    let max = corners.iter().copied().reduce(DVec2::max)?;
+
+                    // pixels across the antimeridian of the source are wrapped around
+                    (max.x - min.x < 0.5 * self.raster_size.0 as f64).then_some((min, max))
+                })
+                .collect::<Vec<_>>()
+        } else {
+            vec![None; width * height]
+        };
+
+        let window = match SourceWindow::bounds(&pixels, &footprints, self.raster_size) {
+            Some((start, end)) => Some(self.read_window(src, start, end, width * height)?),
+            None => None,
+        };
+
+        let color_count = self.color_bands.len();
+        let mut values = vec![0.0; width * height * color_count];
+        let mut densities = vec![0.0; width * height];
+
+        if let Some(window) = &window {
+            values
+                .par_chunks_mut(color_count)
+                .zip(densities.par_iter_mut())
+                .enumerate()
+                .for_each(|(index, (values, density))| {
+                    if let Some(pixel) = pixels[index] {
+                        *density = window.sample(pixel, footprints[index], self.method, values);
+                    }
+                });
+        }
+
+        let (min_value, max_value) = value_range(self.data_type);
+        let round = self.data_type.is_integer();
+        let fill =
+            T::from(self.dst_no_data.unwrap_or(0.0)).ok_or(PreprocessError::NoDataOutOfRange)?;
+
+        let convert = |value: f64| {
+            let value = if round { value.round() } else { value };
+            T::from(value.clamp(min_value, max_value)).unwrap_or(fill)
+        };
+
+        let bands = (1..=self.band_count)
+            .map(|band| {
+                if Some(band) == self.alpha_band {
+                    Ok(densities
+                        .iter()
+                        .map(|&density| convert(density.clamp(0.0, 1.0) * 255.0))
+                        .collect_vec())
+                } else {
+                    let color = self
+                        .color_bands
+                        .iter()
+                        .position(|&index| index == band)
+                        .ok_or(PreprocessError::UnknownBand(band))?;
+
+                    Ok(densities
+                        .iter()
+                        .enumerate()
+                        .map(|(index, &density)| {
+                            if density < MIN_DENSITY {
+                                fill
+                            } else {
+                                convert(values[index * color_count + color])
+                            }
+                        })
+                        .collect_vec())
+                }
+            })
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        Ok(bands)
+    }
+
+    /// The position in source pixels of the uv coordinate on the face.
+    fn source_pixel(&self, face: u32, uv: DVec2) -> Option<DVec2> {
+        let (lon, lat) = lon_lat_from_face_uv(face, uv);
+
+        if lat.is_nan() {
+            return None;
+        }
+
+        let mut position = self.projection.project(lon, lat);
+
+        // geographic sources may span from 0 to 360 degrees instead of -180 to 180
+        if matches!(self.projection, SourceProjection::LonLat) {
+            if position.x < self.x_range.0 {
+                position.x += 360.0;
+            } else if position.x > self.x_range.1 {
+                position.x -= 360.0;
+            }
+        }
+
+        Some(DVec2::from(
+            self.inverse_geo_transform.apply(position.x, position.y),
+        ))
+    }
+
+    /// Reads the color bands and the validity of the source pixels within the bounds.
+    /// Windows, that are far larger than the region, are read at a lower resolution.
+    fn read_window(
+        &self,
+        src: &Dataset,
+        start: IVec2,
+        end: IVec2,
+        region_pixels: usize,
+    ) -> PreprocessResult<SourceWindow> {
+        let window_size = (end - start).as_uvec2();
+        let window_pixels = window_size.x as usize * window_size.y as usize;
+        let max_pixels = max_window_pixels(region_pixels);
+
+        let scale = if window_pixels > max_pixels {
+            (window_pixels as f64 / max_pixels as f64).sqrt().ceil()
+        } else {
+            1.0
+        };
+
+        let size = (window_size.as_dvec2() / scale).ceil().as_uvec2();
+        let (width, height) = (size.x as usize, size.y as usize);
+        let resample_alg = (scale > 1.0).then(|| raster_io_resample_alg(self.method));
+
+        let read = |band: usize| {
+            Ok::<Buffer<f64>, PreprocessError>(src.rasterband(band)?.read_as::<f64>(
+                (start.x as isize, start.y as isize),
+                (window_size.x as usize, window_size.y as usize),
+                (width, height),
+                resample_alg,
+            )?)
+        };
+
+        let color_buffers = self
+            .color_bands
+            .iter()
+            .map(|&band| read(band))
+            .collect::<PreprocessResult<Vec<_>>>()?;
+        let alpha_buffer = self.src_alpha_band.map(read).transpose()?;
+
+        let color_count = self.color_bands.len();
+        let mut values = vec![0.0; width * height * color_count];
+        let mut densities = vec![1.0; width * height];
+
+        for (color, buffer) in color_buffers.iter().enumerate() {
+            for (index, &value) in buffer.data().iter().enumerate() {
+                values[index * color_count + color] = value;
+            }
+        }
+
+        for (index, density) in densities.iter_mut().enumerate() {
+            let pixel = &values[index * color_count..(index + 1) * color_count];
+
+            // like GDAL, a pixel is only invalid, if all of its bands are
+            let is_no_data = pixel
+                .iter()
+                .all(|&value| value.is_nan() || Some(value) == self.src_no_data);
+
+            *density = match &alpha_buffer {
+                _ if is_no_data => 0.0,
+                Some(alpha_buffer) => (alpha_buffer.data()[index] / 255.0).clamp(0.0, 1.0),
+                None => 1.0,
+            };
+        }
+
+        Ok(SourceWindow {
+            start: start.as_dvec2(),
+            scale,
+            width,
+            height,
+            color_count,
+            values,
+            densities,
+        })
+    }
+}
+
+/// A window of the source pixels, read at a possibly lower resolution.
+struct SourceWindow {
+    /// The first source pixel of the window.
+    start: DVec2,
+    /// The number of source pixels per window pixel along each axis.
+    scale: f64,
+    width: usize,
+    height: usize,
+    color_count: usize,
+    /// The values of the color bands, interleaved per pixel.
+    values: Vec<f64>,
+    /// The validity of each pixel between zero and one.
+    densities: Vec<f64>,
+}
+
+impl SourceWindow {
+    /// The bounds of the source pixels, that are needed to resample the pixels and footprints,
+    /// including the radius of the largest kernel.
+    fn bounds(
+        pixels: &[Option<DVec2>],
+        footprints: &[Option<(DVec2, DVec2)>],
+        raster_size: (usize, usize),
+    ) -> Option<(IVec2, IVec2)> {
+        let (min, max) = pixels
+            .iter()
+            .flatten()
+            .map(|&pixel| (pixel, pixel))
+            .chain(footprints.iter().flatten().copied())
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))?;
+
+        let raster_size = IVec2::new(raster_size.0 as i32, raster_size.1 as i32);
+        let start = (min.floor().as_ivec2() - 2).clamp(IVec2::ZERO, raster_size);
+        let end = (max.ceil().as_ivec2() + 2).clamp(IVec2::ZERO, raster_size);
+
+        (start.cmplt(end).all()).then_some((start, end))
+    }
+
+    /// Resamples the source at the position in source pixels and returns the density of the result.
+    fn sample(
+        &self,
+        pixel: DVec2,
+        footprint: Option<(DVec2, DVec2)>,
+        method: ResamplingMethod,
+        values: &mut [f64],
+    ) -> f64 {
+        let position = (pixel - self.start) / self.scale;
+        let footprint = footprint
+            .map(|(min, max)| {
+                (
+                    (min - self.start) / self.scale,
+                    (max - self.start) / self.scale,
+                )
+            })
+            .filter(|(min, max)| (*max - *min).cmpgt(DVec2::ZERO).all());
+
+        // the kernels are centered on the pixels
+        let center = position - 0.5;
+        let base = center.floor();
+        let fraction = center - base;
+        let base = base.as_ivec2();
+
+        match (method, footprint) {
+            (ResamplingMethod::Bilinear, _) => {
+                let samples = iproduct!(0..2, 0..2).map(|(dy, dx)| {
+                    let weight_x = if dx == 0 {
+                        1.0 - fraction.x
+                    } else {
+                        fraction.x
+                    };
+                    let weight_y = if dy == 0 {
+                        1.0 - fraction.y
+                    } else {
+                        fraction.y
+                    };
+
+                    (base + IVec2::new(dx, dy), weight_x * weight_y)
+                });
+
+                self.accumulate(samples, values)
+            }
+            (ResamplingMethod::Cubic, _) => {
+                let samples = iproduct!(-1..3, -1..3).map(|(dy, dx)| {
+                    let weight =
+                        cubic_weight(dx as f64 - fraction.x) * cubic_weight(dy as f64 - fraction.y);
+
+                    (base + IVec2::new(dx, dy), weight)
+                });
+
+                self.accumulate(samples, values)
+            }
+            (ResamplingMethod::Average, Some((min, max))) => {
+                let samples = footprint_pixels(min, max).map(|pixel| {
+                    let pixel_min = pixel.as_dvec2();
+                    let overlap = (max.min(pixel_min + 1.0) - min.max(pixel_min)).max(DVec2::ZERO);
+
+                    (pixel, overlap.element_product())
+                });
+
+                self.accumulate(samples, values)
+            }
+            (ResamplingMethod::Mode, Some((min, max))) => self.mode(min, max, values),
+            // nearest and footprints, that can not be resolved
+            _ => self.accumulate([(position.floor().as_ivec2(), 1.0)].into_iter(), values),
+        }
+    }
+
+    /// The weighted mean of the valid samples, together with their weighted density.
+    fn accumulate(&self, samples: impl Iterator<Item = (IVec2, f64)>, values: &mut [f64]) -> f64 {
+        let mut weight_sum = 0.0;
+        let mut density_sum = 0.0;
+
+        values.fill(0.0);
+
+        for (pixel, weight) in samples {
+            let Some(index) = self.index(pixel) else {
+                continue;
+            };
+
+            let density = self.densities[index];
+            weight_sum += weight;
+
+            if density > 0.0 {
+                density_sum += weight * density;
+
+                for (value, sample) in values.iter_mut().zip(self.pixel_values(index)) {
+                    *value += weight * density * sample;
+                }
+            }
+        }
+
+        if density_sum.abs() < MIN_DENSITY || weight_sum.abs() < MIN_DENSITY {
+            return 0.0;
+        }
+
+        for value in values.iter_mut() {
+            *value /= density_sum;
+        }
+
+        (density_sum / weight_sum).clamp(0.0, 1.0)
+    }
+
+    /// Picks the most common value of the first band among the valid pixels of the footprint.
+    fn mode(&self, min: DVec2, max: DVec2, values: &mut [f64]) -> f64 {
+        let mut counts = Vec::<(u64, usize, usize)>::new();
+        let mut total = 0;
+
+        for pixel in footprint_pixels(min, max) {
+            let Some(index) = self.index(pixel) else {
+                continue;
+            };
+
+            total += 1;
+
+            if self.densities[index] > 0.0 {
+                let key = self.pixel_values(index)[0].to_bits();
+
+                match counts.iter_mut().find(|(value, ..)| *value == key) {
+                    Some((_, count, _)) => *count += 1,
+                    None => counts.push((key, 1, index)),
+                }
+            }
+        }
+
+        let Some(&(_, count, index)) = counts.iter().max_by_key(|(_, count, _)| *count) else {
+            return 0.0;
+        };
+
+        values.copy_from_slice(self.pixel_values(index));
+
+        count as f64 / total as f64
+    }
+
+    fn index(&self, pixel: IVec2) -> Option<usize> {
+        let (x, y) = (pixel.x as usize, pixel.y as usize);
+
+        (pixel.x >= 0 && pixel.y >= 0 && x < self.width && y < self.height)
+            .then_some(y * self.width + x)
+    }
+
+    fn pixel_values(&self, index: usize) -> &[f64] {
+        &self.values[index * self.color_count..(index + 1) * self.color_count]
+    }
+}
+
+/// The number of source pixels, that the window of a region may contain at most.
+fn max_window_pixels(region_pixels: usize) -> usize {
+    (WINDOW_PIXELS_PER_PIXEL * region_pixels).max(MAX_WINDOW_PIXELS)
+}
+
+/// Warps the whole source onto the face image in blocks, whose pixels are computed in parallel.
+pub fn native_warp<T: Copy + GdalType + NumCast>(
+    src: &Dataset,
+    dst: &Dataset,
+    face: u32,
+    context: &PreprocessContext,
+    progress_callback: Option<&ProgressCallback>,
+) -> PreprocessResult<()> {
+    let native_warp = NativeWarp::new(src, context)?;
+    let geo_transform = dst.geo_transform()?;
+    let (width, height) = dst.raster_size();
+
+    let blocks = iproduct!(
+        (0..height).step_by(BLOCK_SIZE),
+        (0..width).step_by(BLOCK_SIZE)
+    )
+    .collect_vec();
+
+    for (index, &(y, x)) in blocks.iter().enumerate() {
+        let size = ((width - x).min(BLOCK_SIZE), (height - y).min(BLOCK_SIZE));
+        let (u, v) = geo_transform.apply(x as f64, y as f64);
+        let block_transform = [u, geo_transform[1], 0.0, v, 0.0, geo_transform[5]];
+
+        let bands = native_warp.warp_region::<T>(src, face, &block_transform, size)?;
+
+        for (band, data) in (1..).zip(bands) {
+            dst.rasterband(band)?.write::<T>(
+                (x as isize, y as isize),
+                size,
+                &mut Buffer::new(size, data),
+            )?;
+        }
+
+        if let Some(progress_callback) = progress_callback {
+            if !progress_callback((index + 1) as f64 / blocks.len() as f64) {
+                return Err(PreprocessError::Cancelled);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The pixels, that overlap the footprint.
+fn footprint_pixels(min: DVec2, max: DVec2) -> impl Iterator<Item = IVec2> {
+    let start = min.floor().as_ivec2();
+    let end = max.ceil().as_ivec2().max(start + 1);
+
+    iproduct!(start.y..end.y, start.x..end.x).map(|(y, x)| IVec2::new(x, y))
+}
+
+/// The cubic convolution kernel with `a = -0.5`, which GDAL uses as well.
+fn cubic_weight(distance: f64) -> f64 {
+    let t = distance.abs();
+
+    if t <= 1.0 {
+        (1.5 * t - 2.5) * t * t + 1.0
+    } else if t < 2.0 {
+        ((-0.5 * t + 2.5) * t - 4.0) * t + 2.0
+    } else {
+        0.0
+    }
+}
+
+/// The range of values, that the data type can represent.
+fn value_range(data_type: GdalDataType) -> (f64, f64) {
+    if !data_type.is_integer() {
+        return (f64::MIN, f64::MAX);
+    }
+
+    let bits = data_type.bits() as i32;
+
+    if data_type.is_signed() {
+        (-2f64.powi(bits - 1), 2f64.powi(bits - 1) - 1.0)
+    } else {
+        (0.0, 2f64.powi(bits) - 1.0)
+    }
+}
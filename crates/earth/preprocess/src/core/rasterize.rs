@@ -0,0 +1,253 @@
+use crate::core::{PreprocessError, PreprocessResult, PreprocessSource, StableHasher};
+use clap::Args;
+use gdal::{
+    Dataset, DriverManager, GeoTransform,
+    raster::{
+        GdalDataType, RasterCreationOptions, RasterizeOptions as GdalRasterizeOptions, rasterize,
+    },
+    vector::{Geometry, LayerAccess},
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+/// The extensions of the OGR sources, that are rasterized before they are processed.
+const VECTOR_EXTENSIONS: [&str; 4] = ["gpkg", "shp", "geojson", "json"];
+
+/// The approximate length of one degree along a great circle, used to convert between
+/// geographic and projected resolutions.
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// How vector sources are burned into a raster, before they are reprojected like raster sources.
+#[derive(Args, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RasterizeOptions {
+    /// The attribute of the vector features, whose value is burned into the raster.
+    /// Features without a value are skipped.
+    #[arg(long = "burn-attribute")]
+    pub attribute: Option<String>,
+    /// The value burned into the raster for every feature, if no attribute is given.
+    #[arg(long, default_value_t = 1.0)]
+    pub burn_value: f64,
+    /// Only rasterizes these layers of the vector sources, instead of all of them.
+    #[arg(long = "layer")]
+    pub layers: Vec<String>,
+    /// The pixel size of the rasterized sources, in the units of their spatial reference.
+    /// Defaults to the pixel size of the highest lod, if the lod count is given.
+    #[arg(long = "vector-resolution")]
+    pub resolution: Option<f64>,
+    /// Burns every pixel touched by a geometry, instead of only those whose center is covered.
+    /// Useful for thin features, like roads and rivers.
+    #[arg(long, default_value_t = false)]
+    pub all_touched: bool,
+}
+
+impl Default for RasterizeOptions {
+    fn default() -> Self {
+        Self {
+            attribute: None,
+            burn_value: 1.0,
+            layers: Vec::new(),
+            resolution: None,
+            all_touched: false,
+        }
+    }
+}
+
+pub(crate) fn is_vector_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        VECTOR_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// Rasterizes the vector source into a GeoTIFF in its own spatial reference,
+/// which is then reprojected onto the tiles like any other raster source.
+///
+/// The rasters are cached in the directory, so that unchanged vector sources are not rasterized
+/// again and the manifest of an interrupted run stays valid.
+/// Pixels without a feature are set to the no data value, or zero if there is none.
+pub(crate) fn rasterize_source(
+    source: &PreprocessSource,
+    options: &RasterizeOptions,
+    data_type: GdalDataType,
+    no_data_value: Option<f64>,
+    default_resolution: Option<f64>,
+    cache_dir: &Path,
+) -> PreprocessResult<PreprocessSource> {
+    let mut hasher = StableHasher::default();
+    source.path.hash(&mut hasher);
+    options.attribute.hash(&mut hasher);
+    options.burn_value.to_bits().hash(&mut hasher);
+    options.layers.hash(&mut hasher);
+    options.resolution.map(f64::to_bits).hash(&mut hasher);
+    options.all_touched.hash(&mut hasher);
+    (data_type as u32).hash(&mut hasher);
+    no_data_value.map(f64::to_bits).hash(&mut hasher);
+    default_resolution.map(f64::to_bits).hash(&mut hasher);
+
+    let stem = source
+        .path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let raster_path = cache_dir.join(format!("{stem}_{:016x}.tif", hasher.finish()));
+
+    let rasterized = PreprocessSource {
+        path: raster_path.clone(),
+        ..source.clone()
+    };
+
+    if is_up_to_date(&raster_path, &source.path)? {
+        return Ok(rasterized);
+    }
+
+    let vector_dataset = Dataset::open(&source.path)?;
+
+    let mut layers = if options.layers.is_empty() {
+        vector_dataset.layers().collect::<Vec<_>>()
+    } else {
+        options
+            .layers
+            .iter()
+            .map(|name| vector_dataset.layer_by_name(name))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let spatial_ref = layers
+        .first()
+        .and_then(|layer| layer.spatial_ref())
+        .ok_or_else(|| PreprocessError::MissingSpatialRef(source.path.clone()))?;
+
+    let mut geometries = Vec::<Geometry>::new();
+    let mut burn_values = Vec::new();
+
+    for layer in &mut layers {
+        if layer.spatial_ref().as_ref() != Some(&spatial_ref) {
+            return Err(PreprocessError::MismatchedSpatialRef(source.path.clone()));
+        }
+
+        for feature in layer.features() {
+            let Some(geometry) = feature.geometry() else {
+                continue;
+            };
+
+            let burn_value = match &options.attribute {
+                Some(attribute) => feature.field_as_double_by_name(attribute)?,
+                None => Some(options.burn_value),
+            };
+
+            if let Some(burn_value) = burn_value {
+                geometries.push(geometry.clone());
+                burn_values.push(burn_value);
+            }
+        }
+    }
+
+    let Some(envelope) = geometries.iter().map(Geometry::envelope).reduce(|a, b| {
+        let mut envelope = a;
+        envelope.MinX = a.MinX.min(b.MinX);
+        envelope.MinY = a.MinY.min(b.MinY);
+        envelope.MaxX = a.MaxX.max(b.MaxX);
+        envelope.MaxY = a.MaxY.max(b.MaxY);
+        envelope
+    }) else {
+        return Err(PreprocessError::EmptyVectorSource(source.path.clone()));
+    };
+
+    // the default resolution is given in degrees
+    let resolution = match (options.resolution, default_resolution) {
+        (Some(resolution), _) => resolution,
+        (None, Some(resolution)) if spatial_ref.is_geographic() => resolution,
+        (None, Some(resolution)) => resolution * METRES_PER_DEGREE,
+        (None, None) => return Err(PreprocessError::MissingResolution(source.path.clone())),
+    };
+
+    let width = ((envelope.MaxX - envelope.MinX) / resolution)
+        .ceil()
+        .max(1.0) as usize;
+    let height = ((envelope.MaxY - envelope.MinY) / resolution)
+        .ceil()
+        .max(1.0) as usize;
+    let geo_transform: GeoTransform = [
+        envelope.MinX,
+        resolution,
+        0.0,
+        envelope.MaxY,
+        0.0,
+        -resolution,
+    ];
+
+    fs::create_dir_all(cache_dir)?;
+
+    // an interrupted run must not leave a partial raster behind, that looks up to date
+    let partial_path = raster_path.with_extension("partial.tif");
+
+    {
+        let driver = DriverManager::get_driver_by_name("GTiff")?;
+        let creation_options = RasterCreationOptions::from_iter([
+            "TILED=YES",
+            "COMPRESS=DEFLATE",
+            "SPARSE_OK=TRUE",
+            "BIGTIFF=IF_SAFER",
+        ]);
+
+        macro_rules! create {
+            ($data_type:ty) => {
+                driver.create_with_band_type_with_options::<$data_type, _>(
+                    &partial_path,
+                    width,
+                    height,
+                    1,
+                    &creation_options,
+                )?
+            };
+        }
+
+        let mut raster = match data_type {
+            GdalDataType::UInt8 => create!(u8),
+            GdalDataType::UInt16 => create!(u16),
+            GdalDataType::UInt32 => create!(u32),
+            GdalDataType::Int8 => create!(i8),
+            GdalDataType::Int16 => create!(i16),
+            GdalDataType::Int32 => create!(i32),
+            GdalDataType::Float32 => create!(f32),
+            GdalDataType::Float64 => create!(f64),
+            _ => return Err(PreprocessError::UnsupportedDataType(data_type)),
+        };
+
+        raster.set_geo_transform(&geo_transform)?;
+        raster.set_spatial_ref(&spatial_ref)?;
+        // unwritten sparse blocks read as the no data value
+        raster.rasterband(1)?.set_no_data_value(no_data_value)?;
+
+        rasterize(
+            &mut raster,
+            &[1],
+            &geometries,
+            &burn_values,
+            Some(GdalRasterizeOptions {
+                all_touched: options.all_touched,
+                ..Default::default()
+            }),
+        )?;
+    }
+
+    fs::rename(partial_path, &raster_path)?;
+
+    Ok(rasterized)
+}
+
+/// Whether the raster exists and has been written after the vector source was last modified.
+fn is_up_to_date(raster_path: &Path, vector_path: &Path) -> PreprocessResult<bool> {
+    if !raster_path.is_file() {
+        return Ok(false);
+    }
+
+    let raster_modified = fs::metadata(raster_path)?.modified()?;
+    let vector_modified = fs::metadata(vector_path)?.modified()?;
+
+    Ok(raster_modified >= vector_modified)
+}
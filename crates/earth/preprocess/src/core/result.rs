@@ -1,19 +1,112 @@
-use gdal::errors::GdalError;
-use std::num::ParseFloatError;
+use gdal::{errors::GdalError, raster::GdalDataType};
+use std::{io, num::ParseFloatError, path::PathBuf, sync::Arc};
 use thiserror::Error;
+use waw_earth_render::{
+    data::{AttachmentFormat, AttachmentLabel, ResamplingMethod, TileCompression},
+    math::TileCoordinate,
+};
 
 #[derive(Error, Debug, Clone)]
 pub enum PreprocessError {
     #[error("unknown rasterband data type")]
     UnknownRasterbandDataType,
+    #[error("the data type {0:?} is not supported")]
+    UnsupportedDataType(GdalDataType),
     #[error("transform operation failed")]
     TransformOperationFailed,
     #[error("The no data value is outside of the datatypes range.")]
     NoDataOutOfRange,
-    #[error("GDAL error")]
+    #[error(
+        "invalid source {0:?}, expected path[,priority=<i32>][,no_data=<f64>][,scale=<f64>][,offset=<f64>]"
+    )]
+    InvalidSource(String),
+    #[error("no .tif files found in {0:?}")]
+    NoSourceFiles(Vec<PathBuf>),
+    #[error("{0:?} has a different spatial reference than the other source files")]
+    MismatchedSpatialRef(PathBuf),
+    #[error("{path:?} has {found} rasterbands, but the other source files have {expected}")]
+    MismatchedBandCount {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+    #[error("the vector source {0:?} has no spatial reference")]
+    MissingSpatialRef(PathBuf),
+    #[error("the vector source {0:?} does not contain any features to rasterize")]
+    EmptyVectorSource(PathBuf),
+    #[error("the vector source {0:?} needs a resolution or lod count to be rasterized")]
+    MissingResolution(PathBuf),
+    #[error("there is no earth at {0:?}, that could be updated")]
+    MissingEarth(PathBuf),
+    #[error("the lod count is unknown, because the sources have not been reprojected yet")]
+    MissingLodCount,
+    #[error("the face {0} has not been reprojected")]
+    MissingFace(u32),
+    #[error("the tile {0} does not exist")]
+    MissingTile(TileCoordinate),
+    #[error("the earth has no {0:?} attachment")]
+    MissingAttachment(AttachmentLabel),
+    #[error("the {0:?} attachment has no raw tiles, because it is packed or was processed by an older version, so it has to be processed again instead of being updated")]
+    MissingRawTiles(AttachmentLabel),
+    #[error("the sources have neither a no data value nor an alpha band, so their valid pixels can not be merged or blended")]
+    UnknownValidPixels,
+    #[error("invalid job file:\n{}", .0.join("\n"))]
+    InvalidJob(Vec<String>),
+    #[error("the format {format:?} stores heights in metres and requires a different data type than {data_type:?}")]
+    MismatchedDataType {
+        format: AttachmentFormat,
+        data_type: GdalDataType,
+    },
+    #[error("{compression:?} compression can not be used for {format:?} tiles")]
+    IncompatibleCompression {
+        compression: TileCompression,
+        format: AttachmentFormat,
+    },
+    #[error("the {0:?} attachment can not be overwritten by its own distance field")]
+    OverwrittenSource(AttachmentLabel),
+    #[error("distance fields can not be stored as {0:?}, only as R16I or R32F")]
+    UnsupportedDistanceFormat(AttachmentFormat),
+    #[error("unknown terrain product {0:?}, expected slope, aspect, hillshade or normals")]
+    UnknownTerrainProduct(String),
+    #[error("the native warp backend only supports geographic and equirectangular sources on WGS84 without rotation, not {0:?}")]
+    UnsupportedNativeProjection(String),
+    #[error("the native warp backend does not support {0:?} resampling")]
+    UnsupportedNativeResampling(ResamplingMethod),
+    #[error("unknown warp backend {0:?}, expected gdal or native")]
+    UnknownWarpBackend(String),
+    #[error("the band {0} is neither a color nor the alpha band of the source")]
+    UnknownBand(usize),
+    #[error("the lossy {0:?} compression would destroy the mask")]
+    LossyMask(TileCompression),
+    #[error("the time slice {slice} is outside of the {count} time slices of the attachment")]
+    TimeSliceOutOfRange { slice: u32, count: u32 },
+    #[error("the topography determines the tiles of the earth and can not have time slices")]
+    TimeSlicedTopography,
+    #[error("the topography does not contain any valid heights")]
+    NoValidHeights,
+    #[error("failed to create the thread pool: {0}")]
+    ThreadPool(String),
+    #[error("the memory budget of {budget} bytes can not hold a single tile of {required} bytes")]
+    MemoryBudgetTooSmall { budget: u64, required: u64 },
+    #[error("the preprocessing was cancelled")]
+    Cancelled,
+    #[error("the earth has {0} issues")]
+    InvalidEarth(usize),
+    #[error("failed to serialize the config: {0}")]
+    Config(String),
+    #[error("IO error: {0}")]
+    Io(#[source] Arc<io::Error>),
+    #[error("GDAL error: {0}")]
     Gdal(#[from] GdalError),
-    #[error("Parse error")]
+    #[error("Parse error: {0}")]
     Parse(#[from] ParseFloatError),
 }
 
+// io::Error is not Clone, so it is shared instead
+impl From<io::Error> for PreprocessError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(Arc::new(error))
+    }
+}
+
 pub type PreprocessResult<T> = Result<T, PreprocessError>;
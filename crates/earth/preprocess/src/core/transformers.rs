@@ -10,7 +10,11 @@ use glam::{DVec2, DVec3};
 use itertools::izip;
 use std::ffi::c_void;
 use std::ptr;
-use waw_earth_render::math::Coordinate;
+use waw_earth_render::math::{Coordinate, EarthShape};
+
+pub(crate) const LON_LAT_PROJ4: &str = "+proj=lonlat +ellps=WGS84 +datum=WGS84";
+/// The shape of the preprocessed earths, whose spheroid matches the datum of [`LON_LAT_PROJ4`].
+pub(crate) const EARTH_SHAPE: EarthShape = EarthShape::WGS84;
 
 impl Transformer for GeoTransform {
     fn transform(
@@ -107,6 +111,31 @@ impl Transformer for ReprojectionTransformer {
     }
 }
 
+/// Maps a geodetic longitude and latitude (in radians) onto the unit sphere,
+/// where the renderer places it on the surface of the spheroid.
+pub(crate) fn unit_position_from_lon_lat(lon: f64, lat: f64) -> DVec3 {
+    EARTH_SHAPE.unit_position_from_lon_lat(lon, lat)
+}
+
+/// Maps a position on the unit sphere to its geodetic longitude and latitude (in radians).
+pub(crate) fn lon_lat_from_unit_position(unit_position: DVec3) -> (f64, f64) {
+    EARTH_SHAPE.lon_lat_from_unit_position(unit_position)
+}
+
+/// Maps the uv coordinate on the face to its longitude and latitude (in degrees).
+pub(crate) fn lon_lat_from_face_uv(face: u32, uv: DVec2) -> (f64, f64) {
+    let (lon, lat) = lon_lat_from_unit_position(Coordinate::new(face, uv).unit_position(true));
+
+    (lon.to_degrees(), lat.to_degrees())
+}
+
+/// Maps the longitude and latitude (in degrees) to the face and uv coordinate, that contain it.
+pub(crate) fn face_coordinate_from_lon_lat(lon: f64, lat: f64) -> Coordinate {
+    let unit_position = unit_position_from_lon_lat(lon.to_radians(), lat.to_radians());
+
+    Coordinate::from_unit_position(unit_position, true)
+}
+
 struct CubeTransformer {
     face: u32,
 }
@@ -126,32 +155,22 @@ impl Transformer for CubeTransformer {
         _: &mut [f64],
         success: &mut [bool],
     ) -> PreprocessResult<()> {
-        // Todo: convert to and from spherical to ellipsoidal lat/lon
-        // Todo: check unit <--> lat/lon
-
         if dst_to_src {
             for (lon_or_u, lat_or_v, success) in
                 izip!(lon_or_u.iter_mut(), lat_or_v.iter_mut(), success.iter_mut())
             {
-                let coordinate = Coordinate::new(self.face, DVec2::new(*lon_or_u, *lat_or_v));
-                let unit_position = coordinate.unit_position(true);
-
-                let lon = unit_position.z.atan2(-unit_position.x);
-                let lat = unit_position.y.asin();
+                let (lon, lat) = lon_lat_from_face_uv(self.face, DVec2::new(*lon_or_u, *lat_or_v));
 
                 *success = *success && !lat.is_nan();
-                *lon_or_u = lon.to_degrees();
-                *lat_or_v = lat.to_degrees();
+                *lon_or_u = lon;
+                *lat_or_v = lat;
             }
         } else {
             for (lon_or_u, lat_or_v, success) in
                 izip!(lon_or_u.iter_mut(), lat_or_v.iter_mut(), success.iter_mut())
             {
-                let lon = lon_or_u.to_radians();
-                let lat = lat_or_v.to_radians();
-
                 let unit_position =
-                    DVec3::new(-lat.cos() * lon.cos(), lat.sin(), lat.cos() * lon.sin());
+                    unit_position_from_lon_lat(lon_or_u.to_radians(), lat_or_v.to_radians());
 
                 let coordinate = Coordinate::from_unit_position(unit_position, true);
 
@@ -206,7 +225,7 @@ impl CustomTransformer {
                 dst_geo_transform,
                 lon_lat_transformer: ReprojectionTransformer::from_ref(
                     &src.spatial_ref()?,
-                    &SpatialRef::from_proj4("+proj=lonlat +ellps=WGS84 +datum=WGS84")?,
+                    &SpatialRef::from_proj4(LON_LAT_PROJ4)?,
                 )?,
                 cube_transformer: CubeTransformer::new(face),
             }),
@@ -256,3 +275,67 @@ impl Transformer for CustomTransformer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use itertools::iproduct;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// A grid of uv coordinates on every face, which avoids the edges and centers of the faces.
+    fn face_uvs() -> Vec<(u32, DVec2)> {
+        iproduct!(0..6, 0..10, 0..10)
+            .map(|(face, i, j)| (face, (DVec2::new(i as f64, j as f64) + 0.5) / 10.0))
+            .collect()
+    }
+
+    /// The longitude and latitude (in degrees), where the renderer places the uv coordinate.
+    fn renderer_lon_lat(face: u32, uv: DVec2) -> (f64, f64) {
+        let unit_position = Coordinate::new(face, uv).unit_position(true);
+        let (lon, lat) = EARTH_SHAPE.lon_lat_from_unit_position(unit_position);
+
+        (lon.to_degrees(), lat.to_degrees())
+    }
+
+    // the round trips of the shape itself are tested by the renderer
+    #[test]
+    fn cube_transformer_matches_the_renderer() {
+        let face_uvs = face_uvs();
+        let (lons, lats): (Vec<_>, Vec<_>) = face_uvs
+            .iter()
+            .map(|&(face, uv)| renderer_lon_lat(face, uv))
+            .unzip();
+
+        for face in 0..6 {
+            let mut transformer = CubeTransformer::new(face);
+
+            let mut x = lons.clone();
+            let mut y = lats.clone();
+            let mut z = vec![0.0; x.len()];
+            let mut success = vec![true; x.len()];
+
+            transformer
+                .transform(false, &mut x, &mut y, &mut z, &mut success)
+                .unwrap();
+
+            for (index, &(point_face, uv)) in face_uvs.iter().enumerate() {
+                // only the points on the face of the transformer can be mapped
+                assert_eq!(success[index], point_face == face);
+
+                if success[index] {
+                    assert!((DVec2::new(x[index], y[index]) - uv).length() < EPSILON);
+                }
+            }
+
+            transformer
+                .transform(true, &mut x, &mut y, &mut z, &mut success)
+                .unwrap();
+
+            for index in (0..success.len()).filter(|&index| success[index]) {
+                assert!((x[index] - lons[index]).abs() < EPSILON);
+                assert!((y[index] - lats[index]).abs() < EPSILON);
+            }
+        }
+    }
+}
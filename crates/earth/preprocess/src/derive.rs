@@ -0,0 +1,392 @@
+use crate::{
+    core::{CountingProgressCallback, PreprocessError, PreprocessResult},
+    pack::{TileFiles, clear_attachment, load_config, pack_attachment, save_config},
+    progress::Progress,
+    window::{TileWindow, pixel_edges, pixel_position, write_tile},
+};
+use clap::Args;
+use glam::{DVec2, DVec3};
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::{path::Path, str::FromStr};
+use waw_earth_render::prelude::*;
+
+/// An attachment, that is derived from the topography.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TerrainProduct {
+    Slope,
+    Aspect,
+    Hillshade,
+    Normals,
+}
+
+impl FromStr for TerrainProduct {
+    type Err = PreprocessError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "slope" => Ok(Self::Slope),
+            "aspect" => Ok(Self::Aspect),
+            "hillshade" => Ok(Self::Hillshade),
+            "normals" => Ok(Self::Normals),
+            other => Err(PreprocessError::UnknownTerrainProduct(other.to_string())),
+        }
+    }
+}
+
+impl TerrainProduct {
+    pub const ALL: [Self; 4] = [Self::Slope, Self::Aspect, Self::Hillshade, Self::Normals];
+
+    pub fn label(self) -> AttachmentLabel {
+        match self {
+            TerrainProduct::Slope => AttachmentLabel::Slope,
+            TerrainProduct::Aspect => AttachmentLabel::Aspect,
+            TerrainProduct::Hillshade => AttachmentLabel::Hillshade,
+            TerrainProduct::Normals => AttachmentLabel::Normals,
+        }
+    }
+
+    pub fn format(self) -> AttachmentFormat {
+        match self {
+            TerrainProduct::Normals => AttachmentFormat::Rg16U,
+            _ => AttachmentFormat::R8Unorm,
+        }
+    }
+}
+
+/// How the terrain products are derived.
+#[derive(Args, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TerrainOptions {
+    /// The direction of the light used for the hillshade, clockwise from north in degrees.
+    #[arg(long, default_value_t = 315.0)]
+    pub sun_azimuth: f64,
+    /// The angle of the light used for the hillshade above the horizon in degrees.
+    #[arg(long, default_value_t = 45.0)]
+    pub sun_elevation: f64,
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        Self {
+            sun_azimuth: 315.0,
+            sun_elevation: 45.0,
+        }
+    }
+}
+
+/// The terrain of a single pixel.
+#[derive(Clone, Copy, Default)]
+struct PixelTerrain {
+    /// in degrees
+    slope: f64,
+    /// in degrees, clockwise from north
+    aspect: f64,
+    hillshade: f64,
+    /// The components of the normal along the u and v axis of the tile.
+    normal: DVec2,
+}
+
+/// Derives the products from the topography of the earth and stores them as attachments,
+/// with the same tiles, size and lods as the topography.
+///
+/// The gradients at the border of a tile are computed from the pixels of its neighbours,
+/// so that the products are continuous across tile and face seams.
+pub fn derive_terrain(
+    earth_path: &Path,
+    products: &[TerrainProduct],
+    options: &TerrainOptions,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    let products = if products.is_empty() {
+        &TerrainProduct::ALL[..]
+    } else {
+        products
+    };
+
+    let mut config = load_config(earth_path)?;
+
+    let topography = config
+        .attachments
+        .get(&AttachmentLabel::Topography)
+        .cloned()
+        .ok_or(PreprocessError::MissingAttachment(AttachmentLabel::Topography))?;
+
+    let topography_files = TileFiles::new(earth_path, &AttachmentLabel::Topography, &topography)?;
+
+    let outputs = products
+        .iter()
+        .map(|&product| {
+            let attachment = AttachmentConfig {
+                format: product.format(),
+                mask: false,
+                reproject_resampling: ResamplingMethod::Bilinear,
+                downsample_resampling: ResamplingMethod::Bilinear,
+                compression: TileCompression::None,
+                packed: false,
+                ..topography.clone()
+            };
+
+            Ok((product, clear_attachment(earth_path, &product.label())?, attachment))
+        })
+        .collect::<PreprocessResult<Vec<_>>>()?;
+
+    progress.run_stage("Deriving terrain", |progress_callback| {
+        let progress_callback =
+            CountingProgressCallback::new(config.tiles.len() as u64, Some(progress_callback));
+
+        config.tiles.par_iter().try_for_each(|&tile| {
+            if topography_files.path(tile).is_some() {
+                let terrain = tile_terrain(tile, &topography_files, &topography, &config, options)?;
+
+                for (product, tile_dir, attachment) in &outputs {
+                    write_product(*product, &tile.path(tile_dir), &terrain, attachment)?;
+                }
+            }
+
+            progress_callback.increment()?;
+
+            Ok::<(), PreprocessError>(())
+        })
+    })?;
+
+    for (product, _, attachment) in outputs {
+        config.add_attachment(product.label(), attachment);
+    }
+
+    save_config(&config, earth_path)?;
+
+    if topography.packed {
+        for product in products {
+            pack_attachment(earth_path, &product.label())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The terrain of all pixels of the tile, including its border.
+fn tile_terrain(
+    tile: TileCoordinate,
+    topography_files: &TileFiles,
+    topography: &AttachmentConfig,
+    config: &EarthConfig,
+    options: &TerrainOptions,
+) -> PreprocessResult<Vec<PixelTerrain>> {
+    let texture_size = topography.texture_size as usize;
+    let center_size = topography.center_size() as usize;
+    let border_size = topography.border_size as usize;
+
+    // the outermost border pixels need one more pixel on each side
+    let margin = (border_size + 1).min(center_size);
+    let window = TileWindow::read(tile, topography_files, topography, margin)?;
+
+    let height = |x: usize, y: usize, dx: isize, dy: isize| {
+        window
+            .tile_value(x as isize + dx, y as isize + dy, border_size)
+            .map(|value| {
+                topography
+                    .format
+                    .decode_height(value, config.min_height, config.max_height)
+            })
+    };
+
+    let zenith = (90.0 - options.sun_elevation).to_radians();
+    let azimuth = options.sun_azimuth.to_radians();
+
+    let terrain = (0..texture_size)
+        .flat_map(|y| (0..texture_size).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let Some(center) = height(x, y, 0, 0) else {
+                return PixelTerrain {
+                    hillshade: zenith.cos(),
+                    ..Default::default()
+                };
+            };
+
+            // central differences, which fall back to one sided ones at missing pixels
+            let difference = |dx: isize, dy: isize| {
+                let forward = height(x, y, dx, dy);
+                let backward = height(x, y, -dx, -dy);
+                let span = forward.is_some() as u32 + backward.is_some() as u32;
+
+                match span {
+                    0 => 0.0,
+                    _ => (forward.unwrap_or(center) - backward.unwrap_or(center)) / span as f64,
+                }
+            };
+
+            let pixel = DVec2::new(x as f64, y as f64) + 0.5 - border_size as f64;
+            let (u_edge, v_edge) = pixel_edges(tile, pixel, center_size, config.shape);
+
+            let height_du = difference(1, 0) / u_edge.length();
+            let height_dv = difference(0, 1) / v_edge.length();
+
+            let u_axis = u_edge.normalize();
+            let v_axis = v_edge.normalize();
+
+            let up = if config.shape.face_count() == 6 {
+                pixel_position(tile, pixel, center_size, config.shape)
+            } else {
+                DVec3::Y
+            };
+
+            let mut normal = u_axis.cross(v_axis).normalize();
+
+            if normal.dot(up) < 0.0 {
+                normal = -normal;
+            }
+
+            // the axes of the cube sphere are not perpendicular, so the gradient is
+            // expressed in their dual basis
+            let cos_axes = u_axis.dot(v_axis);
+            let determinant = 1.0 - cos_axes * cos_axes;
+            let gradient = (height_du - cos_axes * height_dv) / determinant * u_axis
+                + (height_dv - cos_axes * height_du) / determinant * v_axis;
+
+            let slope = gradient.length().atan();
+            let surface_normal = (normal - gradient).normalize();
+
+            let north = DVec3::Y - DVec3::Y.dot(normal) * normal;
+            let aspect = if north.length() < 1e-9 || gradient.length() < 1e-12 {
+                0.0 // the poles and flat terrain have no direction
+            } else {
+                let north = north.normalize();
+                let east = north.cross(normal);
+                let downhill = -gradient;
+
+                downhill.dot(east).atan2(downhill.dot(north)).rem_euclid(std::f64::consts::TAU)
+            };
+
+            let hillshade = zenith.cos() * slope.cos()
+                + zenith.sin() * slope.sin() * (azimuth - aspect).cos();
+
+            PixelTerrain {
+                slope: slope.to_degrees(),
+                aspect: aspect.to_degrees(),
+                hillshade: hillshade.clamp(0.0, 1.0),
+                normal: DVec2::new(surface_normal.dot(u_axis), surface_normal.dot(v_axis)),
+            }
+        })
+        .collect_vec();
+
+    Ok(terrain)
+}
+
+/// Writes the product of the terrain as a new tile, encoded as described by its label.
+fn write_product(
+    product: TerrainProduct,
+    tile_path: &Path,
+    terrain: &[PixelTerrain],
+    attachment: &AttachmentConfig,
+) -> PreprocessResult<()> {
+    let unorm8 = |value: f64| (value.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8;
+    let unorm16 = |value: f64| (value.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16;
+
+    match product {
+        TerrainProduct::Slope => {
+            let data = terrain.iter().map(|pixel| unorm8(pixel.slope / 90.0)).collect_vec();
+            write_tile(tile_path, vec![data], attachment)
+        }
+        TerrainProduct::Aspect => {
+            let data = terrain.iter().map(|pixel| unorm8(pixel.aspect / 360.0)).collect_vec();
+            write_tile(tile_path, vec![data], attachment)
+        }
+        TerrainProduct::Hillshade => {
+            let data = terrain.iter().map(|pixel| unorm8(pixel.hillshade)).collect_vec();
+            write_tile(tile_path, vec![data], attachment)
+        }
+        TerrainProduct::Normals => {
+            let bands = vec![
+                terrain.iter().map(|pixel| unorm16(0.5 * pixel.normal.x + 0.5)).collect_vec(),
+                terrain.iter().map(|pixel| unorm16(0.5 * pixel.normal.y + 0.5)).collect_vec(),
+            ];
+            write_tile(tile_path, bands, attachment)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use glam::IVec2;
+    use std::{fs, process};
+
+    #[test]
+    fn terrain_of_an_inclined_plane() {
+        let earth_path = std::env::temp_dir().join(format!("waw_terrain_{}", process::id()));
+        let tile = TileCoordinate::new(0, 0, IVec2::ZERO);
+
+        let topography = AttachmentConfig {
+            texture_size: 36,
+            border_size: 2,
+            format: AttachmentFormat::R32F,
+            ..Default::default()
+        };
+        // the heights are stored as they are
+        let config = EarthConfig {
+            shape: EarthShape::Plane {
+                side_length: 1000.0,
+            },
+            min_height: 0.0,
+            max_height: 1.0,
+            ..Default::default()
+        };
+
+        let texture_size = topography.texture_size as usize;
+        let center_size = topography.center_size() as usize;
+        let border_size = topography.border_size as usize;
+
+        // the pixels of the plane all have the same size
+        let pixel_size = pixel_edges(tile, DVec2::splat(0.5), center_size, config.shape)
+            .0
+            .length();
+
+        for slope in [0.0_f64, 20.0, 45.0] {
+            // the plane rises along the u axis of the tile
+            let heights = (0..texture_size * texture_size)
+                .map(|index| (index % texture_size) as f64 * pixel_size)
+                .map(|distance| (distance * slope.to_radians().tan()) as f32)
+                .collect_vec();
+
+            let topography_dir = earth_path.join(String::from(&AttachmentLabel::Topography));
+            write_tile(&tile.path(&topography_dir), vec![heights], &topography).unwrap();
+
+            let topography_files =
+                TileFiles::new(&earth_path, &AttachmentLabel::Topography, &topography).unwrap();
+            let terrain = tile_terrain(
+                tile,
+                &topography_files,
+                &topography,
+                &config,
+                &TerrainOptions::default(),
+            )
+            .unwrap();
+
+            // the border has no neighbours to read from, so only the center is derived
+            let center = border_size..border_size + center_size;
+
+            for y in center.clone() {
+                for x in center.clone() {
+                    let pixel = terrain[y * texture_size + x];
+
+                    assert!(
+                        (pixel.slope - slope).abs() < 1e-3,
+                        "({x}, {y}): slope {} instead of {slope}",
+                        pixel.slope
+                    );
+
+                    // the normal is tilted away from the rising u axis
+                    let normal = DVec2::new(-slope.to_radians().sin(), 0.0);
+                    assert!(
+                        pixel.normal.distance(normal) < 1e-4,
+                        "({x}, {y}): normal {} instead of {normal}",
+                        pixel.normal
+                    );
+                }
+            }
+        }
+
+        let _ = fs::remove_dir_all(&earth_path);
+    }
+}
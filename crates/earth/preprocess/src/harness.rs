@@ -0,0 +1,568 @@
+//! Synthetic global rasters and a harness, that runs the preprocess stages on them.
+//!
+//! The harness backs the regression tests of the stages and the benchmarks in `benches`,
+//! which require the `bench` feature.
+
+use crate::{
+    core::{
+        FaceInfo, PreprocessContext, PreprocessDataType, PreprocessNoData, PreprocessResult,
+        RasterizeOptions, WarpBackend, clear_directory, create_mask_and_fill_no_data,
+        delete_directory, load_tile_dataset_if_exists,
+    },
+    process::{downsample_and_stitch, reproject, reproject_to_tiles, split_and_stitch},
+    save_earth_config,
+};
+use gdal::{
+    Dataset, DriverManager,
+    raster::{Buffer, RasterCreationOptions},
+    spatial_ref::SpatialRef,
+};
+use itertools::{Itertools, iproduct};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+use waw_earth_render::prelude::*;
+
+/// The no data value of the synthetic rasters.
+pub const NO_DATA: f64 = -9999.0;
+
+/// The pattern of a synthetic raster.
+#[derive(Clone, Copy, Debug)]
+pub enum SyntheticRaster {
+    /// A smooth gradient, which is periodic in longitude, so that it has no seam at the antimeridian.
+    Gradient,
+    /// Alternating cells of zero and one, which are the size in degrees wide.
+    Checkerboard { cell_size: f64 },
+    /// Two bands with the longitude and the latitude of each pixel in degrees.
+    LonLat,
+}
+
+impl SyntheticRaster {
+    pub fn band_count(self) -> usize {
+        match self {
+            SyntheticRaster::LonLat => 2,
+            _ => 1,
+        }
+    }
+
+    /// The value of the band at the position in degrees.
+    pub fn value(self, band: usize, lon: f64, lat: f64) -> f64 {
+        match self {
+            SyntheticRaster::Gradient => {
+                let (lon, lat) = (lon.to_radians(), lat.to_radians());
+
+                1000.0 * lon.sin() * lat.cos() + 500.0 * (2.0 * lat).sin()
+            }
+            SyntheticRaster::Checkerboard { cell_size } => {
+                let cell = (lon / cell_size).floor() + (lat / cell_size).floor();
+
+                cell.rem_euclid(2.0)
+            }
+            SyntheticRaster::LonLat => [lon, lat][band],
+        }
+    }
+}
+
+/// A global raster in EPSG:4326, that is generated from a [`SyntheticRaster`].
+#[derive(Clone, Debug)]
+pub struct SyntheticSource {
+    pub raster: SyntheticRaster,
+    pub pixels_per_degree: usize,
+    /// The region `[min_lon, min_lat, max_lon, max_lat]` in degrees, which has no data.
+    pub hole: Option<[f64; 4]>,
+}
+
+impl SyntheticSource {
+    pub fn new(raster: SyntheticRaster) -> Self {
+        Self {
+            raster,
+            pixels_per_degree: 1,
+            hole: None,
+        }
+    }
+
+    /// Writes the raster as a float GeoTIFF.
+    pub fn write(&self, path: &Path) -> PreprocessResult<()> {
+        let width = 360 * self.pixels_per_degree;
+        let height = 180 * self.pixels_per_degree;
+        let pixel_size = 1.0 / self.pixels_per_degree as f64;
+
+        let driver = DriverManager::get_driver_by_name("GTiff")?;
+        let options = RasterCreationOptions::from_iter(["TILED=YES"]);
+        let mut dataset = driver.create_with_band_type_with_options::<f32, _>(
+            path,
+            width,
+            height,
+            self.raster.band_count(),
+            &options,
+        )?;
+        dataset.set_geo_transform(&[-180.0, pixel_size, 0.0, 90.0, 0.0, -pixel_size])?;
+        dataset.set_spatial_ref(&SpatialRef::from_epsg(4326)?)?;
+
+        for band in 0..self.raster.band_count() {
+            let data = iproduct!(0..height, 0..width)
+                .map(|(y, x)| {
+                    let lon = (x as f64 + 0.5) * pixel_size - 180.0;
+                    let lat = 90.0 - (y as f64 + 0.5) * pixel_size;
+
+                    let in_hole = self
+                        .hole
+                        .is_some_and(|[min_lon, min_lat, max_lon, max_lat]| {
+                            (min_lon..max_lon).contains(&lon) && (min_lat..max_lat).contains(&lat)
+                        });
+
+                    if in_hole {
+                        NO_DATA as f32
+                    } else {
+                        self.raster.value(band, lon, lat) as f32
+                    }
+                })
+                .collect_vec();
+
+            let mut raster = dataset.rasterband(band + 1)?;
+            raster.set_no_data_value(Some(NO_DATA))?;
+            raster.write(
+                (0, 0),
+                (width, height),
+                &mut Buffer::new((width, height), data),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a synthetic source into a temporary directory and processes it into earths,
+/// stage by stage. The directory is removed, once the harness is dropped.
+pub struct Harness {
+    dir: PathBuf,
+    source_path: PathBuf,
+    pub lod_count: u32,
+    pub texture_size: u32,
+    pub border_size: u32,
+    pub fill_radius: f32,
+    pub resampling: ResamplingMethod,
+    /// The memory budget in MiB of the streaming pipeline.
+    pub memory_budget: Option<u64>,
+}
+
+impl Harness {
+    /// Creates the harness with two lods of 64 pixels, which is finer than the default source,
+    /// so that GDAL does not widen its kernels.
+    pub fn new(name: &str, source: &SyntheticSource) -> PreprocessResult<Self> {
+        let dir = std::env::temp_dir().join(format!("waw_harness_{name}_{}", process::id()));
+        clear_directory(&dir)?;
+
+        let source_path = dir.join("source.tif");
+        source.write(&source_path)?;
+
+        Ok(Self {
+            dir,
+            source_path,
+            lod_count: 2,
+            texture_size: 66,
+            border_size: 1,
+            fill_radius: 0.0,
+            resampling: ResamplingMethod::Bilinear,
+            memory_budget: None,
+        })
+    }
+
+    /// Creates an empty earth, whose topography is the synthetic source.
+    pub fn earth(&self, name: &str) -> PreprocessResult<SyntheticEarth> {
+        let earth_path = self.dir.join(name);
+        delete_directory(&earth_path)?;
+
+        let (src_dataset, context) = PreprocessContext::initialize(
+            earth_path,
+            Some(self.lod_count),
+            AttachmentLabel::Topography,
+            AttachmentConfig {
+                texture_size: self.texture_size,
+                border_size: self.border_size,
+                mip_level_count: 1,
+                mask: false,
+                format: AttachmentFormat::R32F,
+                reproject_resampling: self.resampling,
+                downsample_resampling: self.resampling,
+                compression: TileCompression::None,
+                packed: false,
+                time_slices: 0,
+            },
+            None,
+            vec![self.source_path.clone().into()],
+            None,
+            PreprocessNoData::Source,
+            PreprocessDataType::Source,
+            self.fill_radius,
+            0.0,
+            false,
+            true,
+            false,
+            false,
+            RasterizeOptions::default(),
+            WarpBackend::Gdal,
+            self.memory_budget.is_some(),
+            self.memory_budget,
+        )?;
+
+        Ok(SyntheticEarth {
+            context,
+            src_dataset: Some(src_dataset),
+            faces: HashMap::new(),
+            tiles: Vec::new(),
+        })
+    }
+
+    /// Processes an earth with the classic pipeline, which reprojects whole faces first.
+    pub fn classic(&self, name: &str) -> PreprocessResult<SyntheticEarth> {
+        let mut earth = self.earth(name)?;
+        earth.reproject()?;
+        earth.split_and_stitch()?;
+        earth.downsample_and_stitch()?;
+        earth.fill()?;
+        earth.finish()?;
+
+        Ok(earth)
+    }
+
+    /// Processes an earth with the streaming pipeline, which reprojects directly into tiles.
+    pub fn streaming(&self, name: &str) -> PreprocessResult<SyntheticEarth> {
+        let mut earth = self.earth(name)?;
+        earth.reproject_to_tiles()?;
+        earth.downsample_and_stitch()?;
+        earth.fill()?;
+        earth.finish()?;
+
+        Ok(earth)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// An earth, that is processed from a synthetic source one stage at a time.
+pub struct SyntheticEarth {
+    pub context: PreprocessContext,
+    src_dataset: Option<Dataset>,
+    faces: HashMap<u32, FaceInfo>,
+    /// The tiles produced by the last stage.
+    pub tiles: Vec<TileCoordinate>,
+}
+
+impl SyntheticEarth {
+    pub fn reproject(&mut self) -> PreprocessResult<()> {
+        let src_dataset = self.src_dataset.take().unwrap();
+
+        clear_directory(&self.context.temp_dir)?;
+        self.faces = reproject::<f32>(src_dataset, &mut self.context, None)?;
+
+        Ok(())
+    }
+
+    pub fn reproject_to_tiles(&mut self) -> PreprocessResult<()> {
+        let src_dataset = self.src_dataset.take().unwrap();
+
+        clear_directory(&self.context.temp_dir)?;
+        self.tiles = reproject_to_tiles::<f32>(src_dataset, &mut self.context, None)?;
+
+        Ok(())
+    }
+
+    pub fn split_and_stitch(&mut self) -> PreprocessResult<()> {
+        let faces = std::mem::take(&mut self.faces);
+        self.tiles = split_and_stitch::<f32>(faces, &self.context, None)?;
+
+        Ok(())
+    }
+
+    pub fn downsample_and_stitch(&mut self) -> PreprocessResult<()> {
+        self.tiles = downsample_and_stitch::<f32>(&self.tiles, &self.context, None)?;
+
+        Ok(())
+    }
+
+    pub fn fill(&self) -> PreprocessResult<()> {
+        create_mask_and_fill_no_data(&self.tiles, &self.context, None)
+    }
+
+    /// Removes the temporary files and writes the config of the earth.
+    pub fn finish(&mut self) -> PreprocessResult<()> {
+        delete_directory(&self.context.temp_dir)?;
+        save_earth_config(self.tiles.clone(), &self.context)
+    }
+
+    pub fn earth_path(&self) -> &Path {
+        &self.context.earth_path
+    }
+
+    /// Reads all bands of the tile, including its border, or `None` if it does not exist.
+    /// Until the tile is filled, the raw tile of the previous stages is read instead.
+    pub fn read_tile(&self, tile: TileCoordinate) -> PreprocessResult<Option<Vec<Vec<f32>>>> {
+        let tile_path = tile.path(&self.context.tile_dir);
+
+        let dataset = if tile_path.is_file() {
+            Dataset::open(tile_path)?
+        } else if let Some(dataset) = load_tile_dataset_if_exists(tile, &self.context)? {
+            dataset
+        } else {
+            return Ok(None);
+        };
+
+        let bands = dataset
+            .rasterbands()
+            .map(|band| Ok(band?.read_band_as::<f32>()?.into_shape_and_vec().1))
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        Ok(Some(bands))
+    }
+
+    /// The position of the center of the pixel of a tile, including its border,
+    /// as uv coordinate on its face.
+    pub fn pixel_uv(&self, tile: TileCoordinate, x: usize, y: usize) -> (f64, f64) {
+        let center_size = self.context.attachment.center_size() as f64;
+        let border_size = self.context.attachment.border_size as f64;
+        let face_size = center_size * (1 << tile.lod) as f64;
+
+        (
+            (tile.xy.x as f64 * center_size + x as f64 + 0.5 - border_size) / face_size,
+            (tile.xy.y as f64 * center_size + y as f64 + 0.5 - border_size) / face_size,
+        )
+    }
+}
+
+/// The angle in degrees, by which the longitudes differ, accounting for the antimeridian.
+pub fn lon_difference(a: f64, b: f64) -> f64 {
+    (a - b + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{core::lon_lat_from_face_uv, verify::verify_earth};
+    use glam::DVec2;
+    use std::collections::HashSet;
+
+    /// The values of the center pixels of all tiles with the lod, that exist in the earth.
+    fn center_values(earth: &SyntheticEarth, lod: u32) -> HashMap<TileCoordinate, Vec<Vec<f32>>> {
+        let texture_size = earth.context.attachment.texture_size as usize;
+        let border_size = earth.context.attachment.border_size as usize;
+        let center = border_size..texture_size - border_size;
+
+        earth
+            .tiles
+            .iter()
+            .filter(|tile| tile.lod == lod)
+            .filter_map(|&tile| {
+                let bands = earth.read_tile(tile).unwrap()?;
+
+                let bands = bands
+                    .into_iter()
+                    .map(|band| {
+                        iproduct!(center.clone(), center.clone())
+                            .map(|(y, x)| band[y * texture_size + x])
+                            .collect_vec()
+                    })
+                    .collect_vec();
+
+                Some((tile, bands))
+            })
+            .collect()
+    }
+
+    /// Checks, that every center pixel of the highest lod stores its own longitude and latitude.
+    fn assert_lon_lat(earth: &SyntheticEarth) {
+        let texture_size = earth.context.attachment.texture_size as usize;
+        let border_size = earth.context.attachment.border_size as usize;
+        let lod = earth.context.lod_count.unwrap() - 1;
+
+        let mut checked_pixels = 0;
+
+        for &tile in earth.tiles.iter().filter(|tile| tile.lod == lod) {
+            let bands = earth.read_tile(tile).unwrap().unwrap();
+
+            for (y, x) in iproduct!(
+                border_size..texture_size - border_size,
+                border_size..texture_size - border_size
+            ) {
+                let (u, v) = earth.pixel_uv(tile, x, y);
+                let (lon, lat) = lon_lat_from_face_uv(tile.face, DVec2::new(u, v));
+
+                // the source pixels do not reach the poles and wrap around at the antimeridian
+                if lat.abs() > 89.0 || lon.abs() > 179.0 {
+                    continue;
+                }
+
+                let index = y * texture_size + x;
+                let (value_lon, value_lat) = (bands[0][index] as f64, bands[1][index] as f64);
+
+                assert!(
+                    lon_difference(value_lon, lon).abs() < 1e-2,
+                    "{tile} ({x}, {y}): longitude {value_lon} instead of {lon}"
+                );
+                assert!(
+                    (value_lat - lat).abs() < 1e-2,
+                    "{tile} ({x}, {y}): latitude {value_lat} instead of {lat}"
+                );
+
+                checked_pixels += 1;
+            }
+        }
+
+        assert!(checked_pixels > 0);
+    }
+
+    #[test]
+    fn classic_pixels_store_their_position() {
+        let harness = Harness::new(
+            "classic_position",
+            &SyntheticSource::new(SyntheticRaster::LonLat),
+        )
+        .unwrap();
+
+        assert_lon_lat(&harness.classic("earth").unwrap());
+    }
+
+    #[test]
+    fn streaming_pixels_store_their_position() {
+        let harness = Harness::new(
+            "streaming_position",
+            &SyntheticSource::new(SyntheticRaster::LonLat),
+        )
+        .unwrap();
+
+        assert_lon_lat(&harness.streaming("earth").unwrap());
+    }
+
+    #[test]
+    fn classic_tiles_are_stitched_across_seams() {
+        for raster in [
+            SyntheticRaster::Gradient,
+            SyntheticRaster::Checkerboard { cell_size: 10.0 },
+        ] {
+            let harness = Harness::new("seams", &SyntheticSource::new(raster)).unwrap();
+            let earth = harness.classic("earth").unwrap();
+
+            let report = verify_earth(earth.earth_path(), 1e-6).unwrap();
+
+            assert!(report.is_valid(), "{raster:?}: {report:?}");
+        }
+    }
+
+    #[test]
+    fn streaming_matches_classic() {
+        let mut harness = Harness::new(
+            "equivalence",
+            &SyntheticSource::new(SyntheticRaster::Gradient),
+        )
+        .unwrap();
+        harness.lod_count = 3;
+
+        let classic = harness.classic("classic").unwrap();
+
+        // a tiny budget streams the tiles on a single thread
+        for memory_budget in [None, Some(1)] {
+            harness.memory_budget = memory_budget;
+            let streaming = harness.streaming("streaming").unwrap();
+
+            assert_eq!(
+                classic.tiles.iter().collect::<HashSet<_>>(),
+                streaming.tiles.iter().collect::<HashSet<_>>()
+            );
+
+            // the borders are compared as well, because both pipelines stitch all lods
+            for &tile in &classic.tiles {
+                let classic_bands = classic.read_tile(tile).unwrap().unwrap();
+                let streaming_bands = streaming.read_tile(tile).unwrap().unwrap();
+
+                let max_difference = classic_bands
+                    .iter()
+                    .flatten()
+                    .zip(streaming_bands.iter().flatten())
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0.0, f32::max);
+
+                assert!(
+                    max_difference < 1e-3,
+                    "{tile}: max difference {max_difference}"
+                );
+            }
+
+            let report = verify_earth(streaming.earth_path(), 1e-6).unwrap();
+
+            assert!(report.is_valid(), "{report:?}");
+        }
+    }
+
+    #[test]
+    fn filling_closes_holes() {
+        let source = SyntheticSource {
+            hole: Some([35.0, 15.0, 45.0, 25.0]),
+            ..SyntheticSource::new(SyntheticRaster::Gradient)
+        };
+
+        let mut harness = Harness::new("fill", &source).unwrap();
+        harness.fill_radius = 32.0;
+
+        let earth = harness.classic("earth").unwrap();
+
+        for lod in 0..harness.lod_count {
+            for (tile, bands) in center_values(&earth, lod) {
+                let holes = bands[0]
+                    .iter()
+                    .filter(|&&value| value as f64 == NO_DATA)
+                    .count();
+
+                assert_eq!(holes, 0, "{tile} has {holes} pixels without data");
+            }
+        }
+    }
+
+    #[test]
+    fn filling_can_be_repeated() {
+        let source = SyntheticSource {
+            hole: Some([35.0, 15.0, 45.0, 25.0]),
+            ..SyntheticSource::new(SyntheticRaster::Gradient)
+        };
+
+        let mut harness = Harness::new("refill", &source).unwrap();
+        harness.fill_radius = 8.0;
+
+        let mut earth = harness.earth("earth").unwrap();
+        earth.context.create_mask = true;
+        earth.context.attachment.mask = true;
+        earth.context.attachment.compression = TileCompression::Deflate;
+
+        earth.reproject().unwrap();
+        earth.split_and_stitch().unwrap();
+        earth.downsample_and_stitch().unwrap();
+        earth.fill().unwrap();
+
+        let read_tiles = |earth: &SyntheticEarth| {
+            earth
+                .tiles
+                .iter()
+                .map(|&tile| earth.read_tile(tile).unwrap().unwrap())
+                .collect_vec()
+        };
+
+        let tiles = read_tiles(&earth);
+
+        // a resumed run finishes all pending tiles again, starting from the raw tiles
+        earth.fill().unwrap();
+
+        assert_eq!(tiles, read_tiles(&earth));
+
+        for &tile in &earth.tiles {
+            let tile_path = tile.path(&earth.context.tile_dir);
+
+            assert!(!tile_path.with_extension("fill.tif").exists(), "{tile}");
+        }
+    }
+}
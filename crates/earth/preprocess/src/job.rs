@@ -0,0 +1,329 @@
+use crate::{
+    CONFIG_FILE,
+    cli::EarthCli,
+    core::{
+        PreprocessContext, PreprocessDataType, PreprocessError, PreprocessNoData,
+        PreprocessResult, PreprocessSource, RasterizeOptions, WarpBackend, is_vector_source,
+    },
+    derive::{TerrainOptions, TerrainProduct, derive_terrain},
+    preprocess,
+    progress::Progress,
+};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{fs, path::Path, path::PathBuf};
+use waw_earth_render::prelude::*;
+
+/// Describes all attachments of an earth, so that they can be preprocessed in one go.
+///
+/// Job files are written in RON, relative paths are resolved against the working directory.
+/// ```ron
+/// (
+///     earth_path: "assets/earth/data",
+///     overwrite: true,
+///     attachments: [
+///         (
+///             label: Topography,
+///             sources: ["resources/earth/topography.tif"],
+///             format: R16U,
+///             data_type: "UInt16",
+///         ),
+///         (
+///             label: DayTime,
+///             time_slices: [
+///                 ["resources/earth/daytime_01.tif"],
+///                 ["resources/earth/daytime_02.tif"],
+///             ],
+///             format: Rgb8U,
+///         ),
+///     ],
+/// )
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct EarthJob {
+    pub earth_path: PathBuf,
+    #[serde(default)]
+    pub temp_path: Option<PathBuf>,
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Packs the tiles of every attachment into a single archive.
+    #[serde(default)]
+    pub pack: bool,
+    /// The lod count of all attachments, that do not specify their own.
+    #[serde(default)]
+    pub lod_count: Option<u32>,
+    /// Reprojects the sources of every attachment directly into tiles.
+    #[serde(default)]
+    pub streaming: bool,
+    /// The memory in MiB, that the streaming pipeline may use.
+    #[serde(default)]
+    pub memory_budget: Option<u64>,
+    pub attachments: Vec<AttachmentJob>,
+    /// The attachments derived from the topography, once all attachments are processed.
+    #[serde(default)]
+    pub terrain_products: Vec<TerrainProduct>,
+    #[serde(default)]
+    pub terrain_options: TerrainOptions,
+}
+
+/// The settings of a single attachment of an [`EarthJob`], see [`EarthCli`] for their meaning.
+#[derive(Deserialize, Debug)]
+pub struct AttachmentJob {
+    pub label: AttachmentLabel,
+    #[serde(default)]
+    pub sources: Vec<PreprocessSource>,
+    /// The sources of every time slice, like one composite per month, instead of `sources`.
+    #[serde(default)]
+    pub time_slices: Vec<Vec<PreprocessSource>>,
+    pub format: AttachmentFormat,
+    #[serde(default = "default_data_type")]
+    pub data_type: PreprocessDataType,
+    #[serde(default = "default_no_data")]
+    pub no_data: PreprocessNoData,
+    #[serde(default = "default_fill_radius")]
+    pub fill_radius: f32,
+    #[serde(default)]
+    pub blend_width: f32,
+    #[serde(default)]
+    pub create_mask: bool,
+    #[serde(default)]
+    pub lod_count: Option<u32>,
+    #[serde(default = "default_texture_size")]
+    pub texture_size: u32,
+    #[serde(default = "default_border_size")]
+    pub border_size: u32,
+    #[serde(default = "default_mip_level_count")]
+    pub mip_level_count: u32,
+    #[serde(default)]
+    pub reproject_resampling: Option<ResamplingMethod>,
+    #[serde(default)]
+    pub downsample_resampling: Option<ResamplingMethod>,
+    #[serde(default)]
+    pub compression: TileCompression,
+    /// How vector sources are rasterized.
+    #[serde(default)]
+    pub rasterize: RasterizeOptions,
+    #[serde(default)]
+    pub warp_backend: WarpBackend,
+}
+
+// the defaults match the ones of the cli
+fn default_data_type() -> PreprocessDataType {
+    PreprocessDataType::Source
+}
+
+fn default_no_data() -> PreprocessNoData {
+    PreprocessNoData::Source
+}
+
+fn default_fill_radius() -> f32 {
+    16.0
+}
+
+fn default_texture_size() -> u32 {
+    512
+}
+
+fn default_border_size() -> u32 {
+    1
+}
+
+fn default_mip_level_count() -> u32 {
+    1
+}
+
+impl EarthJob {
+    pub fn load(path: &Path) -> PreprocessResult<Self> {
+        let encoded = fs::read_to_string(path)?;
+
+        ron::from_str(&encoded).map_err(|error| {
+            PreprocessError::InvalidJob(vec![format!("failed to parse {path:?}: {error}")])
+        })
+    }
+
+    /// Checks the whole job up front, so that it does not fail halfway through.
+    pub fn validate(&self) -> PreprocessResult<()> {
+        let mut errors = Vec::new();
+
+        if self.memory_budget.is_some() && !self.streaming {
+            errors.push("the memory budget only applies to the streaming pipeline".to_string());
+        }
+
+        if self.attachments.is_empty() {
+            errors.push("the job does not contain any attachments".to_string());
+        }
+
+        for label in self.attachments.iter().map(|job| &job.label).duplicates() {
+            errors.push(format!("the attachment {label:?} is listed more than once"));
+        }
+
+        for product in &self.terrain_products {
+            if self.attachments.iter().any(|job| job.label == product.label()) {
+                errors.push(format!(
+                    "the attachment {:?} is derived from the topography and can not have sources",
+                    product.label()
+                ));
+            }
+        }
+
+        let has_topography = self
+            .attachments
+            .iter()
+            .any(|job| job.label == AttachmentLabel::Topography);
+
+        if !has_topography && !self.earth_path.join(CONFIG_FILE).is_file() {
+            errors.push(format!(
+                "the job has no topography attachment and there is no existing earth at {:?}",
+                self.earth_path
+            ));
+        }
+
+        for job in &self.attachments {
+            let label = &job.label;
+
+            if job.time_slices.is_empty() {
+                if job.sources.is_empty() {
+                    errors.push(format!("{label:?} has no sources"));
+                }
+            } else {
+                if !job.sources.is_empty() {
+                    errors.push(format!("{label:?} has both sources and time slices"));
+                }
+
+                if *label == AttachmentLabel::Topography {
+                    errors.push("the topography can not have time slices".to_string());
+                }
+
+                for (slice, sources) in job.time_slices.iter().enumerate() {
+                    if sources.is_empty() {
+                        errors.push(format!("the time slice {slice} of {label:?} has no sources"));
+                    }
+                }
+            }
+
+            let sources = job.sources.iter().chain(job.time_slices.iter().flatten());
+
+            for source in sources.clone().filter(|source| !source.path.exists()) {
+                errors.push(format!("the source {:?} of {label:?} does not exist", source.path));
+            }
+
+            if job.texture_size <= 2 * job.border_size {
+                errors.push(format!("the texture size of {label:?} leaves no room for the border"));
+            } else if (job.texture_size - 2 * job.border_size) % 2 != 0 {
+                errors.push(format!("the center size of {label:?} has to be even for downsampling"));
+            }
+
+            if job.mip_level_count == 0 {
+                errors.push(format!("{label:?} needs at least one mip level"));
+            }
+
+            if job.lod_count.or(self.lod_count) == Some(0) {
+                errors.push(format!("{label:?} needs at least one lod"));
+            }
+
+            if job.blend_width < 0.0 {
+                errors.push(format!("the blend width of {label:?} is negative"));
+            }
+
+            let has_vector_source = sources.clone().any(|source| is_vector_source(&source.path));
+
+            if has_vector_source
+                && job.rasterize.resolution.is_none()
+                && job.lod_count.or(self.lod_count).is_none()
+            {
+                errors.push(format!(
+                    "{label:?} has vector sources, but neither a vector resolution nor a lod count"
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PreprocessError::InvalidJob(errors))
+        }
+    }
+
+    /// The attachments in the order they have to be processed in.
+    /// Topography comes first, because it determines the lod count and tiles of the earth.
+    fn ordered_attachments(&self) -> Vec<&AttachmentJob> {
+        self.attachments
+            .iter()
+            .sorted_by_key(|job| job.label != AttachmentLabel::Topography)
+            .collect()
+    }
+
+    /// The arguments for preprocessing the sources of the attachment,
+    /// or of one of its time slices.
+    fn cli(&self, job: &AttachmentJob, time_slice: Option<usize>) -> EarthCli {
+        let src_path = match time_slice {
+            Some(slice) => job.time_slices[slice].clone(),
+            None => job.sources.clone(),
+        };
+
+        EarthCli {
+            src_path,
+            earth_path: self.earth_path.clone(),
+            temp_path: self.temp_path.clone(),
+            overwrite: self.overwrite,
+            update: false,
+            pack: self.pack,
+            no_data: job.no_data,
+            data_type: job.data_type,
+            fill_radius: job.fill_radius,
+            blend_width: job.blend_width,
+            create_mask: job.create_mask,
+            lod_count: job.lod_count.or(self.lod_count),
+            attachment_label: job.label.clone(),
+            texture_size: job.texture_size,
+            border_size: job.border_size,
+            mip_level_count: job.mip_level_count,
+            format: job.format,
+            reproject_resampling: job.reproject_resampling,
+            downsample_resampling: job.downsample_resampling,
+            compression: job.compression,
+            rasterize: job.rasterize.clone(),
+            warp_backend: job.warp_backend,
+            streaming: self.streaming,
+            memory_budget: self.memory_budget,
+            time_slice: time_slice.map(|slice| slice as u32),
+            time_slices: job.time_slices.len() as u32,
+        }
+    }
+}
+
+/// Validates the job and preprocesses all of its attachments.
+pub fn preprocess_job(job: &EarthJob, progress: &Progress) -> PreprocessResult<()> {
+    job.validate()?;
+
+    for attachment in job.ordered_attachments() {
+        if attachment.time_slices.is_empty() {
+            progress.message(&format!("Processing: {:?}", attachment.label));
+
+            let (src_dataset, mut context) =
+                PreprocessContext::from_cli(job.cli(attachment, None))?;
+            preprocess(src_dataset, &mut context, progress)?;
+        }
+
+        for slice in 0..attachment.time_slices.len() {
+            progress.message(&format!("Processing: {:?} time slice {slice}", attachment.label));
+
+            let (src_dataset, mut context) =
+                PreprocessContext::from_cli(job.cli(attachment, Some(slice)))?;
+            preprocess(src_dataset, &mut context, progress)?;
+        }
+    }
+
+    if !job.terrain_products.is_empty() {
+        progress.message(&format!("Deriving: {:?}", job.terrain_products));
+
+        derive_terrain(
+            &job.earth_path,
+            &job.terrain_products,
+            &job.terrain_options,
+            progress,
+        )?;
+    }
+
+    Ok(())
+}
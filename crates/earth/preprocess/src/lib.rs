@@ -1,105 +1,323 @@
 mod cli;
 mod core;
+mod derive;
+#[cfg(any(test, feature = "bench"))]
+#[doc(hidden)]
+pub mod harness;
+mod job;
+mod pack;
 mod process;
+mod progress;
+mod sdf;
+mod verify;
+mod window;
 
 use crate::{
-    cli::PreprocessBar,
     core::create_mask_and_fill_no_data,
-    core::{PreprocessContext, clear_directory, delete_directory},
-    process::{downsample_and_stitch, reproject, reproject_to_tiles, split_and_stitch},
+    core::{
+        EARTH_SHAPE, PreprocessContext, PreprocessError, PreprocessResult, PreprocessStage,
+        SourceRecord, TileManifest, clear_directory, delete_directory,
+    },
+    pack::{is_packed, pack_attachment, unpack_attachment},
+    progress::Progress,
+    process::{
+        blend_sources, downsample_and_stitch, face_infos, reproject, reproject_to_tiles,
+        split_and_stitch, with_neighbours,
+    },
 };
 use gdal::{
     Dataset,
     raster::{GdalDataType, GdalType},
 };
+use itertools::Itertools;
 use num::NumCast;
-use std::time::Instant;
+use std::{collections::HashSet, time::Instant};
 use waw_earth_render::prelude::*;
 
+const CONFIG_FILE: &str = "config.tc.ron";
+
 pub mod prelude {
     pub use crate::{
-        cli::{EarthCli, Cli, Command},
-        core::{PreprocessContext, PreprocessDataType, PreprocessNoData},
+        cli::{
+            EarthCli, Cli, Command, ConvertCli, DeriveCli, JobCli, SdfCli, TerminalProgress,
+            VerifyCli,
+        },
+        core::{
+            PreprocessContext, PreprocessDataType, PreprocessError, PreprocessNoData,
+            PreprocessResult, PreprocessSource, PreprocessStage, RasterizeOptions, TileManifest,
+        },
+        derive::{TerrainOptions, TerrainProduct, derive_terrain},
+        job::{AttachmentJob, EarthJob, preprocess_job},
+        pack::{convert_earth, pack_attachment, unpack_attachment},
         preprocess,
         preprocess_streaming,
+        progress::{CancellationToken, Progress, ProgressSink, SilentProgress},
+        sdf::{SdfOptions, generate_sdf},
+        verify::{AttachmentReport, TileIssue, VerifyReport, verify_earth},
     };
 }
 
-fn preprocess_gen<T: Copy + GdalType + PartialEq + NumCast>(
+fn preprocess_gen<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
     src_dataset: Dataset,
     context: &mut PreprocessContext,
-) {
+    progress: &Progress,
+) -> PreprocessResult<()> {
     if context.overwrite {
-        clear_directory(&context.tile_dir);
+        clear_directory(&context.tile_dir)?;
     }
 
-    clear_directory(&context.temp_dir);
+    let mut manifest = TileManifest::load(context);
+    manifest.update_sources(context)?;
 
     let start_preprocessing = Instant::now();
 
-    let progress_bar = PreprocessBar::new("Reprojecting".to_string());
-    let faces = reproject::<T>(src_dataset, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+    run_stages::<T>(context, &mut manifest, progress, |context, manifest| {
+        // reuse the face images of an interrupted run, if they are still around
+        let faces = manifest
+            .is_complete(PreprocessStage::Reprojecting)
+            .then(|| face_infos(&src_dataset, context))
+            .transpose()?
+            .filter(|faces| faces.values().all(|face| face.path.is_file()));
+
+        let faces = if let Some(faces) = faces {
+            faces
+        } else {
+            clear_directory(&context.temp_dir)?;
+
+            let faces = progress.run_stage("Reprojecting", |progress_callback| {
+                reproject::<T>(src_dataset, context, Some(progress_callback))
+            })?;
 
-    let progress_bar = PreprocessBar::new("Splitting".to_string());
-    let tiles = split_and_stitch::<T>(faces, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+            manifest.complete(PreprocessStage::Reprojecting, &[], context);
+            manifest.save(context)?;
+
+            faces
+        };
 
-    let progress_bar = PreprocessBar::new("Downsampling".to_string());
-    let tiles = downsample_and_stitch::<T>(&tiles, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+        progress.run_stage("Splitting", |progress_callback| {
+            split_and_stitch::<T>(faces, context, Some(progress_callback))
+        })
+    })?;
 
-    let progress_bar = PreprocessBar::new("Filling".to_string());
-    create_mask_and_fill_no_data(&tiles, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+    delete_directory(&context.temp_dir)?;
 
-    delete_directory(&context.temp_dir);
+    save_earth_config(manifest.tiles.into_iter().collect(), context)?;
 
-    save_earth_config(tiles, context);
+    progress.message(&format!("Preprocessing took: {:?}", start_preprocessing.elapsed()));
 
-    println!("Preprocessing took: {:?}", start_preprocessing.elapsed());
+    Ok(())
 }
 
 fn preprocess_streaming_gen<T: Copy + GdalType + PartialEq + NumCast + Send + Sync + std::fmt::Debug>(
     src_dataset: Dataset,
     context: &mut PreprocessContext,
-) {
+    progress: &Progress,
+) -> PreprocessResult<()> {
     if context.overwrite {
-        clear_directory(&context.tile_dir);
+        clear_directory(&context.tile_dir)?;
     }
 
-    clear_directory(&context.temp_dir);
+    let mut manifest = TileManifest::load(context);
+    manifest.update_sources(context)?;
 
     let start_preprocessing = Instant::now();
 
-    let progress_bar = PreprocessBar::new("Reprojecting to tiles".to_string());
-    let tiles = reproject_to_tiles::<T>(src_dataset, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+    run_stages::<T>(context, &mut manifest, progress, |context, manifest| {
+        clear_directory(&context.temp_dir)?;
 
-    let progress_bar = PreprocessBar::new("Downsampling".to_string());
-    let tiles = downsample_and_stitch::<T>(&tiles, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+        let tiles = progress.run_stage("Reprojecting to tiles", |progress_callback| {
+            reproject_to_tiles::<T>(src_dataset, context, Some(progress_callback))
+        })?;
 
-    let progress_bar = PreprocessBar::new("Filling".to_string());
-    create_mask_and_fill_no_data(&tiles, context, Some(progress_bar.callback())).unwrap();
-    progress_bar.finish();
+        manifest.complete(PreprocessStage::Reprojecting, &tiles, context);
 
-    delete_directory(&context.temp_dir);
+        Ok(tiles)
+    })?;
 
-    save_earth_config(tiles, context);
+    delete_directory(&context.temp_dir)?;
 
-    println!("Preprocessing took: {:?}", start_preprocessing.elapsed());
+    save_earth_config(manifest.tiles.into_iter().collect(), context)?;
+
+    progress.message(&format!("Preprocessing took: {:?}", start_preprocessing.elapsed()));
+
+    Ok(())
 }
 
-pub fn preprocess(src_dataset: Dataset, context: &mut PreprocessContext) {
+/// Runs the stages, that turn the sources into the tiles of the attachment.
+///
+/// The `split` step writes the tiles of the highest lod and returns them. They are blended,
+/// downsampled into the lower lods and filled, where only the pending tiles of the manifest
+/// are processed. Every completed stage is saved to the manifest,
+/// so that an interrupted run resumes after it.
+fn run_stages<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
+    context: &mut PreprocessContext,
+    manifest: &mut TileManifest,
+    progress: &Progress,
+    split: impl FnOnce(
+        &mut PreprocessContext,
+        &mut TileManifest,
+    ) -> PreprocessResult<Vec<TileCoordinate>>,
+) -> PreprocessResult<()> {
+    if !manifest.is_complete(PreprocessStage::Splitting) {
+        let tiles = split(context, manifest)?;
+
+        if tiles.is_empty() {
+            progress.warning("the sources do not contain any data");
+        }
+
+        manifest.complete(PreprocessStage::Splitting, &tiles, context);
+        manifest.save(context)?;
+    }
+
+    if !manifest.is_complete(PreprocessStage::Blending) {
+        if context.blends_sources() {
+            let lod = context.max_lod()?;
+            let tiles = manifest
+                .pending_tiles()
+                .into_iter()
+                .filter(|tile| tile.lod == lod)
+                .collect_vec();
+
+            progress.run_stage("Blending", |progress_callback| {
+                blend_sources::<T>(&tiles, context, Some(progress_callback))
+            })?;
+        }
+
+        manifest.complete(PreprocessStage::Blending, &[], context);
+        manifest.save(context)?;
+    }
+
+    if !manifest.is_complete(PreprocessStage::Downsampling) {
+        let input_tiles = match &context.dirty_tiles {
+            Some(dirty_tiles) => dirty_tiles.iter().copied().collect_vec(),
+            None => manifest.tiles.iter().copied().collect_vec(),
+        };
+
+        let tiles = progress.run_stage("Downsampling", |progress_callback| {
+            downsample_and_stitch::<T>(&input_tiles, context, Some(progress_callback))
+        })?;
+
+        manifest.complete(PreprocessStage::Downsampling, &tiles, context);
+        manifest.save(context)?;
+    }
+
+    if !manifest.is_complete(PreprocessStage::Filling) {
+        // the neighbours of rebuilt tiles have been stitched again, so they are refilled as well
+        let tiles = with_neighbours(&manifest.pending_tiles(), context);
+
+        progress.run_stage("Filling", |progress_callback| {
+            create_mask_and_fill_no_data(&tiles, context, Some(progress_callback))
+        })?;
+
+        manifest.complete(PreprocessStage::Filling, &tiles, context);
+        manifest.save(context)?;
+    }
+
+    Ok(())
+}
+
+/// Merges the sources into the tiles of an existing earth.
+/// Only the tiles covered by the sources, their ancestors and their neighbours are touched.
+fn update_gen<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
+    src_dataset: Dataset,
+    context: &mut PreprocessContext,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    let config = EarthConfig::load_file(context.earth_path.join(CONFIG_FILE))
+        .map_err(|_| PreprocessError::MissingEarth(context.earth_path.clone()))?;
+
+    // the sources are merged into the raw tiles, which still contain the invalid pixels
+    if !context.raw_dir.is_dir() {
+        return Err(PreprocessError::MissingRawTiles(context.attachment_label.clone()));
+    }
+
+    let mut manifest = TileManifest::load(context);
+
+    // the updated tiles have to fit into the existing tile pyramid
+    let lod_count = manifest.lod_count.unwrap_or(config.lod_count);
+    context.lod_count = Some(lod_count);
+
+    let sources = context
+        .sources
+        .iter()
+        .map(SourceRecord::new)
+        .collect::<PreprocessResult<Vec<_>>>()?;
+
+    let dirty_tiles = sources
+        .iter()
+        .flat_map(|source| &source.footprints)
+        .flat_map(|footprint| footprint.tiles(lod_count - 1))
+        .collect::<HashSet<_>>();
+
+    if dirty_tiles.is_empty() {
+        progress.warning("the sources do not overlap any tiles of the earth");
+    }
+
+    // the covered tiles are rebuilt like the ones of changed sources, but without deleting them,
+    // so that the sources are merged into their existing pixels
+    manifest.dirty_tiles = dirty_tiles.clone();
+    manifest.completed_stages.clear();
+    context.dirty_tiles = Some(dirty_tiles);
+
+    let start_preprocessing = Instant::now();
+
+    run_stages::<T>(context, &mut manifest, progress, |context, _| {
+        clear_directory(&context.temp_dir)?;
+
+        let faces = progress.run_stage("Reprojecting", |progress_callback| {
+            reproject::<T>(src_dataset, context, Some(progress_callback))
+        })?;
+
+        progress.run_stage("Splitting", |progress_callback| {
+            split_and_stitch::<T>(faces, context, Some(progress_callback))
+        })
+    })?;
+
+    delete_directory(&context.temp_dir)?;
+
+    // a later run, that does not contain the merged sources, rebuilds the tiles they cover
+    for source in sources {
+        manifest.sources.retain(|record| record.path != source.path);
+        manifest.sources.push(source);
+    }
+
+    manifest.min_height = manifest.min_height.min(context.min_height);
+    manifest.max_height = manifest.max_height.max(context.max_height);
+    manifest.save(context)?;
+
+    save_earth_config(manifest.tiles.into_iter().collect(), context)?;
+
+    progress.message(&format!("Updating took: {:?}", start_preprocessing.elapsed()));
+
+    Ok(())
+}
+
+/// Preprocesses the sources into the attachment of the earth described by the context.
+/// The progress is reported to the sink of `progress`, which can also cancel the run.
+pub fn preprocess(
+    src_dataset: Dataset,
+    context: &mut PreprocessContext,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    // updates merge into the raw tiles, which packed attachments do not keep
+    if !context.update {
+        unpack_for_processing(context)?;
+    }
+
     macro_rules! preprocess_gen {
         ($data_type:ty) => {
-            preprocess_gen::<$data_type>(src_dataset, context)
+            if context.update {
+                update_gen::<$data_type>(src_dataset, context, progress)
+            } else if context.streaming {
+                preprocess_streaming_gen::<$data_type>(src_dataset, context, progress)
+            } else {
+                preprocess_gen::<$data_type>(src_dataset, context, progress)
+            }
         };
     }
 
     match context.data_type {
-        GdalDataType::Unknown => panic!("Unknown data type!"),
+        GdalDataType::Unknown => Err(PreprocessError::UnknownRasterbandDataType),
         GdalDataType::UInt8 => preprocess_gen!(u8),
         GdalDataType::UInt16 => preprocess_gen!(u16),
         GdalDataType::UInt32 => preprocess_gen!(u32),
@@ -110,48 +328,70 @@ pub fn preprocess(src_dataset: Dataset, context: &mut PreprocessContext) {
         GdalDataType::Int64 => preprocess_gen!(i64),
         GdalDataType::Float32 => preprocess_gen!(f32),
         GdalDataType::Float64 => preprocess_gen!(f64),
-    };
+    }?;
+
+    pack_after_processing(context)
 }
 
-/// Streaming version that processes tiles directly without creating full face images.
-/// This uses significantly less memory and allows better parallelization.
-pub fn preprocess_streaming(src_dataset: Dataset, context: &mut PreprocessContext) {
-    macro_rules! preprocess_streaming_gen {
-        ($data_type:ty) => {
-            preprocess_streaming_gen::<$data_type>(src_dataset, context)
-        };
+/// Preprocesses the sources like [`preprocess`], but reprojects them directly into tiles,
+/// without creating whole face images first. This bounds the memory and temporary disk space,
+/// while producing the same tiles.
+pub fn preprocess_streaming(
+    src_dataset: Dataset,
+    context: &mut PreprocessContext,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    context.streaming = true;
+
+    preprocess(src_dataset, context, progress)
+}
+
+/// The stages work on loose tiles, so a packed attachment is unpacked first.
+fn unpack_for_processing(context: &PreprocessContext) -> PreprocessResult<()> {
+    if is_packed(&context.earth_path, &context.attachment_label) {
+        unpack_attachment(&context.earth_path, &context.attachment_label)?;
     }
 
-    match context.data_type {
-        GdalDataType::Unknown => panic!("Unknown data type!"),
-        GdalDataType::UInt8 => preprocess_streaming_gen!(u8),
-        GdalDataType::UInt16 => preprocess_streaming_gen!(u16),
-        GdalDataType::UInt32 => preprocess_streaming_gen!(u32),
-        GdalDataType::UInt64 => preprocess_streaming_gen!(u64),
-        GdalDataType::Int8 => preprocess_streaming_gen!(i8),
-        GdalDataType::Int16 => preprocess_streaming_gen!(i16),
-        GdalDataType::Int32 => preprocess_streaming_gen!(i32),
-        GdalDataType::Int64 => preprocess_streaming_gen!(i64),
-        GdalDataType::Float32 => preprocess_streaming_gen!(f32),
-        GdalDataType::Float64 => preprocess_streaming_gen!(f64),
-    };
+    Ok(())
 }
 
-fn save_earth_config(tiles: Vec<TileCoordinate>, context: &PreprocessContext) {
-    let file_path = context.earth_path.join("config.tc.ron");
+fn pack_after_processing(context: &PreprocessContext) -> PreprocessResult<()> {
+    if context.pack {
+        pack_attachment(&context.earth_path, &context.attachment_label)?;
+    }
+
+    Ok(())
+}
+
+fn save_earth_config(tiles: Vec<TileCoordinate>, context: &PreprocessContext) -> PreprocessResult<()> {
+    let file_path = context.earth_path.join(CONFIG_FILE);
 
     let mut config = EarthConfig::load_file(&file_path).unwrap_or_default();
 
-    config.shape = EarthShape::WGS84;
-    config.path = context.earth_path.to_str().unwrap().to_string();
+    config.shape = EARTH_SHAPE;
+    config.path = context.earth_path.to_string_lossy().to_string();
     config.add_attachment(context.attachment_label.clone(), context.attachment.clone());
 
     if context.attachment_label == AttachmentLabel::Topography {
-        config.min_height = context.min_height;
-        config.max_height = context.max_height;
-        config.tiles = tiles;
-        config.lod_count = context.lod_count.unwrap();
+        if context.min_height > context.max_height {
+            return Err(PreprocessError::NoValidHeights);
+        }
+
+        if context.update {
+            // keep all tiles and heights outside of the updated region
+            config.min_height = config.min_height.min(context.min_height);
+            config.max_height = config.max_height.max(context.max_height);
+            config.tiles = config.tiles.into_iter().chain(tiles).unique().collect();
+        } else {
+            config.min_height = context.min_height;
+            config.max_height = context.max_height;
+            config.tiles = tiles;
+        }
+
+        config.lod_count = context.lod_count.ok_or(PreprocessError::MissingLodCount)?;
     }
 
-    config.save_file(&file_path).unwrap();
+    config
+        .save_file(&file_path)
+        .map_err(|error| PreprocessError::Config(error.to_string()))
 }
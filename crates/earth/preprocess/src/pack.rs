@@ -0,0 +1,278 @@
+use crate::{
+    CONFIG_FILE,
+    core::{PreprocessError, PreprocessResult, clear_directory, delete_directory},
+    progress::Progress,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use waw_earth_render::prelude::*;
+
+pub(crate) fn load_config(earth_path: &Path) -> PreprocessResult<EarthConfig> {
+    EarthConfig::load_file(earth_path.join(CONFIG_FILE))
+        .map_err(|_| PreprocessError::MissingEarth(earth_path.to_path_buf()))
+}
+
+pub(crate) fn save_config(config: &EarthConfig, earth_path: &Path) -> PreprocessResult<()> {
+    config
+        .save_file(earth_path.join(CONFIG_FILE))
+        .map_err(|error| PreprocessError::Config(error.to_string()))
+}
+
+/// Whether the attachment of the earth is stored in a [`TileArchive`].
+pub(crate) fn is_packed(earth_path: &Path, label: &AttachmentLabel) -> bool {
+    load_config(earth_path).is_ok_and(|config| {
+        config
+            .attachments
+            .get(label)
+            .is_some_and(|attachment| attachment.packed)
+    })
+}
+
+/// Packs the loose tiles of the attachment into a [`TileArchive`] and removes them afterwards.
+/// Tiles that are listed in the config, but do not exist, are skipped.
+/// Attachments with time slices get one archive per slice.
+pub fn pack_attachment(earth_path: &Path, label: &AttachmentLabel) -> PreprocessResult<()> {
+    let mut config = load_config(earth_path)?;
+
+    let attachment = config
+        .attachments
+        .get_mut(label)
+        .ok_or_else(|| PreprocessError::MissingAttachment(label.clone()))?;
+
+    if attachment.packed {
+        return Ok(());
+    }
+
+    // every time slice has an archive of its own
+    let tile_labels = attachment.tile_labels(label);
+
+    for tile_label in &tile_labels {
+        let tile_dir = earth_path.join(String::from(tile_label));
+        let archive_path = TileArchive::attachment_path(earth_path, tile_label);
+
+        // an interrupted run must not leave a partial archive behind
+        let partial_path = archive_path.with_extension("tiles.partial");
+        let mut writer = TileArchiveWriter::create(&partial_path)?;
+
+        for &tile in &config.tiles {
+            let tile_path = tile.path(&tile_dir);
+
+            if tile_path.is_file() {
+                writer.add_tile(tile, &fs::read(tile_path)?)?;
+            }
+        }
+
+        writer.finish()?;
+        fs::rename(partial_path, archive_path)?;
+    }
+
+    attachment.packed = true;
+    save_config(&config, earth_path)?;
+
+    for tile_label in &tile_labels {
+        delete_directory(&earth_path.join(String::from(tile_label)))?;
+    }
+
+    Ok(())
+}
+
+/// Extracts the tiles of the attachment from its [`TileArchive`] into the loose layout
+/// and removes the archive afterwards.
+pub fn unpack_attachment(earth_path: &Path, label: &AttachmentLabel) -> PreprocessResult<()> {
+    let mut config = load_config(earth_path)?;
+
+    let attachment = config
+        .attachments
+        .get_mut(label)
+        .ok_or_else(|| PreprocessError::MissingAttachment(label.clone()))?;
+
+    if !attachment.packed {
+        return Ok(());
+    }
+
+    let tile_labels = attachment.tile_labels(label);
+
+    // time slices, that have not been processed yet, have no archive
+    let tile_labels = tile_labels
+        .into_iter()
+        .filter(|tile_label| TileArchive::attachment_path(earth_path, tile_label).is_file())
+        .collect::<Vec<_>>();
+
+    for tile_label in &tile_labels {
+        let tile_dir = earth_path.join(String::from(tile_label));
+        let archive = TileArchive::open(&TileArchive::attachment_path(earth_path, tile_label))?;
+
+        for tile in archive.tiles() {
+            let tile_path = tile.path(&tile_dir);
+            if let Some(parent) = tile_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if let Some(bytes) = archive.read_tile(tile)? {
+                fs::write(tile_path, bytes)?;
+            }
+        }
+    }
+
+    attachment.packed = false;
+    save_config(&config, earth_path)?;
+
+    for tile_label in &tile_labels {
+        fs::remove_file(TileArchive::attachment_path(earth_path, tile_label))?;
+    }
+
+    Ok(())
+}
+
+/// Removes all tiles of the attachment, loose or packed, and returns its empty tile directory.
+pub(crate) fn clear_attachment(
+    earth_path: &Path,
+    label: &AttachmentLabel,
+) -> PreprocessResult<PathBuf> {
+    let tile_dir = earth_path.join(String::from(label));
+    clear_directory(&tile_dir)?;
+
+    let archive_path = TileArchive::attachment_path(earth_path, label);
+
+    if archive_path.is_file() {
+        fs::remove_file(archive_path)?;
+    }
+
+    Ok(tile_dir)
+}
+
+/// Packs or unpacks the attachment, or all attachments of the earth if none is given.
+pub fn convert_earth(
+    earth_path: &Path,
+    label: Option<&AttachmentLabel>,
+    unpack: bool,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    let labels = match label {
+        Some(label) => vec![label.clone()],
+        None => load_config(earth_path)?
+            .attachments
+            .keys()
+            .cloned()
+            .collect(),
+    };
+
+    for label in &labels {
+        progress.check_cancelled()?;
+        progress.message(&format!("Converting: {label:?}"));
+
+        if unpack {
+            unpack_attachment(earth_path, label)?;
+        } else {
+            pack_attachment(earth_path, label)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Locates the tiles of an attachment, either as loose files or inside of its archive.
+pub(crate) enum TileFiles {
+    Loose(PathBuf),
+    Packed(TileArchive),
+}
+
+impl TileFiles {
+    pub(crate) fn new(
+        earth_path: &Path,
+        label: &AttachmentLabel,
+        attachment: &AttachmentConfig,
+    ) -> PreprocessResult<Self> {
+        Ok(if attachment.packed {
+            Self::Packed(TileArchive::open(&TileArchive::attachment_path(earth_path, label))?)
+        } else {
+            Self::Loose(earth_path.join(String::from(label)))
+        })
+    }
+
+    /// The path GDAL can open the tile with, or `None` if it does not exist.
+    pub(crate) fn path(&self, tile: TileCoordinate) -> Option<PathBuf> {
+        match self {
+            Self::Loose(tile_dir) => Some(tile.path(tile_dir)).filter(|path| path.is_file()),
+            Self::Packed(archive) => archive.entry(tile).map(|entry| {
+                PathBuf::from(format!(
+                    "/vsisubfile/{}_{},{}",
+                    entry.offset,
+                    entry.length,
+                    archive.path().display()
+                ))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use glam::IVec2;
+    use std::process;
+
+    #[test]
+    fn packed_tiles_can_be_unpacked() {
+        let earth_path = std::env::temp_dir().join(format!("waw_pack_{}", process::id()));
+        let label = AttachmentLabel::Topography;
+        let tile_dir = earth_path.join(String::from(&label));
+        let archive_path = TileArchive::attachment_path(&earth_path, &label);
+
+        // the last tile is listed in the config, but has no file
+        let tiles = [
+            TileCoordinate::new(0, 0, IVec2::ZERO),
+            TileCoordinate::new(1, 2, IVec2::new(3, 1)),
+            TileCoordinate::new(4, 2, IVec2::ONE),
+        ];
+        let contents = [vec![1; 10], vec![2; 11]];
+
+        let mut config = EarthConfig {
+            tiles: tiles.to_vec(),
+            ..Default::default()
+        };
+        config.add_attachment(label.clone(), AttachmentConfig::default());
+
+        clear_directory(&earth_path).unwrap();
+        save_config(&config, &earth_path).unwrap();
+
+        for (tile, bytes) in tiles.iter().zip(&contents) {
+            let tile_path = tile.path(&tile_dir);
+            fs::create_dir_all(tile_path.parent().unwrap()).unwrap();
+            fs::write(tile_path, bytes).unwrap();
+        }
+
+        pack_attachment(&earth_path, &label).unwrap();
+
+        assert!(is_packed(&earth_path, &label));
+        assert!(!tile_dir.exists());
+
+        // GDAL reads the blobs, which follow the twelve byte header, through their offsets
+        let config = load_config(&earth_path).unwrap();
+        let files = TileFiles::new(&earth_path, &label, &config.attachments[&label]).unwrap();
+        let subfile = |offset, length| {
+            Some(PathBuf::from(format!(
+                "/vsisubfile/{offset}_{length},{}",
+                archive_path.display()
+            )))
+        };
+
+        assert_eq!(files.path(tiles[0]), subfile(12, 10));
+        assert_eq!(files.path(tiles[1]), subfile(22, 11));
+        assert_eq!(files.path(tiles[2]), None);
+
+        unpack_attachment(&earth_path, &label).unwrap();
+
+        assert!(!is_packed(&earth_path, &label));
+        assert!(!archive_path.exists());
+
+        for (tile, bytes) in tiles.iter().zip(&contents) {
+            assert_eq!(&fs::read(tile.path(&tile_dir)).unwrap(), bytes);
+        }
+        assert!(!tiles[2].path(&tile_dir).exists());
+
+        let _ = fs::remove_dir_all(&earth_path);
+    }
+}
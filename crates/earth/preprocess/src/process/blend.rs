@@ -0,0 +1,206 @@
+use super::{
+    reproject::warp_region,
+    stitch::{add_neighbours, stitch},
+};
+use crate::core::{
+    CountingProgressCallback, Footprint, NativeWarp, PreprocessContext, PreprocessError,
+    PreprocessResult, ProgressCallback, SharedReadOnlyDataset, WarpBackend, update_tile_dataset,
+    valid_pixels,
+};
+use gdal::{
+    Dataset,
+    raster::{Buffer, GdalType},
+};
+use glam::IVec2;
+use itertools::Itertools;
+use num::NumCast;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use waw_earth_render::math::TileCoordinate;
+
+/// Blends the sources of different priorities into all tiles where they overlap.
+///
+/// The valid pixels of a source override the ones of all sources with a lower priority,
+/// but fade in over the blend width, starting at the edge of their valid region.
+pub fn blend_sources<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
+    tiles: &[TileCoordinate],
+    context: &PreprocessContext,
+    progress_callback: Option<&ProgressCallback>,
+) -> PreprocessResult<Vec<TileCoordinate>> {
+    let lod = context.max_lod()?;
+
+    let mut overlaps = HashMap::<TileCoordinate, usize>::new();
+
+    for path in &context.blend_layers {
+        let footprints = Footprint::from_dataset(&Dataset::open(path)?)?;
+
+        // the parts of a footprint do not overlap each other
+        for tile in footprints.iter().flat_map(|footprint| footprint.tiles(lod)).unique() {
+            *overlaps.entry(tile).or_default() += 1;
+        }
+    }
+
+    let blend_tiles = tiles
+        .iter()
+        .filter(|tile| overlaps.get(tile).is_some_and(|&count| count > 1))
+        .copied()
+        .collect_vec();
+
+    // the borders of the blended tiles and their neighbours are outdated
+    let stitch_tiles = add_neighbours(&blend_tiles, context);
+
+    let count = (blend_tiles.len() + stitch_tiles.len()) as u64;
+    let progress_callback = CountingProgressCallback::new(count, progress_callback);
+
+    let layers = context
+        .blend_layers
+        .iter()
+        .map(|path| SharedReadOnlyDataset::new(path))
+        .collect_vec();
+
+    // the native warp only depends on the layer, so it is shared by all tiles
+    let native_warps = context
+        .blend_layers
+        .iter()
+        .map(|path| -> PreprocessResult<Option<NativeWarp>> {
+            match context.warp_backend {
+                WarpBackend::Gdal => Ok(None),
+                WarpBackend::Native => Ok(Some(NativeWarp::new(&Dataset::open(path)?, context)?)),
+            }
+        })
+        .collect::<PreprocessResult<Vec<_>>>()?;
+
+    blend_tiles.par_iter().try_for_each(|&tile| {
+        blend_tile::<T>(tile, &layers, &native_warps, context)?;
+
+        progress_callback.increment()?;
+
+        Ok::<(), PreprocessError>(())
+    })?;
+
+    stitch::<T>(&stitch_tiles, context, &progress_callback)?;
+
+    Ok(blend_tiles)
+}
+
+fn blend_tile<T: Copy + GdalType + PartialEq + NumCast>(
+    tile: TileCoordinate,
+    layers: &[SharedReadOnlyDataset],
+    native_warps: &[Option<NativeWarp>],
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
+    let texture_size = context.attachment.texture_size as usize;
+    let margin = context.blend_width.ceil() as usize;
+    let size = texture_size + 2 * margin;
+
+    // the window also covers the margin around the tile, so that the distances are correct
+    let pixel_start = tile.xy * context.attachment.center_size() as i32
+        - (context.attachment.border_size as usize + margin) as i32;
+
+    let warp_layer = |index: usize| {
+        let buffers = warp_region::<T>(
+            layers[index].get(),
+            native_warps[index].as_ref(),
+            tile.face,
+            pixel_start,
+            IVec2::splat(size as i32),
+            None,
+            context,
+        )?;
+        let valid = valid_pixels(&buffers, context)?;
+        let values = buffers
+            .iter()
+            .map(|buffer| {
+                buffer
+                    .data()
+                    .iter()
+                    .map(|value| value.to_f64().ok_or(PreprocessError::TransformOperationFailed))
+                    .collect::<PreprocessResult<Vec<_>>>()
+            })
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        Ok::<_, PreprocessError>((values, valid))
+    };
+
+    // the layers are sorted by ascending priority
+    let (mut blended_values, mut blended_valid) = warp_layer(0)?;
+
+    for index in 1..layers.len() {
+        let (values, valid) = warp_layer(index)?;
+        let distances = distance_to_invalid(&valid, size);
+
+        for (index, &distance) in distances.iter().enumerate() {
+            let weight = if !valid[index] {
+                0.0
+            } else if !blended_valid[index] {
+                1.0
+            } else {
+                (distance as f64 / context.blend_width as f64).min(1.0)
+            };
+
+            if weight > 0.0 {
+                for (blended_band, band) in blended_values.iter_mut().zip(&values) {
+                    blended_band[index] += weight * (band[index] - blended_band[index]);
+                }
+
+                blended_valid[index] = true;
+            }
+        }
+    }
+
+    let tile_dataset = update_tile_dataset(tile, context)?;
+
+    for (band_index, band) in blended_values.iter().enumerate() {
+        let data = (margin..margin + texture_size)
+            .flat_map(|y| &band[y * size + margin..y * size + margin + texture_size])
+            .map(|&value| T::from(value).ok_or(PreprocessError::TransformOperationFailed))
+            .collect::<PreprocessResult<Vec<_>>>()?;
+
+        let mut buffer = Buffer::new((texture_size, texture_size), data);
+
+        tile_dataset.rasterband(band_index + 1)?.write::<T>(
+            (0, 0),
+            (texture_size, texture_size),
+            &mut buffer,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Approximates the distance of each valid pixel to the closest invalid one, using a chamfer
+/// distance transform. Pixels outside of the window are considered valid.
+fn distance_to_invalid(valid: &[bool], size: usize) -> Vec<f32> {
+    const DIAGONAL: f32 = std::f32::consts::SQRT_2;
+
+    let mut distances = valid
+        .iter()
+        .map(|&valid| if valid { f32::MAX } else { 0.0 })
+        .collect_vec();
+
+    let mut relax = |x: usize, y: usize, offsets: [(isize, isize, f32); 4]| {
+        for (dx, dy, cost) in offsets {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+
+            if (0..size as isize).contains(&nx) && (0..size as isize).contains(&ny) {
+                let neighbour = distances[ny as usize * size + nx as usize];
+                let distance = &mut distances[y * size + x];
+                *distance = distance.min(neighbour + cost);
+            }
+        }
+    };
+
+    for y in 0..size {
+        for x in 0..size {
+            relax(x, y, [(-1, 0, 1.0), (-1, -1, DIAGONAL), (0, -1, 1.0), (1, -1, DIAGONAL)]);
+        }
+    }
+
+    for y in (0..size).rev() {
+        for x in (0..size).rev() {
+            relax(x, y, [(1, 0, 1.0), (1, 1, DIAGONAL), (0, 1, 1.0), (-1, 1, DIAGONAL)]);
+        }
+    }
+
+    distances
+}
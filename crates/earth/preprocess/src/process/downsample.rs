@@ -1,9 +1,9 @@
-use super::stitch::stitch;
+use super::stitch::{stitch, with_neighbours};
 use crate::core::{
     CountingProgressCallback, PreprocessContext, PreprocessError, PreprocessResult,
-    ProgressCallback, create_tile_dataset, load_tile_dataset_if_exists,
+    ProgressCallback, create_tile_dataset, load_tile_dataset_if_exists, raster_io_resample_alg,
 };
-use gdal::raster::{Buffer, GdalType, RasterBand, ResampleAlg};
+use gdal::raster::{Buffer, GdalType, RasterBand};
 use glam::IVec2;
 use itertools::{Itertools, izip};
 use num::NumCast;
@@ -21,12 +21,22 @@ pub fn downsample_and_stitch<T: Copy + GdalType + PartialEq + NumCast>(
     let mut output_tiles = input_tiles.iter().copied().collect_vec();
     output_tiles.extend(tiles_to_downsample.iter().flatten());
 
-    let count = 2 * tiles_to_downsample.iter().map(Vec::len).sum::<usize>() as u64;
+    // the neighbours of rebuilt tiles are not downsampled again, but still have to be stitched
+    let tiles_to_stitch = tiles_to_downsample
+        .iter()
+        .map(|tiles| with_neighbours(tiles, context))
+        .collect_vec();
+
+    let count = tiles_to_downsample
+        .iter()
+        .chain(&tiles_to_stitch)
+        .map(Vec::len)
+        .sum::<usize>() as u64;
     let progress_callback = CountingProgressCallback::new(count, progress_callback);
 
-    for tiles in tiles_to_downsample {
-        downsample::<T>(&tiles, context, &progress_callback)?;
-        stitch::<T>(&tiles, context, &progress_callback)?;
+    for (tiles, stitch_tiles) in tiles_to_downsample.iter().zip(&tiles_to_stitch) {
+        downsample::<T>(tiles, context, &progress_callback)?;
+        stitch::<T>(stitch_tiles, context, &progress_callback)?;
     }
 
     Ok(output_tiles)
@@ -47,6 +57,7 @@ fn downsample<T: Copy + GdalType + PartialEq + NumCast>(
         context.attachment.center_size() as usize,
     );
     let child_size = (child_center_size as usize, child_center_size as usize);
+    let resample_alg = raster_io_resample_alg(context.attachment.downsample_resampling);
 
     input_tiles.par_iter().try_for_each(|&tile_coordinate| {
         let tile_dataset = create_tile_dataset::<T>(tile_coordinate, context)?;
@@ -70,13 +81,16 @@ fn downsample<T: Copy + GdalType + PartialEq + NumCast>(
                     &mut tile_buffers
                 ) {
                     let child_raster = child_raster?;
-                    let no_data_value = tile_raster.no_data_value().map(|v| T::from(v).unwrap());
+                    let no_data_value = tile_raster
+                        .no_data_value()
+                        .map(|v| T::from(v).ok_or(PreprocessError::NoDataOutOfRange))
+                        .transpose()?;
 
                     let child_buffer = child_raster.read_as::<T>(
                         border_offset,
                         tile_size,
                         child_size,
-                        Some(ResampleAlg::Bilinear),
+                        Some(resample_alg),
                     )?;
 
                     for ((child_y, child_x), &child_value) in
@@ -97,7 +111,7 @@ fn downsample<T: Copy + GdalType + PartialEq + NumCast>(
             tile_raster.write::<T>(border_offset, tile_size, tile_buffer)?;
         }
 
-        progress_callback.increment();
+        progress_callback.increment()?;
 
         Ok::<(), PreprocessError>(())
     })
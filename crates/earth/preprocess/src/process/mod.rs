@@ -1,9 +1,11 @@
+mod blend;
 mod downsample;
 mod reproject;
 mod split;
 mod stitch;
 
+pub use blend::*;
 pub use downsample::*;
-pub use reproject::{reproject, reproject_to_tiles};
+pub use reproject::{face_infos, reproject, reproject_to_tiles};
 pub use split::*;
 pub use stitch::*;
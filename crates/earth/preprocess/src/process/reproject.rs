@@ -1,19 +1,28 @@
 use crate::core::CustomTransformer;
 use crate::core::{
-    CountingProgressCallback, FaceInfo, GDALCustomTransformer, PreprocessContext, PreprocessError,
-    PreprocessResult, ProgressCallback, SuggestedWarpOutput, create_empty_dataset,
-    create_tile_dataset, warp,
+    CountingProgressCallback, FaceInfo, GDALCustomTransformer, NativeWarp, PreprocessContext,
+    PreprocessError, PreprocessResult, ProgressCallback, SharedReadOnlyDataset,
+    SuggestedWarpOutput, Transformer, WARP_MEMORY_LIMIT, WarpBackend, create_empty_dataset,
+    create_memory_dataset, create_tile_dataset, extend_height_range, load_tile_dataset_if_exists,
+    native_warp, warp,
 };
+use crate::process::{stitch, with_neighbours};
 use gdal::{Dataset, GeoTransform, GeoTransformEx, Metadata, raster::{Buffer, GdalType}};
+use gdal_sys::{GDALGetCacheMax64, GDALSetCacheMax64};
 use glam::{DVec2, IVec2, U64Vec2};
 use itertools::{Itertools, iproduct};
 use num::NumCast;
-use rayon::prelude::*;
-use std::collections::HashMap;
-use waw_earth_render::{math::TileCoordinate, prelude::AttachmentLabel};
+use rayon::{ThreadPoolBuilder, prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use waw_earth_render::math::TileCoordinate;
 
 pub struct Transform<'a> {
-    pub transformer: GDALCustomTransformer,
+    /// The transformer of the GDAL warp, the native warp does not need one.
+    pub transformer: Option<GDALCustomTransformer>,
     pub face: u32,
     pub lod: u32,
     pub size: U64Vec2,
@@ -22,10 +31,12 @@ pub struct Transform<'a> {
     pub uv_end: DVec2,
     pub pixel_start: IVec2,
     pub pixel_end: IVec2,
+    /// The resampling factor of the GDAL warp, which is the same for every part of the face.
+    pub scale: Option<DVec2>,
     pub progress_callback: Option<Box<ProgressCallback<'a>>>,
 }
 
-pub fn reproject<T: Copy + GdalType>(
+pub fn reproject<T: Copy + GdalType + NumCast>(
     src_dataset: Dataset,
     context: &mut PreprocessContext,
     progress_callback: Option<&ProgressCallback>,
@@ -47,25 +58,26 @@ pub fn reproject<T: Copy + GdalType>(
                 context,
             )?;
 
-            warp(
-                &src_dataset,
-                &dst_dataset,
-                context,
-                &mut transform.transformer,
-                transform.progress_callback.as_deref(),
-            )?;
-
-            if matches!(context.attachment_label, AttachmentLabel::Topography) {
-                let min_max = dst_dataset
-                    .rasterband(1)
-                    .unwrap()
-                    .compute_raster_min_max(true)
-                    .unwrap();
-
-                context.min_height = context.min_height.min(min_max.min as f32);
-                context.max_height = context.max_height.max(min_max.max as f32);
+            match &mut transform.transformer {
+                Some(transformer) => warp(
+                    &src_dataset,
+                    &dst_dataset,
+                    context,
+                    transformer,
+                    transform.scale,
+                    transform.progress_callback.as_deref(),
+                )?,
+                None => native_warp::<T>(
+                    &src_dataset,
+                    &dst_dataset,
+                    transform.face,
+                    context,
+                    transform.progress_callback.as_deref(),
+                )?,
             }
 
+            extend_height_range(&dst_dataset, context)?;
+
             Ok((
                 transform.face,
                 FaceInfo {
@@ -81,186 +93,461 @@ pub fn reproject<T: Copy + GdalType>(
     Ok(faces)
 }
 
-/// Reproject directly to tiles without creating intermediate face images
+/// Computes the face infos of a previous reprojection, without warping the source again.
+pub fn face_infos(
+    src_dataset: &Dataset,
+    context: &mut PreprocessContext,
+) -> PreprocessResult<HashMap<u32, FaceInfo>> {
+    let transforms = compute_transforms(src_dataset, context, None)?;
+
+    Ok(transforms
+        .into_iter()
+        .map(|transform| {
+            (
+                transform.face,
+                FaceInfo {
+                    lod: transform.lod,
+                    pixel_start: transform.pixel_start,
+                    pixel_end: transform.pixel_end,
+                    path: context.temp_dir.join(format!("face{}.tif", transform.face)),
+                },
+            )
+        })
+        .collect())
+}
+
+/// The number of tiles per thread, that are reprojected before the finished ones are stitched.
+const STREAMING_TILES_PER_THREAD: usize = 4;
+
+/// Reprojects the source directly into the tiles of the highest lod, without creating
+/// intermediate face images.
+///
+/// The tiles are reprojected row by row and every tile is stitched, as soon as all of its
+/// neighbours are reprojected, so the result matches the one of [`split_and_stitch`].
+/// The memory budget of the context bounds the tiles in flight and the block cache of GDAL.
+///
+/// [`split_and_stitch`]: crate::process::split_and_stitch
 pub fn reproject_to_tiles<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
     src_dataset: Dataset,
     context: &mut PreprocessContext,
     progress_callback: Option<&ProgressCallback>,
 ) -> PreprocessResult<Vec<TileCoordinate>> {
-    use crate::core::SharedReadOnlyDataset;
-    use std::sync::Arc;
-    use std::path::PathBuf;
+    // the native warp only depends on the source, so it is shared by all tiles
+    let native_warp = match context.warp_backend {
+        WarpBackend::Gdal => None,
+        WarpBackend::Native => Some(NativeWarp::new(&src_dataset, context)?),
+    };
+
+    let budget =
+        StreamingBudget::new::<T>(context, src_dataset.raster_count(), native_warp.as_ref())?;
+
+    stream_source::<T>(
+        src_dataset,
+        native_warp.as_ref(),
+        &budget,
+        context,
+        progress_callback,
+    )
+}
 
+fn stream_source<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
+    src_dataset: Dataset,
+    native_warp: Option<&NativeWarp>,
+    budget: &StreamingBudget,
+    context: &mut PreprocessContext,
+    progress_callback: Option<&ProgressCallback>,
+) -> PreprocessResult<Vec<TileCoordinate>> {
     if let Some(progress_callback) = progress_callback {
         progress_callback(0.0);
     }
 
     let transforms = compute_transforms(&src_dataset, context, None)?;
 
-    // Get the source path from the dataset description (file path)
-    let src_path_str = src_dataset.description()?;
-    let src_path = PathBuf::from(src_path_str);
-
+    // every thread opens the source on its own
+    let src_path = PathBuf::from(src_dataset.description()?);
     drop(src_dataset);
 
-    let shared_src = Arc::new(SharedReadOnlyDataset::new(&src_path));
-
-    // Compute all tiles that need to be generated
-    let mut input_tiles = Vec::new();
-    let mut face_infos = HashMap::new();
-
-    for transform in &transforms {
-        let xy_start = transform.pixel_start / context.attachment.center_size() as i32;
-        let xy_end = (transform.pixel_end - 1) / context.attachment.center_size() as i32 + 1;
-
-        face_infos.insert(
-            transform.face,
-            FaceInfo {
+    let faces = transforms
+        .iter()
+        .map(|transform| {
+            let face_info = FaceInfo {
                 lod: transform.lod,
                 pixel_start: transform.pixel_start,
                 pixel_end: transform.pixel_end,
-                path: Default::default(), // Not needed for tile-based approach
-            },
-        );
+                path: PathBuf::new(), // there is no face image
+            };
+
+            (transform.face, (face_info, transform.scale))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let previous_cache_size = budget.cache_size.map(|cache_size| unsafe {
+        let previous_cache_size = GDALGetCacheMax64();
+        GDALSetCacheMax64(cache_size);
+        previous_cache_size
+    });
+
+    let output_tiles = stream_tiles::<T>(
+        &faces,
+        &src_path,
+        native_warp,
+        budget,
+        context,
+        progress_callback,
+    );
+
+    if let Some(previous_cache_size) = previous_cache_size {
+        unsafe { GDALSetCacheMax64(previous_cache_size) };
+    }
+
+    let output_tiles = output_tiles?;
+
+    for &tile_coordinate in &output_tiles {
+        if let Some(dataset) = load_tile_dataset_if_exists(tile_coordinate, context)? {
+            extend_height_range(&dataset, context)?;
+        }
+    }
+
+    Ok(output_tiles)
+}
+
+/// How the memory budget of the streaming pipeline is split
+/// between the block cache of GDAL and the buffers of the tiles in flight.
+struct StreamingBudget {
+    threads: usize,
+    /// The number of tiles, that are reprojected or stitched at the same time.
+    tiles_in_flight: usize,
+    /// The size of the block cache in bytes, or `None` to keep the one of GDAL.
+    cache_size: Option<i64>,
+    /// The memory in bytes, that reprojecting a single tile needs.
+    tile_memory: u64,
+    /// The memory in bytes, that stitching a single tile needs.
+    stitch_memory: u64,
+    /// Tracks the memory of the tiles in flight.
+    memory: MemoryTracker,
+}
+
+impl StreamingBudget {
+    fn new<T>(
+        context: &PreprocessContext,
+        band_count: usize,
+        native_warp: Option<&NativeWarp>,
+    ) -> PreprocessResult<Self> {
+        let threads = rayon::current_num_threads();
+
+        let tile_pixels = (context.attachment.texture_size as usize).pow(2);
+        let tile_size = (tile_pixels * band_count * size_of::<T>()) as u64;
+
+        // the warped bands and the working memory of the warp,
+        // which GDAL warps into a dataset in memory first
+        let tile_memory = match native_warp {
+            Some(native_warp) => tile_size + native_warp.working_memory(tile_pixels),
+            None => 2 * tile_size + WARP_MEMORY_LIMIT,
+        };
+        // the borders of a tile and its neighbours, which are smaller than the tile itself
+        let stitch_memory = tile_size;
+
+        let Some(memory_budget) = context.memory_budget else {
+            return Ok(Self {
+                threads,
+                tiles_in_flight: threads * STREAMING_TILES_PER_THREAD,
+                cache_size: None,
+                tile_memory,
+                stitch_memory,
+                memory: MemoryTracker::default(),
+            });
+        };
+
+        // the warps read the source through the block cache, which is shared by all threads
+        let cache_size = memory_budget / 4;
+
+        // nested parallel loops may start more tiles than there are threads,
+        // so the number of tiles in flight is bounded by the size of each batch instead
+        let tiles_in_flight = ((memory_budget - cache_size) / tile_memory) as usize;
+
+        if tiles_in_flight == 0 {
+            return Err(PreprocessError::MemoryBudgetTooSmall {
+                budget: memory_budget,
+                required: cache_size + tile_memory,
+            });
+        }
+
+        Ok(Self {
+            threads: threads.min(tiles_in_flight),
+            tiles_in_flight: tiles_in_flight.min(threads * STREAMING_TILES_PER_THREAD),
+            cache_size: Some(cache_size as i64),
+            tile_memory,
+            stitch_memory,
+            memory: MemoryTracker::default(),
+        })
+    }
+
+    /// The most memory in bytes, that the block cache and the tiles in flight used at once.
+    #[cfg(test)]
+    fn peak_memory(&self) -> u64 {
+        self.cache_size.unwrap_or_default() as u64 + self.memory.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks the memory of the buffers, that are currently in use, and its peak.
+#[derive(Default)]
+struct MemoryTracker {
+    used: AtomicU64,
+    peak: AtomicU64,
+}
+
+impl MemoryTracker {
+    /// Marks the memory as used, until the returned guard is dropped.
+    fn allocate(&self, bytes: u64) -> TrackedMemory<'_> {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+
+        TrackedMemory {
+            tracker: self,
+            bytes,
+        }
+    }
+}
+
+struct TrackedMemory<'a> {
+    tracker: &'a MemoryTracker,
+    bytes: u64,
+}
+
+impl Drop for TrackedMemory<'_> {
+    fn drop(&mut self) {
+        self.tracker.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+fn stream_tiles<T: Copy + GdalType + PartialEq + NumCast + Send + Sync>(
+    faces: &HashMap<u32, (FaceInfo, Option<DVec2>)>,
+    src_path: &Path,
+    native_warp: Option<&NativeWarp>,
+    budget: &StreamingBudget,
+    context: &PreprocessContext,
+    progress_callback: Option<&ProgressCallback>,
+) -> PreprocessResult<Vec<TileCoordinate>> {
+    let mut input_tiles = Vec::new();
+
+    for (&face, (info, _)) in faces {
+        let xy_start = info.pixel_start / context.attachment.center_size() as i32; // round down
+        let xy_end = (info.pixel_end - 1) / context.attachment.center_size() as i32 + 1; // round up
 
         input_tiles.extend(
             iproduct!(xy_start.x..xy_end.x, xy_start.y..xy_end.y)
-                .map(|(x, y)| TileCoordinate::new(transform.face, transform.lod, IVec2::new(x, y))),
+                .map(|(x, y)| TileCoordinate::new(face, info.lod, IVec2::new(x, y)))
+                .filter(|&tile| context.is_dirty(tile)),
         );
     }
 
-    let count = input_tiles.len() as u64;
-    let progress = CountingProgressCallback::new(count, progress_callback);
-
-    // Process tiles in parallel
-    let output_tiles = input_tiles
-        .par_iter()
-        .map(|&tile_coordinate| {
-            let src_dataset = shared_src.get();
-            let face_info = face_infos.get(&tile_coordinate.face).unwrap();
-
-            // Create a new transformer for this tile
-            let tile_pixel_start = tile_coordinate.xy * context.attachment.center_size() as i32;
-            let tile_pixel_end = (tile_coordinate.xy + 1) * context.attachment.center_size() as i32;
-
-            let copy_size =
-                tile_pixel_end.min(face_info.pixel_end) - tile_pixel_start.max(face_info.pixel_start);
-            let tile_offset = (face_info.pixel_start - tile_pixel_start).max(IVec2::ZERO)
-                + context.attachment.border_size as i32;
-
-            // Only process if there's data in this tile region
-            if copy_size.x <= 0 || copy_size.y <= 0 {
-                progress.increment();
-                return Ok::<Option<TileCoordinate>, PreprocessError>(None);
-            }
+    // the tiles are streamed row by row and face by face
+    input_tiles.sort_by_key(|tile| (tile.face, tile.xy.y, tile.xy.x));
 
-            // Compute geo_transform for this specific tile region in global S2 space
-            let max_lod = context.lod_count.unwrap() - 1;
-            let pixel_size = 1.0 / ((1 << max_lod) * context.attachment.center_size()) as f64;
-
-            // The warp region in global pixel coordinates
-            let warp_pixel_start = tile_pixel_start.max(face_info.pixel_start);
-            let warp_pixel_end = tile_pixel_end.min(face_info.pixel_end);
-            let warp_size = warp_pixel_end - warp_pixel_start;
-
-            // Create a temporary dataset for just this warp region
-            // The geo_transform maps the temp dataset's pixels to S2 UV space
-            let warp_geo_transform = GeoTransform::from([
-                warp_pixel_start.x as f64 * pixel_size,  // UV x origin
-                pixel_size,                               // UV x resolution
-                0.0,
-                warp_pixel_start.y as f64 * pixel_size,  // UV y origin
-                0.0,
-                pixel_size,                               // UV y resolution
-            ]);
-
-            let temp_path = std::env::temp_dir().join(format!("temp_tile_{}_{}_{}_{}_{}.tif",
-                std::process::id(), tile_coordinate.face, tile_coordinate.lod,
-                tile_coordinate.xy.x, tile_coordinate.xy.y));
-            let temp_dataset = create_empty_dataset::<T>(
-                &temp_path,
-                U64Vec2::new(warp_size.x as u64, warp_size.y as u64),
-                Some(warp_geo_transform),
-                context,
-            )?;
+    // the neighbours of rebuilt tiles are not reprojected again, but still have to be stitched
+    let reprojected_tiles = input_tiles.iter().copied().collect::<HashSet<_>>();
+    let neighbour_tiles = with_neighbours(&input_tiles, context)
+        .into_iter()
+        .filter(|tile| !reprojected_tiles.contains(tile))
+        .collect_vec();
 
-            // Create transformer for this specific region
-            let mut transformer = CustomTransformer::from_dataset(
-                src_dataset,
-                tile_coordinate.face,
-                Some(warp_geo_transform),
-            )?;
+    let count = (2 * input_tiles.len() + neighbour_tiles.len()) as u64;
+    let progress_callback = CountingProgressCallback::new(count, progress_callback);
 
-            // Warp the source data into the temporary dataset
-            warp(src_dataset, &temp_dataset, context, &mut transformer, None)?;
-
-            // Check if tile has any actual data (not just no-data values)
-            let mut has_data = false;
-            let copy_buffers: Vec<Buffer<T>> = temp_dataset
-                .rasterbands()
-                .map(|raster| {
-                    let raster = raster?;
-                    let buffer = raster.read_as::<T>(
-                        (0, 0),
-                        (warp_size.x as usize, warp_size.y as usize),
-                        (warp_size.x as usize, warp_size.y as usize),
-                        None,
-                    )?;
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(budget.threads)
+        .build()
+        .map_err(|error| PreprocessError::ThreadPool(error.to_string()))?;
+    let shared_src = SharedReadOnlyDataset::new(src_path);
 
-                    let no_data_value = raster
-                        .no_data_value()
-                        .map(|v| T::from(v).ok_or(PreprocessError::NoDataOutOfRange))
-                        .transpose()?;
+    // the tiles are stitched in batches, that fit into the budget as well
+    let stitch_in_flight = |tiles: &[TileCoordinate]| {
+        tiles.chunks(budget.tiles_in_flight).try_for_each(|batch| {
+            let _memory = budget
+                .memory
+                .allocate(batch.len() as u64 * budget.stitch_memory);
 
-                    has_data |= no_data_value.is_none()
-                        || buffer
-                            .data()
-                            .iter()
-                            .any(|&value| value != no_data_value.unwrap());
+            thread_pool.install(|| stitch::<T>(batch, context, &progress_callback))
+        })
+    };
+
+    let mut finished_tiles = HashSet::new();
+    let mut unstitched_tiles = Vec::new();
+    let mut output_tiles = Vec::new();
+
+    for chunk in input_tiles.chunks(budget.tiles_in_flight) {
+        let tiles = thread_pool.install(|| {
+            chunk
+                .par_iter()
+                .map(|&tile_coordinate| {
+                    let (face_info, scale) = &faces[&tile_coordinate.face];
+                    let _memory = budget.memory.allocate(budget.tile_memory);
+
+                    let has_data = reproject_tile::<T>(
+                        shared_src.get(),
+                        native_warp,
+                        tile_coordinate,
+                        face_info,
+                        *scale,
+                        context,
+                    )?;
 
-                    Ok::<Buffer<T>, PreprocessError>(buffer)
+                    progress_callback.increment()?;
+
+                    Ok::<_, PreprocessError>(has_data.then_some(tile_coordinate))
+                })
+                .filter_map(Result::transpose)
+                .collect::<PreprocessResult<Vec<_>>>()
+        })?;
+
+        finished_tiles.extend(chunk);
+        output_tiles.extend(&tiles);
+        unstitched_tiles.extend(tiles);
+
+        // a tile is stitched, once none of its neighbours will be written anymore
+        let (stitch_tiles, waiting_tiles): (Vec<_>, Vec<_>) =
+            unstitched_tiles.into_iter().partition(|tile| {
+                tile.neighbours(true).all(|(neighbour, _)| {
+                    !reprojected_tiles.contains(&neighbour) || finished_tiles.contains(&neighbour)
                 })
-                .try_collect()?;
+            });
+        unstitched_tiles = waiting_tiles;
 
-            // Only create the tile if it has data
-            if has_data {
-                let tile_dataset = create_tile_dataset::<T>(tile_coordinate, context)?;
+        stitch_in_flight(&stitch_tiles)?;
+    }
 
-                for (band_index, mut copy_buffer) in copy_buffers.into_iter().enumerate() {
-                    let mut tile_raster = tile_dataset.rasterband(band_index + 1)?;
+    stitch_in_flight(&neighbour_tiles)?;
 
-                    tile_raster.write::<T>(
-                        (tile_offset.x as isize, tile_offset.y as isize),
-                        (warp_size.x as usize, warp_size.y as usize),
-                        &mut copy_buffer,
-                    )?;
-                }
+    Ok(output_tiles)
+}
+
+/// Warps the part of the face, that is covered by the tile, into the center of the tile.
+/// The tile is only created, if it contains any data.
+fn reproject_tile<T: Copy + GdalType + PartialEq + NumCast>(
+    src_dataset: &Dataset,
+    native_warp: Option<&NativeWarp>,
+    tile_coordinate: TileCoordinate,
+    face_info: &FaceInfo,
+    scale: Option<DVec2>,
+    context: &PreprocessContext,
+) -> PreprocessResult<bool> {
+    let tile_pixel_start = tile_coordinate.xy * context.attachment.center_size() as i32;
+    let tile_pixel_end = (tile_coordinate.xy + 1) * context.attachment.center_size() as i32;
+
+    let warp_pixel_start = tile_pixel_start.max(face_info.pixel_start);
+    let warp_size = tile_pixel_end.min(face_info.pixel_end) - warp_pixel_start;
+    let tile_offset = (face_info.pixel_start - tile_pixel_start).max(IVec2::ZERO)
+        + context.attachment.border_size as i32;
+
+    if warp_size.x <= 0 || warp_size.y <= 0 {
+        return Ok(false);
+    }
+
+    let copy_buffers = warp_region::<T>(
+        src_dataset,
+        native_warp,
+        tile_coordinate.face,
+        warp_pixel_start,
+        warp_size,
+        scale,
+        context,
+    )?;
+
+    let no_data_value = context
+        .no_data_value
+        .map(|v| T::from(v).ok_or(PreprocessError::NoDataOutOfRange))
+        .transpose()?;
+
+    let has_data = copy_buffers
+        .iter()
+        .enumerate()
+        .any(|(band_index, buffer)| match context.alpha_band {
+            // only the alpha band decides, whether a pixel is valid
+            Some(alpha_band) => {
+                band_index + 1 == alpha_band
+                    && buffer.data().iter().any(|value| value.to_f64() != Some(0.0))
+            }
+            None => {
+                no_data_value.is_none()
+                    || buffer
+                        .data()
+                        .iter()
+                        .any(|&value| value != no_data_value.unwrap())
             }
+        });
 
-            // Clean up temp file
-            drop(temp_dataset);
-            let _ = std::fs::remove_file(&temp_path);
+    // only create the tile if it actually contains data
+    if has_data {
+        let tile_dataset = create_tile_dataset::<T>(tile_coordinate, context)?;
 
-            progress.increment();
+        for (band_index, mut copy_buffer) in copy_buffers.into_iter().enumerate() {
+            let mut tile_raster = tile_dataset.rasterband(band_index + 1)?;
 
-            Ok::<Option<TileCoordinate>, PreprocessError>(has_data.then_some(tile_coordinate))
-        })
-        .filter_map(Result::transpose)
-        .collect::<PreprocessResult<Vec<TileCoordinate>>>()?;
-
-    // Update min/max height if needed
-    if matches!(context.attachment_label, AttachmentLabel::Topography) {
-        for tile_coord in &output_tiles {
-            if let Ok(Some(dataset)) = crate::core::load_tile_dataset_if_exists(*tile_coord, context) {
-                if let Ok(min_max) = dataset.rasterband(1).unwrap().compute_raster_min_max(true) {
-                    context.min_height = context.min_height.min(min_max.min as f32);
-                    context.max_height = context.max_height.max(min_max.max as f32);
-                }
-            }
+            tile_raster.write::<T>(
+                (tile_offset.x as isize, tile_offset.y as isize),
+                (warp_size.x as usize, warp_size.y as usize),
+                &mut copy_buffer,
+            )?;
         }
     }
 
-    Ok(output_tiles)
+    Ok(has_data)
+}
+
+/// Warps the source into a region of a face, given in pixels of the highest lod.
+/// Uses the native warp, if one is given, which only has to be set up once per source.
+pub(crate) fn warp_region<T: Copy + GdalType + NumCast>(
+    src_dataset: &Dataset,
+    native_warp: Option<&NativeWarp>,
+    face: u32,
+    pixel_start: IVec2,
+    size: IVec2,
+    scale: Option<DVec2>,
+    context: &PreprocessContext,
+) -> PreprocessResult<Vec<Buffer<T>>> {
+    let max_lod = context.max_lod()?;
+    let pixel_size = 1.0 / ((1 << max_lod) * context.attachment.center_size()) as f64;
+
+    // The geo_transform maps the temp dataset's pixels to S2 UV space
+    let geo_transform = GeoTransform::from([
+        pixel_start.x as f64 * pixel_size, // UV x origin
+        pixel_size,                        // UV x resolution
+        0.0,
+        pixel_start.y as f64 * pixel_size, // UV y origin
+        0.0,
+        pixel_size, // UV y resolution
+    ]);
+
+    if let Some(native_warp) = native_warp {
+        let size = (size.x as usize, size.y as usize);
+
+        return Ok(native_warp
+            .warp_region::<T>(src_dataset, face, &geo_transform, size)?
+            .into_iter()
+            .map(|data| Buffer::new(size, data))
+            .collect());
+    }
+
+    let temp_dataset = create_memory_dataset::<T>(
+        U64Vec2::new(size.x as u64, size.y as u64),
+        Some(geo_transform),
+        context,
+    )?;
+
+    let mut transformer = CustomTransformer::from_dataset(src_dataset, face, Some(geo_transform))?;
+
+    warp(src_dataset, &temp_dataset, context, &mut transformer, scale, None)?;
+
+    let buffers = temp_dataset
+        .rasterbands()
+        .map(|raster| {
+            let size = (size.x as usize, size.y as usize);
+
+            Ok::<Buffer<T>, PreprocessError>(raster?.read_as::<T>((0, 0), size, size, None)?)
+        })
+        .try_collect()?;
+
+    Ok(buffers)
 }
 
 pub fn compute_transforms<'a>(
@@ -272,13 +559,26 @@ pub fn compute_transforms<'a>(
 
     let mut total_area = 0.0;
 
+    let native_warp = match context.warp_backend {
+        WarpBackend::Gdal => None,
+        WarpBackend::Native => Some(NativeWarp::new(src_dataset, context)?),
+    };
+
     for face in 0..6 {
-        let mut transformer = CustomTransformer::from_dataset(src_dataset, face, None)?;
+        let (transformer, suggested_output) = match &native_warp {
+            Some(native_warp) => (None, native_warp.suggested_output(face)),
+            None => {
+                let mut transformer = CustomTransformer::from_dataset(src_dataset, face, None)?;
+                let suggested_output = SuggestedWarpOutput::compute(src_dataset, &mut transformer)?;
+
+                (Some(transformer), suggested_output)
+            }
+        };
 
         let Some(SuggestedWarpOutput {
             size,
             mut geo_transform,
-        }) = SuggestedWarpOutput::compute(src_dataset, &mut transformer)?
+        }) = suggested_output
         else {
             continue;
         };
@@ -302,6 +602,7 @@ pub fn compute_transforms<'a>(
             pixel_end: IVec2::ZERO,
             transformer,
             geo_transform,
+            scale: None,
             progress_callback: None,
         });
     }
@@ -356,8 +657,17 @@ pub fn compute_transforms<'a>(
         ]);
         transform.pixel_start = pixel_start.as_ivec2();
         transform.pixel_end = pixel_end.as_ivec2();
-        transform.transformer =
-            CustomTransformer::from_dataset(src_dataset, transform.face, Some(transform.geo_transform))?;
+
+        if native_warp.is_none() {
+            let mut transformer = CustomTransformer::from_dataset(
+                src_dataset,
+                transform.face,
+                Some(transform.geo_transform),
+            )?;
+
+            transform.scale = warp_scale(&mut transformer, transform.size);
+            transform.transformer = Some(transformer);
+        }
     }
 
     let work_portions = transforms
@@ -386,3 +696,239 @@ pub fn compute_transforms<'a>(
 
     Ok(transforms)
 }
+
+/// The number of face pixels per source pixel along both axes at the center of the face.
+///
+/// GDAL estimates the scale, which widens its kernels when downsampling, for every chunk
+/// of a warp, so the pixels would depend on how the face is split into chunks or tiles.
+fn warp_scale(transformer: &mut GDALCustomTransformer, size: U64Vec2) -> Option<DVec2> {
+    let center = size.as_dvec2() / 2.0;
+
+    let mut x = [center.x, center.x + 1.0, center.x];
+    let mut y = [center.y, center.y, center.y + 1.0];
+    let mut z = [0.0; 3];
+    let mut success = [false; 3];
+
+    transformer
+        .inner
+        .transform(true, &mut x, &mut y, &mut z, &mut success)
+        .ok()?;
+
+    if !success.iter().all(|&success| success) {
+        return None;
+    }
+
+    let step_x = DVec2::new(x[1] - x[0], y[1] - y[0]).length();
+    let step_y = DVec2::new(x[2] - x[0], y[2] - y[0]).length();
+    let scale = 1.0 / DVec2::new(step_x, step_y);
+
+    scale.is_finite().then_some(scale)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{PreprocessDataType, PreprocessNoData, RasterizeOptions};
+    use gdal::{DriverManager, spatial_ref::SpatialRef};
+    use std::path::Path;
+    use tempfile::TempDir;
+    use waw_earth_render::data::{
+        AttachmentConfig, AttachmentFormat, AttachmentLabel, ResamplingMethod, TileCompression,
+    };
+
+    /// A smooth global raster in EPSG:4326 with one pixel per degree.
+    fn create_source(path: &Path) {
+        let driver = DriverManager::get_driver_by_name("GTiff").unwrap();
+        let mut dataset = driver
+            .create_with_band_type::<f32, _>(path, 360, 180, 1)
+            .unwrap();
+        dataset
+            .set_geo_transform(&[-180.0, 1.0, 0.0, 90.0, 0.0, -1.0])
+            .unwrap();
+        dataset
+            .set_spatial_ref(&SpatialRef::from_epsg(4326).unwrap())
+            .unwrap();
+
+        let data = iproduct!(0..180, 0..360)
+            .map(|(y, x)| {
+                let lon = (x as f64 + 0.5 - 180.0).to_radians();
+                let lat = (89.5 - y as f64).to_radians();
+
+                (1000.0 * lon.sin() * lat.cos() + 500.0 * (2.0 * lat).sin()) as f32
+            })
+            .collect_vec();
+
+        dataset
+            .rasterband(1)
+            .unwrap()
+            .write((0, 0), (360, 180), &mut Buffer::new((360, 180), data))
+            .unwrap();
+    }
+
+    /// Prepares the source for an earth with two lods of tiles with 64 pixels.
+    fn initialize(
+        src_path: &Path,
+        test_dir: &Path,
+        method: ResamplingMethod,
+        warp_backend: WarpBackend,
+        memory_budget: Option<u64>,
+    ) -> (Dataset, PreprocessContext) {
+        PreprocessContext::initialize(
+            test_dir.join("earth"),
+            Some(2),
+            AttachmentLabel::Topography,
+            AttachmentConfig {
+                texture_size: 66,
+                border_size: 1,
+                mip_level_count: 1,
+                mask: false,
+                format: AttachmentFormat::R32F,
+                reproject_resampling: method,
+                downsample_resampling: method,
+                compression: TileCompression::None,
+                packed: false,
+                time_slices: 0,
+            },
+            None,
+            vec![src_path.to_path_buf().into()],
+            Some(test_dir.join("temp")),
+            PreprocessNoData::Source,
+            PreprocessDataType::Source,
+            0.0,
+            0.0,
+            false,
+            false,
+            false,
+            false,
+            RasterizeOptions::default(),
+            warp_backend,
+            memory_budget.is_some(),
+            memory_budget,
+        )
+        .unwrap()
+    }
+
+    /// Warps the source onto all six faces at the highest lod, which is finer
+    /// than the source, so that GDAL does not widen its kernels.
+    fn warp_faces(
+        src_path: &Path,
+        test_dir: &Path,
+        method: ResamplingMethod,
+        warp_backend: WarpBackend,
+    ) -> Vec<f32> {
+        let (src_dataset, context) = initialize(src_path, test_dir, method, warp_backend, None);
+
+        let native_warp = match warp_backend {
+            WarpBackend::Gdal => None,
+            WarpBackend::Native => Some(NativeWarp::new(&src_dataset, &context).unwrap()),
+        };
+
+        (0..6)
+            .flat_map(|face| {
+                warp_region::<f32>(
+                    &src_dataset,
+                    native_warp.as_ref(),
+                    face,
+                    IVec2::ZERO,
+                    IVec2::splat(128),
+                    None,
+                    &context,
+                )
+                .unwrap()
+                .remove(0)
+                .data()
+                .to_vec()
+            })
+            .collect()
+    }
+
+    fn compare_backends(method: ResamplingMethod) -> (Vec<f32>, Vec<f32>) {
+        let test_dir = TempDir::new().unwrap();
+
+        let src_path = test_dir.path().join("source.tif");
+        create_source(&src_path);
+
+        let gdal = warp_faces(&src_path, test_dir.path(), method, WarpBackend::Gdal);
+        let native = warp_faces(&src_path, test_dir.path(), method, WarpBackend::Native);
+
+        (gdal, native)
+    }
+
+    #[test]
+    fn native_warp_matches_gdal_nearest() {
+        let (gdal, native) = compare_backends(ResamplingMethod::Nearest);
+
+        // pixels, that map exactly onto the edge between two source pixels, may pick either one
+        let matching = gdal.iter().zip(&native).filter(|(a, b)| a == b).count();
+
+        assert!(matching as f64 >= 0.99 * gdal.len() as f64);
+    }
+
+    #[test]
+    fn native_warp_matches_gdal_bilinear() {
+        let (gdal, native) = compare_backends(ResamplingMethod::Bilinear);
+
+        let max_difference = gdal
+            .iter()
+            .zip(&native)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+
+        assert!(max_difference < 1.0, "max difference {max_difference}");
+    }
+
+    #[test]
+    fn streaming_stays_within_the_memory_budget() {
+        let test_dir = TempDir::new().unwrap();
+
+        let src_path = test_dir.path().join("source.tif");
+        create_source(&src_path);
+
+        let (src_dataset, mut context) = initialize(
+            &src_path,
+            test_dir.path(),
+            ResamplingMethod::Bilinear,
+            WarpBackend::Native,
+            Some(400),
+        );
+
+        let native_warp = NativeWarp::new(&src_dataset, &context).unwrap();
+        let budget =
+            StreamingBudget::new::<f32>(&context, src_dataset.raster_count(), Some(&native_warp))
+                .unwrap();
+
+        // four tiles per face, which do not fit into the budget at once
+        assert!(budget.tiles_in_flight < 24);
+
+        let tiles =
+            stream_source::<f32>(src_dataset, Some(&native_warp), &budget, &mut context, None)
+                .unwrap();
+
+        assert_eq!(tiles.len(), 24);
+        assert!(budget.peak_memory() <= context.memory_budget.unwrap());
+        assert!(budget.peak_memory() >= budget.cache_size.unwrap() as u64 + budget.tile_memory);
+    }
+
+    #[test]
+    fn memory_budget_has_to_hold_a_tile() {
+        let test_dir = TempDir::new().unwrap();
+
+        let src_path = test_dir.path().join("source.tif");
+        create_source(&src_path);
+
+        let (src_dataset, context) = initialize(
+            &src_path,
+            test_dir.path(),
+            ResamplingMethod::Bilinear,
+            WarpBackend::Gdal,
+            Some(1),
+        );
+
+        let budget = StreamingBudget::new::<f32>(&context, src_dataset.raster_count(), None);
+
+        assert!(matches!(
+            budget,
+            Err(PreprocessError::MemoryBudgetTooSmall { .. })
+        ));
+    }
+}
@@ -1,14 +1,18 @@
 use crate::core::{
     CountingProgressCallback, FaceInfo, PreprocessContext, PreprocessError, PreprocessResult,
-    ProgressCallback, SharedReadOnlyDataset, create_tile_dataset,
+    ProgressCallback, SharedReadOnlyDataset, create_tile_dataset, update_tile_dataset,
+    valid_pixels,
+};
+use crate::process::{stitch, with_neighbours};
+use gdal::{
+    Dataset,
+    raster::{Buffer, GdalType},
 };
-use crate::process::stitch;
-use gdal::raster::{Buffer, GdalType};
 use glam::IVec2;
 use itertools::{Itertools, iproduct};
 use num::NumCast;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use waw_earth_render::math::TileCoordinate;
 
 pub fn split_and_stitch<T: Copy + GdalType + PartialEq + NumCast>(
@@ -28,15 +32,26 @@ pub fn split_and_stitch<T: Copy + GdalType + PartialEq + NumCast>(
 
         input_tiles.extend(
             iproduct!(xy_start.x..xy_end.x, xy_start.y..xy_end.y)
-                .map(|(x, y)| TileCoordinate::new(face, info.lod, IVec2::new(x, y))),
+                .map(|(x, y)| TileCoordinate::new(face, info.lod, IVec2::new(x, y)))
+                .filter(|&tile| context.is_dirty(tile)),
         );
     }
 
-    let count = 2 * input_tiles.len() as u64;
+    // the neighbours of rebuilt tiles are not split again, but still have to be stitched
+    let split_tiles = input_tiles.iter().collect::<HashSet<_>>();
+    let neighbour_tiles = with_neighbours(&input_tiles, context)
+        .into_iter()
+        .filter(|tile| !split_tiles.contains(tile))
+        .collect_vec();
+
+    let count = (2 * input_tiles.len() + neighbour_tiles.len()) as u64;
     let progress_callback = CountingProgressCallback::new(count, progress_callback);
 
     let output_tiles = split::<T>(&input_tiles, faces, datasets, context, &progress_callback)?;
-    stitch::<T>(&output_tiles, context, &progress_callback)?;
+
+    let mut stitch_tiles = output_tiles.clone();
+    stitch_tiles.extend(neighbour_tiles);
+    stitch::<T>(&stitch_tiles, context, &progress_callback)?;
 
     Ok(output_tiles)
 }
@@ -51,8 +66,9 @@ fn split<T: Copy + GdalType + PartialEq + NumCast>(
     input_tiles
         .par_iter()
         .map(|&tile_coordinate| {
-            let src_dataset = datasets.get(&tile_coordinate.face).unwrap().get();
-            let face = faces.get(&tile_coordinate.face).unwrap();
+            let missing_face = || PreprocessError::MissingFace(tile_coordinate.face);
+            let src_dataset = datasets.get(&tile_coordinate.face).ok_or_else(missing_face)?.get();
+            let face = faces.get(&tile_coordinate.face).ok_or_else(missing_face)?;
 
             let tile_pixel_start = tile_coordinate.xy * context.attachment.center_size() as i32;
             let tile_pixel_end = (tile_coordinate.xy + 1) * context.attachment.center_size() as i32;
@@ -65,9 +81,10 @@ fn split<T: Copy + GdalType + PartialEq + NumCast>(
 
             let mut has_data = false;
 
-            let copy_buffers: Vec<Buffer<T>> = src_dataset
+            let mut copy_buffers: Vec<Buffer<T>> = src_dataset
                 .rasterbands()
-                .map(|src_raster| {
+                .enumerate()
+                .map(|(band_index, src_raster)| {
                     let src_raster = src_raster?;
 
                     let copy_buffer = src_raster.read_as::<T>(
@@ -82,11 +99,20 @@ fn split<T: Copy + GdalType + PartialEq + NumCast>(
                         .map(|v| T::from(v).ok_or(PreprocessError::NoDataOutOfRange))
                         .transpose()?;
 
-                    has_data |= no_data_value.is_none()
-                        || copy_buffer
-                            .data()
-                            .iter()
-                            .any(|&value| value != no_data_value.unwrap());
+                    has_data |= match context.alpha_band {
+                        // only the alpha band decides, whether a pixel is valid
+                        Some(alpha_band) => {
+                            band_index + 1 == alpha_band
+                                && copy_buffer.data().iter().any(|value| value.to_f64() != Some(0.0))
+                        }
+                        None => {
+                            no_data_value.is_none()
+                                || copy_buffer
+                                    .data()
+                                    .iter()
+                                    .any(|&value| value != no_data_value.unwrap())
+                        }
+                    };
 
                     Ok::<Buffer<T>, PreprocessError>(copy_buffer)
                 })
@@ -94,7 +120,22 @@ fn split<T: Copy + GdalType + PartialEq + NumCast>(
 
             // only create the tile if it actually contains data
             if has_data {
-                let tile_dataset = create_tile_dataset::<T>(tile_coordinate, context).unwrap();
+                let tile_exists = tile_coordinate.path(&context.raw_dir).is_file();
+
+                // merge with the existing tile, instead of replacing it
+                let tile_dataset = if context.update && tile_exists {
+                    let tile_dataset = update_tile_dataset(tile_coordinate, context)?;
+                    merge_with_tile(
+                        tile_coordinate,
+                        &tile_dataset,
+                        &mut copy_buffers,
+                        tile_offset,
+                        context,
+                    )?;
+                    tile_dataset
+                } else {
+                    create_tile_dataset::<T>(tile_coordinate, context)?
+                };
 
                 for (band_index, mut copy_buffer) in copy_buffers.into_iter().enumerate() {
                     let mut tile_raster = tile_dataset.rasterband(band_index + 1)?;
@@ -107,10 +148,53 @@ fn split<T: Copy + GdalType + PartialEq + NumCast>(
                 }
             }
 
-            progress_callback.increment();
+            progress_callback.increment()?;
 
             Ok::<Option<TileCoordinate>, PreprocessError>(has_data.then_some(tile_coordinate))
         })
         .filter_map(Result::transpose)
         .collect::<PreprocessResult<Vec<TileCoordinate>>>()
 }
+
+/// Keeps the previous content of the tile, wherever the new data is invalid.
+fn merge_with_tile<T: Copy + GdalType + PartialEq + NumCast>(
+    tile_coordinate: TileCoordinate,
+    tile_dataset: &Dataset,
+    copy_buffers: &mut [Buffer<T>],
+    tile_offset: IVec2,
+    context: &PreprocessContext,
+) -> PreprocessResult<()> {
+    if tile_dataset.raster_count() != copy_buffers.len() {
+        return Err(PreprocessError::MismatchedBandCount {
+            path: tile_coordinate.path(&context.raw_dir),
+            expected: copy_buffers.len(),
+            found: tile_dataset.raster_count(),
+        });
+    }
+
+    let valid = valid_pixels(copy_buffers, context)?;
+
+    for (band_index, copy_buffer) in copy_buffers.iter_mut().enumerate() {
+        let (width, height) = copy_buffer.shape();
+
+        let tile_buffer = tile_dataset.rasterband(band_index + 1)?.read_as::<T>(
+            (tile_offset.x as isize, tile_offset.y as isize),
+            (width, height),
+            (width, height),
+            None,
+        )?;
+
+        for ((value, &tile_value), &valid) in copy_buffer
+            .data_mut()
+            .iter_mut()
+            .zip(tile_buffer.data())
+            .zip(&valid)
+        {
+            if !valid {
+                *value = tile_value;
+            }
+        }
+    }
+
+    Ok(())
+}
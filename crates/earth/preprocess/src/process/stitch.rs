@@ -3,12 +3,16 @@ use crate::core::{
     load_tile_dataset_if_exists, update_tile_dataset,
 };
 use gdal::Dataset;
-use gdal::raster::{Buffer, GdalType};
+use gdal::raster::{Buffer, GdalType, RasterBand};
 use itertools::izip;
 use ndarray::Axis;
 use num::NumCast;
 use rayon::prelude::*;
-use waw_earth_render::math::{FaceRotation, TileCoordinate};
+use std::collections::HashSet;
+use waw_earth_render::{
+    data::AttachmentConfig,
+    math::{FaceRotation, TileCoordinate},
+};
 
 fn stitch_corners<T: Copy + GdalType + NumCast>(
     tile_dataset: &Dataset,
@@ -55,8 +59,7 @@ fn stitch_corners<T: Copy + GdalType + NumCast>(
                         (1, 1),
                         (1, 1),
                         None,
-                    )
-                    .unwrap()
+                    )?
                     .data()[0],
             )
         });
@@ -71,7 +74,7 @@ fn stitch_corners<T: Copy + GdalType + NumCast>(
             .sum::<f64>()
             / 3.0;
 
-        let mut buffer = Buffer::new((1, 1), vec![T::from(avg).unwrap()]);
+        let mut buffer = Buffer::new((1, 1), vec![T::from(avg).ok_or(PreprocessError::TransformOperationFailed)?]);
 
         raster.write::<T>(dst_offset, (border_size, border_size), &mut buffer)?;
     }
@@ -79,49 +82,98 @@ fn stitch_corners<T: Copy + GdalType + NumCast>(
     Ok(())
 }
 
-fn neighbour_data<T: Copy + GdalType>(
-    tile_dataset: &Dataset,
-    neighbour_dataset: &Dataset,
-    rotation: FaceRotation,
-    i: usize,
-    src_offsets: &[(isize, isize)],
-    dst_offsets: &[(isize, isize)],
-    sizes: &[(usize, usize)],
-) -> PreprocessResult<()> {
-    let size = sizes[i];
-    let dst_offset = dst_offsets[i];
-    let dst_size = size;
+/// The regions of a tile, that are copied from its eight neighbours during stitching.
+/// The first four are the sides, the last four the corners.
+pub(crate) struct BorderLayout {
+    /// The offsets of the regions within the neighbours, that are copied.
+    pub(crate) src_offsets: [(isize, isize); 8],
+    /// The offsets of the border regions within the tile, that are written.
+    pub(crate) dst_offsets: [(isize, isize); 8],
+    pub(crate) sizes: [(usize, usize); 8],
+}
+
+impl BorderLayout {
+    pub(crate) fn new(attachment: &AttachmentConfig) -> Self {
+        Self::with_margin(attachment, attachment.border_size as usize)
+    }
 
-    let (src_offset, src_size) = match rotation {
-        FaceRotation::Identical | FaceRotation::ShiftU | FaceRotation::ShiftV => {
-            (src_offsets[i], size)
+    /// The regions of a window, that extends the center of the tile by the margin on each side,
+    /// instead of its border. The margin may be at most the center size.
+    pub(crate) fn with_margin(attachment: &AttachmentConfig, margin: usize) -> Self {
+        let center_size = attachment.center_size() as usize;
+        let border_size = attachment.border_size as isize;
+        let far_offset = border_size + (center_size - margin) as isize;
+        let offset_size = (margin + center_size) as isize;
+        let margin_offset = margin as isize;
+
+        Self {
+            src_offsets: [
+                (border_size, far_offset),
+                (border_size, border_size),
+                (border_size, border_size),
+                (far_offset, border_size),
+                (far_offset, far_offset),
+                (border_size, far_offset),
+                (border_size, border_size),
+                (far_offset, border_size),
+            ],
+            dst_offsets: [
+                (margin_offset, 0),
+                (offset_size, margin_offset),
+                (margin_offset, offset_size),
+                (0, margin_offset),
+                (0, 0),
+                (offset_size, 0),
+                (offset_size, offset_size),
+                (0, offset_size),
+            ],
+            sizes: [
+                (center_size, margin),
+                (margin, center_size),
+                (center_size, margin),
+                (margin, center_size),
+                (margin, margin),
+                (margin, margin),
+                (margin, margin),
+                (margin, margin),
+            ],
         }
-        FaceRotation::RotateCW => {
-            if i < 4 {
-                (src_offsets[(i + 3) % 4], (size.1, size.0))
-            } else {
-                (src_offsets[4 + ((i + 3) % 4)], (size.1, size.0))
+    }
+
+    /// Reads the pixels of the neighbour, that belong into the i-th border region of the tile,
+    /// already rotated into the orientation of the tile.
+    pub(crate) fn read_neighbour_border<T: Copy + GdalType>(
+        &self,
+        neighbour_raster: &RasterBand,
+        rotation: FaceRotation,
+        i: usize,
+    ) -> PreprocessResult<Buffer<T>> {
+        let size = self.sizes[i];
+
+        let (src_offset, src_size) = match rotation {
+            FaceRotation::Identical | FaceRotation::ShiftU | FaceRotation::ShiftV => {
+                (self.src_offsets[i], size)
             }
-        }
-        FaceRotation::RotateCCW => {
-            if i < 4 {
-                (src_offsets[(i + 1) % 4], (size.1, size.0))
-            } else {
-                (src_offsets[4 + ((i + 1) % 4)], (size.1, size.0))
+            FaceRotation::RotateCW => {
+                if i < 4 {
+                    (self.src_offsets[(i + 3) % 4], (size.1, size.0))
+                } else {
+                    (self.src_offsets[4 + ((i + 3) % 4)], (size.1, size.0))
+                }
             }
-        }
-        FaceRotation::Backside => unreachable!(),
-    };
-
-    for (tile_raster, neighbour_raster) in
-        izip!(tile_dataset.rasterbands(), neighbour_dataset.rasterbands())
-    {
-        let mut tile_raster = tile_raster?;
-        let neighbour_raster = neighbour_raster?;
+            FaceRotation::RotateCCW => {
+                if i < 4 {
+                    (self.src_offsets[(i + 1) % 4], (size.1, size.0))
+                } else {
+                    (self.src_offsets[4 + ((i + 1) % 4)], (size.1, size.0))
+                }
+            }
+            FaceRotation::Backside => unreachable!(),
+        };
 
         let buffer = neighbour_raster.read_as::<T>(src_offset, src_size, src_size, None)?;
 
-        let mut buffer = match rotation {
+        Ok(match rotation {
             FaceRotation::Identical | FaceRotation::ShiftU | FaceRotation::ShiftV => buffer,
             FaceRotation::RotateCW => {
                 let mut array = buffer.to_array()?;
@@ -136,55 +188,70 @@ fn neighbour_data<T: Copy + GdalType>(
                 Buffer::from(array)
             }
             FaceRotation::Backside => unreachable!(),
-        };
+        })
+    }
+}
 
-        tile_raster.write::<T>(dst_offset, dst_size, &mut buffer)?;
+fn neighbour_data<T: Copy + GdalType>(
+    tile_dataset: &Dataset,
+    neighbour_dataset: &Dataset,
+    rotation: FaceRotation,
+    i: usize,
+    layout: &BorderLayout,
+) -> PreprocessResult<()> {
+    for (tile_raster, neighbour_raster) in
+        izip!(tile_dataset.rasterbands(), neighbour_dataset.rasterbands())
+    {
+        let mut tile_raster = tile_raster?;
+        let mut buffer = layout.read_neighbour_border::<T>(&neighbour_raster?, rotation, i)?;
+
+        tile_raster.write::<T>(layout.dst_offsets[i], layout.sizes[i], &mut buffer)?;
     }
 
     Ok(())
 }
 
-pub fn stitch<T: Copy + GdalType + NumCast>(
+/// Extends the tiles by their existing neighbours, whose borders have to be updated as well.
+/// This is only necessary if parts of the attachment are rebuilt, otherwise all neighbours
+/// are already part of the tiles.
+pub(crate) fn with_neighbours(
     tiles: &[TileCoordinate],
     context: &PreprocessContext,
-    progress_callback: &CountingProgressCallback,
-) -> PreprocessResult<()> {
-    let center_size = context.attachment.center_size() as usize;
-    let border_size = context.attachment.border_size as usize;
-    let offset_size = context.attachment.offset_size() as isize;
+) -> Vec<TileCoordinate> {
+    if context.dirty_tiles.is_none() {
+        return tiles.to_vec();
+    }
 
-    let src_offsets: [(isize, isize); 8] = [
-        (border_size as isize, center_size as isize),
-        (border_size as isize, border_size as isize),
-        (border_size as isize, border_size as isize),
-        (center_size as isize, border_size as isize),
-        (center_size as isize, center_size as isize),
-        (border_size as isize, center_size as isize),
-        (border_size as isize, border_size as isize),
-        (center_size as isize, border_size as isize),
-    ];
+    add_neighbours(tiles, context)
+}
 
-    let dst_offsets: [(isize, isize); 8] = [
-        (border_size as isize, 0),
-        (offset_size, border_size as isize),
-        (border_size as isize, offset_size),
-        (0, border_size as isize),
-        (0, 0),
-        (offset_size, 0),
-        (offset_size, offset_size),
-        (0, offset_size),
-    ];
+/// Extends the tiles by their existing neighbours.
+pub(crate) fn add_neighbours(
+    tiles: &[TileCoordinate],
+    context: &PreprocessContext,
+) -> Vec<TileCoordinate> {
+    let mut output_tiles = tiles.iter().copied().collect::<HashSet<_>>();
 
-    let sizes: [(usize, usize); 8] = [
-        (center_size, border_size),
-        (border_size, center_size),
-        (center_size, border_size),
-        (border_size, center_size),
-        (border_size, border_size),
-        (border_size, border_size),
-        (border_size, border_size),
-        (border_size, border_size),
-    ];
+    for &tile in tiles {
+        output_tiles.extend(
+            tile.neighbours(true)
+                .map(|(neighbour, _)| neighbour)
+                .filter(|&neighbour| {
+                    neighbour != TileCoordinate::INVALID
+                        && neighbour.path(&context.raw_dir).is_file()
+                }),
+        );
+    }
+
+    output_tiles.into_iter().collect()
+}
+
+pub fn stitch<T: Copy + GdalType + NumCast>(
+    tiles: &[TileCoordinate],
+    context: &PreprocessContext,
+    progress_callback: &CountingProgressCallback,
+) -> PreprocessResult<()> {
+    let layout = BorderLayout::new(&context.attachment);
 
     tiles.par_iter().try_for_each(|&tile_coordinate| {
         let tile_dataset = update_tile_dataset(tile_coordinate, context)?;
@@ -193,21 +260,13 @@ pub fn stitch<T: Copy + GdalType + NumCast>(
             if let Some(neighbour_dataset) =
                 load_tile_dataset_if_exists(neighbour_coordinate, context)?
             {
-                neighbour_data::<T>(
-                    &tile_dataset,
-                    &neighbour_dataset,
-                    rotation,
-                    i,
-                    &src_offsets,
-                    &dst_offsets,
-                    &sizes,
-                )?;
+                neighbour_data::<T>(&tile_dataset, &neighbour_dataset, rotation, i, &layout)?;
             } else if i >= 4 {
-                stitch_corners::<T>(&tile_dataset, &dst_offsets, i, context)?;
+                stitch_corners::<T>(&tile_dataset, &layout.dst_offsets, i, context)?;
             };
         }
 
-        progress_callback.increment();
+        progress_callback.increment()?;
 
         Ok::<(), PreprocessError>(())
     })
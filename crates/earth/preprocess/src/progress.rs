@@ -0,0 +1,124 @@
+use crate::core::{PreprocessError, PreprocessResult, ProgressCallback};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Receives the progress of the preprocessing, so that it can be shown in the terminal,
+/// an editor or the log of a build server.
+///
+/// The events of a stage may be reported from multiple threads at once.
+pub trait ProgressSink: Send + Sync {
+    fn stage_started(&self, _stage: &str) {}
+    /// Reported after every processed tile or warped chunk, with the completion between 0 and 1.
+    fn stage_progress(&self, _stage: &str, _completion: f64) {}
+    fn stage_finished(&self, _stage: &str, _duration: Duration) {}
+    /// Informs about the run as a whole, like the attachment that is processed next.
+    fn message(&self, _message: &str) {}
+    /// Reports a problem, that does not stop the preprocessing, but likely needs attention.
+    fn warning(&self, _message: &str) {}
+}
+
+/// Ignores all progress.
+pub struct SilentProgress;
+
+impl ProgressSink for SilentProgress {}
+
+/// Stops the preprocessing, once it is cancelled from another thread.
+///
+/// The running stage is interrupted after its current tile and fails with
+/// [`PreprocessError::Cancelled`]. Completed stages are kept, so that the run can be resumed.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Where the progress of a run is reported to and whether it has been cancelled.
+#[derive(Clone)]
+pub struct Progress {
+    sink: Arc<dyn ProgressSink>,
+    cancellation: CancellationToken,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new(SilentProgress)
+    }
+}
+
+impl Progress {
+    pub fn new(sink: impl ProgressSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            cancellation: CancellationToken::default(),
+        }
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub(crate) fn check_cancelled(&self) -> PreprocessResult<()> {
+        if self.cancellation.is_cancelled() {
+            Err(PreprocessError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn message(&self, message: &str) {
+        self.sink.message(message);
+    }
+
+    pub(crate) fn warning(&self, message: &str) {
+        self.sink.warning(message);
+    }
+
+    /// Runs the stage with a [`ProgressCallback`], that forwards its completion to the sink
+    /// and aborts the stage, once the run is cancelled.
+    pub(crate) fn run_stage<R>(
+        &self,
+        stage: &str,
+        run: impl FnOnce(&ProgressCallback) -> PreprocessResult<R>,
+    ) -> PreprocessResult<R> {
+        self.check_cancelled()?;
+
+        self.sink.stage_started(stage);
+        let start = Instant::now();
+
+        let progress_callback = |completion: f64| {
+            self.sink.stage_progress(stage, completion);
+            !self.cancellation.is_cancelled()
+        };
+
+        let result = run(&progress_callback);
+
+        // GDAL reports an aborted operation as a generic error
+        self.check_cancelled()?;
+        let output = result?;
+
+        self.sink.stage_finished(stage, start.elapsed());
+
+        Ok(output)
+    }
+}
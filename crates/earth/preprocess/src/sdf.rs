@@ -0,0 +1,350 @@
+use crate::{
+    core::{CountingProgressCallback, PreprocessError, PreprocessResult},
+    pack::{TileFiles, clear_attachment, load_config, pack_attachment, save_config},
+    progress::Progress,
+    window::{TileWindow, pixel_edges, write_tile},
+};
+use clap::Args;
+use glam::DVec2;
+use itertools::{Itertools, izip};
+use rayon::prelude::*;
+use std::path::Path;
+use waw_earth_render::prelude::*;
+
+/// The squared distance of pixels without a seed, finite so that the parabolas can intersect.
+const FAR: f64 = 1e20;
+
+/// How the distance field of an attachment is computed.
+#[derive(Args, Debug, Clone)]
+pub struct SdfOptions {
+    /// The format of the distance field, r32f stores metres
+    /// and r16i the distance relative to the max distance.
+    #[arg(long, default_value = "r32f")]
+    pub format: AttachmentFormat,
+    /// The distance in metres, at which the field is clamped.
+    #[arg(long, default_value_t = 50_000.0)]
+    pub max_distance: f64,
+    /// Pixels with this value are inside of the region, instead of all non zero pixels.
+    #[arg(long)]
+    pub class: Option<f64>,
+    /// Computes the unsigned distance to the closest border between two different values,
+    /// instead of the signed distance to a region. Useful for ID rasters, like countries.
+    #[arg(long, default_value_t = false, conflicts_with = "class")]
+    pub borders: bool,
+}
+
+/// Computes a distance field in metres from the binary or categorical source attachment
+/// and stores it as the output attachment of the earth.
+///
+/// Pixels outside of the region have a positive and pixels inside a negative distance.
+/// The distances of every tile are computed on a window, that is extended by the pixels
+/// of its neighbours, so that the field stays continuous across tile and face seams.
+/// Pixels without data are never part of a region and are treated as far away.
+pub fn generate_sdf(
+    earth_path: &Path,
+    source_label: &AttachmentLabel,
+    output_label: &AttachmentLabel,
+    options: &SdfOptions,
+    progress: &Progress,
+) -> PreprocessResult<()> {
+    if source_label == output_label {
+        return Err(PreprocessError::OverwrittenSource(source_label.clone()));
+    }
+
+    if !matches!(options.format, AttachmentFormat::R16I | AttachmentFormat::R32F) {
+        return Err(PreprocessError::UnsupportedDistanceFormat(options.format));
+    }
+
+    let mut config = load_config(earth_path)?;
+
+    let source = config
+        .attachments
+        .get(source_label)
+        .cloned()
+        .ok_or_else(|| PreprocessError::MissingAttachment(source_label.clone()))?;
+
+    let output = AttachmentConfig {
+        format: options.format,
+        mask: false,
+        reproject_resampling: ResamplingMethod::Bilinear,
+        downsample_resampling: ResamplingMethod::Bilinear,
+        compression: TileCompression::None,
+        packed: false,
+        ..source.clone()
+    };
+
+    let source_files = TileFiles::new(earth_path, source_label, &source)?;
+    let output_dir = clear_attachment(earth_path, output_label)?;
+
+    progress.run_stage("Computing distances", |progress_callback| {
+        let progress_callback =
+            CountingProgressCallback::new(config.tiles.len() as u64, Some(progress_callback));
+
+        config.tiles.par_iter().try_for_each(|&tile| {
+            if source_files.path(tile).is_some() {
+                let distances =
+                    tile_distances(tile, &source_files, &source, config.shape, options)?;
+                write_distances(&tile.path(&output_dir), &distances, &output, options)?;
+            }
+
+            progress_callback.increment()?;
+
+            Ok::<(), PreprocessError>(())
+        })
+    })?;
+
+    config.add_attachment(output_label.clone(), output);
+    save_config(&config, earth_path)?;
+
+    if source.packed {
+        pack_attachment(earth_path, output_label)?;
+    }
+
+    Ok(())
+}
+
+/// The distances in metres of all pixels of the tile, including its border.
+fn tile_distances(
+    tile: TileCoordinate,
+    source_files: &TileFiles,
+    attachment: &AttachmentConfig,
+    shape: EarthShape,
+    options: &SdfOptions,
+) -> PreprocessResult<Vec<f64>> {
+    let texture_size = attachment.texture_size as usize;
+    let center_size = attachment.center_size() as usize;
+    let border_size = attachment.border_size as usize;
+
+    // the window has to reach the max distance, where the pixels are the smallest
+    let min_pixel_size = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 0.5)]
+        .map(|(x, y)| pixel_size(tile, DVec2::new(x, y) * center_size as f64, center_size, shape))
+        .into_iter()
+        .fold(f64::MAX, f64::min);
+    let margin = ((options.max_distance / min_pixel_size).ceil() as usize + 1)
+        .clamp(border_size, center_size);
+
+    let TileWindow { values, size, .. } =
+        TileWindow::read(tile, source_files, attachment, margin)?;
+
+    let distances = if options.borders {
+        let edges = (0..size * size)
+            .map(|index| {
+                let (x, y) = (index % size, index / size);
+
+                values[index].is_some_and(|value| {
+                    [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+                        let (nx, ny) = (x as isize + dx, y as isize + dy);
+
+                        (0..size as isize).contains(&nx)
+                            && (0..size as isize).contains(&ny)
+                            && values[ny as usize * size + nx as usize]
+                                .is_some_and(|neighbour| neighbour != value)
+                    })
+                })
+            })
+            .collect_vec();
+
+        // the border lies halfway between the edge pixels of both regions
+        distance_transform(&edges, size)
+            .into_iter()
+            .zip(&values)
+            .map(|(distance, value)| value.map(|_| distance.sqrt() + 0.5))
+            .collect_vec()
+    } else {
+        let inside = values
+            .iter()
+            .map(|value| {
+                value.is_some_and(|value| match options.class {
+                    Some(class) => value == class,
+                    None => value != 0.0,
+                })
+            })
+            .collect_vec();
+        let outside = values
+            .iter()
+            .zip(&inside)
+            .map(|(value, &inside)| value.is_some() && !inside)
+            .collect_vec();
+
+        // the boundary lies half a pixel from the centers of the closest pixels
+        izip!(
+            distance_transform(&inside, size),
+            distance_transform(&outside, size),
+            &values,
+            &inside
+        )
+        .map(|(inside_distance, outside_distance, value, &inside)| {
+            value.map(|_| {
+                if inside {
+                    0.5 - outside_distance.sqrt()
+                } else {
+                    inside_distance.sqrt() - 0.5
+                }
+            })
+        })
+        .collect_vec()
+    };
+
+    // the tile starts at its border, which lies within the margin of the window
+    let start = margin - border_size;
+
+    let distances = (0..texture_size)
+        .flat_map(|y| (0..texture_size).map(move |x| (x, y)))
+        .map(|(x, y)| match distances[(start + y) * size + start + x] {
+            Some(distance) => {
+                let pixel = DVec2::new(x as f64, y as f64) + 0.5 - border_size as f64;
+                let metres = distance * pixel_size(tile, pixel, center_size, shape);
+
+                metres.clamp(-options.max_distance, options.max_distance)
+            }
+            None => options.max_distance,
+        })
+        .collect_vec();
+
+    Ok(distances)
+}
+
+/// The size in metres of the pixel at the position within the center of the tile.
+fn pixel_size(tile: TileCoordinate, pixel: DVec2, center_size: usize, shape: EarthShape) -> f64 {
+    let (u_edge, v_edge) = pixel_edges(tile, pixel, center_size, shape);
+
+    0.5 * (u_edge.length() + v_edge.length())
+}
+
+/// Computes the exact squared euclidean distance of every pixel to the closest seed, in pixels,
+/// using the separable transform of Felzenszwalb and Huttenlocher.
+fn distance_transform(seeds: &[bool], size: usize) -> Vec<f64> {
+    let mut distances = seeds
+        .iter()
+        .map(|&seed| if seed { 0.0 } else { FAR })
+        .collect_vec();
+
+    let mut line = vec![0.0; size];
+    let mut transformed = vec![0.0; size];
+    let mut parabolas = vec![0; size];
+    let mut boundaries = vec![0.0; size + 1];
+
+    for y in 0..size {
+        line.copy_from_slice(&distances[y * size..(y + 1) * size]);
+        transform_line(&line, &mut transformed, &mut parabolas, &mut boundaries);
+        distances[y * size..(y + 1) * size].copy_from_slice(&transformed);
+    }
+
+    for x in 0..size {
+        for y in 0..size {
+            line[y] = distances[y * size + x];
+        }
+
+        transform_line(&line, &mut transformed, &mut parabolas, &mut boundaries);
+
+        for y in 0..size {
+            distances[y * size + x] = transformed[y];
+        }
+    }
+
+    distances
+}
+
+/// The one dimensional transform, computing the lower envelope of the parabolas rooted at
+/// every sample.
+fn transform_line(
+    samples: &[f64],
+    transformed: &mut [f64],
+    parabolas: &mut [usize],
+    boundaries: &mut [f64],
+) {
+    let intersection = |q: usize, p: usize| {
+        ((samples[q] + (q * q) as f64) - (samples[p] + (p * p) as f64)) / (2 * (q - p)) as f64
+    };
+
+    let mut k = 0;
+    parabolas[0] = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+
+    for q in 1..samples.len() {
+        let mut s = intersection(q, parabolas[k]);
+
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(q, parabolas[k]);
+        }
+
+        k += 1;
+        parabolas[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+
+    for q in 0..samples.len() {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+
+        let p = parabolas[k];
+        transformed[q] = (q as f64 - p as f64).powi(2) + samples[p];
+    }
+}
+
+/// Writes the distances as a new tile, converted into the data type of the format.
+fn write_distances(
+    tile_path: &Path,
+    distances: &[f64],
+    attachment: &AttachmentConfig,
+    options: &SdfOptions,
+) -> PreprocessResult<()> {
+    match options.format {
+        AttachmentFormat::R16I => {
+            // the tiles are sampled as normalized values, so the full range is used
+            let data = distances
+                .iter()
+                .map(|&distance| (distance / options.max_distance * i16::MAX as f64).round() as i16)
+                .collect_vec();
+
+            write_tile(tile_path, vec![data], attachment)
+        }
+        _ => {
+            let data = distances.iter().map(|&distance| distance as f32).collect_vec();
+
+            write_tile(tile_path, vec![data], attachment)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distance_transform_is_exact() {
+        let size = 16;
+
+        // a few scattered seeds, including ones at the edges and a pair of neighbours
+        let seeds = (0..size * size)
+            .map(|index| [0, 7, 40, 41, 100, 190, 255].contains(&index))
+            .collect_vec();
+
+        let distances = distance_transform(&seeds, size);
+
+        for (index, &distance) in distances.iter().enumerate() {
+            let (x, y) = ((index % size) as f64, (index / size) as f64);
+
+            let expected = seeds
+                .iter()
+                .positions(|&seed| seed)
+                .map(|seed| {
+                    let (seed_x, seed_y) = ((seed % size) as f64, (seed / size) as f64);
+                    (x - seed_x).powi(2) + (y - seed_y).powi(2)
+                })
+                .fold(f64::MAX, f64::min);
+
+            assert_eq!(distance, expected, "({x}, {y})");
+        }
+
+        // without any seed, all pixels are far away
+        let distances = distance_transform(&vec![false; size * size], size);
+        assert!(distances.iter().all(|&distance| distance >= FAR));
+    }
+}
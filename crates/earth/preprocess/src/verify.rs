@@ -0,0 +1,243 @@
+use crate::{
+    CONFIG_FILE,
+    core::{PreprocessError, PreprocessResult, format_data_type},
+    pack::TileFiles,
+    process::BorderLayout,
+};
+use gdal::Dataset;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use waw_earth_render::prelude::*;
+
+/// A problem with a single tile of a processed earth.
+#[derive(Serialize, Debug, Clone)]
+pub enum TileIssue {
+    /// The tile is listed in the config, but does not exist.
+    Missing(TileCoordinate),
+    Unreadable {
+        tile: TileCoordinate,
+        error: String,
+    },
+    WrongSize {
+        tile: TileCoordinate,
+        expected: u32,
+        found: (usize, usize),
+    },
+    WrongBandCount {
+        tile: TileCoordinate,
+        expected: u32,
+        found: usize,
+    },
+    WrongDataType {
+        tile: TileCoordinate,
+        expected: String,
+        found: String,
+    },
+    /// The border of the tile does not match the pixels of its neighbour.
+    BorderMismatch {
+        tile: TileCoordinate,
+        neighbour: TileCoordinate,
+        mismatched_pixels: usize,
+        max_difference: f64,
+    },
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentReport {
+    pub label: AttachmentLabel,
+    pub checked_tiles: usize,
+    pub issues: Vec<TileIssue>,
+}
+
+/// The result of verifying a processed earth, serialized as RON.
+#[derive(Serialize, Debug)]
+pub struct VerifyReport {
+    pub earth_path: PathBuf,
+    pub attachments: Vec<AttachmentReport>,
+}
+
+impl VerifyReport {
+    pub fn issue_count(&self) -> usize {
+        self.attachments
+            .iter()
+            .map(|attachment| attachment.issues.len())
+            .sum()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.issue_count() == 0
+    }
+
+    /// Writes the report to the file, or to stdout if there is none.
+    pub fn write(&self, output_path: Option<&Path>) -> PreprocessResult<()> {
+        let encoded = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|error| PreprocessError::Config(error.to_string()))?;
+
+        match output_path {
+            Some(output_path) => fs::write(output_path, encoded)?,
+            None => writeln!(io::stdout(), "{encoded}")?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks, that all tiles of the earth exist for every attachment, match their attachment config
+/// and are stitched to their neighbours.
+///
+/// Border pixels may differ by up to the tolerance, before they are reported.
+pub fn verify_earth(earth_path: &Path, tolerance: f64) -> PreprocessResult<VerifyReport> {
+    let config = EarthConfig::load_file(earth_path.join(CONFIG_FILE))
+        .map_err(|error| PreprocessError::Config(error.to_string()))?;
+
+    let tiles = config.tiles.iter().copied().collect::<HashSet<_>>();
+
+    // every time slice is checked like an attachment of its own
+    let attachments = config
+        .attachments
+        .iter()
+        .flat_map(|(label, attachment)| {
+            attachment
+                .tile_labels(label)
+                .into_iter()
+                .map(move |label| (label, attachment))
+        })
+        .map(|(label, attachment)| {
+            let tile_files = TileFiles::new(earth_path, &label, attachment)?;
+
+            let mut issues = config
+                .tiles
+                .par_iter()
+                .flat_map_iter(|&tile| {
+                    verify_tile(tile, &tile_files, attachment, &tiles, tolerance)
+                        .unwrap_or_else(|error| {
+                            vec![TileIssue::Unreadable {
+                                tile,
+                                error: error.to_string(),
+                            }]
+                        })
+                })
+                .collect::<Vec<_>>();
+
+            issues.sort_by_key(|issue| format!("{issue:?}"));
+
+            Ok(AttachmentReport {
+                label,
+                checked_tiles: config.tiles.len(),
+                issues,
+            })
+        })
+        .collect::<PreprocessResult<Vec<_>>>()?;
+
+    Ok(VerifyReport {
+        earth_path: earth_path.to_path_buf(),
+        attachments,
+    })
+}
+
+fn verify_tile(
+    tile: TileCoordinate,
+    tile_files: &TileFiles,
+    attachment: &AttachmentConfig,
+    tiles: &HashSet<TileCoordinate>,
+    tolerance: f64,
+) -> PreprocessResult<Vec<TileIssue>> {
+    let Some(tile_path) = tile_files.path(tile) else {
+        return Ok(vec![TileIssue::Missing(tile)]);
+    };
+
+    let tile_dataset = Dataset::open(tile_path)?;
+
+    let size = tile_dataset.raster_size();
+    let band_count = tile_dataset.raster_count();
+    let data_type = tile_dataset.rasterband(1)?.band_type();
+    let expected_data_type = format_data_type(attachment.format);
+
+    let mut issues = Vec::new();
+
+    if size != (attachment.texture_size as usize, attachment.texture_size as usize) {
+        issues.push(TileIssue::WrongSize {
+            tile,
+            expected: attachment.texture_size,
+            found: size,
+        });
+    }
+
+    if band_count != attachment.format.channel_count() as usize {
+        issues.push(TileIssue::WrongBandCount {
+            tile,
+            expected: attachment.format.channel_count(),
+            found: band_count,
+        });
+    }
+
+    if data_type != expected_data_type {
+        issues.push(TileIssue::WrongDataType {
+            tile,
+            expected: expected_data_type.name(),
+            found: data_type.name(),
+        });
+    }
+
+    // the borders can only be compared, if the layout of the tile is correct
+    if !issues.is_empty() {
+        return Ok(issues);
+    }
+
+    let layout = BorderLayout::new(attachment);
+
+    for (i, (neighbour, rotation)) in tile.neighbours(true).enumerate() {
+        if neighbour == TileCoordinate::INVALID || !tiles.contains(&neighbour) {
+            continue;
+        }
+
+        let Some(neighbour_path) = tile_files.path(neighbour) else {
+            continue;
+        };
+
+        let neighbour_dataset = Dataset::open(neighbour_path)?;
+
+        let mut mismatched_pixels = 0;
+        let mut max_difference: f64 = 0.0;
+
+        for (tile_raster, neighbour_raster) in
+            tile_dataset.rasterbands().zip(neighbour_dataset.rasterbands())
+        {
+            let border = tile_raster?.read_as::<f64>(
+                layout.dst_offsets[i],
+                layout.sizes[i],
+                layout.sizes[i],
+                None,
+            )?;
+            let expected_border =
+                layout.read_neighbour_border::<f64>(&neighbour_raster?, rotation, i)?;
+
+            for (&value, &expected_value) in border.data().iter().zip(expected_border.data()) {
+                let difference = (value - expected_value).abs();
+
+                // NaN is used as no data value, so two NaNs match
+                if difference > tolerance || (value.is_nan() != expected_value.is_nan()) {
+                    mismatched_pixels += 1;
+                    max_difference = max_difference.max(difference);
+                }
+            }
+        }
+
+        if mismatched_pixels > 0 {
+            issues.push(TileIssue::BorderMismatch {
+                tile,
+                neighbour,
+                mismatched_pixels,
+                max_difference,
+            });
+        }
+    }
+
+    Ok(issues)
+}
@@ -0,0 +1,168 @@
+use crate::{
+    core::{PreprocessError, PreprocessResult, TILING_OPTIONS},
+    pack::TileFiles,
+    process::BorderLayout,
+};
+use gdal::{
+    Dataset, DriverManager,
+    raster::{Buffer, GdalType, RasterCreationOptions},
+};
+use glam::{DVec2, DVec3};
+use std::{fs, path::Path};
+use waw_earth_render::{math::Coordinate, prelude::*};
+
+/// The pixels of a tile's center, extended by a margin of pixels from its neighbours.
+/// The neighbour pixels are rotated into the orientation of the tile, so that the window
+/// is continuous across tile and face seams.
+pub(crate) struct TileWindow {
+    /// The values of the first band, `None` for pixels without data or neighbour.
+    pub(crate) values: Vec<Option<f64>>,
+    pub(crate) size: usize,
+    pub(crate) margin: usize,
+}
+
+impl TileWindow {
+    /// Reads the center of the tile and the margin around it from the tile and its neighbours.
+    /// The margin may be at most the center size. The tile has to exist.
+    pub(crate) fn read(
+        tile: TileCoordinate,
+        tile_files: &TileFiles,
+        attachment: &AttachmentConfig,
+        margin: usize,
+    ) -> PreprocessResult<Self> {
+        let center_size = attachment.center_size() as usize;
+        let border_size = attachment.border_size as isize;
+        let size = center_size + 2 * margin;
+
+        let mut values = vec![None; size * size];
+
+        let mut copy = |buffer: Buffer<f64>, offset: (isize, isize), no_data: Option<f64>| {
+            let width = buffer.shape().0;
+
+            for (index, &value) in buffer.data().iter().enumerate() {
+                let x = offset.0 as usize + index % width;
+                let y = offset.1 as usize + index / width;
+
+                if !value.is_nan() && Some(value) != no_data {
+                    values[y * size + x] = Some(value);
+                }
+            }
+        };
+
+        let tile_path = tile_files
+            .path(tile)
+            .ok_or(PreprocessError::MissingTile(tile))?;
+        let tile_dataset = Dataset::open(tile_path)?;
+        let tile_raster = tile_dataset.rasterband(1)?;
+        let buffer = tile_raster.read_as::<f64>(
+            (border_size, border_size),
+            (center_size, center_size),
+            (center_size, center_size),
+            None,
+        )?;
+        copy(
+            buffer,
+            (margin as isize, margin as isize),
+            tile_raster.no_data_value(),
+        );
+
+        let layout = BorderLayout::with_margin(attachment, margin);
+
+        // missing neighbours leave their part of the window empty
+        for (i, (neighbour, rotation)) in tile.neighbours(true).enumerate() {
+            if neighbour == TileCoordinate::INVALID {
+                continue;
+            }
+
+            let Some(neighbour_path) = tile_files.path(neighbour) else {
+                continue;
+            };
+
+            let neighbour_dataset = Dataset::open(neighbour_path)?;
+            let neighbour_raster = neighbour_dataset.rasterband(1)?;
+            let no_data = neighbour_raster.no_data_value();
+            let buffer = layout.read_neighbour_border::<f64>(&neighbour_raster, rotation, i)?;
+
+            copy(buffer, layout.dst_offsets[i], no_data);
+        }
+
+        Ok(Self {
+            values,
+            size,
+            margin,
+        })
+    }
+
+    /// The value at the pixel of a tile with the border size, which is offset into the window.
+    pub(crate) fn tile_value(&self, x: isize, y: isize, border_size: usize) -> Option<f64> {
+        let start = (self.margin - border_size) as isize;
+        let (x, y) = (start + x, start + y);
+
+        if (0..self.size as isize).contains(&x) && (0..self.size as isize).contains(&y) {
+            self.values[y as usize * self.size + x as usize]
+        } else {
+            None
+        }
+    }
+}
+
+/// The local position of the position in pixels within the center of the tile,
+/// on the surface of the earth.
+pub(crate) fn pixel_position(
+    tile: TileCoordinate,
+    pixel: DVec2,
+    center_size: usize,
+    shape: EarthShape,
+) -> DVec3 {
+    let face_size = (center_size << tile.lod) as f64;
+    let uv = (tile.xy.as_dvec2() * center_size as f64 + pixel) / face_size;
+
+    Coordinate::new(tile.face, uv).local_position(shape, 0.0)
+}
+
+/// The edges of the pixel in metres along the u and v axis of the tile.
+pub(crate) fn pixel_edges(
+    tile: TileCoordinate,
+    pixel: DVec2,
+    center_size: usize,
+    shape: EarthShape,
+) -> (DVec3, DVec3) {
+    let position = |pixel| pixel_position(tile, pixel, center_size, shape);
+
+    (
+        position(pixel + 0.5 * DVec2::X) - position(pixel - 0.5 * DVec2::X),
+        position(pixel + 0.5 * DVec2::Y) - position(pixel - 0.5 * DVec2::Y),
+    )
+}
+
+/// Writes the bands as a new tile of the attachment.
+pub(crate) fn write_tile<T: Copy + GdalType>(
+    tile_path: &Path,
+    bands: Vec<Vec<T>>,
+    attachment: &AttachmentConfig,
+) -> PreprocessResult<()> {
+    let size = attachment.texture_size as usize;
+
+    if let Some(parent) = tile_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let driver = DriverManager::get_driver_by_name("GTiff")?;
+    let options = RasterCreationOptions::from_iter(TILING_OPTIONS);
+    let dataset = driver.create_with_band_type_with_options::<T, _>(
+        tile_path,
+        size,
+        size,
+        bands.len(),
+        &options,
+    )?;
+
+    for (index, data) in bands.into_iter().enumerate() {
+        let mut buffer = Buffer::new((size, size), data);
+        dataset
+            .rasterband(index + 1)?
+            .write::<T>((0, 0), (size, size), &mut buffer)?;
+    }
+
+    Ok(())
+}
@@ -10,6 +10,10 @@ pub const EARTH_RADIUS: f64 = 6_371_000.0; // meters
 fn main() {
     App::new()
         .add_plugins((
+            // has to be registered before the asset plugin
+            TileArchivePlugin {
+                file_path: "../../assets".into(),
+            },
             DefaultPlugins
                 .set(AssetPlugin {
                     file_path: "../../assets".into(),
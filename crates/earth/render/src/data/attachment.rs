@@ -3,7 +3,14 @@ use bevy::render::render_resource::TextureFormat;
 use bytemuck::cast_slice;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Error, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashSet,
+    fmt::Error,
+    hash::{Hash, Hasher},
+    mem,
+    path::PathBuf,
+    str::FromStr,
+};
 use strum_macros::EnumIter;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash, Default)]
@@ -14,6 +21,17 @@ pub enum AttachmentLabel {
     Bathyometry,
     OceanMask,
     NightTime,
+    /// The steepness of the terrain, derived from the topography.
+    /// Stored as `R8Unorm`, where one maps to 90 degrees.
+    Slope,
+    /// The direction the terrain faces downhill, clockwise from north and derived from the
+    /// topography. Stored as `R8Unorm`, where one maps to 360 degrees.
+    Aspect,
+    /// The shading of the terrain by a distant light, derived from the topography.
+    Hillshade,
+    /// The normal of the terrain in the tangent frame of the tile, derived from the topography.
+    /// Stored as `Rg16U`, with the components along u and v mapped from [-1, 1] to [0, 1].
+    Normals,
     Custom(String), // Todo: this should not be a heap allocated string
     Empty(usize),
 }
@@ -26,6 +44,10 @@ impl From<&AttachmentLabel> for String {
             AttachmentLabel::NightTime => "nighttime".to_string(),
             AttachmentLabel::OceanMask => "ocean".to_string(),
             AttachmentLabel::Bathyometry => "bathyometry".to_string(),
+            AttachmentLabel::Slope => "slope".to_string(),
+            AttachmentLabel::Aspect => "aspect".to_string(),
+            AttachmentLabel::Hillshade => "hillshade".to_string(),
+            AttachmentLabel::Normals => "normals".to_string(),
             AttachmentLabel::Custom(name) => name.clone(),
             AttachmentLabel::Empty(i) => format!("empty_{}", (b'a' + *i as u8) as char).to_string(),
         }
@@ -42,6 +64,10 @@ impl FromStr for AttachmentLabel {
             "ocean" => Ok(Self::OceanMask),
             "bathyometry" => Ok(Self::Bathyometry),
             "nighttime" => Ok(Self::NightTime),
+            "slope" => Ok(Self::Slope),
+            "aspect" => Ok(Self::Aspect),
+            "hillshade" => Ok(Self::Hillshade),
+            "normals" => Ok(Self::Normals),
             name => Ok(Self::Custom(name.to_string())),
         }
     }
@@ -74,6 +100,7 @@ impl FromStr for AttachmentFormat {
             "rgba8u" => Ok(Self::Rgba8U),
             "r16u" => Ok(Self::R16U),
             "r16i" => Ok(Self::R16I),
+            "rg16u" => Ok(Self::Rg16U),
             "r32f" => Ok(Self::R32F),
             _ => Err(Error),
         }
@@ -116,6 +143,198 @@ impl AttachmentFormat {
             AttachmentFormat::R32F => 4,
         }
     }
+
+    /// The factor, that converts a sampled value back into the stored integer,
+    /// whose lowest bit is the mask. Float values store the mask in their own bits instead,
+    /// which is marked by zero.
+    pub(crate) fn mask_scale(self) -> f32 {
+        match self {
+            AttachmentFormat::R32F => 0.0,
+            AttachmentFormat::R16I => i16::MAX as f32, // sampled as snorm
+            AttachmentFormat::R16U => 1.0,             // sampled as uint
+            AttachmentFormat::Rg16U => u16::MAX as f32,
+            _ => u8::MAX as f32,
+        }
+    }
+
+    /// The scale and offset, that convert a sampled value of a topography attachment
+    /// into its height (`height = scale * value + offset`).
+    ///
+    /// Signed and float formats store the height in metres, while normalized unsigned formats
+    /// span the height range of the earth.
+    pub(crate) fn height_decoding(self, min_height: f32, max_height: f32) -> (f32, f32) {
+        match self {
+            AttachmentFormat::R16I => (i16::MAX as f32, 0.0), // sampled as snorm
+            AttachmentFormat::R32F => (1.0, 0.0),
+            _ => (max_height - min_height, min_height),
+        }
+    }
+
+    /// Converts a value of a topography tile into its height in metres,
+    /// matching the decoding of the shaders.
+    pub fn decode_height(self, value: f64, min_height: f32, max_height: f32) -> f64 {
+        let (scale, offset) = self.height_decoding(min_height, max_height);
+
+        // the value the texture is sampled as
+        let sampled = match self {
+            AttachmentFormat::R8Unorm | AttachmentFormat::Rgb8U | AttachmentFormat::Rgba8U => {
+                value / u8::MAX as f64
+            }
+            AttachmentFormat::R16U | AttachmentFormat::Rg16U => value / u16::MAX as f64,
+            AttachmentFormat::R16I => value / i16::MAX as f64,
+            AttachmentFormat::R32F => value,
+        };
+
+        scale as f64 * sampled + offset as f64
+    }
+
+    /// Whether the lowest bit of a value of a masked attachment marks the pixel as valid.
+    pub(crate) fn mask_bit(self, value: f32) -> bool {
+        match self {
+            AttachmentFormat::R32F => value.to_bits() & 1 == 1,
+            AttachmentFormat::R16I => value as i16 & 1 == 1,
+            _ => value as u32 & 1 == 1,
+        }
+    }
+
+    /// Stores the validity of the pixel in the lowest bit of the value of a masked attachment,
+    /// like the preprocessing does. The value is rounded to the range of the format first.
+    pub(crate) fn with_mask_bit(self, value: f32, valid: bool) -> f32 {
+        // `as` saturates at the bounds of the integer types
+        match self {
+            AttachmentFormat::R32F => f32::from_bits((value.to_bits() & !1) | valid as u32),
+            AttachmentFormat::R16I => ((value.round() as i16 & !1) | valid as i16) as f32,
+            AttachmentFormat::R16U | AttachmentFormat::Rg16U => {
+                ((value.round() as u16 & !1) | valid as u16) as f32
+            }
+            AttachmentFormat::R8Unorm | AttachmentFormat::Rgb8U | AttachmentFormat::Rgba8U => {
+                ((value.round() as u8 & !1) | valid as u8) as f32
+            }
+        }
+    }
+
+    /// The number of channels stored in the tiles of this format.
+    pub fn channel_count(self) -> u32 {
+        match self {
+            AttachmentFormat::Rgb8U => 3,
+            AttachmentFormat::Rgba8U => 4,
+            AttachmentFormat::Rg16U => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// The algorithm used to resample an attachment.
+/// Categorical data, like class ids, has to use [`Nearest`](Self::Nearest) or [`Mode`](Self::Mode).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResamplingMethod {
+    Nearest,
+    #[default]
+    Bilinear,
+    Cubic,
+    CubicSpline,
+    Lanczos,
+    Average,
+    /// The value, that appears most often
+    Mode,
+}
+
+impl FromStr for ResamplingMethod {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            "cubic" => Ok(Self::Cubic),
+            "cubicspline" => Ok(Self::CubicSpline),
+            "lanczos" => Ok(Self::Lanczos),
+            "average" => Ok(Self::Average),
+            "mode" => Ok(Self::Mode),
+            _ => Err(Error),
+        }
+    }
+}
+
+/// The compression of the tile files of an attachment.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TileCompression {
+    #[default]
+    None,
+    /// Lossless, with a predictor matching the data type
+    Deflate,
+    /// Lossless, with a predictor matching the data type
+    Zstd,
+    /// Lossy for elevation data, every value deviates by at most `max_error`
+    Lerc { max_error: f64 },
+    /// Lossy for 8 bit RGB(A) imagery, lossless with a quality of 100
+    WebP { quality: u8 },
+    /// Lossy for 8 bit gray or RGB imagery
+    Jpeg { quality: u8 },
+}
+
+impl FromStr for TileCompression {
+    type Err = Error;
+    /// Parses `<name>[:<parameter>]`, e.g. `deflate`, `lerc:0.5` or `webp:90`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.trim().split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter.trim())),
+            None => (s.trim(), None),
+        };
+
+        let quality = || parameter.map_or(Ok(90), |quality| quality.parse().map_err(|_| Error));
+
+        match name {
+            "none" => Ok(Self::None),
+            "deflate" => Ok(Self::Deflate),
+            "zstd" => Ok(Self::Zstd),
+            "lerc" => Ok(Self::Lerc {
+                max_error: parameter.map_or(Ok(0.0), |error| error.parse().map_err(|_| Error))?,
+            }),
+            "webp" => Ok(Self::WebP { quality: quality()? }),
+            "jpeg" => Ok(Self::Jpeg { quality: quality()? }),
+            _ => Err(Error),
+        }
+    }
+}
+
+impl Hash for TileCompression {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+
+        match *self {
+            // adding zero maps -0.0 to 0.0, so that equal errors have equal hashes
+            TileCompression::Lerc { max_error } => (max_error + 0.0).to_bits().hash(state),
+            TileCompression::WebP { quality } | TileCompression::Jpeg { quality } => {
+                quality.hash(state)
+            }
+            TileCompression::None | TileCompression::Deflate | TileCompression::Zstd => {}
+        }
+    }
+}
+
+impl TileCompression {
+    /// Whether the compression changes the values of the tiles.
+    pub fn is_lossy(self) -> bool {
+        match self {
+            TileCompression::None | TileCompression::Deflate | TileCompression::Zstd => false,
+            TileCompression::Lerc { max_error } => max_error > 0.0,
+            TileCompression::WebP { quality } => quality < 100,
+            TileCompression::Jpeg { .. } => true,
+        }
+    }
+}
+
+impl AttachmentLabel {
+    /// Whether the attachment stores classes instead of continuous values,
+    /// which must not be interpolated.
+    pub fn is_categorical(&self) -> bool {
+        matches!(self, AttachmentLabel::OceanMask)
+    }
+
+    /// The label, under which the tiles of a time slice of this attachment are stored.
+    pub fn time_slice(&self, slice: u32) -> Self {
+        AttachmentLabel::Custom(format!("{}_t{slice}", String::from(self)))
+    }
 }
 
 /// Configures an attachment.
@@ -129,6 +348,25 @@ pub struct AttachmentConfig {
     pub mask: bool,
     /// The format of the attachment.
     pub format: AttachmentFormat,
+    /// The resampling method used to reproject the sources.
+    #[serde(default)]
+    pub reproject_resampling: ResamplingMethod,
+    /// The resampling method used to downsample the tiles of one lod into the next lower one.
+    #[serde(default)]
+    pub downsample_resampling: ResamplingMethod,
+    /// The compression of the tile files.
+    #[serde(default)]
+    pub compression: TileCompression,
+    /// Whether the tiles are packed into a single [`TileArchive`](crate::utils::TileArchive),
+    /// instead of being stored as loose files.
+    #[serde(default)]
+    pub packed: bool,
+    /// The number of time slices, like monthly composites, which are spread evenly over one
+    /// cycle of the time of the [`TileAtlas`](crate::data::TileAtlas).
+    /// Each slice is stored in the same tile layout under its
+    /// [`AttachmentLabel::time_slice`]. Zero for attachments, that do not change over time.
+    #[serde(default)]
+    pub time_slices: u32,
 }
 
 impl Default for AttachmentConfig {
@@ -139,6 +377,11 @@ impl Default for AttachmentConfig {
             mip_level_count: 2,
             mask: false,
             format: AttachmentFormat::Rgba8U,
+            reproject_resampling: ResamplingMethod::Bilinear,
+            downsample_resampling: ResamplingMethod::Bilinear,
+            compression: TileCompression::None,
+            packed: false,
+            time_slices: 0,
         }
     }
 }
@@ -151,6 +394,18 @@ impl AttachmentConfig {
     pub fn offset_size(&self) -> u32 {
         self.texture_size - self.border_size
     }
+
+    /// The labels, under which the tiles of the attachment are stored,
+    /// one for each time slice or only its own label.
+    pub fn tile_labels(&self, label: &AttachmentLabel) -> Vec<AttachmentLabel> {
+        if self.time_slices == 0 {
+            vec![label.clone()]
+        } else {
+            (0..self.time_slices)
+                .map(|slice| label.time_slice(slice))
+                .collect()
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -170,7 +425,8 @@ pub enum AttachmentData {
 }
 
 impl AttachmentData {
-    pub(crate) fn from_bytes(data: &[u8], format: AttachmentFormat) -> Self {
+    /// Interprets the pixels of a tile, like they are stored in the TIFF files.
+    pub fn from_bytes(data: &[u8], format: AttachmentFormat) -> Self {
         match format {
             AttachmentFormat::Rgb8U => Self::Rgba8U(
                 data.chunks(3)
@@ -186,6 +442,56 @@ impl AttachmentData {
         }
     }
 
+    pub(crate) fn channel_count(&self) -> usize {
+        match self {
+            AttachmentData::Rgba8U(_) => 4,
+            AttachmentData::Rg16U(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// The values of all channels of all pixels.
+    pub(crate) fn to_values(&self) -> Vec<f32> {
+        match self {
+            AttachmentData::R8Unorm(data) => data.iter().map(|&v| v as f32).collect(),
+            AttachmentData::Rgba8U(data) => data.iter().flatten().map(|&v| v as f32).collect(),
+            AttachmentData::R16U(data) => data.iter().map(|&v| v as f32).collect(),
+            AttachmentData::R16I(data) => data.iter().map(|&v| v as f32).collect(),
+            AttachmentData::Rg16U(data) => data.iter().flatten().map(|&v| v as f32).collect(),
+            AttachmentData::R32F(data) => data.clone(),
+        }
+    }
+
+    /// Creates data of the same format from the values of all channels of all pixels,
+    /// which are rounded and clamped to the range of the format.
+    pub(crate) fn with_values(&self, values: &[f32]) -> Self {
+        // `as` saturates at the bounds of the integer types
+        match self {
+            AttachmentData::R8Unorm(_) => {
+                Self::R8Unorm(values.iter().map(|v| v.round() as u8).collect())
+            }
+            AttachmentData::Rgba8U(_) => Self::Rgba8U(
+                values
+                    .chunks(4)
+                    .map(|v| [0, 1, 2, 3].map(|i| v[i].round() as u8))
+                    .collect(),
+            ),
+            AttachmentData::R16U(_) => {
+                Self::R16U(values.iter().map(|v| v.round() as u16).collect())
+            }
+            AttachmentData::R16I(_) => {
+                Self::R16I(values.iter().map(|v| v.round() as i16).collect())
+            }
+            AttachmentData::Rg16U(_) => Self::Rg16U(
+                values
+                    .chunks(2)
+                    .map(|v| [v[0].round() as u16, v[1].round() as u16])
+                    .collect(),
+            ),
+            AttachmentData::R32F(_) => Self::R32F(values.to_vec()),
+        }
+    }
+
     pub(crate) fn bytes(&self) -> &[u8] {
         match self {
             AttachmentData::Rgba8U(data) => cast_slice(data),
@@ -202,6 +508,10 @@ impl AttachmentData {
 pub struct AttachmentTile {
     pub(crate) coordinate: TileCoordinate,
     pub(crate) label: AttachmentLabel,
+    /// The time slice to load, if the attachment has any.
+    pub(crate) slice: Option<u32>,
+    /// The half of the atlas texture, that the time slice is loaded into.
+    pub(crate) slot: usize,
 }
 
 #[derive(Clone)]
@@ -219,7 +529,10 @@ pub struct Attachment {
     pub(crate) border_size: u32,
     pub(crate) mip_level_count: u32,
     pub(crate) format: AttachmentFormat,
+    pub(crate) compression: TileCompression,
+    pub(crate) packed: bool,
     pub(crate) mask: bool,
+    pub(crate) time_slices: Option<TimeSlices>,
 }
 
 impl Attachment {
@@ -238,7 +551,159 @@ impl Attachment {
             border_size: config.border_size,
             mip_level_count: config.mip_level_count,
             format: config.format,
+            compression: config.compression,
+            packed: config.packed,
             mask: config.mask,
+            time_slices: (config.time_slices > 0).then(|| TimeSlices::new(config.time_slices)),
+        }
+    }
+
+    /// The number of tiles, that have to be loaded for every tile of the atlas.
+    pub(crate) fn slot_count(&self) -> u32 {
+        if self.time_slices.is_some() { 2 } else { 1 }
+    }
+}
+
+/// The two time slices of an attachment, that are blended in the shader.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SliceBlend {
+    /// The offsets of the atlas layers of both slices.
+    pub(crate) layer_offsets: [u32; 2],
+    /// The weight of the second slice.
+    pub(crate) weight: f32,
+}
+
+/// Keeps the two time slices around the current time of an attachment in the atlas.
+///
+/// The atlas texture of the attachment is twice as deep, and each half (slot) stores one slice.
+/// Once the time passes a slice, the slot of the previous slice is reloaded with the next one,
+/// while the other slot stays in place.
+pub(crate) struct TimeSlices {
+    count: u32,
+    /// The slice stored in each slot.
+    pub(crate) slots: [u32; 2],
+    /// The two slices around the current time and the weight of the second one.
+    slices: [u32; 2],
+    weight: f32,
+    /// Whether every loaded tile of the slot stores its current slice.
+    ready: [bool; 2],
+    /// The loaded tiles and their slots, that are still being reloaded with a new slice.
+    pub(crate) reloading: HashSet<(TileCoordinate, usize)>,
+}
+
+impl TimeSlices {
+    fn new(count: u32) -> Self {
+        Self {
+            count,
+            slots: [0, 1 % count],
+            slices: [0, 1 % count],
+            weight: 0.0,
+            ready: [true; 2],
+            reloading: HashSet::new(),
+        }
+    }
+
+    /// Selects the two slices around the time, which is the position within one cycle of the
+    /// slices from zero to one, and returns the slots, that have to be reloaded.
+    pub(crate) fn set_time(&mut self, time: f32) -> Vec<usize> {
+        let position = time.rem_euclid(1.0) * self.count as f32;
+        let first = (position as u32).min(self.count - 1);
+        let second = (first + 1) % self.count;
+
+        self.slices = [first, second];
+        self.weight = (position - first as f32).clamp(0.0, 1.0);
+
+        let mut reassigned = Vec::new();
+
+        for slice in self.slices {
+            if !self.slots.contains(&slice) {
+                // the other slot already stores one of the two slices
+                let slot = (0..2)
+                    .find(|&slot| !self.slices.contains(&self.slots[slot]))
+                    .unwrap();
+
+                self.slots[slot] = slice;
+                self.ready[slot] = false;
+                reassigned.push(slot);
+            }
+        }
+
+        reassigned
+    }
+
+    /// Marks the slot of the tile as reloaded, and returns whether the tile was reloaded at all.
+    pub(crate) fn tile_reloaded(&mut self, coordinate: TileCoordinate, slot: usize) -> bool {
+        let reloaded = self.reloading.remove(&(coordinate, slot));
+        self.update_ready();
+        reloaded
+    }
+
+    pub(crate) fn update_ready(&mut self) {
+        if self.reloading.is_empty() {
+            self.ready = [true; 2];
+        }
+    }
+
+    /// The layers and weight, with which the shader blends the two slices around the time.
+    /// Slots, that are still being reloaded, are left out.
+    pub(crate) fn blend(&self, atlas_size: u32) -> SliceBlend {
+        let slot = |slice| self.slots.iter().position(|&s| s == slice).unwrap();
+        let (first, second) = (slot(self.slices[0]), slot(self.slices[1]));
+
+        let (first, second, weight) = match (self.ready[first], self.ready[second]) {
+            (true, false) => (first, first, 0.0),
+            (false, true) => (second, second, 0.0),
+            _ => (first, second, self.weight),
+        };
+
+        SliceBlend {
+            layer_offsets: [first as u32 * atlas_size, second as u32 * atlas_size],
+            weight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MIN_HEIGHT: f32 = -11_000.0;
+    const MAX_HEIGHT: f32 = 8_800.0;
+
+    #[test]
+    fn heights_in_metres_are_stored_as_they_are() {
+        for height in [-10_994.0, -1.0, 0.0, 4_807.0, 8_848.0] {
+            let decoded = AttachmentFormat::R32F.decode_height(height, MIN_HEIGHT, MAX_HEIGHT);
+            assert_eq!(decoded, height);
+
+            let decoded = AttachmentFormat::R16I.decode_height(height, MIN_HEIGHT, MAX_HEIGHT);
+            assert!((decoded - height).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn normalized_heights_span_the_height_range() {
+        let format = AttachmentFormat::R8Unorm;
+
+        assert_eq!(format.decode_height(0.0, MIN_HEIGHT, MAX_HEIGHT), MIN_HEIGHT as f64);
+        assert_eq!(format.decode_height(255.0, MIN_HEIGHT, MAX_HEIGHT), MAX_HEIGHT as f64);
+
+        let middle = format.decode_height(127.5, MIN_HEIGHT, MAX_HEIGHT);
+        assert!((middle - (MIN_HEIGHT + MAX_HEIGHT) as f64 / 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn decoding_matches_the_shader() {
+        // the shader applies the decoding to the sampled value
+        for (format, value, sampled) in [
+            (AttachmentFormat::R8Unorm, 51.0, 0.2),
+            (AttachmentFormat::R16I, -16_383.5, -0.5),
+            (AttachmentFormat::R32F, 123.0, 123.0),
+        ] {
+            let (scale, offset) = format.height_decoding(MIN_HEIGHT, MAX_HEIGHT);
+            let decoded = format.decode_height(value, MIN_HEIGHT, MAX_HEIGHT);
+
+            assert!((decoded - (scale * sampled + offset) as f64).abs() < 1e-2);
         }
     }
 }
@@ -0,0 +1,259 @@
+use crate::{
+    data::{Attachment, AttachmentData},
+    math::{Coordinate, EarthShape, TileCoordinate},
+};
+use bevy::math::{DVec2, DVec3};
+use itertools::{Itertools, iproduct};
+use serde::{Deserialize, Serialize};
+use std::iter;
+
+/// A crater, that is dug into the topography of a [`TileAtlas`](super::TileAtlas).
+///
+/// The craters are applied to every topography tile once it is loaded, so they persist,
+/// while the tiles are evicted and reloaded. They are saved next to the earth data,
+/// to restore them in the next session.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Crater {
+    /// The geodetic longitude of the center (in radians).
+    pub lon: f64,
+    /// The geodetic latitude of the center (in radians).
+    pub lat: f64,
+    /// The radius of the crater in metres.
+    pub radius: f64,
+    /// The depth in metres, sampled evenly from the center to the radius of the crater.
+    /// Negative depths raise the terrain, e.g. for the rim.
+    /// The last sample should be zero, to blend into the surrounding terrain.
+    pub profile: Vec<f32>,
+}
+
+impl Crater {
+    pub fn new(lon: f64, lat: f64, radius: f64, profile: Vec<f32>) -> Self {
+        Self {
+            lon,
+            lat,
+            radius,
+            profile,
+        }
+    }
+
+    /// A parabolic bowl, which is deepest at its center.
+    pub fn bowl(lon: f64, lat: f64, radius: f64, depth: f32) -> Self {
+        let profile = (0..=16)
+            .map(|i| {
+                let t = i as f32 / 16.0;
+                depth * (1.0 - t * t)
+            })
+            .collect();
+
+        Self::new(lon, lat, radius, profile)
+    }
+
+    /// The depth of the crater at the distance from its center.
+    fn depth(&self, distance: f64) -> f32 {
+        let t = distance / self.radius;
+
+        if t >= 1.0 || self.profile.is_empty() {
+            return 0.0;
+        }
+
+        let position = t as f32 * (self.profile.len() - 1) as f32;
+        let index = position as usize;
+        let next = (index + 1).min(self.profile.len() - 1);
+        let weight = position.fract();
+
+        self.profile[index] * (1.0 - weight) + self.profile[next] * weight
+    }
+
+    fn local_position(&self, shape: EarthShape) -> DVec3 {
+        shape.position_unit_to_local(shape.unit_position_from_lon_lat(self.lon, self.lat), 0.0)
+    }
+
+    /// Whether the crater overlaps the tile including its border.
+    pub(crate) fn overlaps(
+        &self,
+        tile: TileCoordinate,
+        attachment: &Attachment,
+        shape: EarthShape,
+    ) -> bool {
+        let border = attachment.border_size as f64 / attachment.center_size as f64;
+        let local_position = |offset: DVec2| {
+            let uv = (tile.xy.as_dvec2() + offset) / (tile.lod as f64).exp2();
+            Coordinate::new(tile.face, uv).local_position(shape, 0.0)
+        };
+
+        let center = local_position(DVec2::splat(0.5));
+        let radius = [-border, 1.0 + border]
+            .into_iter()
+            .cartesian_product([-border, 1.0 + border])
+            .map(|(x, y)| local_position(DVec2::new(x, y)).distance(center))
+            .fold(0.0, f64::max);
+
+        center.distance(self.local_position(shape)) <= radius + self.radius
+    }
+}
+
+/// Digs all craters overlapping the tile into its topography data.
+pub(crate) fn dig_craters(
+    craters: &[Crater],
+    tile: TileCoordinate,
+    data: AttachmentData,
+    attachment: &Attachment,
+    shape: EarthShape,
+    min_height: f32,
+    max_height: f32,
+) -> AttachmentData {
+    let craters = craters
+        .iter()
+        .filter(|crater| crater.overlaps(tile, attachment, shape))
+        .collect_vec();
+
+    if craters.is_empty() {
+        return data;
+    }
+
+    let centers = craters
+        .iter()
+        .map(|crater| crater.local_position(shape))
+        .collect_vec();
+
+    let texture_size = attachment.texture_size as usize;
+    let border_size = attachment.border_size as f64;
+    let center_size = attachment.center_size as f64;
+    let face_pixels = center_size * (tile.lod as f64).exp2();
+
+    let format = attachment.format;
+    let metres_per_unit = (format.decode_height(1.0, min_height, max_height)
+        - format.decode_height(0.0, min_height, max_height)) as f32;
+
+    let channel_count = data.channel_count();
+    let mut values = data.to_values();
+
+    for (y, x) in iproduct!(0..texture_size, 0..texture_size) {
+        let pixel = DVec2::new(x as f64, y as f64) + 0.5 - border_size;
+        let uv = (tile.xy.as_dvec2() * center_size + pixel) / face_pixels;
+        let position = Coordinate::new(tile.face, uv).local_position(shape, 0.0);
+
+        let depth = iter::zip(&craters, &centers)
+            .map(|(crater, center)| crater.depth(position.distance(*center)))
+            .sum::<f32>();
+
+        if depth == 0.0 {
+            continue;
+        }
+
+        let value = &mut values[(y * texture_size + x) * channel_count];
+        let deformed = *value - depth / metres_per_unit;
+
+        *value = if attachment.mask {
+            // the lowest bit marks the valid pixels and stays unchanged
+            format.with_mask_bit(deformed, format.mask_bit(*value))
+        } else {
+            deformed
+        };
+    }
+
+    data.with_values(&values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{AttachmentConfig, AttachmentFormat};
+    use bevy::math::IVec2;
+
+    const SHAPE: EarthShape = EarthShape::Sphere { radius: 1000.0 };
+
+    fn attachment(format: AttachmentFormat, mask: bool) -> Attachment {
+        Attachment::new(
+            &AttachmentConfig {
+                texture_size: 12,
+                border_size: 2,
+                format,
+                mask,
+                ..Default::default()
+            },
+            "",
+        )
+    }
+
+    /// A tile and a crater, whose center is at the center of the tile.
+    fn crater() -> (TileCoordinate, Crater) {
+        let tile = TileCoordinate::new(0, 3, IVec2::new(4, 4));
+
+        let center = Coordinate::new(0, DVec2::splat(4.5 / 8.0)).unit_position(true);
+        let (lon, lat) = SHAPE.lon_lat_from_unit_position(center);
+
+        (tile, Crater::bowl(lon, lat, 50.0, 5.0))
+    }
+
+    #[test]
+    fn profile_is_interpolated() {
+        let crater = Crater::new(0.0, 0.0, 10.0, vec![4.0, 2.0, 0.0]);
+
+        assert_eq!(crater.depth(0.0), 4.0);
+        assert_eq!(crater.depth(2.5), 3.0);
+        assert_eq!(crater.depth(7.5), 1.0);
+        assert_eq!(crater.depth(10.0), 0.0);
+        assert_eq!(crater.depth(20.0), 0.0);
+    }
+
+    #[test]
+    fn crater_only_deforms_overlapping_pixels() {
+        let attachment = attachment(AttachmentFormat::R32F, false);
+        let (tile, crater) = crater();
+
+        let far = TileCoordinate::new(0, 3, IVec2::new(0, 0));
+        assert!(crater.overlaps(tile, &attachment, SHAPE));
+        assert!(!crater.overlaps(far, &attachment, SHAPE));
+
+        let data = AttachmentData::R32F(vec![10.0; 12 * 12]);
+        let values = dig_craters(&[crater], tile, data, &attachment, SHAPE, 0.0, 100.0).to_values();
+
+        // the pixels next to the center are the deepest, the corners stay untouched
+        assert!(values[5 * 12 + 5] < 6.0);
+        assert!(values[5 * 12 + 5] < values[5 * 12 + 3]);
+        assert_eq!(values[0], 10.0);
+        assert_eq!(values[12 * 12 - 1], 10.0);
+    }
+
+    #[test]
+    fn crater_keeps_the_mask_of_r32f_heights() {
+        let format = AttachmentFormat::R32F;
+        let attachment = attachment(format, true);
+        let (tile, crater) = crater();
+
+        // every other pixel is invalid
+        let masked = (0..12 * 12)
+            .map(|i| format.with_mask_bit(-30.5, i % 2 == 0))
+            .collect_vec();
+
+        let data = AttachmentData::R32F(masked.clone());
+        let values =
+            dig_craters(&[crater], tile, data, &attachment, SHAPE, -100.0, 100.0).to_values();
+
+        for (i, (&before, &after)) in iter::zip(&masked, &values).enumerate() {
+            assert_eq!(format.mask_bit(after), i % 2 == 0);
+            assert!(after <= before + 1e-4);
+        }
+
+        // the heights stay fractional and below sea level
+        assert!(values[5 * 12 + 5] < -34.0);
+        assert!((values[0] + 30.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn crater_deepens_negative_r16i_heights() {
+        let format = AttachmentFormat::R16I;
+        let attachment = attachment(format, true);
+        let (tile, crater) = crater();
+
+        let value = format.with_mask_bit(-100.0, true) as i16;
+        let data = AttachmentData::R16I(vec![value; 12 * 12]);
+        let values =
+            dig_craters(&[crater], tile, data, &attachment, SHAPE, -100.0, 100.0).to_values();
+
+        assert!(values[5 * 12 + 5] <= -103.0);
+        assert!(values.iter().all(|&value| format.mask_bit(value)));
+        assert_eq!(values[0], value as f32);
+    }
+}
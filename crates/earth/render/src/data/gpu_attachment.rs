@@ -1,5 +1,8 @@
 use crate::{
-    data::{AttachmentFormat, AttachmentLabel, TileAtlas, attachment::Attachment},
+    data::{
+        AttachmentFormat, AttachmentLabel, SliceBlend, TileAtlas, TileDownload,
+        attachment::Attachment,
+    },
     plugin::EarthSettings,
     preprocess::MipPipelines,
     utils::GpuBuffer,
@@ -16,6 +19,8 @@ use itertools::Itertools;
 use std::{iter, mem};
 
 const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+/// The number of tiles per attachment, that are at most downloaded each frame.
+pub(crate) const MAX_DOWNLOADS_PER_FRAME: usize = 4;
 
 fn align_byte_size(value: u32) -> u32 {
     // only works for non zero values
@@ -141,7 +146,7 @@ impl AtlasBufferInfo {
         }
     }
 
-    fn _buffer_copy_view<'a>(&'a self, buffer: &'a Buffer, index: u32) -> TexelCopyBufferInfo<'a> {
+    fn buffer_copy_view<'a>(&'a self, buffer: &'a Buffer, index: u32) -> TexelCopyBufferInfo<'a> {
         TexelCopyBufferInfo {
             buffer,
             layout: TexelCopyBufferLayout {
@@ -183,14 +188,19 @@ pub(crate) struct GpuAttachment {
     pub(crate) buffer_info: AtlasBufferInfo,
 
     pub(crate) atlas_texture: Texture,
+    /// The time slices blended by the shader, if the attachment has any.
+    pub(crate) slice_blend: SliceBlend,
 
     pub(crate) mip_pipeline: CachedComputePipelineId,
     pub(crate) mip_views: Vec<TextureView>,
     pub(crate) mips_to_generate: Vec<Vec<u32>>,
     pub(crate) mip_bind_groups: Vec<Vec<BindGroup>>,
 
+    /// The tiles, that are downloaded this frame.
+    pub(crate) downloads: Vec<TileDownload>,
+    pub(crate) download_buffers: Vec<GpuBuffer<()>>,
+
     pub(crate) _atlas_write_section: GpuBuffer<()>,
-    pub(crate) _download_buffers: Vec<GpuBuffer<()>>,
     pub(crate) _bind_group: BindGroup,
     pub(crate) _max_atlas_write_slots: u32,
     pub(crate) _atlas_write_slots: Vec<AtlasTileAttachment>,
@@ -221,7 +231,8 @@ impl GpuAttachment {
             size: Extent3d {
                 width: buffer_info.texture_size,
                 height: buffer_info.texture_size,
-                depth_or_array_layers: settings.atlas_size,
+                // attachments with time slices store two slices per tile
+                depth_or_array_layers: settings.atlas_size * attachment.slot_count(),
             },
             mip_level_count: attachment.mip_level_count,
             sample_count: 1,
@@ -282,13 +293,16 @@ impl GpuAttachment {
             index,
             buffer_info,
             atlas_texture,
+            slice_blend: default(),
             mip_pipeline: CachedComputePipelineId::INVALID,
             mip_views,
             mips_to_generate: vec![default(); buffer_info.mip_level_count as usize],
             mip_bind_groups: vec![default(); buffer_info.mip_level_count as usize],
 
+            downloads: default(),
+            download_buffers: default(),
+
             _atlas_write_section: atlas_write_section,
-            _download_buffers: default(),
             _bind_group: bind_group,
             _max_atlas_write_slots: max_atlas_write_slots,
             _atlas_write_slots: atlas_write_slots,
@@ -330,7 +344,7 @@ impl GpuAttachment {
                 self.buffer_info
                     .texture_copy_view(&self.atlas_texture, tile.atlas_index, 0),
                 self.buffer_info
-                    ._buffer_copy_view(&self._atlas_write_section, section_index as u32),
+                    .buffer_copy_view(&self._atlas_write_section, section_index as u32),
                 self.buffer_info.extend_3d(0),
             );
         }
@@ -340,7 +354,7 @@ impl GpuAttachment {
         for (section_index, tile) in self._atlas_write_slots.iter().enumerate() {
             command_encoder.copy_buffer_to_texture(
                 self.buffer_info
-                    ._buffer_copy_view(&self._atlas_write_section, section_index as u32),
+                    .buffer_copy_view(&self._atlas_write_section, section_index as u32),
                 self.buffer_info
                     .texture_copy_view(&self.atlas_texture, tile.atlas_index, 0),
                 self.buffer_info.extend_3d(0),
@@ -348,20 +362,20 @@ impl GpuAttachment {
         }
     }
 
-    pub(crate) fn _download_tiles(&self, command_encoder: &mut CommandEncoder) {
-        for (tile, download_buffer) in iter::zip(&self._atlas_write_slots, &self._download_buffers)
-        {
+    /// Copies the tiles, that are downloaded this frame, into their download buffers.
+    pub(crate) fn download_tiles(&self, command_encoder: &mut CommandEncoder) {
+        for (download, download_buffer) in iter::zip(&self.downloads, &self.download_buffers) {
             command_encoder.copy_texture_to_buffer(
                 self.buffer_info
-                    .texture_copy_view(&self.atlas_texture, tile.atlas_index, 0),
-                self.buffer_info._buffer_copy_view(download_buffer, 0),
+                    .texture_copy_view(&self.atlas_texture, download.atlas_index, 0),
+                self.buffer_info.buffer_copy_view(download_buffer, 0),
                 self.buffer_info.extend_3d(0),
             );
         }
     }
 
-    pub(crate) fn _create_download_buffers(&mut self, device: &RenderDevice) {
-        self._download_buffers = (0..self._atlas_write_slots.len())
+    pub(crate) fn create_download_buffers(&mut self, device: &RenderDevice) {
+        self.download_buffers = (0..self.downloads.len())
             .map(|_| {
                 GpuBuffer::empty_sized_labeled(
                     None,
@@ -1,7 +1,8 @@
 use crate::{
     earth::EarthComponents,
     data::{
-        AttachmentData, AttachmentLabel, AttachmentTileWithData, GpuAttachment, TileAtlas,
+        AttachmentData, AttachmentFormat, AttachmentLabel, AttachmentTileWithData, GpuAttachment,
+        MAX_DOWNLOADS_PER_FRAME, TileAtlas, TileDownload,
     },
     plugin::EarthSettings,
     preprocess::{MipPipelineKey, MipPipelines},
@@ -22,15 +23,24 @@ use std::{iter, mem};
 /// alongside the data to update it.
 ///
 /// All attachments of newly loaded tiles are copied into their according atlas attachment.
+/// Tiles requested with [`TileAtlas::download_tile`] are copied back into buffers
+/// and read on the async compute task pool.
 #[derive(Component)]
 pub struct GpuTileAtlas {
     /// Stores the atlas attachments of the earth.
     pub(crate) attachments: HashMap<AttachmentLabel, GpuAttachment>,
     pub(crate) upload_tiles: Vec<AttachmentTileWithData>,
-    pub(crate) download_tiles: Vec<Task<AttachmentTileWithData>>,
+    pub(crate) download_tiles: Vec<Task<(TileDownload, AttachmentData)>>,
 }
 
 impl GpuTileAtlas {
+    /// Copies the tiles, that are downloaded this frame, out of the atlas attachments.
+    pub(crate) fn download_tiles(&self, command_encoder: &mut CommandEncoder) {
+        for attachment in self.attachments.values() {
+            attachment.download_tiles(command_encoder);
+        }
+    }
+
     pub(crate) fn generate_mip(&self, pass: &mut ComputePass, pipeline_cache: &PipelineCache) {
         for attachment in self.attachments.values() {
             let Some(pipeline) = pipeline_cache.get_compute_pipeline(attachment.mip_pipeline)
@@ -107,7 +117,11 @@ impl GpuTileAtlas {
                 &mut gpu_tile_atlas.upload_tiles,
             );
 
-            for attachment in gpu_tile_atlas.attachments.values_mut() {
+            for (label, attachment) in gpu_tile_atlas.attachments.iter_mut() {
+                if let Some(time_slices) = &tile_atlas.attachments[label].time_slices {
+                    attachment.slice_blend = time_slices.blend(tile_atlas.atlas_size);
+                }
+
                 attachment
                     .mips_to_generate
                     .iter_mut()
@@ -126,6 +140,25 @@ impl GpuTileAtlas {
                 }
             }
 
+            // the remaining downloads are postponed to the next frames
+            let mut postponed = Vec::new();
+
+            for download in mem::take(&mut tile_atlas.to_download) {
+                // only downloads of known attachments are queued
+                let Some(attachment) = gpu_tile_atlas.attachments.get_mut(&download.tile.label)
+                else {
+                    continue;
+                };
+
+                if attachment.downloads.len() < MAX_DOWNLOADS_PER_FRAME {
+                    attachment.downloads.push(download);
+                } else {
+                    postponed.push(download);
+                }
+            }
+
+            tile_atlas.to_download = postponed;
+
             tile_atlas
                 .downloading_tiles
                 .extend(mem::take(&mut gpu_tile_atlas.download_tiles));
@@ -143,6 +176,7 @@ impl GpuTileAtlas {
         for gpu_tile_atlas in gpu_tile_atlases.values_mut() {
             for attachment in gpu_tile_atlas.attachments.values_mut() {
                 attachment.prepare_mip_bind_groups(&device, &mip_pipelines);
+                attachment.create_download_buffers(&device);
             }
 
             gpu_tile_atlas.upload_tiles(&queue);
@@ -168,9 +202,10 @@ impl GpuTileAtlas {
         }
     }
 
-    pub(crate) fn _cleanup(mut gpu_tile_atlases: ResMut<EarthComponents<GpuTileAtlas>>) {
+    /// Starts reading the tiles, that have been copied into the download buffers this frame.
+    pub(crate) fn cleanup(mut gpu_tile_atlases: ResMut<EarthComponents<GpuTileAtlas>>) {
         for gpu_tile_atlas in gpu_tile_atlases.values_mut() {
-            gpu_tile_atlas._start_downloading_tiles();
+            gpu_tile_atlas.start_downloading_tiles();
         }
     }
 
@@ -195,15 +230,15 @@ impl GpuTileAtlas {
         }
     }
 
-    fn _start_downloading_tiles(&mut self) {
+    fn start_downloading_tiles(&mut self) {
         for attachment in self.attachments.values_mut() {
             let buffer_info = attachment.buffer_info;
-            let download_buffers = mem::take(&mut attachment._download_buffers);
-            let atlas_write_slots = mem::take(&mut attachment._atlas_write_slots);
+            let download_buffers = mem::take(&mut attachment.download_buffers);
+            let downloads = mem::take(&mut attachment.downloads);
 
             self.download_tiles
-                .extend(iter::zip(atlas_write_slots, download_buffers).map(
-                    |(tile, download_buffer)| {
+                .extend(iter::zip(downloads, download_buffers).map(
+                    |(download, download_buffer)| {
                         AsyncComputeTaskPool::get().spawn(async move {
                             let (tx, rx) = async_channel::bounded(1);
 
@@ -239,11 +274,13 @@ impl GpuTileAtlas {
                                 data.truncate(buffer_info.actual_tile_size as usize);
                             }
 
-                            AttachmentTileWithData {
-                                atlas_index: tile.atlas_index,
-                                label: tile.label,
-                                data: AttachmentData::from_bytes(&data, buffer_info.format),
-                            }
+                            // three channel attachments are stored with an alpha channel
+                            let format = match buffer_info.format {
+                                AttachmentFormat::Rgb8U => AttachmentFormat::Rgba8U,
+                                format => format,
+                            };
+
+                            (download, AttachmentData::from_bytes(&data, format))
                         })
                     },
                 ));
@@ -16,20 +16,32 @@
 //! which can be used to access the earth data in shaders.
 
 mod attachment;
+mod crater;
 mod gpu_attachment;
 mod gpu_tile_atlas;
 mod tile_atlas;
+mod tile_detail;
 mod tile_loader;
+mod tile_source;
 mod tile_tree;
 
 pub use self::{
-    attachment::{AttachmentConfig, AttachmentFormat, AttachmentLabel},
+    attachment::{
+        AttachmentConfig, AttachmentData, AttachmentFormat, AttachmentLabel, ResamplingMethod,
+        TileCompression,
+    },
+    crater::Crater,
     gpu_tile_atlas::GpuTileAtlas,
-    tile_atlas::TileAtlas,
+    tile_atlas::{DownloadedTile, TileAtlas, TileDownloadError},
+    tile_detail::DetailTileSource,
+    tile_loader::TileLoader,
+    tile_source::{AssetTileSource, MemoryTileSource, TileRequest, TileSource},
     tile_tree::TileTree,
 };
 
-pub(crate) use self::{attachment::*, gpu_attachment::*, tile_loader::*, tile_tree::*};
+pub(crate) use self::{
+    attachment::*, gpu_attachment::*, tile_atlas::TileDownload, tile_loader::*, tile_tree::*,
+};
 
 pub const INVALID_ATLAS_INDEX: u32 = u32::MAX;
 pub const INVALID_LOD: u32 = u32::MAX;
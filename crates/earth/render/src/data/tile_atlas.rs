@@ -1,24 +1,34 @@
 use crate::{
     earth::EarthConfig,
     data::{
-        Attachment, AttachmentData, AttachmentLabel, AttachmentTile, AttachmentTileWithData,
-        DefaultLoader, TileTree, TileTreeEntry,
+        Attachment, AttachmentData, AttachmentFormat, AttachmentLabel, AttachmentTile,
+        AttachmentTileWithData, Crater, TileCompression, TileLoader, TileTree, TileTreeEntry,
+        crater::dig_craters,
     },
     math::{EarthShape, TileCoordinate},
     plugin::EarthSettings,
     render::EarthUniform,
+    utils::{can_save_tile, save_tile},
     view::EarthViewComponents,
 };
 use bevy::{
-    asset::RenderAssetUsages,
+    asset::{RenderAssetUsages, ron},
     camera::visibility::{VisibilityClass, add_visibility_class},
     platform::collections::{HashMap, HashSet},
     prelude::*,
     render::{render_resource::*, storage::ShaderStorageBuffer},
-    tasks::Task,
+    tasks::{IoTaskPool, Task, block_on},
 };
 use big_space::prelude::*;
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt, fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+/// The file next to the earth data, that stores the craters between sessions.
+const CRATERS_FILE: &str = "craters.ron";
 
 /// The current state of a tile of a [`TileAtlas`].
 ///
@@ -29,6 +39,8 @@ enum LoadingState {
     Loading(u32),
     /// The tile is loaded and can be used.
     Loaded,
+    /// One of the attachments could not be loaded, so the tile is never used.
+    Failed,
 }
 
 /// The internal representation of a present tile in a [`TileAtlas`].
@@ -41,6 +53,60 @@ struct TileState {
     requests: u32,
 }
 
+/// The attachment of a loaded tile, that is downloaded from the GPU.
+#[derive(Clone, Debug)]
+pub(crate) struct TileDownload {
+    pub(crate) tile: AttachmentTile,
+    pub(crate) atlas_index: u32,
+    /// The directory of the attachments, that the tile is saved to.
+    pub(crate) save_path: Option<PathBuf>,
+}
+
+/// The attachment of a tile, that has been downloaded from the GPU.
+pub struct DownloadedTile {
+    pub coordinate: TileCoordinate,
+    /// The label of the attachment, which includes the time slice.
+    pub label: AttachmentLabel,
+    pub data: AttachmentData,
+}
+
+/// The reason, why a tile can not be downloaded with [`TileAtlas::download_tile`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum TileDownloadError {
+    /// The tile atlas has no attachment with the label.
+    UnknownAttachment(AttachmentLabel),
+    /// The tile is not loaded, so there is nothing on the GPU to download.
+    NotLoaded(TileCoordinate),
+    /// The tiles of the attachment are stored in an archive and can not be saved one by one.
+    Packed(AttachmentLabel),
+    /// The tiles of the attachment can not be written in its format and compression.
+    UnsupportedSave {
+        format: AttachmentFormat,
+        compression: TileCompression,
+    },
+}
+
+impl fmt::Display for TileDownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownAttachment(label) => write!(f, "the earth has no {label:?} attachment"),
+            Self::NotLoaded(coordinate) => write!(f, "the tile {coordinate} is not loaded"),
+            Self::Packed(label) => {
+                write!(f, "the tiles of the packed {label:?} attachment can not be saved")
+            }
+            Self::UnsupportedSave {
+                format,
+                compression,
+            } => write!(
+                f,
+                "{format:?} tiles with {compression:?} compression can not be saved"
+            ),
+        }
+    }
+}
+
+impl Error for TileDownloadError {}
+
 // Todo: rename to earths?
 // Todo: consider turning this into an asset
 
@@ -57,7 +123,7 @@ struct TileState {
 /// The [`u32`] can be used for accessing the attached data in systems by the CPU
 /// and in shaders by the GPU.
 #[derive(Component)]
-#[require(Transform, CellCoord, Visibility, VisibilityClass, DefaultLoader)]
+#[require(Transform, CellCoord, Visibility, VisibilityClass, TileLoader)]
 #[component(on_add = add_visibility_class::<TileAtlas>)]
 pub struct TileAtlas {
     pub(crate) attachments: HashMap<AttachmentLabel, Attachment>, // stores the attachment data
@@ -65,10 +131,22 @@ pub struct TileAtlas {
     unused_indices: VecDeque<u32>,
     existing_tiles: HashSet<TileCoordinate>,
     pub(crate) uploading_tiles: Vec<AttachmentTileWithData>,
-    pub(crate) downloading_tiles: Vec<Task<AttachmentTileWithData>>,
+    pub(crate) downloading_tiles: Vec<Task<(TileDownload, AttachmentData)>>,
     pub(crate) to_load: Vec<AttachmentTile>,
+    pub(crate) to_download: Vec<TileDownload>,
+    downloaded_tiles: Vec<DownloadedTile>,
+    /// The craters dug into the topography, which are applied to every loaded tile.
+    craters: Vec<Crater>,
+    /// The file, that the craters are saved to, or `None` if they are not persisted.
+    craters_path: Option<PathBuf>,
+    /// The loaded tiles and their slots, that are reloaded with new craters.
+    deforming: HashSet<(TileCoordinate, usize)>,
 
+    pub(crate) atlas_size: u32,
+    /// The number of lods, including the procedurally synthesised ones.
     pub(crate) lod_count: u32,
+    /// The number of lods, whose tiles are stored in the data.
+    data_lod_count: u32,
     pub(crate) min_height: f32,
     pub(crate) max_height: f32,
     pub(crate) height_scale: f32,
@@ -90,6 +168,18 @@ impl TileAtlas {
             .map(|(label, attachment)| (label.clone(), Attachment::new(attachment, &config.path)))
             .collect();
 
+        let craters_path =
+            (!config.path.is_empty()).then(|| Path::new(&config.path).join(CRATERS_FILE));
+        let craters = craters_path
+            .as_deref()
+            .map(|path| {
+                load_craters(path).unwrap_or_else(|error| {
+                    warn!("Failed to load the craters {}: {error}", path.display());
+                    Vec::new()
+                })
+            })
+            .unwrap_or_default();
+
         let earth_buffer = buffers.add(ShaderStorageBuffer::with_size(
             EarthUniform::min_size().get() as usize,
             RenderAssetUsages::all(),
@@ -101,12 +191,19 @@ impl TileAtlas {
             unused_indices: (0..settings.atlas_size).collect(),
             existing_tiles: HashSet::from_iter(config.tiles.clone()),
             to_load: default(),
+            to_download: default(),
+            downloaded_tiles: default(),
+            craters,
+            craters_path,
+            deforming: default(),
             uploading_tiles: default(),
             downloading_tiles: default(),
-            lod_count: config.lod_count,
+            atlas_size: settings.atlas_size,
+            lod_count: config.lod_count + config.detail_lod_count,
+            data_lod_count: config.lod_count,
             min_height: config.min_height,
             max_height: config.max_height,
-            height_scale: 500.0,
+            height_scale: 20.0,
             shape: config.shape,
             earth_buffer,
         }
@@ -115,7 +212,7 @@ impl TileAtlas {
     pub(crate) fn get_best_tile(&self, tile_coordinate: TileCoordinate) -> TileTreeEntry {
         let mut best_tile_coordinate = tile_coordinate;
 
-        if !self.existing_tiles.contains(&tile_coordinate) {
+        if !self.tile_exists(tile_coordinate) {
             return TileTreeEntry::default();
         }
 
@@ -140,18 +237,66 @@ impl TileAtlas {
         }
     }
 
-    pub(crate) fn tile_loaded(&mut self, tile: AttachmentTile, data: AttachmentData) {
+    pub(crate) fn tile_loaded(&mut self, tile: AttachmentTile, mut data: AttachmentData) {
+        // the remaining attachments of a failed tile are discarded
+        if self
+            .tile_states
+            .get(&tile.coordinate)
+            .is_some_and(|tile_state| matches!(tile_state.state, LoadingState::Failed))
+        {
+            return;
+        }
+
+        let mut reloaded = false;
+
+        if let Some(slice) = tile.slice {
+            let time_slices = self.attachments[&tile.label].time_slices.as_ref().unwrap();
+
+            if time_slices.slots[tile.slot] != slice {
+                // the slot has been assigned another slice in the meantime
+                self.to_load.push(AttachmentTile {
+                    slice: Some(time_slices.slots[tile.slot]),
+                    ..tile
+                });
+                return;
+            }
+
+            reloaded = self
+                .attachments
+                .get_mut(&tile.label)
+                .and_then(|attachment| attachment.time_slices.as_mut())
+                .unwrap()
+                .tile_reloaded(tile.coordinate, tile.slot);
+        }
+
+        if tile.label == AttachmentLabel::Topography {
+            // a pending time slice reload and a crater reload are completed one at a time
+            reloaded = reloaded || self.deforming.remove(&(tile.coordinate, tile.slot));
+
+            data = dig_craters(
+                &self.craters,
+                tile.coordinate,
+                data,
+                &self.attachments[&tile.label],
+                self.shape,
+                self.min_height,
+                self.max_height,
+            );
+        }
+
         if let Some(tile_state) = self.tile_states.get_mut(&tile.coordinate) {
-            tile_state.state = match tile_state.state {
-                LoadingState::Loading(1) => LoadingState::Loaded,
-                LoadingState::Loading(n) => LoadingState::Loading(n - 1),
-                LoadingState::Loaded => {
-                    panic!("Loaded more attachments, than registered with the tile atlas.")
-                }
-            };
+            if !reloaded {
+                tile_state.state = match tile_state.state {
+                    LoadingState::Loading(1) => LoadingState::Loaded,
+                    LoadingState::Loading(n) => LoadingState::Loading(n - 1),
+                    LoadingState::Loaded | LoadingState::Failed => {
+                        panic!("Loaded more attachments, than registered with the tile atlas.")
+                    }
+                };
+            }
 
             self.uploading_tiles.push(AttachmentTileWithData {
-                atlas_index: tile_state.atlas_index,
+                atlas_index: tile_state.atlas_index + tile.slot as u32 * self.atlas_size,
                 label: tile.label,
                 data,
             });
@@ -160,6 +305,240 @@ impl TileAtlas {
         }
     }
 
+    /// Marks the tile as failed, after one of its attachments could not be loaded.
+    ///
+    /// The views fall back to the ancestors of a failed tile and its remaining attachments
+    /// are no longer loaded. Once the tile is released, it is removed, so that loading it
+    /// is retried, when it is requested again.
+    pub(crate) fn tile_failed(&mut self, tile: AttachmentTile) {
+        let Some(tile_state) = self.tile_states.get_mut(&tile.coordinate) else {
+            return;
+        };
+
+        tile_state.state = LoadingState::Failed;
+
+        if tile_state.requests == 0 {
+            self.tile_states.remove(&tile.coordinate);
+        }
+
+        self.to_load
+            .retain(|queued| queued.coordinate != tile.coordinate);
+        self.deforming
+            .retain(|&(coordinate, _)| coordinate != tile.coordinate);
+
+        for time_slices in self
+            .attachments
+            .values_mut()
+            .filter_map(|attachment| attachment.time_slices.as_mut())
+        {
+            time_slices
+                .reloading
+                .retain(|&(coordinate, _)| coordinate != tile.coordinate);
+            time_slices.update_ready();
+        }
+    }
+
+    /// Digs the crater into the topography of all tiles, that it overlaps, at all lods.
+    ///
+    /// Present tiles are reloaded with the crater, which regenerates their mips.
+    /// All other tiles receive it, whenever they are loaded, so it persists eviction.
+    /// The craters are saved next to the earth data and restored with the tile atlas.
+    /// Attachments derived from the topography, like the normals, are not updated.
+    pub fn add_crater(&mut self, crater: Crater) {
+        if let Some(attachment) = self.attachments.get(&AttachmentLabel::Topography) {
+            let time_slices = attachment.time_slices.as_ref();
+            let queued = self
+                .to_load
+                .iter()
+                .filter(|tile| tile.label == AttachmentLabel::Topography)
+                .map(|tile| (tile.coordinate, tile.slot))
+                .collect::<HashSet<_>>();
+
+            for (&coordinate, tile) in &self.tile_states {
+                if matches!(tile.state, LoadingState::Failed)
+                    || !crater.overlaps(coordinate, attachment, self.shape)
+                {
+                    continue;
+                }
+
+                for slot in 0..attachment.slot_count() as usize {
+                    // queued tiles and pending time slice reloads already receive the crater
+                    let queued = queued.contains(&(coordinate, slot));
+                    let reloading = time_slices.is_some_and(|time_slices| {
+                        time_slices.reloading.contains(&(coordinate, slot))
+                    });
+
+                    if queued || reloading || !self.deforming.insert((coordinate, slot)) {
+                        continue;
+                    }
+
+                    self.to_load.push(AttachmentTile {
+                        coordinate,
+                        label: AttachmentLabel::Topography,
+                        slice: time_slices.map(|time_slices| time_slices.slots[slot]),
+                        slot,
+                    });
+                }
+            }
+        }
+
+        self.craters.push(crater);
+
+        if let Some(path) = &self.craters_path
+            && let Err(error) = save_craters(path, &self.craters)
+        {
+            warn!("Failed to save the craters {}: {error}", path.display());
+        }
+    }
+
+    /// The craters dug into the topography so far.
+    pub fn craters(&self) -> &[Crater] {
+        &self.craters
+    }
+
+    /// Sets the time, which selects the two time slices of each attachment, that are blended.
+    ///
+    /// The time is the position within one cycle of the slices from zero to one,
+    /// like the fraction of the year for monthly composites.
+    /// Once the time passes a slice, the next one is loaded for all loaded tiles.
+    pub fn set_time(&mut self, time: f32) {
+        for (label, attachment) in &mut self.attachments {
+            let Some(time_slices) = &mut attachment.time_slices else {
+                continue;
+            };
+
+            for slot in time_slices.set_time(time) {
+                let slice = time_slices.slots[slot];
+
+                for (&coordinate, tile) in &self.tile_states {
+                    // loading tiles requeue their outdated slices themselves
+                    if matches!(tile.state, LoadingState::Loaded)
+                        && time_slices.reloading.insert((coordinate, slot))
+                    {
+                        self.to_load.push(AttachmentTile {
+                            coordinate,
+                            label: label.clone(),
+                            slice: Some(slice),
+                            slot,
+                        });
+                    }
+                }
+            }
+
+            time_slices.update_ready();
+        }
+    }
+
+    /// Downloads the attachment of a loaded tile from the GPU, e.g. after it has been edited
+    /// there. Attachments with time slices download the tile of both loaded slices.
+    ///
+    /// The downloaded tiles can be taken with [`Self::take_downloaded_tiles`] a few frames later.
+    /// If a directory is given, they are additionally saved into it in the layout of
+    /// [`TileCoordinate::path`], like the earth path of a [`EarthConfig`].
+    /// Saving is only supported for loose attachments, whose format and compression
+    /// can be written, which is checked before anything is downloaded.
+    pub fn download_tile(
+        &mut self,
+        coordinate: TileCoordinate,
+        label: AttachmentLabel,
+        save_path: Option<PathBuf>,
+    ) -> Result<(), TileDownloadError> {
+        let attachment = self
+            .attachments
+            .get(&label)
+            .ok_or_else(|| TileDownloadError::UnknownAttachment(label.clone()))?;
+
+        if save_path.is_some() {
+            if attachment.packed {
+                return Err(TileDownloadError::Packed(label));
+            }
+
+            if !can_save_tile(attachment.format, attachment.compression) {
+                return Err(TileDownloadError::UnsupportedSave {
+                    format: attachment.format,
+                    compression: attachment.compression,
+                });
+            }
+        }
+
+        let tile = self
+            .tile_states
+            .get(&coordinate)
+            .filter(|tile| matches!(tile.state, LoadingState::Loaded))
+            .ok_or(TileDownloadError::NotLoaded(coordinate))?;
+
+        for slot in 0..attachment.slot_count() as usize {
+            let slice = attachment
+                .time_slices
+                .as_ref()
+                .map(|time_slices| time_slices.slots[slot]);
+
+            self.to_download.push(TileDownload {
+                tile: AttachmentTile {
+                    coordinate,
+                    label: label.clone(),
+                    slice,
+                    slot,
+                },
+                atlas_index: tile.atlas_index + slot as u32 * self.atlas_size,
+                save_path: save_path.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Takes the tiles, that have been downloaded since the last call.
+    pub fn take_downloaded_tiles(&mut self) -> Vec<DownloadedTile> {
+        mem::take(&mut self.downloaded_tiles)
+    }
+
+    /// Collects the tiles, whose download from the GPU has completed, and saves them.
+    pub(crate) fn finish_downloading(mut tile_atlases: Query<&mut TileAtlas>) {
+        for mut tile_atlas in &mut tile_atlases {
+            let finished = tile_atlas
+                .downloading_tiles
+                .extract_if(.., |task| task.is_finished())
+                .map(block_on)
+                .collect::<Vec<_>>();
+
+            for (download, data) in finished {
+                let Some(attachment) = tile_atlas.attachments.get(&download.tile.label) else {
+                    continue;
+                };
+
+                // every time slice is stored like an attachment of its own
+                let label = match download.tile.slice {
+                    Some(slice) => download.tile.label.time_slice(slice),
+                    None => download.tile.label.clone(),
+                };
+
+                if let Some(save_path) = download.save_path {
+                    let path = download
+                        .tile
+                        .coordinate
+                        .path(&save_path.join(String::from(&label)));
+                    let (data, format) = (data.clone(), attachment.format);
+                    let compression = attachment.compression;
+
+                    IoTaskPool::get()
+                        .spawn(async move {
+                            if let Err(error) = save_tile(&path, &data, format, compression) {
+                                warn!("Failed to save the tile {}: {error}", path.display());
+                            }
+                        })
+                        .detach();
+                }
+
+                tile_atlas.downloaded_tiles.push(DownloadedTile {
+                    coordinate: download.tile.coordinate,
+                    label,
+                    data,
+                });
+            }
+        }
+    }
+
     /// Updates the tile atlas according to all corresponding tile_trees.
     pub(crate) fn update(
         mut tile_trees: ResMut<EarthViewComponents<TileTree>>,
@@ -188,8 +567,24 @@ impl TileAtlas {
         }
     }
 
+    /// Whether the tile is stored in the data or synthesised from a stored ancestor.
+    fn tile_exists(&self, tile_coordinate: TileCoordinate) -> bool {
+        let data_lod = self.data_lod_count - 1;
+
+        if tile_coordinate.lod <= data_lod {
+            self.existing_tiles.contains(&tile_coordinate)
+        } else {
+            let shift = (tile_coordinate.lod - data_lod) as i32;
+            self.existing_tiles.contains(&TileCoordinate::new(
+                tile_coordinate.face,
+                data_lod,
+                tile_coordinate.xy >> shift,
+            ))
+        }
+    }
+
     fn request_tile(&mut self, tile_coordinate: TileCoordinate) {
-        if !self.existing_tiles.contains(&tile_coordinate) {
+        if !self.tile_exists(tile_coordinate) {
             return;
         }
 
@@ -215,22 +610,33 @@ impl TileAtlas {
                 tile_coordinate,
                 TileState {
                     requests: 1,
-                    state: LoadingState::Loading(self.attachments.len() as u32),
+                    state: LoadingState::Loading(
+                        self.attachments.values().map(Attachment::slot_count).sum(),
+                    ),
                     atlas_index,
                 },
             );
 
-            for label in self.attachments.keys() {
-                self.to_load.push(AttachmentTile {
-                    coordinate: tile_coordinate,
-                    label: label.clone(),
-                });
+            for (label, attachment) in &self.attachments {
+                for slot in 0..attachment.slot_count() as usize {
+                    let slice = attachment
+                        .time_slices
+                        .as_ref()
+                        .map(|time_slices| time_slices.slots[slot]);
+
+                    self.to_load.push(AttachmentTile {
+                        coordinate: tile_coordinate,
+                        label: label.clone(),
+                        slice,
+                        slot,
+                    });
+                }
             }
         }
     }
 
     fn release_tile(&mut self, tile_coordinate: TileCoordinate) {
-        if !self.existing_tiles.contains(&tile_coordinate) {
+        if !self.tile_exists(tile_coordinate) {
             return;
         }
 
@@ -240,7 +646,177 @@ impl TileAtlas {
         if tile.requests == 0 {
             self.unused_indices.push_back(tile.atlas_index);
 
-            // Todo: we should cancel loading tiles, that have not yet started loading and a no longer requested
+            match tile.state {
+                LoadingState::Loading(remaining) => self.cancel_loading(tile_coordinate, remaining),
+                LoadingState::Failed => {
+                    self.tile_states.remove(&tile_coordinate);
+                }
+                LoadingState::Loaded => {}
+            }
+        }
+    }
+
+    /// Removes the attachments of a tile, that is no longer requested, from the load queue.
+    ///
+    /// The tile is only dropped, if none of its attachments have started loading yet.
+    /// Otherwise it keeps loading, so that its state stays consistent with the loader.
+    fn cancel_loading(&mut self, tile_coordinate: TileCoordinate, remaining: u32) {
+        let queued = self
+            .to_load
+            .iter()
+            .filter(|tile| tile.coordinate == tile_coordinate)
+            .count();
+
+        // reloads with new craters are loaded in addition to the remaining attachments
+        let deforming = self
+            .deforming
+            .iter()
+            .filter(|&&(coordinate, _)| coordinate == tile_coordinate)
+            .count();
+
+        if queued == remaining as usize + deforming {
+            self.to_load.retain(|tile| tile.coordinate != tile_coordinate);
+            self.deforming
+                .retain(|&(coordinate, _)| coordinate != tile_coordinate);
+            self.tile_states.remove(&tile_coordinate);
         }
     }
 }
+
+/// Loads the craters saved by a previous session, or none if there are none yet.
+fn load_craters(path: &Path) -> io::Result<Vec<Crater>> {
+    match fs::read_to_string(path) {
+        Ok(encoded) => ron::from_str(&encoded).map_err(io::Error::other),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Saves all craters, which is cheap enough to do on every new one.
+fn save_craters(path: &Path, craters: &[Crater]) -> io::Result<()> {
+    let encoded = ron::ser::to_string_pretty(craters, default()).map_err(io::Error::other)?;
+    fs::write(path, encoded)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::data::{AttachmentConfig, INVALID_ATLAS_INDEX};
+
+    /// A tile atlas with a topography and a day time attachment, that has requested the tile.
+    pub(crate) fn tile_atlas(tile: TileCoordinate) -> TileAtlas {
+        let mut config = EarthConfig {
+            tiles: vec![tile],
+            ..default()
+        };
+        config
+            .add_attachment(AttachmentLabel::Topography, AttachmentConfig::default())
+            .add_attachment(AttachmentLabel::DayTime, AttachmentConfig::default());
+
+        let mut tile_atlas = TileAtlas::new(
+            &config,
+            &mut Assets::default(),
+            &EarthSettings {
+                atlas_size: 4,
+                ..default()
+            },
+        );
+
+        tile_atlas.request_tile(tile);
+        tile_atlas
+    }
+
+    #[test]
+    fn released_tiles_are_removed_from_the_queue() {
+        let tile = TileCoordinate::new(2, 1, IVec2::new(1, 0));
+        let mut tile_atlas = tile_atlas(tile);
+        assert_eq!(tile_atlas.to_load.len(), 2);
+
+        tile_atlas.release_tile(tile);
+        assert!(tile_atlas.to_load.is_empty());
+        assert!(!tile_atlas.tile_states.contains_key(&tile));
+        assert_eq!(tile_atlas.unused_indices.len(), 4);
+
+        // the tile is queued again, once it is requested again
+        tile_atlas.request_tile(tile);
+        assert_eq!(tile_atlas.to_load.len(), 2);
+    }
+
+    #[test]
+    fn released_tiles_keep_loading_once_started() {
+        let tile = TileCoordinate::new(2, 1, IVec2::new(1, 0));
+        let mut tile_atlas = tile_atlas(tile);
+
+        // the loader has started loading one of the attachments
+        let started = tile_atlas.to_load.pop().unwrap();
+
+        tile_atlas.release_tile(tile);
+        assert_eq!(tile_atlas.to_load.len(), 1);
+        assert!(matches!(
+            tile_atlas.tile_states[&tile].state,
+            LoadingState::Loading(2)
+        ));
+
+        // the tile is cached once loaded, so that it can be reused without loading it again
+        let queued = tile_atlas.to_load.pop().unwrap();
+        tile_atlas.tile_loaded(started, AttachmentData::Rgba8U(Vec::new()));
+        tile_atlas.tile_loaded(queued, AttachmentData::Rgba8U(Vec::new()));
+
+        assert!(matches!(
+            tile_atlas.tile_states[&tile].state,
+            LoadingState::Loaded
+        ));
+        let atlas_index = tile_atlas.tile_states[&tile].atlas_index;
+        assert!(tile_atlas.unused_indices.contains(&atlas_index));
+    }
+
+    #[test]
+    fn failed_tiles_are_released() {
+        let tile = TileCoordinate::new(2, 1, IVec2::new(1, 0));
+        let mut tile_atlas = tile_atlas(tile);
+
+        let failed = tile_atlas.to_load.pop().unwrap();
+        tile_atlas.tile_failed(failed);
+
+        // the remaining attachment is no longer loaded and the view falls back to the parent
+        assert!(tile_atlas.to_load.is_empty());
+        assert!(matches!(
+            tile_atlas.tile_states[&tile].state,
+            LoadingState::Failed
+        ));
+        assert_eq!(
+            tile_atlas.get_best_tile(tile).atlas_index,
+            INVALID_ATLAS_INDEX
+        );
+
+        tile_atlas.release_tile(tile);
+        assert!(!tile_atlas.tile_states.contains_key(&tile));
+        assert_eq!(tile_atlas.unused_indices.len(), 4);
+
+        // loading the tile is retried, once it is requested again
+        tile_atlas.request_tile(tile);
+        assert_eq!(tile_atlas.to_load.len(), 2);
+    }
+
+    #[test]
+    fn craters_are_restored_with_the_tile_atlas() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = EarthConfig {
+            path: directory.path().to_string_lossy().to_string(),
+            ..default()
+        };
+        config.add_attachment(AttachmentLabel::Topography, AttachmentConfig::default());
+        let settings = EarthSettings::default();
+
+        let mut tile_atlas = TileAtlas::new(&config, &mut Assets::default(), &settings);
+        assert!(tile_atlas.craters().is_empty());
+        tile_atlas.add_crater(Crater::bowl(0.5, 0.25, 1000.0, 100.0));
+        tile_atlas.add_crater(Crater::bowl(-1.0, 0.5, 2000.0, 50.0));
+
+        // the next session starts with the craters of the previous one
+        let restored = TileAtlas::new(&config, &mut Assets::default(), &settings);
+        assert_eq!(restored.craters().len(), 2);
+        assert_eq!(restored.craters()[1].lon, -1.0);
+        assert_eq!(restored.craters()[1].radius, 2000.0);
+    }
+}
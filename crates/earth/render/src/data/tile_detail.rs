@@ -0,0 +1,412 @@
+use crate::{
+    data::{AttachmentData, AttachmentFormat, AttachmentLabel, TileRequest, TileSource},
+    earth::EarthConfig,
+    math::{EarthShape, TileCoordinate},
+};
+use bevy::{asset::AssetServer, prelude::*, tasks::BoxedFuture};
+use itertools::{Itertools, iproduct};
+
+/// Synthesises the tiles below the finest lod of the data from their ancestors,
+/// so that the terrain stays detailed, once the view gets closer than the data resolves.
+///
+/// Every lod is upsampled from its parent. The topography is additionally displaced by
+/// one octave of noise per lod, whose amplitude follows the slope of the parent, which
+/// adds up to fractal detail. Flat terrain and everything at or below the sea level stays
+/// smooth. The noise only depends on the [`TileCoordinate`] and the pixel,
+/// so that every client synthesises the same terrain.
+///
+/// The tiles of the data lods are loaded from the wrapped source.
+pub struct DetailTileSource<S: TileSource> {
+    source: S,
+    data_lod_count: u32,
+    shape: EarthShape,
+    min_height: f32,
+    max_height: f32,
+    roughness: f32,
+}
+
+impl<S: TileSource> DetailTileSource<S> {
+    pub fn new(source: S, config: &EarthConfig) -> Self {
+        Self {
+            source,
+            data_lod_count: config.lod_count,
+            shape: config.shape,
+            min_height: config.min_height,
+            max_height: config.max_height,
+            roughness: 0.5,
+        }
+    }
+
+    /// Sets the largest displacement of the topography relative to the size of a pixel,
+    /// which is reached on slopes of 45 degrees and steeper.
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+}
+
+impl<S: TileSource> TileSource for DetailTileSource<S> {
+    fn load_tile(
+        &self,
+        request: TileRequest,
+        asset_server: &AssetServer,
+    ) -> BoxedFuture<'static, Result<AttachmentData, BevyError>> {
+        let data_lod = self.data_lod_count - 1;
+
+        if request.coordinate.lod <= data_lod {
+            return self.source.load_tile(request, asset_server);
+        }
+
+        let load_ancestor = self.source.load_tile(
+            TileRequest {
+                coordinate: ancestor(request.coordinate, data_lod),
+                ..request.clone()
+            },
+            asset_server,
+        );
+
+        let refinement = Refinement {
+            shape: self.shape,
+            min_height: self.min_height,
+            max_height: self.max_height,
+            roughness: self.roughness,
+            request,
+        };
+
+        Box::pin(async move {
+            let mut data = load_ancestor.await?;
+
+            for lod in data_lod + 1..=refinement.request.coordinate.lod {
+                data = refinement.refine(&data, ancestor(refinement.request.coordinate, lod));
+            }
+
+            Ok(data)
+        })
+    }
+}
+
+/// The ancestor of the tile at the lod, or the tile itself.
+fn ancestor(tile: TileCoordinate, lod: u32) -> TileCoordinate {
+    TileCoordinate::new(tile.face, lod, tile.xy >> (tile.lod - lod) as i32)
+}
+
+/// The pixels of a tile, that is upsampled into one of its children.
+struct ParentTile {
+    values: Vec<f32>,
+    channel_count: usize,
+    texture_size: usize,
+}
+
+impl ParentTile {
+    fn value(&self, x: usize, y: usize, channel: usize) -> f32 {
+        self.values[(y * self.texture_size + x) * self.channel_count + channel]
+    }
+
+    fn nearest(&self, position: Vec2, channel: usize) -> f32 {
+        let max = (self.texture_size - 1) as f32;
+        let position = (position + 0.5).floor().clamp(Vec2::ZERO, Vec2::splat(max));
+
+        self.value(position.x as usize, position.y as usize, channel)
+    }
+
+    fn bilinear(&self, position: Vec2, channel: usize) -> f32 {
+        let max = (self.texture_size - 1) as f32;
+        let position = position.clamp(Vec2::ZERO, Vec2::splat(max));
+
+        let (x0, y0) = (position.x as usize, position.y as usize);
+        let (x1, y1) = (
+            (x0 + 1).min(self.texture_size - 1),
+            (y0 + 1).min(self.texture_size - 1),
+        );
+        let t = position.fract();
+
+        let top = self.value(x0, y0, channel) * (1.0 - t.x) + self.value(x1, y0, channel) * t.x;
+        let bottom = self.value(x0, y1, channel) * (1.0 - t.x) + self.value(x1, y1, channel) * t.x;
+
+        top * (1.0 - t.y) + bottom * t.y
+    }
+}
+
+struct Refinement {
+    request: TileRequest,
+    shape: EarthShape,
+    min_height: f32,
+    max_height: f32,
+    roughness: f32,
+}
+
+impl Refinement {
+    /// Synthesises the tile from the data of its parent.
+    fn refine(&self, parent: &AttachmentData, tile: TileCoordinate) -> AttachmentData {
+        let texture_size = self.request.texture_size as usize;
+        let border_size = self.request.border_size as f32;
+        let center_size = texture_size as f32 - 2.0 * border_size;
+
+        let parent_tile = ParentTile {
+            values: parent.to_values(),
+            channel_count: parent.channel_count(),
+            texture_size,
+        };
+
+        // the position of the tile within its parent
+        let quadrant = (tile.xy % 2).as_vec2() * center_size / 2.0;
+
+        let values = iproduct!(0..texture_size, 0..texture_size)
+            .flat_map(|(y, x)| {
+                let center = Vec2::new(x as f32, y as f32) - border_size;
+                let position = quadrant + (center + 0.5) / 2.0 - 0.5 + border_size;
+
+                (0..parent_tile.channel_count)
+                    .map(|channel| self.synthesise(&parent_tile, tile, (x, y), position, channel))
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        parent.with_values(&values)
+    }
+
+    fn synthesise(
+        &self,
+        parent: &ParentTile,
+        tile: TileCoordinate,
+        pixel: (usize, usize),
+        position: Vec2,
+        channel: usize,
+    ) -> f32 {
+        if self.request.label.is_categorical() {
+            return parent.nearest(position, channel);
+        }
+
+        let value = parent.bilinear(position, channel);
+
+        if self.request.label != AttachmentLabel::Topography {
+            return value;
+        }
+
+        let value = value + self.displacement(parent, tile, pixel, position);
+
+        if self.request.mask {
+            // the lowest bit marks the valid pixels and is taken from the parent unchanged
+            let format = self.request.format;
+            let valid = format.mask_bit(parent.nearest(position, channel));
+            format.with_mask_bit(value, valid)
+        } else {
+            value
+        }
+    }
+
+    /// One octave of noise, that displaces the upsampled height of the pixel.
+    ///
+    /// The slope is measured half a pixel around the pixel within the parent, which always
+    /// lies inside of the parent, so that the borders of neighbouring tiles stay identical.
+    fn displacement(
+        &self,
+        parent: &ParentTile,
+        tile: TileCoordinate,
+        pixel: (usize, usize),
+        position: Vec2,
+    ) -> f32 {
+        let border_size = self.request.border_size as i64;
+        let center_size = self.request.texture_size as i64 - 2 * border_size;
+        let face_pixels = center_size << tile.lod;
+
+        let global_x = tile.xy.x as i64 * center_size + pixel.0 as i64 - border_size;
+        let global_y = tile.xy.y as i64 * center_size + pixel.1 as i64 - border_size;
+
+        // the pixels along the face edges are not displaced, since the neighbouring face
+        // samples the noise in other coordinates
+        if global_x.min(global_y) < border_size
+            || global_x.max(global_y) >= face_pixels - border_size
+        {
+            return 0.0;
+        }
+
+        let format = self.request.format;
+        let height = |position: Vec2| {
+            format.decode_height(
+                parent.bilinear(position, 0) as f64,
+                self.min_height,
+                self.max_height,
+            ) as f32
+        };
+
+        if height(position) <= 0.0 {
+            return 0.0;
+        }
+
+        let pixel_size = (self.shape.face_size() / face_pixels as f64) as f32;
+        let (dx, dy) = (Vec2::new(0.25, 0.0), Vec2::new(0.0, 0.25));
+        let slope = Vec2::new(
+            height(position + dx) - height(position - dx),
+            height(position + dy) - height(position - dy),
+        )
+        .length()
+            / pixel_size;
+
+        let metres_per_unit = (format.decode_height(1.0, self.min_height, self.max_height)
+            - format.decode_height(0.0, self.min_height, self.max_height))
+            as f32;
+
+        let amplitude = self.roughness * pixel_size * slope.min(1.0);
+
+        amplitude * noise(tile.face, tile.lod, global_x, global_y) / metres_per_unit
+    }
+}
+
+/// A deterministic value between -1 and 1 for every pixel of the face at the lod.
+fn noise(face: u32, lod: u32, x: i64, y: i64) -> f32 {
+    let seed = split_mix(((face as u64) << 32) | lod as u64);
+    let hash = split_mix(split_mix(seed ^ x as u64) ^ y as u64);
+
+    (hash >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+fn split_mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::TileCompression;
+
+    const TEXTURE_SIZE: usize = 10;
+    const BORDER_SIZE: usize = 1;
+
+    fn refinement() -> Refinement {
+        masked_refinement(AttachmentFormat::R16U, false)
+    }
+
+    fn masked_refinement(format: AttachmentFormat, mask: bool) -> Refinement {
+        Refinement {
+            request: TileRequest {
+                coordinate: TileCoordinate::new(0, 4, IVec2::ZERO),
+                label: AttachmentLabel::Topography,
+                path: default(),
+                texture_size: TEXTURE_SIZE as u32,
+                border_size: BORDER_SIZE as u32,
+                format,
+                compression: TileCompression::None,
+                packed: false,
+                mask,
+            },
+            shape: EarthShape::Sphere { radius: 1000.0 },
+            min_height: 0.0,
+            max_height: 100.0,
+            roughness: 0.5,
+        }
+    }
+
+    /// A steep slope rising along x, whose borders match its neighbours.
+    fn slope(tile: TileCoordinate) -> AttachmentData {
+        let center_size = TEXTURE_SIZE - 2 * BORDER_SIZE;
+
+        AttachmentData::R16U(
+            iproduct!(0..TEXTURE_SIZE, 0..TEXTURE_SIZE)
+                .map(|(_, x)| {
+                    let global_x =
+                        (tile.xy.x as usize * center_size + x) as i64 - BORDER_SIZE as i64;
+                    (20000 + global_x * 1000) as u16
+                })
+                .collect(),
+        )
+    }
+
+    fn refine(tile: TileCoordinate) -> Vec<f32> {
+        let parent = ancestor(tile, tile.lod - 1);
+        refinement().refine(&slope(parent), tile).to_values()
+    }
+
+    #[test]
+    fn detail_is_deterministic() {
+        let tile = TileCoordinate::new(0, 2, IVec2::new(1, 2));
+
+        assert_eq!(refine(tile), refine(tile));
+    }
+
+    #[test]
+    fn detail_follows_parent() {
+        let tile = TileCoordinate::new(0, 2, IVec2::new(1, 2));
+        let parent = slope(ancestor(tile, 1)).to_values();
+        let values = refine(tile);
+
+        let pixel_size = refinement().shape.face_size() / (8 << 2) as f64;
+        let tolerance = (0.5 * pixel_size * u16::MAX as f64 / 100.0) as f32 + 1.0;
+        let mut displaced = false;
+
+        for (y, x) in iproduct!(0..TEXTURE_SIZE, 0..TEXTURE_SIZE) {
+            // the child covers the right half of its parent
+            let parent_x = 4.5 + (x as f32 - 0.5) / 2.0;
+            let expected = parent[0] + (parent[1] - parent[0]) * parent_x;
+            let value = values[y * TEXTURE_SIZE + x];
+
+            assert!((value - expected).abs() <= tolerance);
+            displaced |= (value - expected).abs() > 1.0;
+        }
+
+        assert!(displaced);
+    }
+
+    #[test]
+    fn masked_r32f_keeps_fractional_and_negative_heights() {
+        let format = AttachmentFormat::R32F;
+        let tile = TileCoordinate::new(0, 2, IVec2::new(1, 2));
+
+        for height in [10.3, -50.25] {
+            for valid in [true, false] {
+                let value = format.with_mask_bit(height, valid);
+                let parent = AttachmentData::R32F(vec![value; TEXTURE_SIZE * TEXTURE_SIZE]);
+                let values = masked_refinement(format, true)
+                    .refine(&parent, tile)
+                    .to_values();
+
+                for value in values {
+                    // flat terrain is not displaced, only the lowest bit of the mantissa changes
+                    assert!((value - height).abs() < 1e-4);
+                    assert_eq!(format.mask_bit(value), valid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn masked_r16i_keeps_negative_heights() {
+        let format = AttachmentFormat::R16I;
+        let tile = TileCoordinate::new(0, 2, IVec2::new(1, 2));
+
+        for valid in [true, false] {
+            let value = format.with_mask_bit(-1200.0, valid) as i16;
+            let parent = AttachmentData::R16I(vec![value; TEXTURE_SIZE * TEXTURE_SIZE]);
+            let values = masked_refinement(format, true)
+                .refine(&parent, tile)
+                .to_values();
+
+            for refined in values {
+                assert_eq!(refined, value as f32);
+                assert_eq!(format.mask_bit(refined), valid);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_tiles_share_their_borders() {
+        let size = TEXTURE_SIZE;
+
+        // tiles with the same parent and with different parents
+        for (left, right) in [
+            (IVec2::new(2, 5), IVec2::new(3, 5)),
+            (IVec2::new(3, 5), IVec2::new(4, 5)),
+        ] {
+            let left = refine(TileCoordinate::new(0, 3, left));
+            let right = refine(TileCoordinate::new(0, 3, right));
+
+            for y in 0..size {
+                assert_eq!(left[y * size + size - 1], right[y * size + 1]);
+                assert_eq!(left[y * size + size - 2], right[y * size]);
+            }
+        }
+    }
+}
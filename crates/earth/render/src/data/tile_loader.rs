@@ -1,91 +1,387 @@
-use crate::data::{AttachmentData, AttachmentFormat, AttachmentTile, TileAtlas};
+use crate::{
+    data::{
+        AssetTileSource, AttachmentData, AttachmentTile, TileAtlas, TileRequest, TileSource,
+        TileTree,
+    },
+    math::TileCoordinate,
+    view::EarthViewComponents,
+};
 use bevy::{
-    asset::{AssetServer, Assets, Handle},
-    image::Image,
+    asset::AssetServer,
+    math::DVec3,
+    platform::collections::HashMap,
     prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, futures_lite::future},
 };
+use itertools::Itertools;
 use slab::Slab;
+use std::{cmp::Ordering, collections::BinaryHeap, mem};
 
 struct LoadingTile {
-    handle: Handle<Image>,
+    task: Task<Result<AttachmentData, BevyError>>,
+    tile: AttachmentTile,
+}
+
+/// A tile waiting to be loaded, ordered by how urgently it is needed by the views.
+///
+/// Tiles inside the frustum of any view come first, then coarser tiles, since they serve as
+/// fallback for all of their children, and finally the tiles closest to any view.
+struct PrioritizedTile {
+    visible: bool,
+    lod: u32,
+    distance: f64,
     tile: AttachmentTile,
-    format: AttachmentFormat,
 }
 
+impl PrioritizedTile {
+    fn new(tile: AttachmentTile, (distance, visible): (f64, bool)) -> Self {
+        Self {
+            visible,
+            lod: tile.coordinate.lod,
+            distance,
+            tile,
+        }
+    }
+}
+
+/// The distance to the closest view and whether the tile is inside the frustum of any view.
+fn tile_visibility(tile: TileCoordinate, tile_trees: &[&TileTree]) -> (f64, bool) {
+    tile_trees
+        .iter()
+        .map(|tile_tree| tile_tree.tile_visibility(tile))
+        .fold(
+            (f64::INFINITY, false),
+            |(min_distance, any_visible), (distance, visible)| {
+                (min_distance.min(distance), any_visible || visible)
+            },
+        )
+}
+
+/// The state of a view, that the visibility of the tiles depends on.
+#[derive(PartialEq)]
+struct ViewState {
+    local_position: DVec3,
+    approximate_height: f32,
+    world_from_earth: Affine3A,
+    half_spaces: [Vec4; 6],
+}
+
+impl ViewState {
+    fn new(tile_tree: &TileTree) -> Self {
+        Self {
+            local_position: tile_tree.view_local_position,
+            approximate_height: tile_tree.approximate_height,
+            world_from_earth: tile_tree.world_from_earth,
+            half_spaces: tile_tree.half_spaces,
+        }
+    }
+}
+
+impl Ord for PrioritizedTile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.visible
+            .cmp(&other.visible)
+            .then(other.lod.cmp(&self.lod))
+            .then(other.distance.total_cmp(&self.distance))
+    }
+}
+
+impl PartialOrd for PrioritizedTile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PrioritizedTile {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PrioritizedTile {}
+
+/// Loads the tiles requested by the [`TileAtlas`] of the earth from a [`TileSource`].
+///
+/// By default the tiles are read from the assets by the [`AssetTileSource`].
+/// Insert a loader with another source alongside the tile atlas, to replace it.
 #[derive(Component)]
-pub struct DefaultLoader {
+pub struct TileLoader {
+    source: Box<dyn TileSource>,
     loading_tiles: Slab<LoadingTile>,
+    /// The views, that the visibilities have been computed for.
+    views: Vec<ViewState>,
+    /// The visibilities of the queued tiles, which are reused until any view changes.
+    visibilities: HashMap<TileCoordinate, (f64, bool)>,
 }
 
-impl Default for DefaultLoader {
+impl Default for TileLoader {
     fn default() -> Self {
+        Self::new(AssetTileSource)
+    }
+}
+
+impl TileLoader {
+    pub fn new(source: impl TileSource) -> Self {
         Self {
+            source: Box::new(source),
             loading_tiles: Slab::with_capacity(32),
+            views: Vec::new(),
+            visibilities: HashMap::new(),
         }
     }
-}
 
-impl DefaultLoader {
-    fn to_load_next(&self, tiles: &mut Vec<AttachmentTile>) -> Option<AttachmentTile> {
-        // Todo: tile prioritization goes here
-        tiles.pop()
+    /// Orders the queued tiles by their priority for all views of the earth.
+    ///
+    /// The visibility of a tile only depends on the views, so it is computed once
+    /// and reused, until any of the views moves or turns.
+    fn prioritize(
+        &mut self,
+        tiles: Vec<AttachmentTile>,
+        tile_trees: &[&TileTree],
+    ) -> BinaryHeap<PrioritizedTile> {
+        let views = tile_trees
+            .iter()
+            .map(|tile_tree| ViewState::new(tile_tree))
+            .collect_vec();
+
+        if views != self.views {
+            self.views = views;
+            self.visibilities.clear();
+        }
+
+        tiles
+            .into_iter()
+            .map(|tile| {
+                // all attachments of a tile share its priority
+                let visibility = *self
+                    .visibilities
+                    .entry(tile.coordinate)
+                    .or_insert_with(|| tile_visibility(tile.coordinate, tile_trees));
+
+                PrioritizedTile::new(tile, visibility)
+            })
+            .collect()
     }
 
-    fn finish_loading(
-        &mut self,
-        atlas: &mut TileAtlas,
-        asset_server: &mut AssetServer,
-        images: &mut Assets<Image>,
-    ) {
+    fn finish_loading(&mut self, atlas: &mut TileAtlas) {
         self.loading_tiles.retain(|_, tile| {
-            if asset_server.is_loaded(tile.handle.id()) {
-                let image = images.get(tile.handle.id()).unwrap();
-                let data = AttachmentData::from_bytes(image.data.as_ref().unwrap(), tile.format);
-                atlas.tile_loaded(tile.tile.clone(), data);
+            let Some(result) = future::block_on(future::poll_once(&mut tile.task)) else {
+                return true;
+            };
 
-                false
-            } else {
-                !asset_server.load_state(tile.handle.id()).is_failed()
+            match result {
+                Ok(data) => atlas.tile_loaded(tile.tile.clone(), data),
+                Err(error) => {
+                    warn!(
+                        "Failed to load the tile {} of {:?}: {error}",
+                        tile.tile.coordinate, tile.tile.label
+                    );
+                    atlas.tile_failed(tile.tile.clone());
+                }
             }
+
+            false
         });
     }
 
-    fn start_loading(&mut self, atlas: &mut TileAtlas, asset_server: &mut AssetServer) {
-        while self.loading_tiles.len() < self.loading_tiles.capacity() {
-            if let Some(tile) = self.to_load_next(&mut atlas.to_load) {
-                let attachment = &atlas.attachments[&tile.label];
+    fn start_loading(
+        &mut self,
+        atlas: &mut TileAtlas,
+        tile_trees: &[&TileTree],
+        asset_server: &AssetServer,
+    ) {
+        if self.loading_tiles.len() == self.loading_tiles.capacity() || atlas.to_load.is_empty() {
+            return;
+        }
+
+        let mut to_load = self.prioritize(mem::take(&mut atlas.to_load), tile_trees);
 
-                let path = tile
-                    .coordinate
-                    .path(&attachment.path.join(String::from(&tile.label)));
+        while self.loading_tiles.len() < self.loading_tiles.capacity() {
+            if let Some(PrioritizedTile { tile, .. }) = to_load.pop() {
+                let request = TileRequest::new(&tile, &atlas.attachments[&tile.label]);
+                let load = self.source.load_tile(request, asset_server);
 
                 self.loading_tiles.insert(LoadingTile {
-                    handle: asset_server.load(path),
+                    task: AsyncComputeTaskPool::get().spawn(load),
                     tile,
-                    format: attachment.format,
                 });
             } else {
                 break;
             }
         }
+
+        atlas.to_load = to_load.into_iter().map(|tile| tile.tile).collect();
     }
 }
 
-pub fn finish_loading(
-    mut earths: Query<(&mut TileAtlas, &mut DefaultLoader)>,
-    mut asset_server: ResMut<AssetServer>,
-    mut images: ResMut<Assets<Image>>,
-) {
+pub fn finish_loading(mut earths: Query<(&mut TileAtlas, &mut TileLoader)>) {
     for (mut tile_atlas, mut loader) in &mut earths {
-        loader.finish_loading(&mut tile_atlas, &mut asset_server, &mut images);
+        loader.finish_loading(&mut tile_atlas);
     }
 }
 
 pub fn start_loading(
-    mut earths: Query<(&mut TileAtlas, &mut DefaultLoader)>,
-    mut asset_server: ResMut<AssetServer>,
+    mut earths: Query<(Entity, &mut TileAtlas, &mut TileLoader)>,
+    tile_trees: Res<EarthViewComponents<TileTree>>,
+    asset_server: Res<AssetServer>,
 ) {
-    for (mut tile_atlas, mut loader) in &mut earths {
-        loader.start_loading(&mut tile_atlas, &mut asset_server);
+    for (earth, mut tile_atlas, mut loader) in &mut earths {
+        let tile_trees = tile_trees
+            .iter()
+            .filter(|(&(tree_earth, _view), _)| tree_earth == earth)
+            .map(|(_, tile_tree)| tile_tree)
+            .collect_vec();
+
+        loader.start_loading(&mut tile_atlas, &tile_trees, &asset_server);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{
+        AttachmentLabel, INVALID_ATLAS_INDEX, MemoryTileSource,
+        tile_atlas::test::tile_atlas,
+        tile_tree::test::{tile_below, tile_tree},
+    };
+    use bevy::math::DVec3;
+    use std::iter;
+
+    fn attachment_tile(coordinate: TileCoordinate, label: AttachmentLabel) -> AttachmentTile {
+        AttachmentTile {
+            coordinate,
+            label,
+            ..default()
+        }
+    }
+
+    fn load_order(
+        loader: &mut TileLoader,
+        tiles: &[AttachmentTile],
+        tile_trees: &[&TileTree],
+    ) -> Vec<TileCoordinate> {
+        let mut to_load = loader.prioritize(tiles.to_vec(), tile_trees);
+
+        iter::from_fn(|| to_load.pop())
+            .map(|tile| tile.tile.coordinate)
+            .collect()
+    }
+
+    #[test]
+    fn visible_and_coarse_tiles_are_loaded_first() {
+        let tile_tree = tile_tree();
+
+        let below = tile_below(DVec3::X, 3);
+        let next = TileCoordinate::new(below.face, 3, below.xy + IVec2::new(2, 0));
+        let coarse = tile_below(DVec3::X, 1);
+        let hidden = tile_below(-DVec3::X, 0);
+
+        let tiles = [
+            attachment_tile(hidden, AttachmentLabel::Topography),
+            attachment_tile(next, AttachmentLabel::Topography),
+            attachment_tile(below, AttachmentLabel::Topography),
+            attachment_tile(coarse, AttachmentLabel::Topography),
+            attachment_tile(below, AttachmentLabel::DayTime),
+        ];
+
+        let mut loader = TileLoader::default();
+
+        // all attachments of a tile share its priority
+        assert_eq!(
+            load_order(&mut loader, &tiles, &[&tile_tree]),
+            [coarse, below, below, next, hidden]
+        );
+
+        // without any view, only the lod decides
+        assert_eq!(load_order(&mut loader, &tiles, &[])[..2], [hidden, coarse]);
+    }
+
+    #[test]
+    fn priorities_are_updated_once_the_view_changes() {
+        let mut tile_tree = tile_tree();
+
+        let below = tile_below(DVec3::X, 3);
+        let hidden = tile_below(-DVec3::X, 3);
+        let tiles = [
+            attachment_tile(below, AttachmentLabel::Topography),
+            attachment_tile(hidden, AttachmentLabel::Topography),
+        ];
+
+        let mut loader = TileLoader::default();
+        assert_eq!(
+            load_order(&mut loader, &tiles, &[&tile_tree]),
+            [below, hidden]
+        );
+
+        // the visibilities are reused, while the view stays the same
+        loader.visibilities.insert(hidden, (0.0, true));
+        assert_eq!(
+            load_order(&mut loader, &tiles, &[&tile_tree]),
+            [hidden, below]
+        );
+
+        // the view turns around, so the tiles are prioritized again
+        tile_tree.half_spaces[0] = Vec4::new(-1.0, 0.0, 0.0, 0.0);
+        assert_eq!(
+            load_order(&mut loader, &tiles, &[&tile_tree]),
+            [hidden, below]
+        );
+        assert!(!loader.visibilities[&below].1);
+        assert!(loader.visibilities[&hidden].1);
+    }
+
+    /// Loads all queued tiles of the atlas from the source.
+    fn load_tiles(source: MemoryTileSource, tile_atlas: &mut TileAtlas) {
+        let mut app = App::new();
+        app.add_plugins((TaskPoolPlugin::default(), AssetPlugin::default()));
+        let asset_server = app.world().resource::<AssetServer>().clone();
+
+        let mut loader = TileLoader::new(source);
+        loader.start_loading(tile_atlas, &[], &asset_server);
+
+        while !loader.loading_tiles.is_empty() {
+            loader.finish_loading(tile_atlas);
+        }
+    }
+
+    #[test]
+    fn tiles_are_loaded_from_the_source() {
+        let tile = TileCoordinate::new(2, 1, IVec2::new(1, 0));
+        let mut tile_atlas = tile_atlas(tile);
+
+        let mut source = MemoryTileSource::default();
+        for label in [AttachmentLabel::Topography, AttachmentLabel::DayTime] {
+            source.insert(tile, label, AttachmentData::Rgba8U(Vec::new()));
+        }
+
+        load_tiles(source, &mut tile_atlas);
+
+        assert_eq!(tile_atlas.uploading_tiles.len(), 2);
+        assert_ne!(
+            tile_atlas.get_best_tile(tile).atlas_index,
+            INVALID_ATLAS_INDEX
+        );
+    }
+
+    #[test]
+    fn failed_tiles_are_not_used() {
+        let tile = TileCoordinate::new(2, 1, IVec2::new(1, 0));
+        let mut tile_atlas = tile_atlas(tile);
+
+        // the day time attachment is missing
+        let mut source = MemoryTileSource::default();
+        source.insert(
+            tile,
+            AttachmentLabel::Topography,
+            AttachmentData::Rgba8U(Vec::new()),
+        );
+
+        load_tiles(source, &mut tile_atlas);
+
+        assert!(tile_atlas.to_load.is_empty());
+        assert_eq!(
+            tile_atlas.get_best_tile(tile).atlas_index,
+            INVALID_ATLAS_INDEX
+        );
     }
 }
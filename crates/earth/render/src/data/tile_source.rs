@@ -0,0 +1,149 @@
+use crate::{
+    data::{
+        Attachment, AttachmentData, AttachmentFormat, AttachmentLabel, AttachmentTile,
+        TileCompression,
+    },
+    math::TileCoordinate,
+    utils::{TILE_ARCHIVE_SOURCE, TileArchive, decode_tiff},
+};
+use bevy::{
+    asset::{AssetPath, AssetServer, io::Reader},
+    platform::collections::HashMap,
+    prelude::*,
+    tasks::BoxedFuture,
+};
+use std::path::PathBuf;
+
+/// Describes an attachment tile, that a [`TileSource`] should provide.
+#[derive(Clone, Debug)]
+pub struct TileRequest {
+    pub coordinate: TileCoordinate,
+    /// The label of the attachment, which already includes the time slice.
+    pub label: AttachmentLabel,
+    /// The directory of the attachments of the earth.
+    pub path: PathBuf,
+    /// The size of the tile including its border.
+    pub texture_size: u32,
+    pub border_size: u32,
+    pub format: AttachmentFormat,
+    pub compression: TileCompression,
+    /// Whether the tiles of the attachment are packed into a [`TileArchive`].
+    pub packed: bool,
+    /// Whether the lowest bit of the values marks the valid pixels.
+    pub mask: bool,
+}
+
+impl TileRequest {
+    pub(crate) fn new(tile: &AttachmentTile, attachment: &Attachment) -> Self {
+        // every time slice is stored like an attachment of its own
+        let label = match tile.slice {
+            Some(slice) => tile.label.time_slice(slice),
+            None => tile.label.clone(),
+        };
+
+        Self {
+            coordinate: tile.coordinate,
+            label,
+            path: attachment.path.clone(),
+            texture_size: attachment.texture_size,
+            border_size: attachment.border_size,
+            format: attachment.format,
+            compression: attachment.compression,
+            packed: attachment.packed,
+            mask: attachment.mask,
+        }
+    }
+}
+
+/// Provides the data of the tiles, that are loaded into a [`TileAtlas`](super::TileAtlas).
+///
+/// The data has to match the format and texture size of the request.
+/// Use it with a [`TileLoader`](super::TileLoader) on the earth entity, to replace the default
+/// [`AssetTileSource`], e.g. by procedurally generated or in-memory tiles.
+pub trait TileSource: Send + Sync + 'static {
+    /// Starts loading the tile, which is then completed on the async compute task pool.
+    fn load_tile(
+        &self,
+        request: TileRequest,
+        asset_server: &AssetServer,
+    ) -> BoxedFuture<'static, Result<AttachmentData, BevyError>>;
+}
+
+/// Reads the tiles written by the preprocessing from the assets, either as loose TIFF files
+/// or from the [`TileArchive`] of the attachment.
+#[derive(Default)]
+pub struct AssetTileSource;
+
+impl TileSource for AssetTileSource {
+    fn load_tile(
+        &self,
+        request: TileRequest,
+        asset_server: &AssetServer,
+    ) -> BoxedFuture<'static, Result<AttachmentData, BevyError>> {
+        let path = if request.packed {
+            let archive_path = TileArchive::attachment_path(&request.path, &request.label);
+            AssetPath::from(request.coordinate.archive_path(&archive_path))
+                .with_source(TILE_ARCHIVE_SOURCE)
+        } else {
+            AssetPath::from(
+                request
+                    .coordinate
+                    .path(&request.path.join(String::from(&request.label))),
+            )
+        };
+
+        let asset_server = asset_server.clone();
+
+        Box::pin(async move {
+            let source = asset_server.get_source(path.source().clone())?;
+            let mut reader = source.reader().read(path.path()).await?;
+
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let (data, _, _) = decode_tiff(&bytes, request.compression)?;
+
+            Ok(AttachmentData::from_bytes(&data, request.format))
+        })
+    }
+}
+
+/// Serves tiles, that are kept in memory, like the fixtures of tests.
+#[derive(Default)]
+pub struct MemoryTileSource {
+    tiles: HashMap<(TileCoordinate, AttachmentLabel), AttachmentData>,
+}
+
+impl MemoryTileSource {
+    pub fn insert(
+        &mut self,
+        coordinate: TileCoordinate,
+        label: AttachmentLabel,
+        data: AttachmentData,
+    ) {
+        self.tiles.insert((coordinate, label), data);
+    }
+}
+
+impl TileSource for MemoryTileSource {
+    fn load_tile(
+        &self,
+        request: TileRequest,
+        _asset_server: &AssetServer,
+    ) -> BoxedFuture<'static, Result<AttachmentData, BevyError>> {
+        let data = self
+            .tiles
+            .get(&(request.coordinate, request.label.clone()))
+            .cloned();
+
+        Box::pin(async move {
+            data.ok_or_else(|| {
+                format!(
+                    "the tile {} of {:?} is not in memory",
+                    request.coordinate, request.label
+                )
+                .into()
+            })
+        })
+    }
+}
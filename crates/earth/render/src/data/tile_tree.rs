@@ -123,6 +123,8 @@ pub struct TileTree {
     pub(crate) view_lod: u32,
     pub(crate) view_local_position: DVec3,
     pub(crate) view_world_position: Vec3,
+    /// The global transform of the earth, which maps its local positions into world space.
+    pub(crate) world_from_earth: Affine3A,
     pub(crate) view_coordinates: [Coordinate; 6],
     pub(crate) half_spaces: [Vec4; 6],
     pub(crate) surface_approximation: [crate::math::SurfaceApproximation; 6],
@@ -144,7 +146,7 @@ impl TileTree {
     ) -> Self {
         let data = Array4::default((
             config.shape.face_count() as usize,
-            config.lod_count as usize,
+            (config.lod_count + config.detail_lod_count) as usize,
             view_config.tree_size as usize,
             view_config.tree_size as usize,
         ));
@@ -173,7 +175,7 @@ impl TileTree {
 
         Self {
             tree_size: view_config.tree_size,
-            lod_count: config.lod_count,
+            lod_count: config.lod_count + config.detail_lod_count,
             shape: config.shape,
             geometry_tile_count: view_config.geometry_tile_count,
             refinement_count: view_config.refinement_count,
@@ -193,10 +195,11 @@ impl TileTree {
             view_lod: view_config.view_lod,
             view_local_position: default(),
             view_world_position: default(),
+            world_from_earth: Affine3A::IDENTITY,
             data,
             tiles: Array4::default((
                 config.shape.face_count() as usize,
-                config.lod_count as usize,
+                (config.lod_count + config.detail_lod_count) as usize,
                 view_config.tree_size as usize,
                 view_config.tree_size as usize,
             )),
@@ -257,6 +260,38 @@ impl TileTree {
         tile_local_position.distance(self.view_local_position)
     }
 
+    /// Computes the distance between the viewer and the tile and whether the bounding sphere of
+    /// the tile intersects the view frustum.
+    ///
+    /// The tile is approximated at the height of the terrain below the viewer.
+    pub(crate) fn tile_visibility(&self, tile: TileCoordinate) -> (f64, bool) {
+        let distance = self.compute_tile_distance(tile, self.view_coordinates[tile.face as usize]);
+
+        let tile_count = (tile.lod as f64).exp2();
+
+        // the half spaces are in world space, so the tile is transformed with the earth
+        let tile_position = |uv: DVec2| {
+            let local_position = Coordinate::new(tile.face, (tile.xy.as_dvec2() + uv) / tile_count)
+                .local_position(self.shape, self.approximate_height);
+
+            self.world_from_earth
+                .transform_point3(local_position.as_vec3())
+        };
+
+        let center = tile_position(DVec2::splat(0.5));
+        let radius = [DVec2::ZERO, DVec2::X, DVec2::Y, DVec2::ONE]
+            .map(|corner| tile_position(corner).distance(center))
+            .into_iter()
+            .fold(0.0, f32::max);
+
+        let visible = self
+            .half_spaces
+            .iter()
+            .all(|half_space| half_space.dot(center.extend(1.0)) + radius >= 0.0);
+
+        (distance, visible)
+    }
+
     fn update(&mut self) {
         let view_coordinate = Coordinate::from_local_position(self.view_local_position, self.shape);
         self.view_face = view_coordinate.face;
@@ -326,17 +361,17 @@ impl TileTree {
         camera: Query<&Camera>,
         mut tile_trees: ResMut<EarthViewComponents<TileTree>>,
         grids: Grids,
-        views: Query<(&Transform, &CellCoord)>,
+        views: Query<(&Transform, &GlobalTransform, &CellCoord)>,
+        earths: Query<&GlobalTransform, With<TileAtlas>>,
     ) {
-        for (&(_, view), tile_tree) in tile_trees.iter_mut() {
+        for (&(earth, view), tile_tree) in tile_trees.iter_mut() {
             let camera = camera.get(view).unwrap();
             let grid = grids.parent_grid(view).unwrap();
-            let (transform, cell) = views.get(view).unwrap();
-
-            // Todo: transform should be global transform?
+            let (transform, global_transform, cell) = views.get(view).unwrap();
 
+            // the frustum and the earth are both relative to the floating origin
             let clip_from_view = camera.clip_from_view();
-            let world_from_view = transform.to_matrix();
+            let world_from_view = global_transform.to_matrix();
             let clip_from_world = clip_from_view * world_from_view.inverse();
 
             let half_spaces = Frustum::from_clip_from_world(&clip_from_world)
@@ -344,7 +379,8 @@ impl TileTree {
                 .map(|space| space.normal_d());
 
             tile_tree.view_local_position = grid.grid_position_double(cell, transform);
-            tile_tree.view_world_position = transform.translation;
+            tile_tree.view_world_position = global_transform.translation();
+            tile_tree.world_from_earth = earths.get(earth).unwrap().affine();
             tile_tree.half_spaces = half_spaces;
             tile_tree.update();
         }
@@ -403,3 +439,67 @@ impl TileTree {
         tile_tree.approximate_height = trigger.event().to_shader_type();
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) const SHAPE: EarthShape = EarthShape::Sphere { radius: 1000.0 };
+
+    /// A tile tree, whose view is above the earth in the direction of the x axis and whose
+    /// frustum only contains the positive half of the world along this axis.
+    pub(crate) fn tile_tree() -> TileTree {
+        let mut world = World::new();
+        let mut buffers = Assets::<ShaderStorageBuffer>::default();
+
+        let config = EarthConfig {
+            shape: SHAPE,
+            ..default()
+        };
+
+        let mut tile_tree = TileTree::new(
+            &config,
+            &EarthViewConfig::default(),
+            (Entity::PLACEHOLDER, Entity::PLACEHOLDER),
+            &mut world.commands(),
+            &mut buffers,
+        );
+
+        tile_tree.view_local_position = DVec3::X * 1100.0;
+        tile_tree.half_spaces[0] = Vec4::new(1.0, 0.0, 0.0, 0.0);
+        tile_tree.update();
+
+        tile_tree
+    }
+
+    /// The tile below the unit position.
+    pub(crate) fn tile_below(unit_position: DVec3, lod: u32) -> TileCoordinate {
+        let coordinate = Coordinate::from_local_position(unit_position * 1000.0, SHAPE);
+        let xy = (coordinate.uv * (lod as f64).exp2()).as_ivec2();
+
+        TileCoordinate::new(coordinate.face, lod, xy)
+    }
+
+    #[test]
+    fn visibility_follows_the_earth_transform() {
+        let mut tile_tree = tile_tree();
+        let tile = tile_below(DVec3::X, 3);
+
+        let (distance, visible) = tile_tree.tile_visibility(tile);
+        assert!(visible);
+        assert!(distance < 110.0);
+
+        // the opposite side of the earth faces the view
+        tile_tree.world_from_earth = Affine3A::from_rotation_y(std::f32::consts::PI);
+        assert_eq!(tile_tree.tile_visibility(tile), (distance, false));
+
+        // the earth is moved behind the view
+        let translation = Affine3A::from_translation(Vec3::X * -1500.0);
+        tile_tree.world_from_earth = translation;
+        assert!(!tile_tree.tile_visibility(tile).1);
+
+        // the earth is moved behind the view, but scaled up, so that the tile stays in front
+        tile_tree.world_from_earth = translation * Affine3A::from_scale(Vec3::splat(2.0));
+        assert!(tile_tree.tile_visibility(tile).1);
+    }
+}
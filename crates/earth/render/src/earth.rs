@@ -22,6 +22,10 @@ pub struct EarthConfig {
     pub path: String,
     pub shape: EarthShape,
     pub lod_count: u32,
+    /// The number of lods below the finest lod of the data, whose tiles are synthesised
+    /// procedurally by the [`DetailTileSource`](crate::data::DetailTileSource).
+    #[serde(default)]
+    pub detail_lod_count: u32,
     pub min_height: f32,
     pub max_height: f32,
     pub attachments: HashMap<AttachmentLabel, AttachmentConfig>,
@@ -33,6 +37,7 @@ impl Default for EarthConfig {
         Self {
             shape: EarthShape::WGS84,
             lod_count: 6,
+            detail_lod_count: 0,
             min_height: 0.0,
             max_height: 1.0,
             path: default(),
@@ -20,14 +20,17 @@ pub mod prelude {
         },
         earth::EarthConfig,
         data::{
-            AttachmentConfig, AttachmentFormat, AttachmentLabel, GpuTileAtlas, TileAtlas, TileTree,
+            AssetTileSource, AttachmentConfig, AttachmentData, AttachmentFormat, AttachmentLabel,
+            Crater, DetailTileSource, DownloadedTile, GpuTileAtlas, MemoryTileSource,
+            ResamplingMethod, TileAtlas, TileCompression, TileDownloadError, TileLoader,
+            TileRequest, TileSource, TileTree,
         },
         material::EarthMaterial,
         math::{EarthShape, TileCoordinate},
         picking::{EarthPickingPlugin, PickingData},
         plugin::{EarthPlugin, EarthSettings},
         render::EarthMaterialPlugin,
-        utils::SpawnEarthCommandsExt,
+        utils::{SpawnEarthCommandsExt, TileArchive, TileArchivePlugin, TileArchiveWriter},
         view::{EarthViewComponents, EarthViewConfig},
     };
     pub use big_space::{commands::BigSpaceCommands, grid::Grid};
@@ -11,6 +11,7 @@ use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 // Todo: get rid of is_spherical check
@@ -120,6 +121,11 @@ impl TileCoordinate {
         ))
     }
 
+    /// The path of the tile inside of a [`TileArchive`](crate::utils::TileArchive).
+    pub fn archive_path(self, archive_path: &Path) -> PathBuf {
+        archive_path.join(format!("{self}.tif"))
+    }
+
     pub fn parent(self) -> Option<Self> {
         self.lod.checked_sub(1).map(|lod| Self {
             face: self.face,
@@ -187,6 +193,27 @@ impl fmt::Display for TileCoordinate {
     }
 }
 
+impl FromStr for TileCoordinate {
+    type Err = ();
+
+    /// Parses the format written by [`Display`](fmt::Display): `face_lod_x_y`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('_');
+        let mut next = || parts.next().ok_or(());
+
+        let face = next()?.parse().map_err(|_| ())?;
+        let lod = next()?.parse().map_err(|_| ())?;
+        let x = next()?.parse().map_err(|_| ())?;
+        let y = next()?.parse().map_err(|_| ())?;
+
+        if parts.next().is_some() {
+            return Err(());
+        }
+
+        Ok(Self::new(face, lod, IVec2::new(x, y)))
+    }
+}
+
 #[derive(Copy, Clone, Default, Debug, ShaderType)]
 pub struct ViewCoordinate {
     pub xy: IVec2,
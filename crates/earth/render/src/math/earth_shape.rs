@@ -64,6 +64,41 @@ impl EarthShape {
         local_position + height * local_normal
     }
 
+    /// Maps the geodetic longitude and latitude (in radians) onto the unit sphere,
+    /// so that [`position_unit_to_local`](Self::position_unit_to_local) places it on the surface
+    /// at that latitude.
+    ///
+    /// Scaling the unit sphere into a spheroid preserves the parametric latitude,
+    /// which is why the geodetic latitude is converted to it first.
+    pub fn unit_position_from_lon_lat(self, lon: f64, lat: f64) -> DVec3 {
+        let lat = match self {
+            Self::Spheroid {
+                major_axis,
+                minor_axis,
+            } => (minor_axis * lat.sin()).atan2(major_axis * lat.cos()),
+            _ => lat,
+        };
+
+        DVec3::new(-lat.cos() * lon.cos(), lat.sin(), lat.cos() * lon.sin())
+    }
+
+    /// Maps a position on the unit sphere to its geodetic longitude and latitude (in radians).
+    /// This is the inverse of [`unit_position_from_lon_lat`](Self::unit_position_from_lon_lat).
+    pub fn lon_lat_from_unit_position(self, unit_position: DVec3) -> (f64, f64) {
+        let lon = unit_position.z.atan2(-unit_position.x);
+        let radius = unit_position.xz().length();
+
+        let lat = match self {
+            Self::Spheroid {
+                major_axis,
+                minor_axis,
+            } => (major_axis * unit_position.y).atan2(minor_axis * radius),
+            _ => unit_position.y.atan2(radius),
+        };
+
+        (lon, lat)
+    }
+
     pub fn position_local_to_unit(self, local_position: DVec3) -> DVec3 {
         match self {
             Self::Plane { .. } => DVec3::new(1.0, 0.0, 1.0) * local_position / self.scale(),
@@ -79,3 +114,73 @@ impl EarthShape {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Coordinate;
+    use std::f64::consts::FRAC_PI_2;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// A grid of longitudes and latitudes including the poles, which avoids the edges of the faces.
+    fn lon_lats() -> impl Iterator<Item = (f64, f64)> {
+        (-12..12).flat_map(|i| {
+            let lon = (i as f64 * 15.0 + 7.0).to_radians();
+            (-6..=6).map(move |j| (lon, j as f64 * FRAC_PI_2 / 6.0))
+        })
+    }
+
+    #[test]
+    fn lon_lat_round_trip() {
+        for shape in [EarthShape::WGS84, EarthShape::Sphere { radius: 1.0 }] {
+            for (lon, lat) in lon_lats() {
+                let unit_position = shape.unit_position_from_lon_lat(lon, lat);
+                let (lon_result, lat_result) = shape.lon_lat_from_unit_position(unit_position);
+
+                assert!((unit_position.length() - 1.0).abs() < EPSILON);
+                assert!((lat_result - lat).abs() < EPSILON);
+
+                // the longitude of the poles is arbitrary
+                if lat.abs() < FRAC_PI_2 {
+                    let difference = (lon_result - lon).rem_euclid(std::f64::consts::TAU);
+                    assert!(difference < EPSILON || difference > std::f64::consts::TAU - EPSILON);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spheroid_surface_has_geodetic_latitude() {
+        let shape = EarthShape::WGS84;
+
+        for (lon, lat) in lon_lats() {
+            let unit_position = shape.unit_position_from_lon_lat(lon, lat);
+            let local_position = shape.position_unit_to_local(unit_position, 0.0);
+
+            // the geodetic latitude is the angle between the normal of the surface and the equator
+            let normal = local_position / (shape.scale() * shape.scale());
+            let geodetic_lat = normal.y.atan2(normal.xz().length());
+
+            assert!((geodetic_lat - lat).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn local_position_round_trip() {
+        let shape = EarthShape::WGS84;
+
+        for (lon, lat) in lon_lats() {
+            let unit_position = shape.unit_position_from_lon_lat(lon, lat);
+            let coordinate = Coordinate::from_unit_position(unit_position, true);
+            let local_position = coordinate.local_position(shape, 0.0);
+
+            let result = Coordinate::from_local_position(local_position, shape);
+            let (_, lat_result) = shape.lon_lat_from_unit_position(result.unit_position(true));
+
+            assert_eq!(result.face, coordinate.face);
+            assert!((result.uv - coordinate.uv).length() < EPSILON);
+            assert!((lat_result - lat).abs() < EPSILON);
+        }
+    }
+}
@@ -40,7 +40,6 @@ impl Default for EarthSettings {
                 AttachmentLabel::DayTime,
                 AttachmentLabel::NightTime,
                 AttachmentLabel::OceanMask,
-                AttachmentLabel::Bathyometry
             ],
             atlas_size: 1028,
         }
@@ -68,6 +67,7 @@ impl Plugin for EarthPlugin {
                     (
                         TileTree::compute_requests,
                         finish_loading,
+                        TileAtlas::finish_downloading,
                         TileAtlas::update,
                         start_loading,
                         TileTree::adjust_to_tile_atlas,
@@ -112,7 +112,7 @@ impl Plugin for EarthPlugin {
                     sort_phase_system::<EarthItem>.in_set(RenderSystems::PhaseSort),
                     prepare_earth_depth_textures.in_set(RenderSystems::PrepareResources),
                     (queue_tiling_prepass, GpuTileAtlas::queue).in_set(RenderSystems::Queue),
-                    GpuTileAtlas::_cleanup
+                    GpuTileAtlas::cleanup
                         .before(World::clear_entities)
                         .in_set(RenderSystems::Cleanup),
                 ),
@@ -55,6 +55,10 @@ impl render_graph::Node for MipPrepass {
 
             drop(pass);
 
+            for gpu_tile_atlas in gpu_tile_atlases.values() {
+                gpu_tile_atlas.download_tiles(&mut encoder);
+            }
+
             encoder.finish()
         });
 
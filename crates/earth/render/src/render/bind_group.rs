@@ -1,7 +1,7 @@
 #![allow(unexpected_cfgs)]
 use crate::{
     earth::EarthComponents,
-    data::{GpuAttachment, GpuTileAtlas, TileAtlas},
+    data::{AttachmentFormat, AttachmentLabel, GpuAttachment, GpuTileAtlas, TileAtlas},
     utils::GpuBuffer,
 };
 use bevy::{
@@ -16,7 +16,7 @@ use bevy::{
         render_asset::RenderAssets,
         render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass},
         render_resource::*,
-        renderer::RenderDevice,
+        renderer::{RenderDevice, RenderQueue},
         storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
         texture::FallbackImage,
     },
@@ -56,9 +56,13 @@ struct AttachmentConfig {
     scale: f32,
     offset: f32,
     mask: u32,
-    padding1: u32,
-    padding2: u32,
-    padding3: u32,
+    /// Converts a sampled value into the integer, that stores the mask, zero for float formats.
+    mask_scale: f32,
+    /// The offsets of the atlas layers of the two blended time slices.
+    slice_offset_a: u32,
+    slice_offset_b: u32,
+    /// The weight of the second time slice.
+    slice_blend: f32,
 }
 
 impl AttachmentConfig {
@@ -71,9 +75,10 @@ impl AttachmentConfig {
             offset: attachment.buffer_info.border_size as f32
                 / attachment.buffer_info.texture_size as f32,
             mask: attachment.buffer_info.mask as u32,
-            padding1: 0,
-            padding2: 0,
-            padding3: 0,
+            mask_scale: attachment.buffer_info.format.mask_scale(),
+            slice_offset_a: attachment.slice_blend.layer_offsets[0],
+            slice_offset_b: attachment.slice_blend.layer_offsets[1],
+            slice_blend: attachment.slice_blend.weight,
         }
     }
 }
@@ -107,6 +112,8 @@ pub struct EarthUniform {
     min_height: f32,
     max_height: f32,
     height_scale: f32,
+    height_decode_scale: f32,
+    height_decode_offset: f32,
     world_from_local: [Vec4; 3],
     local_from_world_transpose_a: [Vec4; 2],
     local_from_world_transpose_b: f32,
@@ -119,12 +126,20 @@ impl EarthUniform {
         let (local_from_world_transpose_a, local_from_world_transpose_b) =
             transform.inverse_transpose_3x3();
 
+        let (height_decode_scale, height_decode_offset) = tile_atlas
+            .attachments
+            .get(&AttachmentLabel::Topography)
+            .map_or(AttachmentFormat::R8Unorm, |attachment| attachment.format)
+            .height_decoding(tile_atlas.min_height, tile_atlas.max_height);
+
         Self {
             lod_count: tile_atlas.lod_count,
             scale: tile_atlas.shape.scale().as_vec3(),
             min_height: tile_atlas.min_height * tile_atlas.height_scale,
             max_height: tile_atlas.max_height * tile_atlas.height_scale,
             height_scale: tile_atlas.height_scale,
+            height_decode_scale: height_decode_scale * tile_atlas.height_scale,
+            height_decode_offset: height_decode_offset * tile_atlas.height_scale,
             world_from_local,
             local_from_world_transpose_a,
             local_from_world_transpose_b,
@@ -151,7 +166,7 @@ impl GpuEarth {
         let attachment_buffer = GpuBuffer::create(
             device,
             &AttachmentUniform::new(gpu_tile_atlas),
-            BufferUsages::UNIFORM,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         );
 
         let attachment_textures = array::from_fn(|i| {
@@ -209,12 +224,20 @@ impl GpuEarth {
 
     pub(crate) fn prepare(
         device: Res<RenderDevice>,
+        queue: Res<RenderQueue>,
         buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+        gpu_tile_atlases: Res<EarthComponents<GpuTileAtlas>>,
         mut gpu_earths: ResMut<EarthComponents<GpuEarth>>,
     ) {
-        for gpu_earth in &mut gpu_earths.values_mut() {
+        for (earth, gpu_earth) in gpu_earths.iter_mut() {
             let earth_buffer = buffers.get(&gpu_earth.earth_buffer).unwrap();
 
+            // the blended time slices change with the time of the tile atlas
+            gpu_earth
+                .attachment_buffer
+                .set_value(AttachmentUniform::new(&gpu_tile_atlases[earth]));
+            gpu_earth.attachment_buffer.update(&queue);
+
             // Todo: be smarter about bind group recreation
             gpu_earth.earth_bind_group = Some(device.create_bind_group(
                 "earth_bind_group",
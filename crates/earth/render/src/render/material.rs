@@ -1,7 +1,7 @@
 use crate::{
     debug::DebugEarth,
     earth::EarthComponents,
-    data::GpuTileAtlas,
+    data::{AttachmentLabel, GpuTileAtlas},
     render::{
         DrawEarthCommand, EARTH_DEPTH_FORMAT, EarthItem, EarthTilingPrepassPipelines, GpuEarthView,
         SetEarthBindGroup, SetEarthViewBindGroup,
@@ -50,6 +50,7 @@ bitflags::bitflags! {
         const TILE_TREE_LOD      = 1 << 10;
         const SAMPLE_GRAD        = 1 << 12;
         const HIGH_PRECISION     = 1 << 13;
+        const NORMAL_MAP         = 1 << 14;
         const MSAA_RESERVED_BITS = EarthPipelineFlags::MSAA_MASK_BITS << EarthPipelineFlags::MSAA_SHIFT_BITS;
     }
 }
@@ -154,6 +155,9 @@ impl EarthPipelineFlags {
         if self.contains(EarthPipelineFlags::HIGH_PRECISION) {
             shader_defs.push("HIGH_PRECISION".into());
         }
+        if self.contains(EarthPipelineFlags::NORMAL_MAP) {
+            shader_defs.push("NORMAL_MAP".into());
+        }
 
         shader_defs
     }
@@ -325,7 +329,7 @@ pub(crate) fn queue_earth<M: Material>(
             continue;
         };
 
-        for (&earth, _gpu_tile_atlas) in gpu_tile_atlases.iter() {
+        for (&earth, gpu_tile_atlas) in gpu_tile_atlases.iter() {
             let Some(gpu_earth_view) = gpu_earth_views.get(&(earth, view)) else {
                 continue;
             };
@@ -338,6 +342,14 @@ pub(crate) fn queue_earth<M: Material>(
                 flags |= EarthPipelineFlags::SAMPLE_GRAD;
             }
 
+            // use the precomputed normals instead of differentiating the heights
+            if gpu_tile_atlas
+                .attachments
+                .contains_key(&AttachmentLabel::Normals)
+            {
+                flags |= EarthPipelineFlags::NORMAL_MAP;
+            }
+
             let key = EarthPipelineKey { flags };
 
             let pipeline = pipelines.specialize(&pipeline_cache, &earth_pipeline, key);
@@ -2,6 +2,9 @@
 
 #import waw_earth_render::types::{AtlasTile, TangentSpace, AttachmentConfig, SampleUV, WorldCoordinate}
 #import waw_earth_render::bindings::{earth, earth_view, earth_sampler, attachments, topography_attachment}
+#ifdef NORMAL_MAP
+#import waw_earth_render::bindings::normals_attachment
+#endif
 
 #ifdef FRAGMENT
 fn compute_sample_uv(tile: AtlasTile, attachment: AttachmentConfig) -> SampleUV {
@@ -26,16 +29,16 @@ fn sample_height(tile: AtlasTile) -> f32 {
 
 #ifdef FRAGMENT
 #ifdef SAMPLE_GRAD
-    let normalized_height = textureSampleGrad(topography_attachment, earth_sampler, uv.uv, tile.index, uv.dx, uv.dy).x;
+    let value = textureSampleGrad(topography_attachment, earth_sampler, uv.uv, tile.index, uv.dx, uv.dy).x;
 #else
-    let normalized_height = textureSampleLevel(topography_attachment, earth_sampler, uv.uv, tile.index, tile.blend_ratio).x;
+    let value = textureSampleLevel(topography_attachment, earth_sampler, uv.uv, tile.index, tile.blend_ratio).x;
 #endif
 #else
-    let normalized_height = textureSampleLevel(topography_attachment, earth_sampler, uv.uv, tile.index, 0.0).x;
+    let value = textureSampleLevel(topography_attachment, earth_sampler, uv.uv, tile.index, 0.0).x;
 #endif
 
-    // Map normalized height (0.0-1.0) to actual height range (min_height to max_height)
-    return mix(earth.min_height, earth.max_height, normalized_height);
+    // Normalized formats span the height range of the earth, signed and float formats store metres
+    return earth.height_decode_scale * value + earth.height_decode_offset;
 }
 
 fn sample_height_mask(tile: AtlasTile) -> bool {
@@ -45,13 +48,45 @@ fn sample_height_mask(tile: AtlasTile) -> bool {
 
     let uv         = tile.coordinate.uv * attachment.scale + attachment.offset;
     let raw_height = textureGather(0, topography_attachment, earth_sampler, uv, tile.index);
-    let mask       = bitcast<vec4<u32>>(raw_height) & vec4<u32>(1);
+
+    // float formats store the mask in their bits, the others in the integer they are sampled from
+    var bits = bitcast<vec4<u32>>(raw_height);
+    if (attachment.mask_scale != 0.0) {
+        bits = bitcast<vec4<u32>>(vec4<i32>(round(raw_height * attachment.mask_scale)));
+    }
+
+    let mask = bits & vec4<u32>(1);
 
     return any(mask == vec4<u32>(0));
 }
 
 #ifdef FRAGMENT
+#ifdef NORMAL_MAP
+// Converts the precomputed normal into the change of height per tile uv.
+fn sample_height_duv(tile: AtlasTile) -> vec2<f32> {
+    let uv = compute_sample_uv(tile, attachments.normals);
+
+#ifdef SAMPLE_GRAD
+    let packed = textureSampleGrad(normals_attachment, earth_sampler, uv.uv, tile.index, uv.dx, uv.dy).xy;
+#else
+    let packed = textureSampleLevel(normals_attachment, earth_sampler, uv.uv, tile.index, tile.blend_ratio).xy;
+#endif
+
+    let normal_uv = 2.0 * packed - 1.0;
+    let normal_z  = sqrt(max(1.0 - dot(normal_uv, normal_uv), 1e-6));
+
+    // The normals store the slope in metres per metre, the tile size is approximated by the
+    // length of its edge on a sphere with the major axis as radius
+    let tile_size = 0.5 * 3.14159265 * earth.scale.x / exp2(f32(tile.coordinate.lod));
+
+    return -earth.height_scale * tile_size * normal_uv / normal_z;
+}
+#endif
+
 fn sample_surface_gradient(tile: AtlasTile, tangent_space: TangentSpace) -> vec3<f32> {
+#ifdef NORMAL_MAP
+    let height_duv = sample_height_duv(tile);
+#else
     let attachment = attachments.topography;
     let uv         = compute_sample_uv(tile, attachment);
     let scale      = max(max(length(uv.dx), length(uv.dy)), 1e-6);
@@ -67,8 +102,8 @@ fn sample_surface_gradient(tile: AtlasTile, tangent_space: TangentSpace) -> vec3
     let height_v = textureSampleLevel(topography_attachment, earth_sampler, uv.uv + vec2<f32>(-step,  step), tile.index, tile.blend_ratio).x;
 #endif
 
-    // Denormalize heights from 0.0-1.0 to actual height range
-    let height_range = earth.max_height - earth.min_height;
+    // Convert the sampled values into heights, the offset cancels out
+    let height_range = earth.height_decode_scale;
     var height_duv = height_range * vec2<f32>(height_u - height, height_v - height) / scale;
 
     let start = 0.5;
@@ -101,6 +136,7 @@ fn sample_surface_gradient(tile: AtlasTile, tangent_space: TangentSpace) -> vec3
         let upscaled_height_duv = height_range * attachment.texture_size * vec2(dot(Y, dX * height_matrix), dot(dY, X * height_matrix));
         height_duv = mix(height_duv, upscaled_height_duv, ratio);
     }
+#endif
 
     let height_dx = dot(height_duv, tile.coordinate.uv_dx);
     let height_dy = dot(height_duv, tile.coordinate.uv_dy);
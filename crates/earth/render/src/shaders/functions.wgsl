@@ -76,10 +76,18 @@ fn compute_world_coordinate(coordinate: Coordinate, height: f32, view_distance:
 fn sample_attachment(tile: AtlasTile, attachment: texture_2d_array<f32>, attachment_label: AttachmentConfig) -> vec4<f32> {
     let uv = compute_sample_uv(tile, attachment_label);
 
+    // attachments with time slices blend the two slices around the current time
+    let index_a = tile.index + attachment_label.slice_offset_a;
+    let index_b = tile.index + attachment_label.slice_offset_b;
+
 #ifdef SAMPLE_GRAD
-    return textureSampleGrad(attachment, earth_sampler, uv.uv, tile.index, uv.dx, uv.dy);
+    let value = textureSampleGrad(attachment, earth_sampler, uv.uv, index_a, uv.dx, uv.dy);
+    if (attachment_label.slice_blend == 0.0) { return value; }
+    return mix(value, textureSampleGrad(attachment, earth_sampler, uv.uv, index_b, uv.dx, uv.dy), attachment_label.slice_blend);
 #else
-    return textureSampleLevel(attachment, earth_sampler, uv.uv, tile.index, tile.blend_ratio);
+    let value = textureSampleLevel(attachment, earth_sampler, uv.uv, index_a, tile.blend_ratio);
+    if (attachment_label.slice_blend == 0.0) { return value; }
+    return mix(value, textureSampleLevel(attachment, earth_sampler, uv.uv, index_b, tile.blend_ratio), attachment_label.slice_blend);
 #endif
 }
 #endif
@@ -6,6 +6,8 @@ struct Earth {
     min_height: f32,
     max_height: f32,
     height_scale: f32,
+    height_decode_scale: f32,
+    height_decode_offset: f32,
     world_from_unit: mat3x4<f32>,
     unit_from_world_transpose_a: mat2x4<f32>,
     unit_from_world_transpose_b: f32,
@@ -127,9 +129,10 @@ struct AttachmentConfig {
     scale: f32,
     offset: f32,
     mask: u32,
-    paddinga: u32,
-    paddingb: u32,
-    paddingc: u32,
+    mask_scale: f32,
+    slice_offset_a: u32,
+    slice_offset_b: u32,
+    slice_blend: f32,
 }
 
 
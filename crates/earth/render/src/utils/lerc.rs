@@ -0,0 +1,908 @@
+//! A decoder for LERC2 blobs, which GDAL writes into the tiles of `COMPRESS=LERC` tiffs.
+//!
+//! Only the lossy and raw encodings are supported, the Huffman coded variants
+//! (used for lossless byte and float data) are rejected.
+
+use std::io;
+
+const FILE_KEY: &[u8] = b"Lerc2 ";
+
+/// The data type of the values stored in a blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DataType {
+    Char,
+    Byte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl DataType {
+    fn new(value: i32) -> io::Result<Self> {
+        Ok(match value {
+            0 => Self::Char,
+            1 => Self::Byte,
+            2 => Self::Short,
+            3 => Self::UShort,
+            4 => Self::Int,
+            5 => Self::UInt,
+            6 => Self::Float,
+            7 => Self::Double,
+            _ => return Err(invalid("unknown data type")),
+        })
+    }
+
+    fn index(self) -> i32 {
+        self as i32
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::Char | Self::Byte => 1,
+            Self::Short | Self::UShort => 2,
+            Self::Int | Self::UInt | Self::Float => 4,
+            Self::Double => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Self::Float | Self::Double)
+    }
+
+    /// The smaller type, that the offset of a block has been reduced to.
+    fn reduced(self, reduction: i32) -> io::Result<Self> {
+        let index = match self {
+            Self::Short | Self::Int => self.index() - reduction,
+            Self::UShort | Self::UInt => self.index() - 2 * reduction,
+            Self::Float => match reduction {
+                0 => self.index(),
+                1 => Self::Short.index(),
+                _ => Self::Byte.index(),
+            },
+            Self::Double => match reduction {
+                0 => self.index(),
+                _ => self.index() - 2 * reduction + 1,
+            },
+            _ => self.index(),
+        };
+
+        Self::new(index)
+    }
+
+    /// Converts the value into the little endian bytes of this type.
+    fn write(self, value: f64, bytes: &mut Vec<u8>) {
+        match self {
+            Self::Char => bytes.extend((value as i8).to_le_bytes()),
+            Self::Byte => bytes.extend((value as u8).to_le_bytes()),
+            Self::Short => bytes.extend((value as i16).to_le_bytes()),
+            Self::UShort => bytes.extend((value as u16).to_le_bytes()),
+            Self::Int => bytes.extend((value as i32).to_le_bytes()),
+            Self::UInt => bytes.extend((value as u32).to_le_bytes()),
+            Self::Float => bytes.extend((value as f32).to_le_bytes()),
+            Self::Double => bytes.extend(value.to_le_bytes()),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid LERC blob: {message}"),
+    )
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + count)
+            .ok_or_else(|| invalid("unexpected end"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_le_bytes(self.read()?))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.read()?))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.read()?))
+    }
+
+    fn value(&mut self, data_type: DataType) -> io::Result<f64> {
+        Ok(match data_type {
+            DataType::Char => i8::from_le_bytes(self.read()?) as f64,
+            DataType::Byte => u8::from_le_bytes(self.read()?) as f64,
+            DataType::Short => i16::from_le_bytes(self.read()?) as f64,
+            DataType::UShort => u16::from_le_bytes(self.read()?) as f64,
+            DataType::Int => i32::from_le_bytes(self.read()?) as f64,
+            DataType::UInt => u32::from_le_bytes(self.read()?) as f64,
+            DataType::Float => f32::from_le_bytes(self.read()?) as f64,
+            DataType::Double => f64::from_le_bytes(self.read()?),
+        })
+    }
+
+    /// Reads an unsigned integer, that is stored in the given number of bytes.
+    fn uint(&mut self, size: usize) -> io::Result<u32> {
+        Ok(match size {
+            1 => self.u8()? as u32,
+            2 => u16::from_le_bytes(self.read()?) as u32,
+            4 => u32::from_le_bytes(self.read()?),
+            _ => return Err(invalid("unsupported integer size")),
+        })
+    }
+}
+
+struct Header {
+    version: i32,
+    height: usize,
+    width: usize,
+    depth: usize,
+    valid_count: usize,
+    block_size: usize,
+    data_type: DataType,
+    pass_no_data: bool,
+    max_z_error: f64,
+    z_min: f64,
+    z_max: f64,
+    no_data: f64,
+    no_data_original: f64,
+}
+
+impl Header {
+    fn read(reader: &mut ByteReader) -> io::Result<Self> {
+        if reader.take(FILE_KEY.len())? != FILE_KEY {
+            return Err(invalid("missing file key"));
+        }
+
+        let version = reader.i32()?;
+
+        // the bit stuffing of older versions is not supported
+        if !(3..=6).contains(&version) {
+            return Err(invalid("unsupported version"));
+        }
+
+        let _checksum = reader.read::<4>()?;
+        let height = reader.i32()? as usize;
+        let width = reader.i32()? as usize;
+        let depth = if version >= 4 {
+            reader.i32()? as usize
+        } else {
+            1
+        };
+        let valid_count = reader.i32()? as usize;
+        let block_size = reader.i32()? as usize;
+        let _blob_size = reader.i32()?;
+        let data_type = DataType::new(reader.i32()?)?;
+
+        let mut pass_no_data = false;
+
+        if version >= 6 {
+            let _blobs_more = reader.i32()?;
+            pass_no_data = reader.u8()? != 0;
+            let _is_int = reader.u8()?;
+            let _reserved = reader.read::<2>()?;
+        }
+
+        let max_z_error = reader.f64()?;
+        let z_min = reader.f64()?;
+        let z_max = reader.f64()?;
+
+        let (no_data, no_data_original) = if version >= 6 {
+            (reader.f64()?, reader.f64()?)
+        } else {
+            (0.0, 0.0)
+        };
+
+        if depth == 0 || block_size == 0 {
+            return Err(invalid("empty dimensions"));
+        }
+
+        Ok(Self {
+            version,
+            height,
+            width,
+            depth,
+            valid_count,
+            block_size,
+            data_type,
+            pass_no_data,
+            max_z_error,
+            z_min,
+            z_max,
+            no_data,
+            no_data_original,
+        })
+    }
+}
+
+/// A decoded LERC2 blob.
+pub(crate) struct LercImage {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// The number of values per pixel.
+    pub(crate) depth: usize,
+    /// The size of a single value in bytes.
+    pub(crate) value_size: usize,
+    /// The values of all pixels, interleaved and in little endian byte order.
+    pub(crate) bytes: Vec<u8>,
+}
+
+/// Decodes the blob and replaces all invalid pixels with the no data value.
+pub(crate) fn decode_lerc(blob: &[u8], no_data: f64) -> io::Result<LercImage> {
+    let mut reader = ByteReader {
+        bytes: blob,
+        position: 0,
+    };
+
+    let header = Header::read(&mut reader)?;
+    let valid = read_mask(&mut reader, &header)?;
+
+    let pixel_count = header.width * header.height;
+    let mut values = vec![0.0; pixel_count * header.depth];
+
+    let (z_min, z_max) = if header.valid_count == 0 {
+        (vec![0.0; header.depth], vec![0.0; header.depth])
+    } else if header.z_min == header.z_max {
+        (
+            vec![header.z_min; header.depth],
+            vec![header.z_max; header.depth],
+        )
+    } else if header.version >= 4 {
+        let z_min = (0..header.depth)
+            .map(|_| reader.value(header.data_type))
+            .collect::<io::Result<Vec<_>>>()?;
+        let z_max = (0..header.depth)
+            .map(|_| reader.value(header.data_type))
+            .collect::<io::Result<Vec<_>>>()?;
+        (z_min, z_max)
+    } else {
+        (vec![header.z_min], vec![header.z_max])
+    };
+
+    let is_constant = z_min
+        .iter()
+        .zip(&z_max)
+        .all(|(z_min, z_max)| z_min == z_max);
+
+    if header.valid_count > 0 && is_constant {
+        for pixel in (0..pixel_count).filter(|&pixel| valid[pixel]) {
+            values[pixel * header.depth..(pixel + 1) * header.depth].copy_from_slice(&z_min);
+        }
+    } else if header.valid_count > 0 {
+        let one_sweep = reader.u8()? != 0;
+
+        if one_sweep {
+            for pixel in (0..pixel_count).filter(|&pixel| valid[pixel]) {
+                for dimension in 0..header.depth {
+                    values[pixel * header.depth + dimension] = reader.value(header.data_type)?;
+                }
+            }
+        } else {
+            let tries_huffman = match header.data_type {
+                DataType::Char | DataType::Byte => header.max_z_error == 0.5,
+                DataType::Float | DataType::Double => {
+                    header.version >= 6 && header.max_z_error == 0.0
+                }
+                _ => header.version >= 6 && header.max_z_error == 0.5,
+            };
+
+            if tries_huffman && reader.u8()? != 0 {
+                return Err(invalid("Huffman coding is not supported"));
+            }
+
+            read_blocks(&mut reader, &header, &valid, &z_max, &mut values)?;
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(values.len() * header.data_type.size());
+
+    for (index, &value) in values.iter().enumerate() {
+        let value = if !valid[index / header.depth] {
+            no_data
+        } else if header.pass_no_data && value == header.no_data {
+            header.no_data_original
+        } else {
+            value
+        };
+
+        header.data_type.write(value, &mut bytes);
+    }
+
+    Ok(LercImage {
+        width: header.width,
+        height: header.height,
+        depth: header.depth,
+        value_size: header.data_type.size(),
+        bytes,
+    })
+}
+
+fn read_mask(reader: &mut ByteReader, header: &Header) -> io::Result<Vec<bool>> {
+    let pixel_count = header.width * header.height;
+    let mask_size = reader.i32()? as usize;
+
+    if header.valid_count == 0 {
+        return Ok(vec![false; pixel_count]);
+    }
+    if header.valid_count == pixel_count {
+        return Ok(vec![true; pixel_count]);
+    }
+    if mask_size == 0 {
+        return Err(invalid("missing mask"));
+    }
+
+    let mask_end = reader.position + mask_size;
+    let mut bits = Vec::with_capacity(pixel_count.div_ceil(8));
+
+    // run length encoded, terminated by i16::MIN
+    loop {
+        let count = reader.i16()?;
+
+        match count {
+            i16::MIN => break,
+            count if count > 0 => bits.extend_from_slice(reader.take(count as usize)?),
+            count => {
+                let byte = reader.u8()?;
+                bits.extend(std::iter::repeat_n(byte, count.unsigned_abs() as usize));
+            }
+        }
+    }
+
+    if bits.len() < pixel_count.div_ceil(8) {
+        return Err(invalid("mask too short"));
+    }
+
+    reader.position = mask_end;
+
+    Ok((0..pixel_count)
+        .map(|pixel| bits[pixel >> 3] & (128 >> (pixel & 7)) != 0)
+        .collect())
+}
+
+fn read_blocks(
+    reader: &mut ByteReader,
+    header: &Header,
+    valid: &[bool],
+    z_max: &[f64],
+    values: &mut [f64],
+) -> io::Result<()> {
+    let block_size = header.block_size;
+    let depth = header.depth;
+
+    for block_y in (0..header.height).step_by(block_size) {
+        for block_x in (0..header.width).step_by(block_size) {
+            let pixels = (block_y..(block_y + block_size).min(header.height))
+                .flat_map(|y| {
+                    (block_x..(block_x + block_size).min(header.width))
+                        .map(move |x| y * header.width + x)
+                })
+                .filter(|&pixel| valid[pixel])
+                .collect::<Vec<_>>();
+
+            for dimension in 0..depth {
+                let flag = reader.u8()?;
+
+                // the upper bits of the flag encode the column of the block as an integrity check
+                let (is_difference, test_code, expected_code) = if header.version >= 5 {
+                    (flag & 4 != 0, (flag >> 3) & 7, (block_x >> 3) as u8 & 7)
+                } else {
+                    (false, (flag >> 2) & 15, (block_x >> 3) as u8 & 15)
+                };
+
+                if test_code != expected_code {
+                    return Err(invalid("integrity check failed"));
+                }
+
+                if is_difference && dimension == 0 {
+                    return Err(invalid("the first dimension can not be difference encoded"));
+                }
+
+                // differences are encoded relative to the previous dimension
+                let base = |values: &[f64], pixel: usize| {
+                    if is_difference {
+                        values[pixel * depth + dimension - 1]
+                    } else {
+                        0.0
+                    }
+                };
+
+                let data_type = match (is_difference, header.data_type.is_integer()) {
+                    (false, _) => header.data_type,
+                    (true, true) => DataType::Int,
+                    (true, false) => DataType::Double,
+                };
+
+                match flag & 3 {
+                    // uncompressed
+                    0 => {
+                        for &pixel in &pixels {
+                            values[pixel * depth + dimension] =
+                                base(values, pixel) + reader.value(data_type)?;
+                        }
+                    }
+                    // constant zero
+                    2 => {
+                        for &pixel in &pixels {
+                            values[pixel * depth + dimension] = base(values, pixel);
+                        }
+                    }
+                    compression => {
+                        let offset = reader.value(data_type.reduced((flag >> 6) as i32)?)?;
+
+                        let quantized = if compression == 3 {
+                            vec![0; pixels.len()]
+                        } else {
+                            let quantized = unstuff(reader, pixels.len())?;
+
+                            if quantized.len() != pixels.len() {
+                                return Err(invalid("block size mismatch"));
+                            }
+
+                            quantized
+                        };
+
+                        let scale = 2.0 * header.max_z_error;
+
+                        for (&pixel, &quantized) in pixels.iter().zip(&quantized) {
+                            let value = offset + quantized as f64 * scale;
+                            let value = if is_difference {
+                                base(values, pixel) + value
+                            } else {
+                                value.min(z_max[dimension])
+                            };
+
+                            values[pixel * depth + dimension] = value;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a bit stuffed array of unsigned integers, optionally compressed with a lookup table.
+fn unstuff(reader: &mut ByteReader, max_count: usize) -> io::Result<Vec<u32>> {
+    let flags = reader.u8()?;
+    let count_size = match flags >> 6 {
+        0 => 4,
+        size => 3 - size as usize,
+    };
+    let uses_lookup_table = flags & 32 != 0;
+    let bit_count = (flags & 31) as usize;
+
+    let count = reader.uint(count_size)? as usize;
+
+    if count > max_count {
+        return Err(invalid("too many values"));
+    }
+
+    if !uses_lookup_table {
+        return if bit_count == 0 {
+            Ok(vec![0; count])
+        } else {
+            unstuff_bits(reader, count, bit_count)
+        };
+    }
+
+    if bit_count == 0 {
+        return Err(invalid("empty lookup table"));
+    }
+
+    // the table does not store the leading zero
+    let table_size = reader.u8()? as usize - 1;
+    let mut table = vec![0];
+    table.extend(unstuff_bits(reader, table_size, bit_count)?);
+
+    let index_bit_count = (usize::BITS - table_size.leading_zeros()) as usize;
+
+    if index_bit_count == 0 {
+        return Err(invalid("empty lookup table"));
+    }
+
+    unstuff_bits(reader, count, index_bit_count)?
+        .into_iter()
+        .map(|index| {
+            table
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| invalid("lookup table index out of range"))
+        })
+        .collect()
+}
+
+/// Reads values, that are packed into little endian u32s, starting with the least significant bit.
+/// The unused tail bytes of the last u32 are omitted.
+fn unstuff_bits(reader: &mut ByteReader, count: usize, bit_count: usize) -> io::Result<Vec<u32>> {
+    let total_bits = count * bit_count;
+    let word_count = total_bits.div_ceil(32);
+    let tail_bits = total_bits & 31;
+    let skipped_bytes = if tail_bits == 0 {
+        0
+    } else {
+        4 - tail_bits.div_ceil(8)
+    };
+
+    let mut bytes = reader.take(word_count * 4 - skipped_bytes)?.to_vec();
+    bytes.resize(word_count * 4 + 4, 0);
+
+    let words = bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as u64)
+        .collect::<Vec<_>>();
+
+    let mask = (1u64 << bit_count) - 1;
+
+    Ok((0..count)
+        .map(|index| {
+            let position = index * bit_count;
+            let (word, shift) = (position / 32, position % 32);
+            let bits = words[word] | (words[word + 1] << 32);
+
+            ((bits >> shift) & mask) as u32
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The header of a test blob, which is written in the `Lerc2` layout of libLERC.
+    struct TestHeader {
+        version: i32,
+        width: usize,
+        height: usize,
+        depth: usize,
+        block_size: usize,
+        data_type: DataType,
+        max_z_error: f64,
+        z_min: f64,
+        z_max: f64,
+        /// The internal and original no data value of version 6 blobs, that pass them through.
+        no_data: Option<(f64, f64)>,
+    }
+
+    impl TestHeader {
+        fn new(version: i32, width: usize, height: usize, data_type: DataType) -> Self {
+            Self {
+                version,
+                width,
+                height,
+                depth: 1,
+                block_size: 8,
+                data_type,
+                max_z_error: if data_type.is_integer() { 0.5 } else { 0.0 },
+                z_min: 0.0,
+                z_max: 0.0,
+                no_data: None,
+            }
+        }
+
+        /// Writes the header and the run length encoded mask, followed by the body.
+        /// The checksum is left empty, since the decoder does not verify it.
+        fn blob(&self, valid: &[bool], body: &[u8]) -> Vec<u8> {
+            let valid_count = valid.iter().filter(|&&valid| valid).count();
+
+            let mut bytes = FILE_KEY.to_vec();
+            bytes.extend(self.version.to_le_bytes());
+            bytes.extend(0u32.to_le_bytes());
+            bytes.extend((self.height as i32).to_le_bytes());
+            bytes.extend((self.width as i32).to_le_bytes());
+            if self.version >= 4 {
+                bytes.extend((self.depth as i32).to_le_bytes());
+            }
+            bytes.extend((valid_count as i32).to_le_bytes());
+            bytes.extend((self.block_size as i32).to_le_bytes());
+            bytes.extend(0i32.to_le_bytes());
+            bytes.extend(self.data_type.index().to_le_bytes());
+            if self.version >= 6 {
+                bytes.extend(0i32.to_le_bytes());
+                bytes.extend([self.no_data.is_some() as u8, 0, 0, 0]);
+            }
+            bytes.extend(self.max_z_error.to_le_bytes());
+            bytes.extend(self.z_min.to_le_bytes());
+            bytes.extend(self.z_max.to_le_bytes());
+            if self.version >= 6 {
+                let (no_data, no_data_original) = self.no_data.unwrap_or_default();
+                bytes.extend(no_data.to_le_bytes());
+                bytes.extend(no_data_original.to_le_bytes());
+            }
+
+            if valid_count == 0 || valid_count == valid.len() {
+                bytes.extend(0i32.to_le_bytes());
+            } else {
+                let mut bits = vec![0u8; valid.len().div_ceil(8)];
+                for (pixel, _) in valid.iter().enumerate().filter(|(_, valid)| **valid) {
+                    bits[pixel >> 3] |= 128 >> (pixel & 7);
+                }
+
+                // a single literal run, followed by the end marker
+                let mut mask = (bits.len() as i16).to_le_bytes().to_vec();
+                mask.extend(&bits);
+                mask.extend(i16::MIN.to_le_bytes());
+
+                bytes.extend((mask.len() as i32).to_le_bytes());
+                bytes.extend(mask);
+            }
+
+            bytes.extend(body);
+            bytes
+        }
+    }
+
+    fn values(data_type: DataType, values: &[f64]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &value in values {
+            data_type.write(value, &mut bytes);
+        }
+        bytes
+    }
+
+    /// Packs the values into little endian u32s, starting with the least significant bit,
+    /// like the bit stuffing of libLERC since version 3.
+    fn stuff(values: &[u32], bit_count: usize) -> Vec<u8> {
+        let total_bits = values.len() * bit_count;
+        let mut words = vec![0u32; total_bits.div_ceil(32) + 1];
+
+        for (index, &value) in values.iter().enumerate() {
+            let (word, shift) = (index * bit_count / 32, index * bit_count % 32);
+            words[word] |= value << shift;
+            if shift + bit_count > 32 {
+                words[word + 1] |= value >> (32 - shift);
+            }
+        }
+
+        let mut bytes = words[..total_bits.div_ceil(32)]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        // the unused bytes of the last word are omitted
+        if total_bits % 32 != 0 {
+            bytes.truncate(bytes.len() - (4 - (total_bits % 32).div_ceil(8)));
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn raw_values_of_every_data_type() {
+        let signed = [-100.0, -1.0, 0.0, 100.0];
+        let unsigned = [0.0, 1.0, 100.0, 200.0];
+
+        for (data_type, pixels) in [
+            (DataType::Char, signed),
+            (DataType::Byte, unsigned),
+            (DataType::Short, [-30000.0, -1.0, 0.0, 30000.0]),
+            (DataType::UShort, [0.0, 1.0, 1000.0, 65535.0]),
+            (DataType::Int, [-100000.0, -1.0, 0.0, 100000.0]),
+            (DataType::UInt, [0.0, 1.0, 100000.0, 4000000000.0]),
+            (DataType::Float, [-10.25, -0.5, 0.0, 8848.5]),
+            (DataType::Double, [-10.125, -0.5, 0.0, 1e10]),
+        ] {
+            let header = TestHeader {
+                z_min: pixels[0],
+                z_max: pixels[3],
+                ..TestHeader::new(3, 2, 2, data_type)
+            };
+
+            // the values are stored in one sweep
+            let mut body = vec![1];
+            body.extend(values(data_type, &pixels));
+
+            let image = decode_lerc(&header.blob(&[true; 4], &body), 0.0).unwrap();
+
+            assert_eq!((image.width, image.height, image.depth), (2, 2, 1));
+            assert_eq!(image.value_size, data_type.size());
+            assert_eq!(image.bytes, values(data_type, &pixels), "{data_type:?}");
+        }
+    }
+
+    #[test]
+    fn constant_and_empty_blobs() {
+        let header = TestHeader {
+            z_min: 7.0,
+            z_max: 7.0,
+            ..TestHeader::new(3, 2, 2, DataType::Byte)
+        };
+
+        let image = decode_lerc(&header.blob(&[true; 4], &[]), 0.0).unwrap();
+        assert_eq!(image.bytes, vec![7; 4]);
+
+        // blobs without valid pixels contain nothing but the no data value
+        let header = TestHeader::new(3, 2, 2, DataType::Float);
+
+        let image = decode_lerc(&header.blob(&[false; 4], &[]), -9999.0).unwrap();
+        assert_eq!(image.bytes, values(DataType::Float, &[-9999.0; 4]));
+    }
+
+    #[test]
+    fn invalid_pixels_are_replaced_with_no_data() {
+        let valid = [true, false, true, true, false, true];
+        let header = TestHeader {
+            z_min: -2.5,
+            z_max: 12.0,
+            ..TestHeader::new(3, 3, 2, DataType::Float)
+        };
+
+        // only the valid pixels are stored
+        let mut body = vec![1];
+        body.extend(values(DataType::Float, &[-2.5, 1.0, 4.0, 12.0]));
+
+        let image = decode_lerc(&header.blob(&valid, &body), -9999.0).unwrap();
+
+        assert_eq!(
+            image.bytes,
+            values(DataType::Float, &[-2.5, -9999.0, 1.0, 4.0, -9999.0, 12.0])
+        );
+    }
+
+    #[test]
+    fn no_data_values_are_passed_through() {
+        let header = TestHeader {
+            z_min: -1.0,
+            z_max: 500.0,
+            no_data: Some((-1.0, -32768.0)),
+            ..TestHeader::new(6, 3, 1, DataType::Short)
+        };
+
+        // the ranges of version 4 and above precede the values
+        let mut body = values(DataType::Short, &[-1.0, 500.0]);
+        body.push(1);
+        body.extend(values(DataType::Short, &[-1.0, 0.0, 500.0]));
+
+        let image = decode_lerc(&header.blob(&[true; 3], &body), 0.0).unwrap();
+
+        assert_eq!(
+            image.bytes,
+            values(DataType::Short, &[-32768.0, 0.0, 500.0])
+        );
+    }
+
+    #[test]
+    fn values_of_multiple_dimensions_are_interleaved() {
+        let header = TestHeader {
+            depth: 2,
+            z_min: 0.0,
+            z_max: 300.0,
+            ..TestHeader::new(4, 2, 1, DataType::UShort)
+        };
+
+        // the ranges of each dimension precede the values
+        let mut body = values(DataType::UShort, &[0.0, 10.0, 200.0, 300.0]);
+        body.push(1);
+        body.extend(values(DataType::UShort, &[0.0, 10.0, 200.0, 300.0]));
+
+        let image = decode_lerc(&header.blob(&[true; 2], &body), 0.0).unwrap();
+
+        assert_eq!(image.depth, 2);
+        assert_eq!(
+            image.bytes,
+            values(DataType::UShort, &[0.0, 10.0, 200.0, 300.0])
+        );
+    }
+
+    #[test]
+    fn bit_stuffed_blocks() {
+        // two block columns, so that the integrity check of the second one is not zero
+        let header = TestHeader {
+            z_min: 42.0,
+            z_max: 115.0,
+            ..TestHeader::new(3, 10, 2, DataType::UShort)
+        };
+
+        let quantized = (0..16).collect::<Vec<u32>>();
+
+        let mut body = vec![0];
+
+        // bit stuffed with an offset of 100
+        body.push(1);
+        body.extend(100u16.to_le_bytes());
+        body.push((2 << 6) | 4);
+        body.push(16);
+        body.extend(stuff(&quantized, 4));
+
+        // a constant offset of 42, which is reduced to a byte
+        body.push((1 << 6) | (1 << 2) | 3);
+        body.push(42);
+
+        let image = decode_lerc(&header.blob(&[true; 20], &body), 0.0).unwrap();
+
+        let expected = (0..20)
+            .map(|pixel| {
+                let (x, y) = (pixel % 10, pixel / 10);
+                if x < 8 {
+                    100.0 + (y * 8 + x) as f64
+                } else {
+                    42.0
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(image.bytes, values(DataType::UShort, &expected));
+
+        // the block column is encoded into the flag of every block
+        let mut corrupted = header.blob(&[true; 20], &body);
+        let flag = corrupted.len() - 2;
+        corrupted[flag] &= !(1 << 2);
+
+        assert!(decode_lerc(&corrupted, 0.0).is_err());
+    }
+
+    #[test]
+    fn bit_stuffed_lookup_table() {
+        let header = TestHeader {
+            z_min: -500.0,
+            z_max: 500.0,
+            ..TestHeader::new(3, 4, 1, DataType::Int)
+        };
+
+        let mut body = vec![0];
+
+        body.push(1);
+        body.extend((-500i32).to_le_bytes());
+        // the table stores 200 and 1000 with ten bits, the indices need two bits
+        body.push((2 << 6) | 32 | 10);
+        body.push(4);
+        body.push(3);
+        body.extend(stuff(&[200, 1000], 10));
+        body.extend(stuff(&[0, 1, 2, 1], 2));
+
+        let image = decode_lerc(&header.blob(&[true; 4], &body), 0.0).unwrap();
+
+        assert_eq!(
+            image.bytes,
+            values(DataType::Int, &[-500.0, -300.0, 500.0, -300.0])
+        );
+    }
+
+    #[test]
+    fn huffman_coded_blobs_are_rejected() {
+        for (version, data_type) in [(3, DataType::Byte), (6, DataType::Float)] {
+            let header = TestHeader {
+                z_min: 0.0,
+                z_max: 5.0,
+                ..TestHeader::new(version, 2, 1, data_type)
+            };
+
+            let ranges = if version >= 4 {
+                values(data_type, &[0.0, 5.0])
+            } else {
+                Vec::new()
+            };
+
+            // tiled, but Huffman coded
+            let body = [ranges.as_slice(), &[0, 1]].concat();
+            let error = decode_lerc(&header.blob(&[true; 2], &body), 0.0).unwrap_err();
+            assert!(error.to_string().contains("Huffman"), "{data_type:?}");
+
+            // the tiling mode is decoded like any other tiled blob
+            let body = [ranges.as_slice(), &[0, 0, 2]].concat();
+            let image = decode_lerc(&header.blob(&[true; 2], &body), 0.0).unwrap();
+            assert_eq!(image.bytes, values(data_type, &[0.0, 0.0]));
+        }
+    }
+}
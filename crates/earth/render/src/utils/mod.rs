@@ -1,7 +1,11 @@
+mod lerc;
 mod tiff;
+mod tile_archive;
 mod util;
 mod spawn;
 
 pub use util::*;
 pub use spawn::*;
-pub use tiff::TiffLoader;
\ No newline at end of file
+pub use tiff::{TiffLoader, TiffLoaderSettings};
+pub(crate) use tiff::{can_save_tile, decode_tiff, save_tile};
+pub use tile_archive::*;
\ No newline at end of file
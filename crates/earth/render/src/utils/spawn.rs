@@ -1,6 +1,6 @@
 use crate::{
     earth::EarthConfig,
-    data::{TileAtlas, TileTree},
+    data::{AssetTileSource, DetailTileSource, TileAtlas, TileLoader, TileTree},
     plugin::EarthSettings,
     view::{EarthViewComponents, EarthViewConfig},
 };
@@ -67,6 +67,12 @@ pub(crate) fn spawn_earths<M: Material>(
                     ))
                     .id();
 
+                if config.detail_lod_count > 0 {
+                    commands
+                        .entity(earth)
+                        .insert(TileLoader::new(DetailTileSource::new(AssetTileSource, &config)));
+                }
+
                 commands.entity(root).add_child(earth);
 
                 tile_trees.insert(
@@ -1,3 +1,7 @@
+use crate::{
+    data::{AttachmentData, AttachmentFormat, TileCompression},
+    utils::lerc::decode_lerc,
+};
 use bevy::{
     asset::{AssetLoader, LoadContext, RenderAssetUsages, io::Reader},
     image::ImageLoaderError,
@@ -5,41 +9,61 @@ use bevy::{
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
 };
 use bytemuck::cast_slice;
-use std::io::Cursor;
-use tiff::decoder::{Decoder, DecodingResult};
+use image_webp::WebPDecoder;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Cursor, Read, Seek},
+    path::Path,
+};
+use tiff::{
+    TiffError,
+    decoder::{ChunkType, Decoder, DecodingResult},
+    encoder::{Compression, DeflateLevel, TiffEncoder, colortype},
+    tags::Tag,
+};
+
+// the codes of the tiff compression tag
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_JPEG: u16 = 7;
+const COMPRESSION_DEFLATE: u16 = 8;
+const COMPRESSION_ZSTD: u16 = 50000;
+const COMPRESSION_LERC: u16 = 34887;
+const COMPRESSION_WEBP: u16 = 50001;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+pub struct TiffLoaderSettings {
+    /// The compression the tiles have been written with.
+    pub compression: TileCompression,
+}
+
+fn invalid_data(error: impl ToString) -> ImageLoaderError {
+    ImageLoaderError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        error.to_string(),
+    ))
+}
+
+fn from_tiff(error: TiffError) -> ImageLoaderError {
+    invalid_data(error)
+}
 
 #[derive(Default)]
 pub struct TiffLoader;
 impl AssetLoader for TiffLoader {
     type Asset = Image;
-    type Settings = ();
+    type Settings = TiffLoaderSettings;
     type Error = ImageLoaderError;
     async fn load(
         &self,
         reader: &mut dyn Reader,
-        _settings: &Self::Settings,
+        settings: &Self::Settings,
         _load_context: &mut LoadContext<'_>,
     ) -> Result<Image, Self::Error> {
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
 
-        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
-
-        let (width, height) = decoder.dimensions().unwrap();
-
-        let data = match decoder.read_image().unwrap() {
-            DecodingResult::U8(data) => cast_slice(&data).to_vec(),
-            DecodingResult::U16(data) => cast_slice(&data).to_vec(),
-            DecodingResult::U32(data) => cast_slice(&data).to_vec(),
-            DecodingResult::U64(data) => cast_slice(&data).to_vec(),
-            DecodingResult::F16(_) => panic!("TIFF F16 format is not supported in Bevy"),
-            DecodingResult::F32(data) => cast_slice(&data).to_vec(),
-            DecodingResult::F64(data) => cast_slice(&data).to_vec(),
-            DecodingResult::I8(data) => cast_slice(&data).to_vec(),
-            DecodingResult::I16(data) => cast_slice(&data).to_vec(),
-            DecodingResult::I32(data) => cast_slice(&data).to_vec(),
-            DecodingResult::I64(data) => cast_slice(&data).to_vec(),
-        };
+        let (data, width, height) = decode_tiff(&bytes, settings.compression)?;
 
         let mut image = Image::new_uninit(
             Extent3d {
@@ -62,3 +86,297 @@ impl AssetLoader for TiffLoader {
         &["tif", "tiff"]
     }
 }
+
+/// Decodes the pixels of a tile, that has been written with the compression,
+/// and returns them together with the size of the tile.
+pub(crate) fn decode_tiff(
+    bytes: &[u8],
+    tile_compression: TileCompression,
+) -> Result<(Vec<u8>, u32, u32), ImageLoaderError> {
+    let mut decoder = Decoder::new(Cursor::new(bytes)).map_err(from_tiff)?;
+
+    let (width, height) = decoder.dimensions().map_err(from_tiff)?;
+
+    let compression = decoder
+        .find_tag_unsigned::<u16>(Tag::Compression)
+        .map_err(from_tiff)?
+        .unwrap_or(COMPRESSION_NONE);
+
+    let expected_compression = match tile_compression {
+        TileCompression::None => COMPRESSION_NONE,
+        TileCompression::Deflate => COMPRESSION_DEFLATE,
+        TileCompression::Zstd => COMPRESSION_ZSTD,
+        TileCompression::Lerc { .. } => COMPRESSION_LERC,
+        TileCompression::WebP { .. } => COMPRESSION_WEBP,
+        TileCompression::Jpeg { .. } => COMPRESSION_JPEG,
+    };
+
+    if compression != expected_compression {
+        return Err(invalid_data(format!(
+            "expected a tile with {tile_compression:?} compression, but found the compression code {compression}"
+        )));
+    }
+
+    let data = match compression {
+        // the tiff crate does not support these codecs, so the chunks are decoded by hand
+        COMPRESSION_LERC | COMPRESSION_WEBP => {
+            decode_chunks(&mut decoder, bytes, compression, width, height)?
+        }
+        _ => match decoder.read_image().map_err(from_tiff)? {
+            DecodingResult::U8(data) => cast_slice(&data).to_vec(),
+            DecodingResult::U16(data) => cast_slice(&data).to_vec(),
+            DecodingResult::U32(data) => cast_slice(&data).to_vec(),
+            DecodingResult::U64(data) => cast_slice(&data).to_vec(),
+            DecodingResult::F16(_) => {
+                return Err(invalid_data("TIFF F16 format is not supported in Bevy"));
+            }
+            DecodingResult::F32(data) => cast_slice(&data).to_vec(),
+            DecodingResult::F64(data) => cast_slice(&data).to_vec(),
+            DecodingResult::I8(data) => cast_slice(&data).to_vec(),
+            DecodingResult::I16(data) => cast_slice(&data).to_vec(),
+            DecodingResult::I32(data) => cast_slice(&data).to_vec(),
+            DecodingResult::I64(data) => cast_slice(&data).to_vec(),
+        },
+    };
+
+    Ok((data, width, height))
+}
+
+/// Whether tiles of the format can be written with the compression by [`save_tile`].
+///
+/// Only uncompressed and deflate compressed tiles of single channel or eight bit formats
+/// can be written.
+pub(crate) fn can_save_tile(format: AttachmentFormat, compression: TileCompression) -> bool {
+    matches!(
+        compression,
+        TileCompression::None | TileCompression::Deflate
+    ) && format != AttachmentFormat::Rg16U
+}
+
+/// Saves the tile as a TIFF file, that can be loaded like the ones of the preprocessing.
+pub(crate) fn save_tile(
+    path: &Path,
+    data: &AttachmentData,
+    format: AttachmentFormat,
+    compression: TileCompression,
+) -> io::Result<()> {
+    if !can_save_tile(format, compression) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{format:?} tiles with {compression:?} compression can not be written"),
+        ));
+    }
+
+    let compression = match compression {
+        TileCompression::Deflate => Compression::Deflate(DeflateLevel::Balanced),
+        _ => Compression::Uncompressed,
+    };
+
+    let size = (data.bytes().len() / format.pixel_size() as usize).isqrt() as u32;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))
+        .map_err(io::Error::other)?
+        .with_compression(compression);
+
+    match (data, format) {
+        (AttachmentData::R8Unorm(data), _) => {
+            encoder.write_image::<colortype::Gray8>(size, size, data)
+        }
+        (AttachmentData::Rgba8U(data), AttachmentFormat::Rgb8U) => {
+            // the alpha channel is only added for the upload
+            let data = data
+                .iter()
+                .flat_map(|pixel| &pixel[..3])
+                .copied()
+                .collect::<Vec<_>>();
+            encoder.write_image::<colortype::RGB8>(size, size, &data)
+        }
+        (AttachmentData::Rgba8U(data), _) => {
+            encoder.write_image::<colortype::RGBA8>(size, size, cast_slice(data))
+        }
+        (AttachmentData::R16U(data), _) => {
+            encoder.write_image::<colortype::Gray16>(size, size, data)
+        }
+        (AttachmentData::R16I(data), _) => {
+            encoder.write_image::<colortype::GrayI16>(size, size, data)
+        }
+        (AttachmentData::R32F(data), _) => {
+            encoder.write_image::<colortype::Gray32Float>(size, size, data)
+        }
+        (AttachmentData::Rg16U(_), _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tiles with two channels can not be written",
+            ));
+        }
+    }
+    .map_err(io::Error::other)
+}
+
+/// Decodes every tile or strip of the image individually and assembles
+/// their pixel interleaved values.
+fn decode_chunks(
+    decoder: &mut Decoder<impl Read + Seek>,
+    bytes: &[u8],
+    compression: u16,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, ImageLoaderError> {
+    let (offsets_tag, byte_counts_tag) = match decoder.get_chunk_type() {
+        ChunkType::Tile => (Tag::TileOffsets, Tag::TileByteCounts),
+        ChunkType::Strip => (Tag::StripOffsets, Tag::StripByteCounts),
+    };
+
+    let offsets = decoder.get_tag_u64_vec(offsets_tag).map_err(from_tiff)?;
+    let byte_counts = decoder
+        .get_tag_u64_vec(byte_counts_tag)
+        .map_err(from_tiff)?;
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+
+    // invalid LERC pixels are replaced with the no data value of GDAL
+    let no_data = decoder
+        .find_tag(Tag::GdalNodata)
+        .map_err(from_tiff)?
+        .and_then(|value| value.into_string().ok())
+        .and_then(|value| value.trim_end_matches('\0').trim().parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    let (width, height) = (width as usize, height as usize);
+    let chunks_across = width.div_ceil(chunk_width as usize);
+
+    let mut data = Vec::new();
+    let mut pixel_size = None;
+
+    for (index, (&offset, &byte_count)) in offsets.iter().zip(&byte_counts).enumerate() {
+        let chunk = bytes
+            .get(offset as usize..(offset + byte_count) as usize)
+            .ok_or_else(|| invalid_data("chunk outside of the file"))?;
+
+        let (chunk_data, decoded_width, decoded_height, chunk_pixel_size) = match compression {
+            COMPRESSION_LERC => {
+                let image = decode_lerc(chunk, no_data)?;
+                let pixel_size = image.depth * image.value_size;
+                (image.bytes, image.width, image.height, pixel_size)
+            }
+            _ => {
+                let mut webp = WebPDecoder::new(Cursor::new(chunk)).map_err(invalid_data)?;
+                let (decoded_width, decoded_height) = webp.dimensions();
+                let pixel_size = if webp.has_alpha() { 4 } else { 3 };
+
+                let mut chunk_data = vec![0; webp.output_buffer_size().unwrap_or(0)];
+                webp.read_image(&mut chunk_data).map_err(invalid_data)?;
+
+                (
+                    chunk_data,
+                    decoded_width as usize,
+                    decoded_height as usize,
+                    pixel_size,
+                )
+            }
+        };
+
+        let pixel_size = *pixel_size.get_or_insert(chunk_pixel_size);
+
+        if chunk_pixel_size != pixel_size
+            || chunk_data.len() < decoded_width * decoded_height * pixel_size
+        {
+            return Err(invalid_data("inconsistent chunk layout"));
+        }
+
+        if data.is_empty() {
+            data = vec![0; width * height * pixel_size];
+        }
+
+        let x = (index % chunks_across) * chunk_width as usize;
+        let y = (index / chunks_across) * chunk_height as usize;
+
+        // the chunks at the right and bottom edge may extend past the image
+        let copy_width = decoded_width.min(width.saturating_sub(x));
+        let copy_height = decoded_height.min(height.saturating_sub(y));
+
+        for row in 0..copy_height {
+            let src_start = row * decoded_width * pixel_size;
+            let dst_start = ((y + row) * width + x) * pixel_size;
+
+            data[dst_start..dst_start + copy_width * pixel_size]
+                .copy_from_slice(&chunk_data[src_start..src_start + copy_width * pixel_size]);
+        }
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process;
+
+    fn round_trip(data: AttachmentData, format: AttachmentFormat, compression: TileCompression) {
+        let path = std::env::temp_dir()
+            .join(format!("waw_save_tile_{}", process::id()))
+            .join(format!("{format:?}_{compression:?}.tif"));
+
+        save_tile(&path, &data, format, compression).unwrap();
+        let (bytes, width, height) = decode_tiff(&fs::read(&path).unwrap(), compression).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((width, height), (4, 4));
+        assert_eq!(
+            AttachmentData::from_bytes(&bytes, format).bytes(),
+            data.bytes()
+        );
+    }
+
+    #[test]
+    fn saved_tiles_can_be_loaded() {
+        let heights = (0..16).map(|i| i as f32 * 10.25 - 50.0).collect::<Vec<_>>();
+        let colors = (0..16).map(|i| [i, 2 * i, 3 * i, 255]).collect::<Vec<_>>();
+
+        for compression in [TileCompression::None, TileCompression::Deflate] {
+            round_trip(
+                AttachmentData::R32F(heights.clone()),
+                AttachmentFormat::R32F,
+                compression,
+            );
+            round_trip(
+                AttachmentData::R16I((-8..8).map(|i| i * 1000).collect()),
+                AttachmentFormat::R16I,
+                compression,
+            );
+            round_trip(
+                AttachmentData::R8Unorm((0..16).collect()),
+                AttachmentFormat::R8Unorm,
+                compression,
+            );
+            // the alpha channel of the uploaded data is not saved
+            round_trip(
+                AttachmentData::Rgba8U(colors.clone()),
+                AttachmentFormat::Rgb8U,
+                compression,
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_tiles_are_not_saved() {
+        assert!(!can_save_tile(
+            AttachmentFormat::Rg16U,
+            TileCompression::None
+        ));
+        assert!(!can_save_tile(
+            AttachmentFormat::R32F,
+            TileCompression::Zstd
+        ));
+
+        let path = std::env::temp_dir().join(format!("waw_unsupported_{}.tif", process::id()));
+        let data = AttachmentData::R16U(vec![0; 16]);
+        let error = save_tile(&path, &data, AttachmentFormat::R16U, TileCompression::Zstd);
+
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{data::AttachmentLabel, math::TileCoordinate};
+use bevy::{
+    asset::io::{
+        AssetReader, AssetReaderError, AssetSource, AssetSourceId, PathStream, Reader, VecReader,
+        file::FileAssetReader,
+    },
+    platform::collections::HashMap,
+    prelude::*,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+const MAGIC: &[u8; 8] = b"WAWTILES";
+const VERSION: u32 = 1;
+/// face, lod, x, y, offset and length
+const ENTRY_SIZE: usize = 4 * 4 + 2 * 8;
+
+/// The name of the asset source, that serves the tiles of archives.
+pub const TILE_ARCHIVE_SOURCE: &str = "tile_archive";
+pub const TILE_ARCHIVE_EXTENSION: &str = "tiles";
+
+/// The location of a tile inside of an archive.
+#[derive(Clone, Copy, Debug)]
+pub struct TileArchiveEntry {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A single file, that packs all tiles of an attachment, instead of storing them as loose files.
+///
+/// The file starts with a small header, followed by the tile blobs and an index mapping each
+/// [`TileCoordinate`] to its blob. The last eight bytes store the offset of the index.
+/// All values are little endian.
+pub struct TileArchive {
+    path: PathBuf,
+    index: HashMap<TileCoordinate, TileArchiveEntry>,
+}
+
+impl TileArchive {
+    /// The path of the archive of the attachment, next to its loose tile directory.
+    pub fn attachment_path(earth_path: &Path, label: &AttachmentLabel) -> PathBuf {
+        earth_path.join(format!("{}.{TILE_ARCHIVE_EXTENSION}", String::from(label)))
+    }
+
+    /// Opens the archive and reads its index.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(invalid("not a tile archive"));
+        }
+        if read_u32(&mut reader)? != VERSION {
+            return Err(invalid("unsupported tile archive version"));
+        }
+
+        reader.seek(SeekFrom::End(-8))?;
+        let index_offset = read_u64(&mut reader)?;
+        reader.seek(SeekFrom::Start(index_offset))?;
+
+        let count = read_u64(&mut reader)?;
+        let mut index = HashMap::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let mut entry = [0; ENTRY_SIZE];
+            reader.read_exact(&mut entry)?;
+
+            let u32_at =
+                |start: usize| u32::from_le_bytes(entry[start..start + 4].try_into().unwrap());
+            let u64_at =
+                |start: usize| u64::from_le_bytes(entry[start..start + 8].try_into().unwrap());
+
+            let tile = TileCoordinate::new(
+                u32_at(0),
+                u32_at(4),
+                IVec2::new(u32_at(8) as i32, u32_at(12) as i32),
+            );
+
+            index.insert(
+                tile,
+                TileArchiveEntry {
+                    offset: u64_at(16),
+                    length: u64_at(24),
+                },
+            );
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            index,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = TileCoordinate> + '_ {
+        self.index.keys().copied()
+    }
+
+    pub fn entry(&self, tile: TileCoordinate) -> Option<TileArchiveEntry> {
+        self.index.get(&tile).copied()
+    }
+
+    /// Reads the blob of the tile, or returns `None` if the archive does not contain it.
+    pub fn read_tile(&self, tile: TileCoordinate) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.entry(tile) else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+
+        let mut bytes = vec![0; entry.length as usize];
+        file.read_exact(&mut bytes)?;
+
+        Ok(Some(bytes))
+    }
+}
+
+/// Writes the tiles of an attachment into a new [`TileArchive`].
+pub struct TileArchiveWriter {
+    writer: BufWriter<File>,
+    position: u64,
+    index: Vec<(TileCoordinate, TileArchiveEntry)>,
+}
+
+impl TileArchiveWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            position: (MAGIC.len() + 4) as u64,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn add_tile(&mut self, tile: TileCoordinate, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+
+        self.index.push((
+            tile,
+            TileArchiveEntry {
+                offset: self.position,
+                length: bytes.len() as u64,
+            },
+        ));
+        self.position += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// Appends the index and flushes the archive.
+    pub fn finish(mut self) -> io::Result<()> {
+        let index_offset = self.position;
+
+        self.writer
+            .write_all(&(self.index.len() as u64).to_le_bytes())?;
+
+        for (tile, entry) in &self.index {
+            self.writer.write_all(&tile.face.to_le_bytes())?;
+            self.writer.write_all(&tile.lod.to_le_bytes())?;
+            self.writer.write_all(&tile.xy.x.to_le_bytes())?;
+            self.writer.write_all(&tile.xy.y.to_le_bytes())?;
+            self.writer.write_all(&entry.offset.to_le_bytes())?;
+            self.writer.write_all(&entry.length.to_le_bytes())?;
+        }
+
+        self.writer.write_all(&index_offset.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Serves the tiles of archives under paths like `earth/topography.tiles/0_3_1_2.tif`,
+/// so that they are loaded with the regular [`TiffLoader`](super::TiffLoader).
+pub struct TileArchiveAssetReader {
+    root_path: PathBuf,
+    // the indices are only read once per archive
+    archives: Mutex<HashMap<PathBuf, Arc<TileArchive>>>,
+}
+
+impl TileArchiveAssetReader {
+    pub fn new(root_path: PathBuf) -> Self {
+        Self {
+            root_path,
+            archives: default(),
+        }
+    }
+
+    fn archive(&self, archive_path: &Path) -> Result<Arc<TileArchive>, AssetReaderError> {
+        let mut archives = self.archives.lock().unwrap();
+
+        if let Some(archive) = archives.get(archive_path) {
+            return Ok(archive.clone());
+        }
+
+        let full_path = self.root_path.join(archive_path);
+
+        if !full_path.is_file() {
+            return Err(AssetReaderError::NotFound(full_path));
+        }
+
+        let archive = Arc::new(TileArchive::open(&full_path)?);
+        archives.insert(archive_path.to_path_buf(), archive.clone());
+
+        Ok(archive)
+    }
+
+    fn read_tile(&self, path: &Path) -> Result<Vec<u8>, AssetReaderError> {
+        let not_found = || AssetReaderError::NotFound(path.to_path_buf());
+
+        let archive_path = path.parent().ok_or_else(not_found)?;
+        let tile = path
+            .file_stem()
+            .and_then(|name| name.to_str()?.parse::<TileCoordinate>().ok())
+            .ok_or_else(not_found)?;
+
+        self.archive(archive_path)?
+            .read_tile(tile)?
+            .ok_or_else(not_found)
+    }
+}
+
+impl AssetReader for TileArchiveAssetReader {
+    async fn read<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        Ok(VecReader::new(self.read_tile(path)?))
+    }
+
+    async fn read_meta<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        // the archives do not store meta files, so the default settings are used
+        Err::<VecReader, _>(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        Err(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn is_directory<'a>(&'a self, _path: &'a Path) -> Result<bool, AssetReaderError> {
+        Ok(false)
+    }
+}
+
+/// Registers the [`TILE_ARCHIVE_SOURCE`], that loads tiles from packed attachments.
+///
+/// Asset sources have to be registered before the `AssetPlugin`,
+/// so this plugin has to be added before the `DefaultPlugins`.
+pub struct TileArchivePlugin {
+    /// The asset folder, has to match the one of the `AssetPlugin`.
+    pub file_path: String,
+}
+
+impl Default for TileArchivePlugin {
+    fn default() -> Self {
+        Self {
+            file_path: "assets".to_string(),
+        }
+    }
+}
+
+impl Plugin for TileArchivePlugin {
+    fn build(&self, app: &mut App) {
+        let root_path = FileAssetReader::get_base_path().join(&self.file_path);
+
+        app.register_asset_source(
+            AssetSourceId::from(TILE_ARCHIVE_SOURCE),
+            AssetSource::build()
+                .with_reader(move || Box::new(TileArchiveAssetReader::new(root_path.clone()))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn packed_tiles_can_be_read() {
+        let test_dir = std::env::temp_dir().join(format!("waw_tile_archive_{}", process::id()));
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let archive_path = Path::new("topography.tiles");
+        let tiles = [
+            (TileCoordinate::new(0, 0, IVec2::ZERO), vec![1, 2, 3]),
+            (TileCoordinate::new(2, 3, IVec2::new(5, 7)), Vec::new()),
+            (
+                TileCoordinate::new(5, 12, IVec2::new(4095, 17)),
+                vec![42; 1000],
+            ),
+        ];
+
+        let mut writer = TileArchiveWriter::create(&test_dir.join(archive_path)).unwrap();
+        for (tile, bytes) in &tiles {
+            writer.add_tile(*tile, bytes).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let archive = TileArchive::open(&test_dir.join(archive_path)).unwrap();
+        assert_eq!(archive.tiles().count(), tiles.len());
+
+        // the blobs follow the header in the order they were added
+        let mut offset = (MAGIC.len() + 4) as u64;
+
+        for (tile, bytes) in &tiles {
+            let entry = archive.entry(*tile).unwrap();
+            assert_eq!((entry.offset, entry.length), (offset, bytes.len() as u64));
+            assert_eq!(archive.read_tile(*tile).unwrap().as_ref(), Some(bytes));
+
+            offset += bytes.len() as u64;
+        }
+
+        let missing = TileCoordinate::new(0, 1, IVec2::ONE);
+        assert!(archive.entry(missing).is_none());
+        assert_eq!(archive.read_tile(missing).unwrap(), None);
+
+        // the asset reader resolves the archives relative to its root
+        let reader = TileArchiveAssetReader::new(test_dir.clone());
+        let (tile, bytes) = &tiles[2];
+
+        assert_eq!(
+            &reader.read_tile(&tile.archive_path(archive_path)).unwrap(),
+            bytes
+        );
+        assert!(matches!(
+            reader.read_tile(&missing.archive_path(archive_path)),
+            Err(AssetReaderError::NotFound(_))
+        ));
+
+        let _ = fs::remove_dir_all(&test_dir);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = std::env::temp_dir().join(format!("waw_not_an_archive_{}.tiles", process::id()));
+        fs::write(&path, b"WAWTILEZ\x01\0\0\0\0\0\0\0\0\0\0\0").unwrap();
+
+        let error = TileArchive::open(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}